@@ -1,3 +1,35 @@
 #!/bin/bash
+# el_issue.conf の設定で EL の VC を発行する
+# 使い方: ./generate_el_vc.sh <アカウント名> [出力ファイル]
+# Holder の公開鍵は環境変数 HOLDER_PUBLIC_KEY (既定は el_holder_public_key_ES256.pem)
+set -euo pipefail
 
-env $(grep -v '^#' el_issue.conf | xargs) target/debug/el_issuer
+ACCOUNT_NAME=${1:?usage: generate_el_vc.sh <account_name> [output]}
+OUTPUT=${2:-vc.jwt}
+
+# el_issue.conf の値 (コメント行は除く)
+conf() {
+  grep -v '^#' el_issue.conf | grep "^$1=" | tail -n 1 | cut -d= -f2- || true
+}
+
+# カンマ区切りの値を JSON の文字列の配列にする
+json_array() {
+  local IFS=,
+  local items=()
+  for item in $1; do
+    items+=("\"$item\"")
+  done
+  echo "[${items[*]}]"
+}
+
+IP_ADDRESS=$(conf IP_ADDRESS)
+: "${IP_ADDRESS:?IP_ADDRESS must be set in el_issue.conf}"
+target/debug/vcctl issue \
+  --type fujitaapp_credential \
+  --holder-key "${HOLDER_PUBLIC_KEY:-el_holder_public_key_ES256.pem}" \
+  --claim "account_name=$ACCOUNT_NAME" \
+  --claim-json "ip_addresses=$(json_array "$IP_ADDRESS")" \
+  --claim-json "dns_addresses=$(json_array "$(conf DNS_ADDRESSES)")" \
+  --claim-json "route_networks=$(json_array "$(conf ROUTE_NETWORK_ADDRESSES)")" \
+  --claim "group_name=$(conf GROUP)" \
+  --out "$OUTPUT"
@@ -0,0 +1,305 @@
+//! JWS の署名アルゴリズム
+//!
+//! アルゴリズムは鍵の種類、または JWS ヘッダの `alg` から実行時に決定する。
+
+use anyhow::{anyhow, bail, Result};
+use josekit::{
+    jwk::alg::{ec::EcCurve, ec::EcKeyPair, ed::EdKeyPair, rsa::RsaKeyPair, rsapss::RsaPssKeyPair},
+    jws::{EdDSA, JwsSigner, JwsVerifier, ES256, ES384, ES512, PS256, RS256},
+};
+use p256::pkcs8::{
+    spki::{ObjectIdentifier, SubjectPublicKeyInfoRef},
+    AssociatedOid as _,
+};
+use serde_json::Value;
+use std::{fmt, str::FromStr};
+
+/// id-ecPublicKey
+const OID_EC_PUBLIC_KEY: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.2.1");
+/// id-Ed25519
+const OID_ED25519: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.101.112");
+/// rsaEncryption
+const OID_RSA_ENCRYPTION: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.1.1");
+/// id-RSASSA-PSS
+const OID_RSASSA_PSS: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.1.10");
+
+/// 対応している JWS の署名アルゴリズム
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SigningAlgorithm {
+    /// ECDSA P-256 + SHA-256
+    ES256,
+    /// ECDSA P-384 + SHA-384
+    ES384,
+    /// ECDSA P-521 + SHA-512
+    ES512,
+    /// Ed25519
+    EdDSA,
+    /// RSASSA-PKCS1-v1_5 + SHA-256
+    RS256,
+    /// RSASSA-PSS + SHA-256
+    PS256,
+}
+
+impl SigningAlgorithm {
+    /// JWS ヘッダの `alg` に設定する名前
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::ES256 => "ES256",
+            Self::ES384 => "ES384",
+            Self::ES512 => "ES512",
+            Self::EdDSA => "EdDSA",
+            Self::RS256 => "RS256",
+            Self::PS256 => "PS256",
+        }
+    }
+
+    /// 同じ鍵で署名できるアルゴリズムかどうか
+    /// RSA 鍵は RS256 と PS256 のどちらにも使える
+    pub fn is_compatible_with(&self, other: SigningAlgorithm) -> bool {
+        self == &other || (self.is_rsa() && other.is_rsa())
+    }
+
+    fn is_rsa(&self) -> bool {
+        matches!(self, Self::RS256 | Self::PS256)
+    }
+
+    /// JWT (JWS Compact Serialization) のヘッダの `alg` からアルゴリズムを判定
+    pub fn from_jwt(jwt: &str) -> Result<Self> {
+        let header = josekit::jwt::decode_header(jwt)
+            .map_err(|e| anyhow!("failed to decode jwt header e={e:?}"))?;
+        header
+            .claim("alg")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| anyhow!("jws header has no alg"))?
+            .parse()
+    }
+
+    /// PEM形式の鍵 (秘密鍵・公開鍵のどちらでもよい) の種類からアルゴリズムを判定
+    /// rsaEncryption の RSA 鍵は RS256 とする
+    pub fn from_pem(pem: &[u8]) -> Result<Self> {
+        let parsed = pem::parse(pem)?;
+        match parsed.tag() {
+            "PUBLIC KEY" => Self::from_spki_der(parsed.contents()),
+            "RSA PUBLIC KEY" => Ok(Self::RS256),
+            _ => Self::from_private_key_pem(pem),
+        }
+    }
+
+    /// PEM形式の秘密鍵からアルゴリズムを判定
+    fn from_private_key_pem(pem: &[u8]) -> Result<Self> {
+        if let Ok(key_pair) = EcKeyPair::from_pem(pem, None) {
+            return match key_pair.curve() {
+                EcCurve::P256 => Ok(Self::ES256),
+                EcCurve::P384 => Ok(Self::ES384),
+                EcCurve::P521 => Ok(Self::ES512),
+                curve => bail!("unsupported EC curve {}", curve.name()),
+            };
+        }
+        if EdKeyPair::from_pem(pem).is_ok() {
+            return Ok(Self::EdDSA);
+        }
+        if RsaKeyPair::from_pem(pem).is_ok() {
+            return Ok(Self::RS256);
+        }
+        if RsaPssKeyPair::from_pem(pem, None, None, None).is_ok() {
+            return Ok(Self::PS256);
+        }
+        bail!("unsupported private key type")
+    }
+
+    /// DER形式の SubjectPublicKeyInfo からアルゴリズムを判定
+    fn from_spki_der(der: &[u8]) -> Result<Self> {
+        let spki = SubjectPublicKeyInfoRef::try_from(der)
+            .map_err(|e| anyhow!("failed to parse public key e={e:?}"))?;
+        let (oid, params) = spki
+            .algorithm
+            .oids()
+            .map_err(|e| anyhow!("failed to parse public key algorithm e={e:?}"))?;
+        match (oid, params) {
+            (OID_EC_PUBLIC_KEY, Some(curve)) if curve == p256::NistP256::OID => Ok(Self::ES256),
+            (OID_EC_PUBLIC_KEY, Some(curve)) if curve == p384::NistP384::OID => Ok(Self::ES384),
+            (OID_EC_PUBLIC_KEY, Some(curve)) if curve == p521::NistP521::OID => Ok(Self::ES512),
+            (OID_ED25519, _) => Ok(Self::EdDSA),
+            (OID_RSA_ENCRYPTION, _) => Ok(Self::RS256),
+            (OID_RSASSA_PSS, _) => Ok(Self::PS256),
+            _ => bail!("unsupported public key algorithm {oid}"),
+        }
+    }
+
+    /// JWK の `alg`、なければ `kty` と `crv` からアルゴリズムを判定
+    pub fn from_jwk(jwk: &Value) -> Result<Self> {
+        let field = |name: &str| jwk.get(name).and_then(|v| v.as_str());
+        if let Some(alg) = field("alg") {
+            return alg.parse();
+        }
+        match (field("kty"), field("crv")) {
+            (Some("EC"), Some("P-256")) => Ok(Self::ES256),
+            (Some("EC"), Some("P-384")) => Ok(Self::ES384),
+            (Some("EC"), Some("P-521")) => Ok(Self::ES512),
+            (Some("OKP"), Some("Ed25519")) => Ok(Self::EdDSA),
+            (Some("RSA"), _) => Ok(Self::RS256),
+            (kty, crv) => bail!("unsupported jwk kty={kty:?} crv={crv:?}"),
+        }
+    }
+
+    /// PEM形式の秘密鍵から署名器を作成
+    pub fn signer_from_pem(&self, private_key: &[u8]) -> Result<Box<dyn JwsSigner>> {
+        let signer: Box<dyn JwsSigner> = match self {
+            Self::ES256 => Box::new(ES256.signer_from_pem(private_key)?),
+            Self::ES384 => Box::new(ES384.signer_from_pem(private_key)?),
+            Self::ES512 => Box::new(ES512.signer_from_pem(private_key)?),
+            Self::EdDSA => Box::new(EdDSA.signer_from_pem(private_key)?),
+            Self::RS256 => Box::new(RS256.signer_from_pem(private_key)?),
+            // rsaEncryption の RSA 鍵も使えるよう DER で渡す (PEM だと RSASSA-PSS 鍵に限られる)
+            Self::PS256 => Box::new(PS256.signer_from_der(pem::parse(private_key)?.contents())?),
+        };
+        Ok(signer)
+    }
+
+    /// PEM形式の公開鍵から検証器を作成
+    pub fn verifier_from_pem(&self, public_key: &[u8]) -> Result<Box<dyn JwsVerifier>> {
+        let verifier: Box<dyn JwsVerifier> = match self {
+            Self::ES256 => Box::new(ES256.verifier_from_pem(public_key)?),
+            Self::ES384 => Box::new(ES384.verifier_from_pem(public_key)?),
+            Self::ES512 => Box::new(ES512.verifier_from_pem(public_key)?),
+            Self::EdDSA => Box::new(EdDSA.verifier_from_pem(public_key)?),
+            Self::RS256 => Box::new(RS256.verifier_from_pem(public_key)?),
+            Self::PS256 => Box::new(PS256.verifier_from_der(pem::parse(public_key)?.contents())?),
+        };
+        Ok(verifier)
+    }
+
+    /// JWK形式の公開鍵から検証器を作成
+    /// 鍵が直接指定されているので、JWS ヘッダの `kid` との照合は行わない
+    pub fn verifier_from_jwk(&self, jwk: &Value) -> Result<Box<dyn JwsVerifier>> {
+        let mut jwk = jwk.clone();
+        if let Some(obj) = jwk.as_object_mut() {
+            obj.remove("kid");
+            // RSA 鍵は RS256 / PS256 のどちらでも使えるので、JWK の alg には縛られない
+            if self.is_rsa() {
+                obj.remove("alg");
+            }
+        }
+        let jwk = crate::jwk::json_to_jwk(&jwk)?;
+        let verifier: Box<dyn JwsVerifier> = match self {
+            Self::ES256 => Box::new(ES256.verifier_from_jwk(&jwk)?),
+            Self::ES384 => Box::new(ES384.verifier_from_jwk(&jwk)?),
+            Self::ES512 => Box::new(ES512.verifier_from_jwk(&jwk)?),
+            Self::EdDSA => Box::new(EdDSA.verifier_from_jwk(&jwk)?),
+            Self::RS256 => Box::new(RS256.verifier_from_jwk(&jwk)?),
+            Self::PS256 => Box::new(PS256.verifier_from_jwk(&jwk)?),
+        };
+        Ok(verifier)
+    }
+}
+
+impl FromStr for SigningAlgorithm {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "ES256" => Ok(Self::ES256),
+            "ES384" => Ok(Self::ES384),
+            "ES512" => Ok(Self::ES512),
+            "EdDSA" => Ok(Self::EdDSA),
+            "RS256" => Ok(Self::RS256),
+            "PS256" => Ok(Self::PS256),
+            _ => bail!("unsupported signing algorithm {s}"),
+        }
+    }
+}
+
+impl fmt::Display for SigningAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key::{generate_key_pair, public_key_pem_to_jwk};
+    use josekit::jws::{self, JwsHeader};
+
+    const ALGORITHMS: [SigningAlgorithm; 6] = [
+        SigningAlgorithm::ES256,
+        SigningAlgorithm::ES384,
+        SigningAlgorithm::ES512,
+        SigningAlgorithm::EdDSA,
+        SigningAlgorithm::RS256,
+        SigningAlgorithm::PS256,
+    ];
+
+    fn sign(alg: SigningAlgorithm, private_key: &[u8]) -> String {
+        let mut header = JwsHeader::new();
+        header.set_token_type("JWT");
+        let signer = alg.signer_from_pem(private_key).unwrap();
+        jws::serialize_compact(b"payload", &header, signer.as_ref()).unwrap()
+    }
+
+    #[test]
+    fn sign_and_verify_with_pem_and_jwk() {
+        for alg in ALGORITHMS {
+            let (private_key, public_key) = generate_key_pair(alg).unwrap();
+            let jws = sign(alg, &private_key);
+            assert_eq!(SigningAlgorithm::from_jwt(&jws).unwrap(), alg);
+
+            let verifier = alg.verifier_from_pem(&public_key).unwrap();
+            let (payload, _) = jws::deserialize_compact(&jws, verifier.as_ref()).unwrap();
+            assert_eq!(payload, b"payload", "{alg}");
+
+            let jwk = public_key_pem_to_jwk(&public_key).unwrap();
+            assert!(alg.is_compatible_with(SigningAlgorithm::from_jwk(&jwk).unwrap()));
+            let verifier = alg.verifier_from_jwk(&jwk).unwrap();
+            jws::deserialize_compact(&jws, verifier.as_ref()).unwrap();
+        }
+    }
+
+    #[test]
+    fn key_type_detection() {
+        for alg in ALGORITHMS {
+            let (private_key, public_key) = generate_key_pair(alg).unwrap();
+            // 生成した RSA 鍵は rsaEncryption なので RS256 になる
+            let expected = if alg.is_rsa() {
+                SigningAlgorithm::RS256
+            } else {
+                alg
+            };
+            assert_eq!(SigningAlgorithm::from_pem(&private_key).unwrap(), expected);
+            assert_eq!(SigningAlgorithm::from_pem(&public_key).unwrap(), expected);
+            assert_eq!(alg.name().parse::<SigningAlgorithm>().unwrap(), alg);
+        }
+    }
+
+    #[test]
+    fn rsa_encryption_key_signs_ps256() {
+        let (private_key, public_key) = generate_key_pair(SigningAlgorithm::RS256).unwrap();
+        let jws = sign(SigningAlgorithm::PS256, &private_key);
+        let verifier = SigningAlgorithm::PS256
+            .verifier_from_pem(&public_key)
+            .unwrap();
+        jws::deserialize_compact(&jws, verifier.as_ref()).unwrap();
+        // PKCS#1 v1.5 の検証器では検証できない
+        let verifier = SigningAlgorithm::RS256
+            .verifier_from_pem(&public_key)
+            .unwrap();
+        assert!(jws::deserialize_compact(&jws, verifier.as_ref()).is_err());
+    }
+
+    #[test]
+    fn algorithm_from_jwk_without_alg() {
+        let jwk = |kty: &str, crv: &str| serde_json::json!({ "kty": kty, "crv": crv });
+        let from_jwk = |jwk| SigningAlgorithm::from_jwk(&jwk).unwrap();
+        assert_eq!(from_jwk(jwk("EC", "P-256")), SigningAlgorithm::ES256);
+        assert_eq!(from_jwk(jwk("EC", "P-384")), SigningAlgorithm::ES384);
+        assert_eq!(from_jwk(jwk("EC", "P-521")), SigningAlgorithm::ES512);
+        assert_eq!(from_jwk(jwk("OKP", "Ed25519")), SigningAlgorithm::EdDSA);
+        assert_eq!(
+            from_jwk(serde_json::json!({ "kty": "RSA" })),
+            SigningAlgorithm::RS256
+        );
+        assert!(SigningAlgorithm::from_jwk(&jwk("EC", "secp256k1")).is_err());
+        assert!(!SigningAlgorithm::ES256.is_compatible_with(SigningAlgorithm::ES384));
+        assert!(SigningAlgorithm::RS256.is_compatible_with(SigningAlgorithm::PS256));
+    }
+}
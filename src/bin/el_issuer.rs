@@ -1,132 +1,154 @@
-use anyhow::{anyhow, Result};
-use base64::Engine;
-use josekit::{
-    jwk::Jwk,
-    jws::{JwsHeader, ES256},
-    jwt::{self, JwtPayload},
+use anyhow::{anyhow, bail, Result};
+use serde_json::{json, Map, Value};
+use std::{env, sync::Arc, time::Duration};
+use vc_vp::{
+    config::{no_more_args, required, take_flag, take_switch, AudienceConfig},
+    jwk::jwk_thumbprint_sha256,
+    key::public_key_to_jwk,
+    keyset::IssuerKeySet,
+    oid4vci::{CredentialIssuerConfig, CredentialIssuerServer},
+    server, signer,
+    status_list::{self, StatusListRegistry},
+    GenerateVCParams, Issuer,
 };
-use rand::{
-    seq::SliceRandom, // SliceRandomトレイトをインポート
-};
-#[cfg(feature = "EdDSA")]
-use ring::signature::{Ed25519KeyPair, KeyPair};
-use sd_jwt_payload::{Disclosure, SdJwt, SdObjectEncoder};
-use serde_json::{json, Value};
-use std::env;
-
-struct GenerateVCParams {
-    pub issuer: String,
-    pub vct: String,
-    pub private_key: Vec<u8>,
-    pub jwk: Jwk,
-    pub account_name: String,
-    pub ip_address: String,
-    pub route_networks: Vec<String>,
-    pub dns_addresses: Vec<String>,
-    pub group_name: String,
-    pub vc_expires_in: u64,
-    pub key_id: String,
+
+/// 選択的開示のポリシーの既定値 (環境変数 DISCLOSURE_POLICY で JSON Pointer のカンマ区切りで変更できる)
+/// 配列は要素ごとに隠したうえで配列全体も隠し、Holder が要素を 1 つずつ選んで開示できるようにする
+const DISCLOSURE_POLICY: &str = "/account_name,/ip_addresses/*,/ip_addresses,\
+/dns_addresses/*,/dns_addresses,/route_networks/*,/route_networks,/group_name";
+
+/// 環境変数の値、未設定の場合は既定値
+fn env_or(name: &str, default: &str) -> String {
+    env::var(name).unwrap_or_else(|_e| default.to_string())
 }
 
-/// SD-JWT形式のVCを生成
-fn generate_sd_jwt_vc(params: GenerateVCParams) -> anyhow::Result<String> {
-    // This function should generate a real SD-JWT formatted VC
-    // ======================= Issuer part =======================
-    let ip_addresses = [params.ip_address];
-
-    let mut object = serde_json::json!({
-      "account_name": params.account_name,
-      "ip_addresses": ip_addresses,
-      "dns_addresses": params.dns_addresses,
-      "route_networks": params.route_networks,
-      "group_name": params.group_name,
-    });
+const USAGE: &str = "\
+usage: el_issuer <command> [options]
 
-    let mut inner_jwk = serde_json::Map::new();
-    let jwk_obj = serde_json::from_str(&params.jwk.to_string())
-        .map_err(|e| anyhow!("failed to convert serde Value from JWK: {e:?}"))?;
-    inner_jwk.insert("jwk".to_string(), jwk_obj);
-    let cnf = serde_json::to_value(inner_jwk)
-        .map_err(|e| anyhow!("failed to add JWK to serde object: {e:?}"))?;
+commands:
+  issue        VC を発行する
+  serve        OID4VCI の発行サーバとして起動する
+  status       アカウントの VC のステータスを変更する (STATUS_LIST_URI が必要)
+  status-list  署名したステータスリストを書き出す (STATUS_LIST_URI が必要)
+  jwks         VC を検証する公開鍵の JWKS を書き出す
 
-    if let Value::Object(ref mut map) = object {
-        map.insert("cnf".to_string(), cnf);
-    }
+キーセット (ISSUER_KEY_SET) の鍵は `vcctl keys` で管理する
 
-    let mut encoder: SdObjectEncoder = object.try_into()?;
-    let disclosures: Vec<Disclosure> = vec![
-        encoder.conceal("/account_name", None)?,
-        encoder.conceal("/ip_addresses", None)?,
-        encoder.conceal("/dns_addresses", None)?,
-        encoder.conceal("/route_networks", None)?,
-        encoder.conceal("/group_name", None)?,
-    ];
-
-    encoder.add_decoys("", 2)?; // Add decoys to the top level.
-
-    // Create the JWT.
-    // Creating JWTs is outside the scope of this library, josekit is used here as an example.
-    let mut header = JwsHeader::new();
-    let token_type = format!("vc+{}", sd_jwt_payload::HEADER_TYP);
-    header.set_token_type(token_type);
-    #[cfg(feature = "EdDSA")]
-    header.set_algorithm("EdDSA"); // EdDSA署名アルゴリズムの指定
-    #[cfg(feature = "ES256")]
-    header.set_algorithm("ES256"); // EdDSA署名アルゴリズムの指定
-    header.set_key_id(params.key_id);
-
-    // Use the encoded object as a payload for the JWT.
-    let mut payload = JwtPayload::from_map(encoder.object()?.clone())?;
-    payload.set_issuer(params.issuer);
-    let _ = payload.set_claim("vct", Some(Value::from(params.vct)));
-    let audiences = vec!["el-client".to_string()];
-    payload.set_audience(audiences);
-    let now = std::time::SystemTime::now();
-    let expires_at = now + std::time::Duration::from_secs(params.vc_expires_in);
-    payload.set_issued_at(&now);
-    payload.set_expires_at(&expires_at);
-
-    #[cfg(feature = "EdDSA")]
-    let signer = EdDSA
-        .signer_from_pem(params.private_key)
-        .map_err(|e| anyhow!("failed to convert signer from pem: {e:?}"))?;
-    #[cfg(feature = "ES256")]
-    let signer = ES256
-        .signer_from_pem(params.private_key)
-        .map_err(|e| anyhow!("failed to convert signer from pem: {e:?}"))?;
-
-    println!("loaded signer's private key");
-    let jwt = jwt::encode_with_signer(&payload, &header, &signer)
-        .map_err(|e| anyhow!("failed to encode with signer: {e:?}"))?;
-
-    // Create an SD_JWT by collecting the disclosures and creating an `SdJwt` instance.
-    let mut disclosures: Vec<String> = disclosures
-        .into_iter()
-        .map(|disclosure| disclosure.to_string())
-        .collect();
-
-    // 乱数生成器を取得
-    let mut rng = rand::rng();
-    // ベクタの中身をランダムに入れ替える
-    disclosures.shuffle(&mut rng);
-
-    // disclosures の配列の中身をランダムに並べ替える
-    let sd_jwt: SdJwt = SdJwt::new(jwt, disclosures, None);
-    Ok(sd_jwt.presentation())
-}
+`el_issuer <command> --help` で各コマンドのオプションを表示する";
 
-fn main() -> Result<()> {
-    let issuer = env::var("ISSUER")
-        .unwrap_or_else(|_e| "https://fujita-el-issuer.emotionlink.jp".to_string());
-    let vct = env::var("VCT")
-        .unwrap_or_else(|_e| "https://credentials.emotionlink.jp/fujitaapp_credential".to_string());
+const ISSUE_USAGE: &str = "\
+usage: el_issuer issue --account-name <name> --ip-address <ip> --expires-days <days> [--out <vc.jwt>]
+
+  --account-name  VC の account_name
+  --ip-address    VC の ip_addresses に入れる IP アドレス
+  --expires-days  有効期間 (日)
+  --out           出力先 (既定は vc.jwt)
+
+  Holder の公開鍵は環境変数 HOLDER_PRIV_KEY、dns_addresses・route_networks・group_name は
+  DNS_ADDRESSES・ROUTE_NETWORK_ADDRESSES・GROUP";
+
+const SERVE_USAGE: &str = "\
+usage: el_issuer serve
+
+  設定は el_issue.conf の環境変数 (VC の ip_addresses は IP_ADDRESS、待ち受けアドレスは BIND_ADDRESS)";
+
+const STATUS_USAGE: &str = "\
+usage: el_issuer status --account-name <name> --status <valid|revoked|suspended>
+
+  アカウントに発行したすべての VC のステータスを変更する (退職者の VC の失効など)";
+
+const STATUS_LIST_USAGE: &str = "\
+usage: el_issuer status-list [--out <status_list.jwt>]
 
-    #[cfg(feature = "EdDSA")]
-    const ISSUER_KEY: &str = "el_issuer_private_key_ed25519.pem";
-    #[cfg(feature = "ES256")]
-    const ISSUER_KEY: &str = "el_issuer_private_key_ES256.pem";
+  署名したステータスリストを書き出す (静的に公開する場合、既定は status_list.jwt)";
 
-    let private_key = std::fs::read_to_string(ISSUER_KEY).expect("Failed to read private key file");
+const JWKS_USAGE: &str = "\
+usage: el_issuer jwks [--out <jwks.json>]
+
+  VC を検証する公開鍵の JWKS を書き出す (キーセットの場合はローテーション前後の鍵も含める、既定は jwks.json)";
+
+fn main() -> Result<()> {
+    // サーバのアクセスログ・トラストレジストリの警告などの出力 (RUST_LOG で変更でき、既定は info)
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    if args.is_empty() {
+        println!("{USAGE}");
+        return Ok(());
+    }
+    let command = args.remove(0);
+    let usage = match command.as_str() {
+        "issue" => ISSUE_USAGE,
+        "serve" => SERVE_USAGE,
+        "status" => STATUS_USAGE,
+        "status-list" => STATUS_LIST_USAGE,
+        "jwks" => JWKS_USAGE,
+        "help" | "--help" | "-h" => {
+            println!("{USAGE}");
+            return Ok(());
+        }
+        command => bail!("unknown command {command}\n\n{USAGE}"),
+    };
+    if take_switch(&mut args, "--help") {
+        println!("{usage}");
+        return Ok(());
+    }
+    // VC / KB-JWT の aud (--vc-audience, VC_AUDIENCE, vc_vp.conf など)
+    let audiences = AudienceConfig::load(&mut args)?;
+
+    let issuer = env_or("ISSUER", "https://fujita-el-issuer.emotionlink.jp");
+    let vct = env_or(
+        "VCT",
+        "https://credentials.emotionlink.jp/fujitaapp_credential",
+    );
+
+    // 署名アルゴリズムは鍵の種類から判定する
+    // ISSUER_KEY_SET を設定した場合は ISSUER_KEY の代わりにキーセットの現在の署名鍵を使う
+    let issuer_key = env_or("ISSUER_KEY", "el_issuer_private_key_ES256.pem");
+    let key_set = IssuerKeySet::from_env()?;
+
+    let disclosable = parse_split_string(&env_or("DISCLOSURE_POLICY", DISCLOSURE_POLICY));
+
+    // ステータスリスト (STATUS_LIST_URI を設定した場合のみ)
+    let status_list = StatusListRegistry::from_env()?;
+    match command.as_str() {
+        // 退職者の VC などを失効させる
+        "status" => {
+            let account_name = required(
+                take_flag(&mut args, "--account-name")?,
+                "--account-name",
+                usage,
+            )?;
+            let status = required(take_flag(&mut args, "--status")?, "--status", usage)?;
+            no_more_args(&args, usage)?;
+            let status_list = status_list.ok_or_else(|| anyhow!("STATUS_LIST_URI must be set"))?;
+            let indexes = status_list.set_subject_status(&account_name, status.parse()?)?;
+            println!("{account_name}: {status} (idx={indexes:?})");
+            return Ok(());
+        }
+        "status-list" => {
+            let output = take_flag(&mut args, "--out")?.unwrap_or_else(|| "status_list.jwt".into());
+            no_more_args(&args, usage)?;
+            let status_list = status_list.ok_or_else(|| anyhow!("STATUS_LIST_URI must be set"))?;
+            let issuer = load_issuer(issuer, &issuer_key, key_set.as_ref())?;
+            let expires_in =
+                Duration::from_secs(env_or("STATUS_LIST_EXPIRES_IN", "86400").parse()?);
+            let token = status_list.token(&issuer, status_list::DEFAULT_TTL, expires_in)?;
+            std::fs::write(&output, token)?;
+            println!("wrote {output}");
+            return Ok(());
+        }
+        // (`serve` では `/jwks.json` と `/.well-known/jwt-vc-issuer` で公開する)
+        "jwks" => {
+            let output = take_flag(&mut args, "--out")?.unwrap_or_else(|| "jwks.json".into());
+            no_more_args(&args, usage)?;
+            let issuer = load_issuer(issuer, &issuer_key, key_set.as_ref())?;
+            std::fs::write(&output, serde_json::to_string_pretty(&issuer.jwks()?)?)?;
+            println!("wrote {output}");
+            return Ok(());
+        }
+        _ => {}
+    }
 
     let route_network_addresses =
         env::var("ROUTE_NETWORK_ADDRESSES").expect("ROUTE_NETWORK_ADDRESSES must be set");
@@ -135,61 +157,115 @@ fn main() -> Result<()> {
     let dns_addresses = parse_split_string(&dns_addresses);
     let group = env::var("GROUP").expect("GROUP must be set");
 
-    #[cfg(feature = "EdDSA")]
-    let holder_key = env::var("HOLDER_PRIV_KEY")
-        .unwrap_or_else(|_e| "el_holder_private_key_ed25519.pem".to_string());
-    #[cfg(feature = "ES256")]
-    let holder_key = env::var("HOLDER_PRIV_KEY")
-        .unwrap_or_else(|_e| "el_holder_public_key_ES256.pem".to_string());
-
-    let args: Vec<String> = env::args().collect();
+    // OID4VCI の発行サーバとして動かす
+    if command == "serve" {
+        no_more_args(&args, usage)?;
+        let ip_address = env::var("IP_ADDRESS").map_err(|_| anyhow!("IP_ADDRESS must be set"))?;
+        let config = CredentialIssuerConfig {
+            credential_configuration_id: vct.rsplit('/').next().unwrap_or(&vct).to_string(),
+            credential_issuer: issuer.clone(),
+            vct,
+            format: env_or("VC_FORMAT", "vc+sd-jwt"),
+            access_token_audience: env_or("ACCESS_TOKEN_AUDIENCE", &issuer),
+            access_token_scope: env_or("ACCESS_TOKEN_SCOPE", "RightToConnectToEmotionLink"),
+            client_id: env_or("CLIENT_ID", "fujita-app"),
+            authorization_details_type: env_or("AUTHORIZATION_DETAILS_TYPE", "openid_credential"),
+            proof_type: env_or("PROOF_TYPE", "jwt"),
+            token_expires_in: env_or("TOKEN_EXPIRES_IN", "600").parse()?,
+            vc_expires_in: env_or("VC_EXPIRES_IN", "604800").parse()?,
+            vc_audience: audiences.vc_audience,
+            account_name_key: env_or("ACCOUNT_NAME_KEY", "did"),
+            disclosable: disclosable.clone(),
+            decoys: 2,
+        };
+        // 認証サーバ (アサーションの署名者) の公開鍵
+        let authentication_key_file =
+            env_or("AUTHENTICATION_PARAM_FILE", "auth_issuer_pub_key.pem");
+        let authentication_key = std::fs::read(&authentication_key_file).map_err(|e| {
+            anyhow!("failed to read authentication key {authentication_key_file}: {e:?}")
+        })?;
 
-    // 第一引数が存在するか確認
-    let account_name = match args.get(1) {
-        Some(v) => v.to_string(),
-        None => "takehi".to_string(),
-    };
+        let issuer = load_issuer(issuer, &issuer_key, key_set.as_ref())?;
+        let mut server = CredentialIssuerServer::new(
+            config,
+            issuer,
+            authentication_key,
+            Box::new(move |account_name| {
+                Ok(el_claims(
+                    account_name,
+                    &ip_address,
+                    &dns_addresses,
+                    &route_network_addresses,
+                    &group,
+                ))
+            }),
+        );
+        if let Some(status_list) = status_list {
+            server = server.with_status_list(Arc::new(status_list));
+        }
+        // 実行中にローテーションした鍵にもリクエストごとに切り替える
+        if let Some(key_set) = key_set {
+            server = server.with_key_set(Arc::new(key_set));
+        }
+        return server::serve(&env_or("BIND_ADDRESS", "[::]:8080"), |req| {
+            server.handle(req)
+        });
+    }
 
-    let expires_days = match args.get(2) {
-        Some(v) => v.parse()?,
-        None => 7,
-    };
+    let account_name = required(
+        take_flag(&mut args, "--account-name")?,
+        "--account-name",
+        usage,
+    )?;
+    let ip_address = required(take_flag(&mut args, "--ip-address")?, "--ip-address", usage)?;
+    let expires_days: u64 = required(
+        take_flag(&mut args, "--expires-days")?,
+        "--expires-days",
+        usage,
+    )?
+    .parse()
+    .map_err(|e| anyhow!("--expires-days: {e}"))?;
     let vc_expires_in = expires_days * 24 * 60 * 60;
+    let output = take_flag(&mut args, "--out")?.unwrap_or_else(|| "vc.jwt".to_string());
+    no_more_args(&args, usage)?;
 
-    let ip_address = match args.get(3) {
-        Some(v) => v.to_string(),
-        None => "10.0.0.100".to_string(),
-    };
-
-    let key_id = match args.get(4) {
-        Some(v) => v.to_string(),
-        None => "VCVk4e6-JsLk_Wrv6Z2OFQ-4G2ejbvw0JAAWCqJfJus".to_string(),
-    };
+    let holder_key = env_or("HOLDER_PRIV_KEY", "el_holder_public_key_ES256.pem");
 
     // ======================= Holder part =======================
     // PEMファイルから秘密鍵を読み込み、公開鍵を取り出す
     let pubkey_jwk =
         public_key_to_jwk(&holder_key).map_err(|e| anyhow!("failed to convert to jwk e={e:?}"))?;
     println!("pubkey_jwk={pubkey_jwk}");
-    let jwk = josekit::jwk::Jwk::from_map(pubkey_jwk.as_object().unwrap().clone()).unwrap();
 
+    let claims = el_claims(
+        &account_name,
+        &ip_address,
+        &dns_addresses,
+        &route_network_addresses,
+        &group,
+    );
+
+    let status = match &status_list {
+        Some(status_list) => Some(status_list.allocate(&account_name)?),
+        None => None,
+    };
+    let issuer = load_issuer(issuer, &issuer_key, key_set.as_ref())?;
     let params = GenerateVCParams {
-        issuer,
-        vct,
-        private_key: private_key.as_bytes().to_vec(),
-        jwk,
-        account_name,
-        ip_address,
-        route_networks: route_network_addresses,
-        dns_addresses,
-        group_name: group,
+        vct: Some(vct),
+        vct_integrity: None,
+        holder_jwk: pubkey_jwk,
+        holder_kid: None,
+        claims,
+        disclosable,
+        decoys: 2,
+        audience: audiences.vc_audience,
         vc_expires_in,
-        key_id,
+        status,
     };
-    match generate_sd_jwt_vc(params) {
+    match issuer.generate_sd_jwt_vc(params) {
         Ok(vc) => {
             println!("VC={vc}");
-            std::fs::write("vc.jwt", vc)?;
+            std::fs::write(&output, vc)?;
         }
         Err(e) => {
             eprintln!("{e:?}");
@@ -199,105 +275,36 @@ fn main() -> Result<()> {
     Ok(())
 }
 
-/// PEMファイルから鍵を取り出す
-#[cfg(feature = "EdDSA")]
-fn read_pem_file(file_path: &str) -> Result<Vec<u8>> {
-    use pem::parse;
-    let pem = parse(std::fs::read(file_path)?)?;
-    Ok(pem.contents().to_vec())
-}
-
-/// キーペアを生成
-#[cfg(feature = "EdDSA")]
-fn generate_key_pair(file_path: &str) -> Result<Ed25519KeyPair> {
-    let secret_key_bytes = read_pem_file(file_path)?;
-    let key_pair = Ed25519KeyPair::from_pkcs8_maybe_unchecked(&secret_key_bytes)?;
-    Ok(key_pair)
-}
-
-/// 公開鍵をJWK形式に変換する
-#[cfg(feature = "EdDSA")]
-fn public_key_to_jwk(file_path: &str) -> Result<Value, Box<dyn std::error::Error>> {
-    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
-    use jsonwebtoken::jwk::{
-        AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, OctetKeyPairParameters,
-        OctetKeyPairType, PublicKeyUse,
-    };
-
-    let key_pair = generate_key_pair(file_path)?;
-
-    let public_key_bytes = key_pair.public_key().as_ref();
-    let x = URL_SAFE_NO_PAD.encode(public_key_bytes);
-
-    let common = CommonParameters {
-        public_key_use: Some(PublicKeyUse::Signature),
-        key_operations: None,
-        key_algorithm: None,
-        key_id: None,
-        x509_url: None,
-        x509_chain: None,
-        x509_sha1_fingerprint: None,
-        x509_sha256_fingerprint: None,
-    };
-
-    let params = OctetKeyPairParameters {
-        key_type: OctetKeyPairType::OctetKeyPair,
-        curve: EllipticCurve::Ed25519,
-        x,
+/// 署名する発行者
+/// キーセットがあれば現在の署名鍵、なければ ISSUER_KEY の鍵 (`kid` は KEY_ID、既定は JWK Thumbprint)
+fn load_issuer(issuer: String, issuer_key: &str, key_set: Option<&IssuerKeySet>) -> Result<Issuer> {
+    if let Some(key_set) = key_set {
+        return key_set.issuer(issuer);
+    }
+    let signer = signer::open(issuer_key)?;
+    let key_id = match env::var("KEY_ID") {
+        Ok(kid) => kid,
+        Err(_) => jwk_thumbprint_sha256(&signer.public_jwk()?)?,
     };
-    let algorithm = AlgorithmParameters::OctetKeyPair(params);
-
-    let jwk = Jwk { common, algorithm };
-
-    let jwk_value = serde_json::to_value(&jwk)?;
-    Ok(jwk_value)
+    Ok(Issuer::from_signer(issuer, signer, key_id))
 }
 
-#[cfg(feature = "ES256")]
-fn public_key_to_jwk(file_path: &str) -> Result<Value> {
-    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
-    use p256::{elliptic_curve::sec1::ToEncodedPoint as _, pkcs8::DecodePublicKey};
-
-    let secret_key = std::fs::read_to_string(file_path)?;
-    let public_key = p256::PublicKey::from_public_key_pem(&secret_key).inspect_err(|&e| {
-        eprintln!("failed convert public key from pem e={e:?}");
-    })?;
-    // 座標を取り出す (圧縮なしのポイントにする: to_encoded_point(false))
-    let encoded_point = public_key.to_encoded_point(false);
-    let x_bytes = encoded_point
-        .x()
-        .ok_or("Failed to get X coordinate")
-        .map_err(|e| {
-            eprintln!("failed get x from pubkey e={e:?}");
-            anyhow!(e)
-        })?;
-    let y_bytes = encoded_point
-        .y()
-        .ok_or("Failed to get Y coordinate")
-        .map_err(|e| {
-            eprintln!("failed get y from pubkey e={e:?}");
-            anyhow!(e)
-        })?;
-
-    // ---- (3) x, y を Base64URL (padding なし) でエンコード
-    let x = URL_SAFE_NO_PAD.encode(x_bytes);
-    let y = URL_SAFE_NO_PAD.encode(y_bytes);
-
-    // ---- (4) 必要なフィールドを揃えて JWK (公開鍵) を生成
-    // ES256(P-256) の JWK には "kty", "crv", "x", "y" が必須となります
-    // "alg" や "kid", "use", "key_ops" 等は必要に応じて追加してください
-    let jwk = json!({
-        "kty": "EC",
-        "crv": "P-256",
-        "x": x,
-        "y": y,
-        "alg": "ES256",
-        "use": "sig", // 署名用途の場合は例えばこう指定
-        // "kid": "任意のキーID",
-        // "key_ops": ["verify"], など
+/// EL の VC に含めるクレーム
+fn el_claims(
+    account_name: &str,
+    ip_address: &str,
+    dns_addresses: &[String],
+    route_network_addresses: &[String],
+    group: &str,
+) -> Map<String, Value> {
+    let object = json!({
+      "account_name": account_name,
+      "ip_addresses": [ip_address],
+      "dns_addresses": dns_addresses,
+      "route_networks": route_network_addresses,
+      "group_name": group,
     });
-
-    Ok(jwk)
+    object.as_object().cloned().unwrap_or_default()
 }
 
 /// カンマ区切りの分割された文字列を取得
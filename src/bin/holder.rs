@@ -1,146 +1,279 @@
-use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
-use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
-use sd_jwt_payload::{KeyBindingJwtClaims, SdJwt, Sha256Hasher};
-use serde_json::{json, Number, Value};
-use std::{error::Error, fs::File, io::Read};
-
-fn main() -> Result<(), Box<dyn Error>> {
-    // 秘密鍵をファイルから読み込み
-    #[cfg(feature = "EdDSA")]
-    const ISSUER_PUBLIC_KEY: &str = "issuer_public_key_ed25519.pem";
-    #[cfg(feature = "ES256")]
-    const ISSUER_PUBLIC_KEY: &str = "issuer_public_key_ES256.pem";
-    #[cfg(feature = "EdDSA")]
-    const HOLDER_PRIVATE_KEY: &str = "holder_private_key_ed25519.pem";
-    #[cfg(feature = "ES256")]
-    const HOLDER_PRIVATE_KEY: &str = "holder_private_key_ES256_pkcs8.pem";
-
-    let vc = std::fs::read_to_string("vc.jwt").unwrap();
-
-    let sd_jwt: SdJwt = SdJwt::parse(&vc)?;
-    println!("sd_jwt: {sd_jwt:?}");
+use anyhow::{anyhow, bail, Result};
+use josekit::jwt;
+use sd_jwt_payload::SdJwt;
+use std::env;
+use vc_vp::{
+    config::{no_more_args, required, take_flag, take_switch, AudienceConfig},
+    credential_store::{CredentialFilter, CredentialStore, StoredCredential},
+    vcdm,
+    wallet::{self, Wallet},
+    Holder, Verifier,
+};
 
-    let public_key = read_pem_file(ISSUER_PUBLIC_KEY)?;
-    println!("public_key: {public_key:?}");
-    #[cfg(feature = "EdDSA")]
-    let decoding_key = DecodingKey::from_ed_pem(&public_key)?;
-    #[cfg(feature = "ES256")]
-    let decoding_key = DecodingKey::from_ec_pem(&public_key)?;
-    println!("decoding_key");
-    #[cfg(feature = "EdDSA")]
-    let mut validation = Validation::new(Algorithm::EdDSA);
-    #[cfg(feature = "ES256")]
-    let mut validation = Validation::new(Algorithm::ES256);
-    validation.set_audience(&["fujita-app"]);
-    let token_data = jsonwebtoken::decode::<Value>(&sd_jwt.jwt, &decoding_key, &validation)?;
-    println!("sd-jwt's header={:?}", token_data.header);
-    println!("sd-jwt's payload={:?}", token_data.claims);
-    println!();
+const USAGE: &str = "\
+usage: holder [command] [options]
+
+commands:
+  (なし)   vc.jwt (または環境変数 CREDENTIAL_ID のストアのクレデンシャル) を DISCLOSE のクレームを開示して vp.jwt に提示する
+  respond  OID4VP の要求に応答する
+  mdoc     mdoc.cbor から要素を選んで DeviceResponse を作成する
+  wallet   WALLET_DIR (既定は wallet) のクレデンシャルを管理する
+
+`holder <command> --help` で各コマンドのオプションを表示する";
+
+const RESPOND_USAGE: &str = "\
+usage: holder respond --request <authorization request>
+
+  --request  `openid4vp://?...` の URL、`request_uri`、Request Object の JWT のいずれか
+
+  クレデンシャルは環境変数 CREDENTIALS (カンマ区切り、既定は vc.jwt)、
+  CREDENTIALS を設定せずに WALLET_DIR を設定した場合はストアの期限切れでないクレデンシャル";
+
+const MDOC_USAGE: &str = "\
+usage: holder mdoc [--elements <names>]
+
+  --elements  開示する要素名のカンマ区切り (既定は did)
+
+  nonce は環境変数 NONCE、なければ verifier が書き出した nonce.txt";
+
+const WALLET_USAGE: &str = "\
+usage: holder wallet add --vc <file>...
+       holder wallet list [--vct <vct>] [--all]
+       holder wallet show --id <id>
+       holder wallet remove --id <id>
+       holder wallet prune
 
-    // disclosures の中から、公開したいものを Base64url decode して中身を見て選別する
-    let mut disclosures = Vec::new();
-
-    for encoded_str in sd_jwt.disclosures {
-        let mut buffer = Vec::<u8>::new();
-        let check_strings = ["did"];
-
-        // Base64urlデコードを試みる
-        if URL_SAFE_NO_PAD
-            .decode_vec(encoded_str.as_bytes(), &mut buffer)
-            .is_ok()
-        {
-            // デコードされた文字列を取得
-            if let Ok(decoded_str) = String::from_utf8(buffer) {
-                // JSONとしてパース
-                if let Ok(json) = serde_json::from_str::<Value>(&decoded_str) {
-                    println!("checking decoded={json:?}, encoded={encoded_str}");
-                    // JSONが配列形式であり、2番目の要素が指定した文字列のいずれかに一致するかチェック
-                    if json.as_array().is_some_and(|arr| {
-                        arr.get(1).is_some_and(|second_element| {
-                            check_strings.contains(&second_element.as_str().unwrap_or(""))
-                        })
-                    }) {
-                        // 条件を満たす場合、元のBase64urlエンコードされた文字列を保存
-                        println!("MATCH! decoded={json:?}, encoded={encoded_str}");
-                        disclosures.push(encoded_str.to_string());
-                    }
-                }
+  add     クレデンシャルを保存する (--vc は複数指定できる、Holder の鍵は HOLDER_PRIVATE_KEY を記録する)
+  list    期限切れでないクレデンシャルの一覧 (--all で期限切れも含める)
+  show    クレデンシャルを表示する
+  remove  クレデンシャルを削除する
+  prune   期限切れのクレデンシャルを削除する";
+
+fn main() -> Result<()> {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let command = match args.first().map(String::as_str) {
+        Some("respond" | "mdoc" | "wallet") => Some(args.remove(0)),
+        _ => None,
+    };
+    let usage = match command.as_deref() {
+        Some("respond") => RESPOND_USAGE,
+        Some("mdoc") => MDOC_USAGE,
+        Some("wallet") => WALLET_USAGE,
+        _ => USAGE,
+    };
+    if take_switch(&mut args, "--help") {
+        println!("{usage}");
+        return Ok(());
+    }
+    // VC / KB-JWT の aud (--vc-audience, VC_AUDIENCE, vc_vp.conf など)
+    let audiences = AudienceConfig::load(&mut args)?;
+
+    // 鍵ファイル (署名アルゴリズムは鍵の種類から判定する)
+    let issuer_public_key = env::var("ISSUER_PUBLIC_KEY")
+        .unwrap_or_else(|_e| "issuer_public_key_ES256.pem".to_string());
+    let holder_private_key = env::var("HOLDER_PRIVATE_KEY")
+        .unwrap_or_else(|_e| "holder_private_key_ES256_pkcs8.pem".to_string());
+
+    // `holder wallet` で WALLET_DIR (既定は wallet) のクレデンシャルを管理する
+    if command.as_deref() == Some("wallet") {
+        let store = CredentialStore::from_env()?;
+        return wallet_command(&store, args, &holder_private_key);
+    }
+
+    // `holder respond --request <Authorization Request>` で OID4VP の要求に応答する
+    // 要求は `openid4vp://?...` の URL、`request_uri`、Request Object の JWT のいずれか
+    if command.as_deref() == Some("respond") {
+        let request = required(take_flag(&mut args, "--request")?, "--request", usage)?;
+        no_more_args(&args, usage)?;
+        let request = wallet::resolve_request(&request)?;
+        println!("request={request:?}");
+
+        // 保存しているクレデンシャル (環境変数 CREDENTIALS にカンマ区切り、既定は vc.jwt)
+        // CREDENTIALS を設定せずに WALLET_DIR を設定した場合はストアの期限切れでないクレデンシャル
+        let holder = Holder::from_key(&holder_private_key)?;
+        let wallet = match (env::var("CREDENTIALS"), env::var("WALLET_DIR")) {
+            (Err(_), Ok(_)) => Wallet::from_store(holder, &CredentialStore::from_env()?)?,
+            (credentials, _) => {
+                let credentials: Vec<String> = credentials
+                    .unwrap_or_else(|_e| "vc.jwt".to_string())
+                    .split(',')
+                    .map(String::from)
+                    .collect();
+                Wallet::from_files(holder, &credentials)?
             }
+        };
+        let response = wallet.submit(&request)?;
+        println!("response={response}");
+        return Ok(());
+    }
+
+    // `--elements` は `mdoc` だけのフラグ
+    let elements = match command.as_deref() {
+        Some("mdoc") => take_flag(&mut args, "--elements")?,
+        _ => None,
+    };
+    no_more_args(&args, usage)?;
+
+    // Verifier から受け取った nonce (環境変数 NONCE、なければ verifier が書き出した nonce.txt)
+    let nonce = match env::var("NONCE") {
+        Ok(v) => v,
+        Err(_) => std::fs::read_to_string("nonce.txt")
+            .map_err(|e| anyhow!("failed to read nonce.txt (run `verifier nonce` first): {e:?}"))?
+            .trim()
+            .to_string(),
+    };
+
+    // `holder mdoc [--elements <要素名>]` で mdoc.cbor から要素を選んで DeviceResponse を作成する
+    if command.as_deref() == Some("mdoc") {
+        let elements = elements.unwrap_or_else(|| "did".to_string());
+        let elements: Vec<&str> = elements.split(',').filter(|e| !e.is_empty()).collect();
+        let issuer_signed = std::fs::read("mdoc.cbor")?;
+        let holder = Holder::from_key(&holder_private_key)?;
+        // redirect_uri スキームなので client_id と response_uri はどちらも KB-JWT の aud
+        let response = holder.present_mdoc(
+            &issuer_signed,
+            &elements,
+            &nonce,
+            &audiences.kb_audience,
+            &audiences.kb_audience,
+        )?;
+        println!("device_response={} bytes", response.len());
+        std::fs::write("device_response.cbor", response)?;
+        return Ok(());
+    }
+
+    // 環境変数 CREDENTIAL_ID を設定した場合はストアのクレデンシャルを提示する
+    // (保存時に記録した Holder の鍵があればその鍵を使う)
+    let (vc, holder_private_key) = match env::var("CREDENTIAL_ID") {
+        Ok(id) => {
+            let stored = CredentialStore::from_env()?.get(&id)?;
+            let key = stored.holder_key.unwrap_or(holder_private_key);
+            (stored.credential, key)
         }
+        Err(_) => (std::fs::read_to_string("vc.jwt")?, holder_private_key),
+    };
+    let vc = vc.trim();
+
+    // W3C VCDM 2.0 の VC-JWT の場合は VP-JWT で提示する
+    if jwt::decode_header(vc)
+        .ok()
+        .and_then(|header| header.claim("typ").cloned())
+        .is_some_and(|typ| typ == vcdm::VC_JWT_TYP)
+    {
+        let verifier = Verifier::from_pem_file(
+            &issuer_public_key,
+            &audiences.vc_audience,
+            &audiences.kb_audience,
+        )?;
+        let (_, claims) = verifier.verify_vc_jwt(vc)?;
+        println!("vc-jwt's payload={claims:?}");
+        // credentialSubject の id (Holder の DID) を VP の holder にする
+        let subject_id = claims
+            .get("credentialSubject")
+            .and_then(|subject| subject.get("id"))
+            .and_then(|id| id.as_str());
+        let holder = Holder::from_key(&holder_private_key)?;
+        let vp = holder.present_vc_jwt(&[vc], subject_id, &nonce, &audiences.kb_audience)?;
+        println!("VP={vp:?}");
+        std::fs::write("vp.jwt", vp)?;
+        return Ok(());
     }
-    let nonce = "nonce".to_string();
-    let audience = "el-server".to_string();
-    let hasher = Sha256Hasher::new();
-    let key_binding_jwt = KeyBindingJwtClaims::new(
-        &hasher,
-        sd_jwt.jwt.clone(),
-        disclosures.clone(),
-        nonce,
-        audience,
-        0,
-    );
-
-    #[cfg(feature = "EdDSA")]
-    let mut header = Header::new(Algorithm::EdDSA);
-    #[cfg(feature = "ES256")]
-    let mut header = Header::new(Algorithm::ES256);
-    header.typ = Some("kb+jwt".to_string());
-
-    let binding = json!({});
-    let mut payload = binding.as_object().unwrap().clone();
-
-    payload.insert("nonce".to_string(), Value::String(key_binding_jwt.nonce));
-    payload.insert(
-        "sd_hash".to_string(),
-        Value::String(key_binding_jwt.sd_hash),
-    );
-    payload.insert(
-        String::from("aud"),
-        Value::String(key_binding_jwt.aud.clone()),
-    );
-
-    let now = std::time::SystemTime::now();
-    let val = Number::from(
-        now.duration_since(std::time::SystemTime::UNIX_EPOCH)
-            .unwrap()
-            .as_secs(),
-    );
-    payload.insert("iat".to_string(), Value::Number(val));
-
-    let expires_at = now + std::time::Duration::from_secs(60);
-    let val = Number::from(
-        expires_at
-            .duration_since(std::time::SystemTime::UNIX_EPOCH)
-            .unwrap()
-            .as_secs(),
-    );
-    payload.insert("exp".to_string(), Value::Number(val));
-
-    let holder_private_key = std::fs::read(HOLDER_PRIVATE_KEY).unwrap();
-    #[cfg(feature = "EdDSA")]
-    let encoding_key = EncodingKey::from_ed_pem(&holder_private_key)?;
-    #[cfg(feature = "ES256")]
-    let encoding_key = EncodingKey::from_ec_pem(&holder_private_key)?;
-    println!("loaded signer's private key");
-    let key_binding_jwt = jsonwebtoken::encode(&header, &payload, &encoding_key)?;
-    println!("kb-jwt: {key_binding_jwt:?}");
+
+    let sd_jwt: SdJwt = SdJwt::parse(vc)?;
+    println!("sd_jwt: {sd_jwt:?}");
+
+    // 受け取った VC の発行者署名を確認
+    let verifier = Verifier::from_pem_file(
+        &issuer_public_key,
+        &audiences.vc_audience,
+        &audiences.kb_audience,
+    )?;
+    let (header, claims) = verifier.verify_credential(&sd_jwt.jwt)?;
+    println!("sd-jwt's header={header:?}");
+    println!("sd-jwt's payload={claims:?}");
     println!();
 
-    let sd_jwt: SdJwt = SdJwt::new(sd_jwt.jwt, disclosures.clone(), Some(key_binding_jwt));
-    let sd_jwt: String = sd_jwt.presentation();
+    // disclosures の中から、公開したいものを選別して KB-JWT を付与する
+    // 環境変数 DISCLOSE (クレーム名または `/route_networks/0` のような JSON Pointer のカンマ区切り)
+    let disclose = env::var("DISCLOSE").unwrap_or_else(|_e| "did".to_string());
+    let selectors: Vec<&str> = disclose.split(',').filter(|s| !s.is_empty()).collect();
+    let holder = Holder::from_key(&holder_private_key)?;
+    let vp = holder.present(vc, &selectors, &nonce, &audiences.kb_audience)?;
 
-    println!("VP={sd_jwt:?}");
-    std::fs::write("vp.jwt", sd_jwt)?;
+    println!("VP={vp:?}");
+    std::fs::write("vp.jwt", vp)?;
 
     Ok(())
 }
 
-/// PEMファイルから読み取り
-fn read_pem_file(file_path: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-    let mut file = File::open(file_path)?;
-    let mut contents = vec![];
-    file.read_to_end(&mut contents)?;
-    // let pem = parse(contents)?;
-    // Ok(pem.contents().to_vec())
-    Ok(contents)
+/// `holder wallet` のサブコマンド (`WALLET_USAGE`)
+fn wallet_command(store: &CredentialStore, mut args: Vec<String>, holder_key: &str) -> Result<()> {
+    if args.is_empty() {
+        bail!("{WALLET_USAGE}");
+    }
+    match args.remove(0).as_str() {
+        "add" => {
+            let mut files = Vec::new();
+            while let Some(file) = take_flag(&mut args, "--vc")? {
+                files.push(file);
+            }
+            no_more_args(&args, WALLET_USAGE)?;
+            if files.is_empty() {
+                bail!("--vc is required\n\n{WALLET_USAGE}");
+            }
+            for file in files {
+                let credential = std::fs::read_to_string(&file)
+                    .map_err(|e| anyhow!("failed to read credential file {file}: {e:?}"))?;
+                let stored = store.add(&credential, Some(holder_key))?;
+                println!("added {}", summary(&stored));
+            }
+        }
+        "list" => {
+            let filter = CredentialFilter {
+                vct: take_flag(&mut args, "--vct")?,
+                include_expired: take_switch(&mut args, "--all"),
+                ..Default::default()
+            };
+            no_more_args(&args, WALLET_USAGE)?;
+            for stored in store.list(&filter)? {
+                println!("{}", summary(&stored));
+            }
+        }
+        "show" => {
+            let id = required(take_flag(&mut args, "--id")?, "--id", WALLET_USAGE)?;
+            no_more_args(&args, WALLET_USAGE)?;
+            let stored = store.get(&id)?;
+            println!("{}", serde_json::to_string_pretty(&stored)?);
+        }
+        "remove" => {
+            let id = required(take_flag(&mut args, "--id")?, "--id", WALLET_USAGE)?;
+            no_more_args(&args, WALLET_USAGE)?;
+            let stored = store.remove(&id)?;
+            println!("removed {}", summary(&stored));
+        }
+        "prune" => {
+            no_more_args(&args, WALLET_USAGE)?;
+            for stored in store.remove_expired()? {
+                println!("removed {}", summary(&stored));
+            }
+        }
+        command => bail!("unknown wallet command {command}\n\n{WALLET_USAGE}"),
+    }
+    Ok(())
+}
+
+/// 一覧の 1 行 (`<id> <format> <vct> <iss> exp=<exp>`)
+fn summary(stored: &StoredCredential) -> String {
+    let or_dash = |value: &Option<String>| value.clone().unwrap_or_else(|| "-".to_string());
+    format!(
+        "{} {} {} {} exp={}",
+        stored.id,
+        stored.format,
+        or_dash(&stored.vct),
+        or_dash(&stored.issuer),
+        stored
+            .expires_at
+            .map(|exp| exp.to_string())
+            .unwrap_or_else(|| "-".to_string())
+    )
 }
@@ -0,0 +1,149 @@
+use anyhow::{anyhow, bail, Result};
+use serde_json::{Map, Value};
+use std::env;
+use vc_vp::{
+    config::{no_more_args, required, take_flag, take_switch, AudienceConfig},
+    credential_type::{CredentialTypeRegistry, DEFAULT_CREDENTIAL_TYPES_FILE},
+    jwk::jwk_thumbprint_sha256,
+    key::public_key_to_jwk,
+    signer,
+    status_list::StatusListRegistry,
+    type_metadata::integrity,
+    Issuer,
+};
+
+/// 環境変数の値、未設定の場合は既定値
+fn env_or(name: &str, default: &str) -> String {
+    env::var(name).unwrap_or_else(|_e| default.to_string())
+}
+
+const USAGE: &str = "\
+usage: vc_issuer <command> [options]
+
+commands:
+  list           定義されている VC の種類を表示する
+  type-metadata  `vct` の URL で公開する Type Metadata を書き出す
+  issue          VC の種類の定義から SD-JWT VC を発行する
+
+  VC の種類の定義は環境変数 CREDENTIAL_TYPES (既定は credential_types.json)
+  `vc_issuer <command> --help` で各コマンドのオプションを表示する";
+
+const TYPE_METADATA_USAGE: &str = "\
+usage: vc_issuer type-metadata --type <id|vct> [--out <file>]
+
+  --type  VC の種類の id または vct
+  --out   出力先 (既定は <id>.type-metadata.json)
+
+  発行する VC の `vct#integrity` はこの文書のバイト列のハッシュなので、書き出したファイルをそのまま公開する";
+
+const ISSUE_USAGE: &str = "\
+usage: vc_issuer issue --type <id|vct> --claims <json|file> [--out <vc.jwt>]
+
+  --type    VC の種類の id または vct
+  --claims  クレームの JSON オブジェクト、またはそのファイル
+  --out     出力先 (既定は vc.jwt)
+
+  Holder の公開鍵は環境変数 HOLDER_PUBLIC_KEY、種類の定義で省略した発行者の設定は ISSUER・ISSUER_KEY・KEY_ID";
+
+fn main() -> Result<()> {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    if args.is_empty() {
+        println!("{USAGE}");
+        return Ok(());
+    }
+    let command = args.remove(0);
+    let usage = match command.as_str() {
+        "list" => USAGE,
+        "type-metadata" => TYPE_METADATA_USAGE,
+        "issue" => ISSUE_USAGE,
+        "help" | "--help" | "-h" => {
+            println!("{USAGE}");
+            return Ok(());
+        }
+        command => bail!("unknown command {command}\n\n{USAGE}"),
+    };
+    if take_switch(&mut args, "--help") {
+        println!("{usage}");
+        return Ok(());
+    }
+    // VC / KB-JWT の aud (--vc-audience, VC_AUDIENCE, vc_vp.conf など)
+    let audiences = AudienceConfig::load(&mut args)?;
+
+    // VC の種類の定義 (環境変数 CREDENTIAL_TYPES、既定は credential_types.json)
+    let registry = CredentialTypeRegistry::from_file(env_or(
+        "CREDENTIAL_TYPES",
+        DEFAULT_CREDENTIAL_TYPES_FILE,
+    ))?;
+
+    if command == "list" {
+        no_more_args(&args, usage)?;
+        for t in registry.types() {
+            println!("{}\t{}", t.id, t.vct);
+        }
+        return Ok(());
+    }
+
+    let type_id = required(take_flag(&mut args, "--type")?, "--type", usage)?;
+    let credential_type = registry
+        .get(&type_id)
+        .ok_or_else(|| anyhow!("unknown credential type {type_id}"))?;
+
+    if command == "type-metadata" {
+        let output = take_flag(&mut args, "--out")?
+            .unwrap_or_else(|| format!("{}.type-metadata.json", credential_type.id));
+        no_more_args(&args, usage)?;
+        let document = credential_type.type_metadata_document()?;
+        std::fs::write(&output, &document)?;
+        println!("wrote {output}");
+        println!("vct#integrity={}", integrity(&document));
+        return Ok(());
+    }
+
+    let claims = required(take_flag(&mut args, "--claims")?, "--claims", usage)?;
+    let output = take_flag(&mut args, "--out")?.unwrap_or_else(|| "vc.jwt".to_string());
+    no_more_args(&args, usage)?;
+    let claims = if claims.trim_start().starts_with('{') {
+        claims
+    } else {
+        std::fs::read_to_string(&claims)
+            .map_err(|e| anyhow!("failed to read claims file {claims}: {e:?}"))?
+    };
+    let claims: Map<String, Value> =
+        serde_json::from_str(&claims).map_err(|e| anyhow!("invalid claims: {e}"))?;
+
+    // 種類の定義で省略された発行者の設定は環境変数から
+    let iss = match &credential_type.iss {
+        Some(iss) => iss.clone(),
+        None => env::var("ISSUER").map_err(|_| anyhow!("ISSUER must be set"))?,
+    };
+    let issuer_key = match &credential_type.key_file {
+        Some(file) => file.display().to_string(),
+        None => env::var("ISSUER_KEY").map_err(|_| anyhow!("ISSUER_KEY must be set"))?,
+    };
+    // kid も省略した場合は発行者の公開鍵の JWK Thumbprint
+    let signer = signer::open(&issuer_key)?;
+    let key_id = match (&credential_type.kid, env::var("KEY_ID")) {
+        (Some(kid), _) => kid.clone(),
+        (None, Ok(kid)) => kid,
+        (None, Err(_)) => jwk_thumbprint_sha256(&signer.public_jwk()?)?,
+    };
+    let issuer = Issuer::from_signer(iss, signer, key_id);
+
+    let holder_key = env_or("HOLDER_PUBLIC_KEY", "holder_public_key_ES256.pem");
+    let holder_jwk =
+        public_key_to_jwk(&holder_key).map_err(|e| anyhow!("failed to convert to jwk e={e:?}"))?;
+
+    // 先にクレームを検証し、不正なクレームでステータスリストのインデックスを消費しない
+    credential_type.validate(&claims)?;
+    // STATUS_LIST_URI を設定した場合は `subject_claim` の値でインデックスを割り当てる
+    let status = match StatusListRegistry::from_env()? {
+        Some(registry) => Some(registry.allocate(&credential_type.subject(&claims)?)?),
+        None => None,
+    };
+    let params = credential_type.params(claims, holder_jwk, audiences.vc_audience, status)?;
+    let vc = issuer.generate_sd_jwt_vc(params)?;
+    println!("VC={vc}");
+    std::fs::write(&output, vc)?;
+
+    Ok(())
+}
@@ -0,0 +1,677 @@
+use anyhow::{anyhow, bail, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use josekit::jwt;
+use serde_json::{json, Map, Value};
+use std::{
+    env,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+use vc_vp::{
+    config::{no_more_args, positional, required, take_flag, take_switch, AudienceConfig},
+    credential_type::{CredentialTypeRegistry, DEFAULT_CREDENTIAL_TYPES_FILE},
+    jwk::{is_jwk, jwk_thumbprint_sha256, jwk_thumbprint_uri, parse_jwk, private_jwk_to_pem},
+    key::{
+        generate_key_pair, private_key_to_pkcs8_pem, public_key_pem_to_jwk, public_key_to_jwk,
+        write_private_key,
+    },
+    keyset::IssuerKeySet,
+    signer::{self, decrypt_private_key_pem, encrypt_private_key_pem, is_encrypted_pem},
+    status_list::{StatusListRegistry, DEFAULT_REGISTRY_FILE},
+    type_metadata::{TypeMetadataResolver, DEFAULT_TYPE_METADATA_MAX_AGE},
+    vcdm, Holder, SigningAlgorithm, Verifier,
+};
+
+const USAGE: &str = "\
+usage: vcctl <command> [options]
+
+commands:
+  keygen   鍵ペアを生成する
+  jwk      鍵ファイルの公開鍵を JWK・JWKS にする
+  pkcs8    秘密鍵を PKCS#8 にする (SEC1 の変換、パスワードでの暗号化・復号)
+  keys     発行者のキーセットの鍵を管理する (追加・ローテーション・削除)
+  issue    VC の種類の定義から SD-JWT VC を発行する
+  present  VC から VP を作成する
+  verify   VP を検証する
+  inspect  VC・VP を署名を検証せずに表示する
+
+`vcctl <command> --help` で各コマンドのオプションを表示する";
+
+const KEYGEN_USAGE: &str = "\
+usage: vcctl keygen --out <private.pem> [--public-out <public.pem>] [--jwk-out <jwk.json>]
+                    [--jwks-out <jwks.json>] [--alg <alg>] [--encrypt] [--force]
+
+  --out         秘密鍵 (PKCS#8 PEM) の出力先
+  --public-out  公開鍵 (SubjectPublicKeyInfo PEM) の出力先 (省略時は標準出力)
+  --jwk-out     公開鍵の JWK の出力先 (`kid` は RFC 7638 の JWK Thumbprint)
+  --jwks-out    公開鍵の JWKS の出力先
+  --alg         ES256 (既定)・ES384・ES512・EdDSA・RS256・PS256
+  --encrypt     秘密鍵を環境変数 PRIVATE_KEY_PASSWORD のパスワードで暗号化する
+  --force       既存のファイルを上書きする";
+
+const JWK_USAGE: &str = "\
+usage: vcctl jwk <key.pem>... [--kid <kid> | --thumbprint-uri] [--jwks] [--out <file>]
+
+  <key.pem>         公開鍵または秘密鍵の PEM・JWK、PKCS#11 URI (秘密鍵の場合は公開鍵部分だけを出力する)
+  --kid             `kid` (鍵が 1 つの場合のみ、省略時は RFC 7638 の JWK Thumbprint)
+  --thumbprint-uri  `kid` を RFC 9278 の JWK Thumbprint URI (urn:ietf:params:oauth:jwk-thumbprint:sha-256:...) にする
+  --jwks            `{\"keys\": [...]}` の JWKS にする (鍵が複数の場合は常に JWKS)
+  --out             出力先 (省略時は標準出力)";
+
+const PKCS8_USAGE: &str = "\
+usage: vcctl pkcs8 <private.pem> --out <pkcs8.pem> [--encrypt] [--force]
+
+  秘密鍵を PKCS#8 (PRIVATE KEY) にする
+  秘密鍵の JWK (`d` を含むもの) と SEC1 (EC PRIVATE KEY) の EC 鍵は変換し、`openssl ecparam -genkey` の EC PARAMETERS のブロックは読み飛ばす
+  暗号化した鍵 (ENCRYPTED PRIVATE KEY) は環境変数 PRIVATE_KEY_PASSWORD のパスワードで復号する
+
+  --encrypt  PRIVATE_KEY_PASSWORD のパスワードで暗号化した PKCS#8 (ENCRYPTED PRIVATE KEY) にする
+  --force    既存のファイルを上書きする";
+
+const KEYS_USAGE: &str = "\
+usage: vcctl keys [list] --key-set <keys.json>
+       vcctl keys add --key-set <keys.json> --key <private.pem> [--kid <kid>]
+       vcctl keys rotate --key-set <keys.json> --vc-expires-in <seconds> [--delay <seconds>] [--alg <alg>]
+       vcctl keys prune --key-set <keys.json>
+
+  list             鍵の一覧 (状態・kid・使い始める時刻・JWKS から外す時刻・ファイル)
+  add              既存の鍵を追加してすぐに署名に使う
+  rotate           鍵を issuer_keys/<kid>.pem に生成して切り替える (PRIVATE_KEY_PASSWORD があれば暗号化する)
+  prune            JWKS から外した鍵をキーセットから削除する (秘密鍵のファイルは削除しない)
+
+  --key-set        キーセットの JSON ファイル (省略時は環境変数 ISSUER_KEY_SET)
+  --key            秘密鍵 (PEM・JWK) または PKCS#11 URI
+  --kid            `kid` (省略時は公開鍵の JWK Thumbprint)
+  --vc-expires-in  VC の有効期間 (秒、省略時は環境変数 VC_EXPIRES_IN)。それまでの鍵は切り替えのこの秒数後に JWKS から外す
+  --delay          切り替えまでの秒数 (既定は 0、それまでも新しい鍵を JWKS で公開する)
+  --alg            生成する鍵 ES256 (既定)・ES384・ES512・EdDSA・RS256・PS256";
+
+const ISSUE_USAGE: &str = "\
+usage: vcctl issue --type <id|vct> --holder-key <public.pem> [--claims <json|file>]
+                   [--claim <name>=<string>]... [--claim-json <name>=<json>]... [options]
+
+  --type             VC の種類 (credential_types.json の id または vct)
+  --holder-key       `cnf` に入れる Holder の公開鍵 (PEM・JWK、秘密鍵でもよい)
+  --claims           クレームの JSON オブジェクト、またはそのファイル
+  --claim            文字列のクレーム (--claims のクレームを上書きする)
+  --claim-json       JSON の値のクレーム (配列など)
+  --types            VC の種類の定義 (既定は credential_types.json)
+  --iss              `iss` (省略時は種類の定義、環境変数 ISSUER)
+  --issuer-key       発行者の秘密鍵 (PEM・JWK) または PKCS#11 URI (省略時は種類の定義、環境変数 ISSUER_KEY)
+  --kid              JWS ヘッダの `kid` (省略時は種類の定義、環境変数 KEY_ID、発行者の鍵の JWK Thumbprint)
+  --expires-in       有効期間 (秒、省略時は種類の定義)
+  --status-list-uri  ステータスリストの URI (指定した場合は `status` を入れる)
+  --status-list-file インデックスの割り当てを保存するファイル (既定は status_list.json)
+  --vc-audience      VC の `aud` (省略時は環境変数 VC_AUDIENCE、vc_vp.conf)
+  --out              出力先 (既定は vc.jwt)";
+
+const PRESENT_USAGE: &str = "\
+usage: vcctl present --vc <vc.jwt> --holder-key <private.pem> (--nonce <nonce> | --nonce-file <file>)
+                     [--disclose <claims>] [--kb-audience <aud>] [--out <vp.jwt>]
+
+  --vc           提示する SD-JWT VC または VC-JWT
+  --holder-key   Holder の秘密鍵 (PEM・JWK) または PKCS#11 URI
+  --nonce        Verifier の nonce
+  --nonce-file   Verifier の nonce のファイル (`verifier nonce` の nonce.txt など)
+  --disclose     開示するクレーム名または JSON Pointer のカンマ区切り (省略時は開示しない)
+  --kb-audience  KB-JWT の `aud` (省略時は環境変数 KB_AUDIENCE、vc_vp.conf)
+  --out          出力先 (既定は vp.jwt)";
+
+const VERIFY_USAGE: &str = "\
+usage: vcctl verify --vp <vp.jwt> --issuer-key <public.pem> (--nonce <nonce> | --nonce-file <file>)
+                    [--type-metadata <vct>=<file>]... [--vc-audience <aud>] [--kb-audience <aud>]
+
+  --vp             SD-JWT VC の VP、または VC-JWT を含む VP-JWT
+  --issuer-key     発行者の公開鍵 (PEM・JWK)
+  --nonce          発行した nonce
+  --nonce-file     発行した nonce のファイル
+  --type-metadata  `vct` の Type Metadata のファイル (指定した場合は `vct#integrity` とスキーマを検証する)
+  --vc-audience    VC の `aud`
+  --kb-audience    KB-JWT の `aud`";
+
+const INSPECT_USAGE: &str = "\
+usage: vcctl inspect <file|->
+
+  SD-JWT (VC・VP) または JWT のヘッダ・ペイロード・disclosure・KB-JWT を署名を検証せずに表示する
+  `-` の場合は標準入力から読み込む";
+
+fn main() -> Result<()> {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    if args.is_empty() {
+        println!("{USAGE}");
+        return Ok(());
+    }
+    let command = args.remove(0);
+    match command.as_str() {
+        "keygen" => keygen(args),
+        "jwk" => jwk(args),
+        "pkcs8" => pkcs8(args),
+        "keys" => keys(args),
+        "issue" => issue(args),
+        "present" => present(args),
+        "verify" => verify(args),
+        "inspect" => inspect(args),
+        "help" | "--help" | "-h" => {
+            println!("{USAGE}");
+            Ok(())
+        }
+        command => bail!("unknown command {command}\n\n{USAGE}"),
+    }
+}
+
+/// `vcctl keygen`
+fn keygen(mut args: Vec<String>) -> Result<()> {
+    if take_switch(&mut args, "--help") {
+        println!("{KEYGEN_USAGE}");
+        return Ok(());
+    }
+    let out = required(take_flag(&mut args, "--out")?, "--out", KEYGEN_USAGE)?;
+    let public_out = take_flag(&mut args, "--public-out")?;
+    let jwk_out = take_flag(&mut args, "--jwk-out")?;
+    let jwks_out = take_flag(&mut args, "--jwks-out")?;
+    let alg: SigningAlgorithm = take_flag(&mut args, "--alg")?
+        .as_deref()
+        .unwrap_or("ES256")
+        .parse()?;
+    let encrypt = take_switch(&mut args, "--encrypt");
+    let force = take_switch(&mut args, "--force");
+    no_more_args(&args, KEYGEN_USAGE)?;
+
+    let outputs = [
+        Some(&out),
+        public_out.as_ref(),
+        jwk_out.as_ref(),
+        jwks_out.as_ref(),
+    ];
+    for path in outputs.into_iter().flatten() {
+        if !force && std::path::Path::new(path).exists() {
+            bail!("{path} already exists (use --force to overwrite)");
+        }
+    }
+    let (private_key, public_key) = generate_key_pair(alg)?;
+    let jwk = public_key_pem_to_jwk(&public_key)?;
+    let private_key = if encrypt {
+        encrypt_private_key_pem(&private_key, password()?.as_bytes())?
+    } else {
+        private_key
+    };
+    write_private_key(&out, &private_key, force)?;
+    eprintln!("wrote {out} ({}, kid={})", alg.name(), jwk["kid"]);
+    match public_out {
+        Some(public_out) => {
+            std::fs::write(&public_out, &public_key)?;
+            eprintln!("wrote {public_out}");
+        }
+        None => print!("{}", String::from_utf8_lossy(&public_key)),
+    }
+    if let Some(jwk_out) = jwk_out {
+        write_output(Some(&jwk_out), &serde_json::to_string_pretty(&jwk)?)?;
+    }
+    if let Some(jwks_out) = jwks_out {
+        let jwks = json!({ "keys": [jwk] });
+        write_output(Some(&jwks_out), &serde_json::to_string_pretty(&jwks)?)?;
+    }
+    Ok(())
+}
+
+/// `vcctl jwk`
+fn jwk(mut args: Vec<String>) -> Result<()> {
+    if take_switch(&mut args, "--help") {
+        println!("{JWK_USAGE}");
+        return Ok(());
+    }
+    let kid = take_flag(&mut args, "--kid")?;
+    let thumbprint_uri = take_switch(&mut args, "--thumbprint-uri");
+    let jwks = take_switch(&mut args, "--jwks");
+    let out = take_flag(&mut args, "--out")?;
+    if let Some(flag) = args.iter().find(|arg| arg.starts_with("--")) {
+        bail!("unknown option {flag}\n\n{JWK_USAGE}");
+    }
+    if args.is_empty() {
+        bail!("no key file\n\n{JWK_USAGE}");
+    }
+    if kid.is_some() && args.len() > 1 {
+        bail!("--kid cannot be used with multiple keys");
+    }
+    if kid.is_some() && thumbprint_uri {
+        bail!("--kid cannot be used with --thumbprint-uri");
+    }
+
+    let mut keys = Vec::new();
+    for key_file in &args {
+        let mut jwk = public_key_to_jwk(key_file)
+            .map_err(|e| anyhow!("failed to convert {key_file}: {e}"))?;
+        let kid = match &kid {
+            Some(kid) => kid.clone(),
+            None if thumbprint_uri => jwk_thumbprint_uri(&jwk)?,
+            None => jwk_thumbprint_sha256(&jwk)?,
+        };
+        if let Some(obj) = jwk.as_object_mut() {
+            obj.insert("kid".to_string(), Value::String(kid));
+        }
+        keys.push(jwk);
+    }
+    let output = if jwks || keys.len() > 1 {
+        json!({ "keys": keys })
+    } else {
+        keys.remove(0)
+    };
+    write_output(out.as_deref(), &serde_json::to_string_pretty(&output)?)
+}
+
+/// `vcctl pkcs8`
+fn pkcs8(mut args: Vec<String>) -> Result<()> {
+    if take_switch(&mut args, "--help") {
+        println!("{PKCS8_USAGE}");
+        return Ok(());
+    }
+    let out = required(take_flag(&mut args, "--out")?, "--out", PKCS8_USAGE)?;
+    let encrypt = take_switch(&mut args, "--encrypt");
+    let force = take_switch(&mut args, "--force");
+    let [key_file] = positional::<1>(args, PKCS8_USAGE)?;
+    if !force && std::path::Path::new(&out).exists() {
+        bail!("{out} already exists (use --force to overwrite)");
+    }
+    let private_key =
+        std::fs::read(&key_file).map_err(|e| anyhow!("failed to read {key_file}: {e:?}"))?;
+    let private_key = if is_jwk(&private_key) {
+        private_jwk_to_pem(&parse_jwk(&private_key)?)?
+    } else if is_encrypted_pem(&private_key) {
+        decrypt_private_key_pem(&private_key, password()?.as_bytes())?
+    } else {
+        private_key_to_pkcs8_pem(&private_key)?
+    };
+    let private_key = if encrypt {
+        encrypt_private_key_pem(&private_key, password()?.as_bytes())?
+    } else {
+        private_key
+    };
+    write_private_key(&out, &private_key, force)?;
+    eprintln!("wrote {out}");
+    Ok(())
+}
+
+/// `vcctl keys`
+fn keys(mut args: Vec<String>) -> Result<()> {
+    if take_switch(&mut args, "--help") {
+        println!("{KEYS_USAGE}");
+        return Ok(());
+    }
+    let command = match args.first() {
+        Some(arg) if !arg.starts_with("--") => args.remove(0),
+        _ => "list".to_string(),
+    };
+    let key_set = take_flag(&mut args, "--key-set")?
+        .or_else(|| env::var("ISSUER_KEY_SET").ok())
+        .ok_or_else(|| anyhow!("--key-set or ISSUER_KEY_SET must be set\n\n{KEYS_USAGE}"))?;
+    let now = SystemTime::now();
+    match command.as_str() {
+        "list" => {
+            no_more_args(&args, KEYS_USAGE)?;
+            for (entry, state) in IssuerKeySet::open(key_set)?.keys(now)? {
+                let not_after = entry
+                    .not_after
+                    .map_or_else(|| "-".to_string(), |t| t.to_string());
+                println!(
+                    "{state}\t{}\t{}\t{not_after}\t{}",
+                    entry.kid,
+                    entry.not_before,
+                    entry.file.display()
+                );
+            }
+        }
+        "add" => {
+            let key = required(take_flag(&mut args, "--key")?, "--key", KEYS_USAGE)?;
+            let kid = take_flag(&mut args, "--kid")?;
+            no_more_args(&args, KEYS_USAGE)?;
+            let entry = IssuerKeySet::open(key_set)?.add(key, kid, now)?;
+            eprintln!("added {} ({})", entry.kid, entry.file.display());
+        }
+        "rotate" => {
+            let vc_expires_in: u64 = take_flag(&mut args, "--vc-expires-in")?
+                .or_else(|| env::var("VC_EXPIRES_IN").ok())
+                .ok_or_else(|| {
+                    anyhow!("--vc-expires-in or VC_EXPIRES_IN must be set\n\n{KEYS_USAGE}")
+                })?
+                .parse()
+                .map_err(|e| anyhow!("--vc-expires-in: {e}"))?;
+            let delay: u64 = take_flag(&mut args, "--delay")?
+                .map(|v| v.parse())
+                .transpose()
+                .map_err(|e| anyhow!("--delay: {e}"))?
+                .unwrap_or(0);
+            let alg: SigningAlgorithm = take_flag(&mut args, "--alg")?
+                .as_deref()
+                .unwrap_or("ES256")
+                .parse()?;
+            no_more_args(&args, KEYS_USAGE)?;
+            let entry = IssuerKeySet::open(key_set)?.rotate(
+                alg,
+                now + Duration::from_secs(delay),
+                Duration::from_secs(vc_expires_in),
+            )?;
+            eprintln!(
+                "generated {} ({}), active from {}",
+                entry.kid,
+                entry.file.display(),
+                entry.not_before
+            );
+        }
+        "prune" => {
+            no_more_args(&args, KEYS_USAGE)?;
+            for entry in IssuerKeySet::open(key_set)?.remove_expired()? {
+                eprintln!("removed {} ({})", entry.kid, entry.file.display());
+            }
+        }
+        command => bail!("unknown keys command {command}\n\n{KEYS_USAGE}"),
+    }
+    Ok(())
+}
+
+/// 秘密鍵を暗号化・復号するパスワード (環境変数 PRIVATE_KEY_PASSWORD または PRIVATE_KEY_PASSWORD_FILE)
+fn password() -> Result<String> {
+    signer::password_from_env()?
+        .filter(|password| !password.is_empty())
+        .ok_or_else(|| anyhow!("PRIVATE_KEY_PASSWORD or PRIVATE_KEY_PASSWORD_FILE must be set"))
+}
+
+/// `vcctl issue`
+fn issue(mut args: Vec<String>) -> Result<()> {
+    if take_switch(&mut args, "--help") {
+        println!("{ISSUE_USAGE}");
+        return Ok(());
+    }
+    let audiences = AudienceConfig::load(&mut args)?;
+    let type_id = required(take_flag(&mut args, "--type")?, "--type", ISSUE_USAGE)?;
+    let holder_key = required(
+        take_flag(&mut args, "--holder-key")?,
+        "--holder-key",
+        ISSUE_USAGE,
+    )?;
+    let mut claims = match take_flag(&mut args, "--claims")? {
+        Some(claims) => read_claims(&claims)?,
+        None => Map::new(),
+    };
+    while let Some(claim) = take_flag(&mut args, "--claim")? {
+        let (name, value) = split_assignment(&claim, "--claim")?;
+        claims.insert(name.to_string(), Value::String(value.to_string()));
+    }
+    while let Some(claim) = take_flag(&mut args, "--claim-json")? {
+        let (name, value) = split_assignment(&claim, "--claim-json")?;
+        let value = serde_json::from_str(value)
+            .map_err(|e| anyhow!("--claim-json {name}: invalid JSON: {e}"))?;
+        claims.insert(name.to_string(), value);
+    }
+    let types_file = take_flag(&mut args, "--types")?
+        .unwrap_or_else(|| DEFAULT_CREDENTIAL_TYPES_FILE.to_string());
+    let iss = take_flag(&mut args, "--iss")?;
+    let issuer_key = take_flag(&mut args, "--issuer-key")?;
+    let kid = take_flag(&mut args, "--kid")?;
+    let expires_in: Option<u64> = take_flag(&mut args, "--expires-in")?
+        .map(|v| v.parse())
+        .transpose()
+        .map_err(|e| anyhow!("--expires-in: {e}"))?;
+    let status_list_uri = take_flag(&mut args, "--status-list-uri")?;
+    let status_list_file = take_flag(&mut args, "--status-list-file")?
+        .unwrap_or_else(|| DEFAULT_REGISTRY_FILE.to_string());
+    let out = take_flag(&mut args, "--out")?.unwrap_or_else(|| "vc.jwt".to_string());
+    no_more_args(&args, ISSUE_USAGE)?;
+
+    let registry = CredentialTypeRegistry::from_file(&types_file)?;
+    let credential_type = registry
+        .get(&type_id)
+        .ok_or_else(|| anyhow!("unknown credential type {type_id} in {types_file}"))?;
+
+    // フラグ > 種類の定義 > 環境変数 の順
+    let or_env = |flag: Option<String>, defined: Option<String>, name: &str, env_name: &str| {
+        flag.or(defined)
+            .or_else(|| env::var(env_name).ok())
+            .ok_or_else(|| anyhow!("{name} or {env_name} must be set"))
+    };
+    let iss = or_env(iss, credential_type.iss.clone(), "--iss", "ISSUER")?;
+    let issuer_key = or_env(
+        issuer_key,
+        credential_type
+            .key_file
+            .as_ref()
+            .map(|file| file.display().to_string()),
+        "--issuer-key",
+        "ISSUER_KEY",
+    )?;
+    // kid はどれも指定しない場合は発行者の公開鍵の JWK Thumbprint
+    let signer = signer::open(&issuer_key)?;
+    let kid = match or_env(kid, credential_type.kid.clone(), "--kid", "KEY_ID") {
+        Ok(kid) => kid,
+        Err(_) => jwk_thumbprint_sha256(&signer.public_jwk()?)?,
+    };
+    let issuer = vc_vp::Issuer::from_signer(iss, signer, kid);
+
+    let holder_jwk = public_key_to_jwk(&holder_key)
+        .map_err(|e| anyhow!("failed to convert {holder_key} to jwk: {e}"))?;
+
+    // 先にクレームを検証し、不正なクレームでステータスリストのインデックスを消費しない
+    credential_type.validate(&claims)?;
+    let status = match status_list_uri {
+        Some(uri) => Some(
+            StatusListRegistry::open(&status_list_file, &uri)?
+                .allocate(&credential_type.subject(&claims)?)?,
+        ),
+        None => None,
+    };
+    let mut params = credential_type.params(claims, holder_jwk, audiences.vc_audience, status)?;
+    if let Some(expires_in) = expires_in {
+        params.vc_expires_in = expires_in;
+    }
+    let vc = issuer.generate_sd_jwt_vc(params)?;
+    std::fs::write(&out, &vc)?;
+    eprintln!("wrote {out}");
+    Ok(())
+}
+
+/// `vcctl present`
+fn present(mut args: Vec<String>) -> Result<()> {
+    if take_switch(&mut args, "--help") {
+        println!("{PRESENT_USAGE}");
+        return Ok(());
+    }
+    let audiences = AudienceConfig::load(&mut args)?;
+    let vc_file = required(take_flag(&mut args, "--vc")?, "--vc", PRESENT_USAGE)?;
+    let holder_key = required(
+        take_flag(&mut args, "--holder-key")?,
+        "--holder-key",
+        PRESENT_USAGE,
+    )?;
+    let nonce = take_nonce(&mut args, PRESENT_USAGE)?;
+    let disclose = take_flag(&mut args, "--disclose")?.unwrap_or_default();
+    let out = take_flag(&mut args, "--out")?.unwrap_or_else(|| "vp.jwt".to_string());
+    no_more_args(&args, PRESENT_USAGE)?;
+
+    let vc = std::fs::read_to_string(&vc_file)
+        .map_err(|e| anyhow!("failed to read {vc_file}: {e:?}"))?;
+    let vc = vc.trim();
+    let holder = Holder::from_key(&holder_key)?;
+
+    let vp = if is_vc_jwt(vc) {
+        // credentialSubject の id (Holder の DID) を VP の holder にする
+        let claims = jwt_payload(vc)?;
+        let subject_id = claims
+            .get("credentialSubject")
+            .and_then(|subject| subject.get("id"))
+            .and_then(Value::as_str);
+        holder.present_vc_jwt(&[vc], subject_id, &nonce, &audiences.kb_audience)?
+    } else {
+        let selectors: Vec<&str> = disclose.split(',').filter(|s| !s.is_empty()).collect();
+        holder.present(vc, &selectors, &nonce, &audiences.kb_audience)?
+    };
+    std::fs::write(&out, vp)?;
+    eprintln!("wrote {out}");
+    Ok(())
+}
+
+/// `vcctl verify`
+fn verify(mut args: Vec<String>) -> Result<()> {
+    if take_switch(&mut args, "--help") {
+        println!("{VERIFY_USAGE}");
+        return Ok(());
+    }
+    let audiences = AudienceConfig::load(&mut args)?;
+    let vp_file = required(take_flag(&mut args, "--vp")?, "--vp", VERIFY_USAGE)?;
+    let issuer_key = required(
+        take_flag(&mut args, "--issuer-key")?,
+        "--issuer-key",
+        VERIFY_USAGE,
+    )?;
+    let nonce = take_nonce(&mut args, VERIFY_USAGE)?;
+    let mut type_metadata: Option<TypeMetadataResolver> = None;
+    while let Some(entry) = take_flag(&mut args, "--type-metadata")? {
+        // vct は URL なので最後の `=` で分ける
+        let (vct, file) = entry
+            .rsplit_once('=')
+            .ok_or_else(|| anyhow!("--type-metadata: expected <vct>=<file>: {entry}"))?;
+        let resolver = type_metadata.take().unwrap_or_else(|| {
+            TypeMetadataResolver::new(DEFAULT_TYPE_METADATA_MAX_AGE).with_fetch(false)
+        });
+        type_metadata = Some(resolver.with_file(vct, file));
+    }
+    no_more_args(&args, VERIFY_USAGE)?;
+
+    let vp = std::fs::read_to_string(&vp_file)
+        .map_err(|e| anyhow!("failed to read {vp_file}: {e:?}"))?;
+    let mut verifier =
+        Verifier::from_pem_file(&issuer_key, &audiences.vc_audience, &audiences.kb_audience)?;
+    if let Some(type_metadata) = type_metadata {
+        verifier = verifier.with_type_metadata(Arc::new(type_metadata));
+    }
+    // W3C VCDM 2.0 の VP-JWT の場合は含まれる VC-JWT ごとに結果を表示する
+    let results = if vp.trim_end().contains('~') {
+        vec![verifier.verify_presentation(vp.trim(), &nonce)?]
+    } else {
+        verifier.verify_vp_jwt(vp.trim(), &nonce)?
+    };
+    let claims: Vec<Value> = results
+        .into_iter()
+        .map(|result| Value::Object(result.claims))
+        .collect();
+    println!("{}", serde_json::to_string_pretty(&claims)?);
+    Ok(())
+}
+
+/// `vcctl inspect`
+fn inspect(mut args: Vec<String>) -> Result<()> {
+    if take_switch(&mut args, "--help") {
+        println!("{INSPECT_USAGE}");
+        return Ok(());
+    }
+    let [input] = positional::<1>(args, INSPECT_USAGE)?;
+    let contents = if input == "-" {
+        std::io::read_to_string(std::io::stdin())?
+    } else {
+        std::fs::read_to_string(&input).map_err(|e| anyhow!("failed to read {input}: {e:?}"))?
+    };
+    let contents = contents.trim();
+
+    let mut output = Map::new();
+    let jwt = if contents.contains('~') {
+        let sd_jwt = vc_vp::sd_jwt::parse(contents)?;
+        let disclosures = sd_jwt
+            .disclosures
+            .iter()
+            .map(|disclosure| decode_json(disclosure))
+            .collect::<Result<Vec<_>>>()?;
+        output.insert("disclosures".to_string(), Value::Array(disclosures));
+        if let Some(kb_jwt) = &sd_jwt.key_binding_jwt {
+            output.insert("key_binding_jwt".to_string(), inspect_jwt(kb_jwt)?);
+        }
+        sd_jwt.jwt
+    } else {
+        contents.to_string()
+    };
+    let mut inspected = inspect_jwt(&jwt)?;
+    if let Some(obj) = inspected.as_object_mut() {
+        obj.extend(output);
+    }
+    println!("{}", serde_json::to_string_pretty(&inspected)?);
+    Ok(())
+}
+
+/// JWT のヘッダとペイロード
+fn inspect_jwt(jwt: &str) -> Result<Value> {
+    let mut parts = jwt.split('.');
+    let (Some(header), Some(payload), Some(_), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        bail!("not a JWT");
+    };
+    Ok(json!({ "header": decode_json(header)?, "payload": decode_json(payload)? }))
+}
+
+/// Base64url の JSON を復号する
+fn decode_json(encoded: &str) -> Result<Value> {
+    let bytes = URL_SAFE_NO_PAD
+        .decode(encoded)
+        .map_err(|e| anyhow!("invalid base64url: {e}"))?;
+    serde_json::from_slice(&bytes).map_err(|e| anyhow!("invalid JSON: {e}"))
+}
+
+/// JWT のペイロード (Holder 自身の VC の確認用で、署名は検証しない)
+fn jwt_payload(jwt: &str) -> Result<Map<String, Value>> {
+    match inspect_jwt(jwt)?.get("payload") {
+        Some(Value::Object(payload)) => Ok(payload.clone()),
+        _ => bail!("JWT payload is not an object"),
+    }
+}
+
+/// W3C VCDM 2.0 の VC-JWT か
+fn is_vc_jwt(vc: &str) -> bool {
+    jwt::decode_header(vc)
+        .ok()
+        .and_then(|header| header.claim("typ").cloned())
+        .is_some_and(|typ| typ == vcdm::VC_JWT_TYP)
+}
+
+/// `--nonce` または `--nonce-file` の nonce
+fn take_nonce(args: &mut Vec<String>, usage: &str) -> Result<String> {
+    match (
+        take_flag(args, "--nonce")?,
+        take_flag(args, "--nonce-file")?,
+    ) {
+        (Some(nonce), None) => Ok(nonce),
+        (None, Some(file)) => Ok(std::fs::read_to_string(&file)
+            .map_err(|e| anyhow!("failed to read nonce file {file}: {e:?}"))?
+            .trim()
+            .to_string()),
+        (Some(_), Some(_)) => bail!("--nonce and --nonce-file cannot be used together"),
+        (None, None) => bail!("--nonce or --nonce-file is required\n\n{usage}"),
+    }
+}
+
+/// クレームの JSON オブジェクト、またはそのファイル
+fn read_claims(claims: &str) -> Result<Map<String, Value>> {
+    let claims = if claims.trim_start().starts_with('{') {
+        claims.to_string()
+    } else {
+        std::fs::read_to_string(claims)
+            .map_err(|e| anyhow!("failed to read claims file {claims}: {e:?}"))?
+    };
+    serde_json::from_str(&claims).map_err(|e| anyhow!("invalid claims: {e}"))
+}
+
+/// `<name>=<value>`
+fn split_assignment<'a>(value: &'a str, flag: &str) -> Result<(&'a str, &'a str)> {
+    value
+        .split_once('=')
+        .filter(|(name, _)| !name.is_empty())
+        .ok_or_else(|| anyhow!("{flag}: expected <name>=<value>: {value}"))
+}
+
+/// 出力先のファイル、省略時は標準出力
+fn write_output(out: Option<&str>, contents: &str) -> Result<()> {
+    match out {
+        Some(out) => {
+            std::fs::write(out, contents)?;
+            eprintln!("wrote {out}");
+        }
+        None => println!("{contents}"),
+    }
+    Ok(())
+}
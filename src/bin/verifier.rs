@@ -1,135 +1,235 @@
 use anyhow::{anyhow, Result};
-use jsonwebtoken::{jwk::Jwk, Algorithm, DecodingKey, Validation};
-use sd_jwt_payload::{KeyBindingJwtClaims, SdJwt, SdObjectDecoder, Sha256Hasher};
-use serde_json::Value;
-use std::{fs::File, io::Read};
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+use vc_vp::{
+    config::{no_more_args, take_switch, AudienceConfig},
+    did::DidResolver,
+    issuer_metadata::{IssuerKeyResolver, DEFAULT_KEYS_MAX_AGE},
+    nonce::generate_nonce,
+    oid4vp::{VerifierServer, VerifierServerConfig, DEFAULT_MAX_PENDING_REQUESTS},
+    server,
+    status_list::{StatusListCache, DEFAULT_CACHE_MAX_AGE},
+    trust::TrustRegistry,
+    type_metadata::{TypeMetadataResolver, DEFAULT_TYPE_METADATA_MAX_AGE},
+    Verifier,
+};
+
+/// Holder に渡す nonce を保存するファイル
+const NONCE_FILE: &str = "nonce.txt";
+
+const USAGE: &str = "\
+usage: verifier [command] [--vc-audience <aud>] [--kb-audience <aud>] [--config <file>]
+
+commands:
+  (なし)  vp.jwt を nonce.txt の nonce で検証する (成功すると nonce.txt を削除する)
+  nonce   nonce を発行して nonce.txt に書き出す
+  mdoc    device_response.cbor の mdoc の DeviceResponse を検証する
+  serve   OID4VP の Verifier サーバとして起動する (設定は環境変数 VERIFIER_URL など)
+
+  発行者の公開鍵は環境変数 ISSUER_PUBLIC_KEY (TRUST_REGISTRY・JWT_VC_ISSUERS を設定した場合はそちら)";
 
 fn main() -> Result<()> {
-    // Holderの公開鍵をファイルから読み込み
-    #[cfg(feature = "EdDSA")]
-    const ISSUER_PUBLIC_KEY: &str = "issuer_public_key_ed25519.pem";
-    #[cfg(feature = "ES256")]
-    const ISSUER_PUBLIC_KEY: &str = "issuer_public_key_ES256.pem";
+    // サーバのアクセスログ・トラストレジストリの警告などの出力 (RUST_LOG で変更でき、既定は info)
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
 
-    // Holderから提出されたVP（署名付きJWTとして）
-    let vp = std::fs::read_to_string("vp.jwt").unwrap();
-
-    // Decoding the SD-JWT
-    // Extract the payload from the JWT of the SD-JWT after verifying the signature.
-    let public_key =
-        read_pem_file(ISSUER_PUBLIC_KEY).map_err(|e| anyhow!("failed to read pem e={e:?}"))?;
-    println!("public_key: {public_key:?}");
-    #[cfg(feature = "EdDSA")]
-    let issuer_decoding_key = DecodingKey::from_ed_pem(&public_key)?;
-    #[cfg(feature = "ES256")]
-    let issuer_decoding_key = DecodingKey::from_ec_pem(&public_key)?;
-    println!("issuer_decoding_key");
-
-    let sd_jwt: SdJwt = SdJwt::parse(&vp)?;
-
-    // Holder の公開鍵を SD-JWT の cnf から取り出す
-    #[cfg(feature = "EdDSA")]
-    let mut validation = Validation::new(Algorithm::EdDSA);
-    #[cfg(feature = "ES256")]
-    let mut validation = Validation::new(Algorithm::ES256);
-    validation.set_audience(&["fujita-app"]);
-    let vc_token = jsonwebtoken::decode::<Value>(&sd_jwt.jwt, &issuer_decoding_key, &validation)?;
-    println!("VC header={:?}", vc_token.header);
-    println!("VC payload={:?}", vc_token.claims);
-
-    // VC の header の typ が vc+sd-jwt であるかチェック
-    match vc_token.header.typ {
-        Some(v) => match v.as_str() {
-            "vc+sd-jwt" => {}
-            _ => panic!("vc header typ is not vc+sd-jwt!"),
-        },
-        _ => panic!("vc header typ is None!"),
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    if take_switch(&mut args, "--help") {
+        println!("{USAGE}");
+        return Ok(());
     }
+    // VC / KB-JWT の aud (--vc-audience, VC_AUDIENCE, vc_vp.conf など)
+    let audiences = AudienceConfig::load(&mut args)?;
+    let command = match args.first().map(String::as_str) {
+        Some("nonce" | "mdoc" | "serve") => Some(args.remove(0)),
+        _ => None,
+    };
+    no_more_args(&args, USAGE)?;
 
-    // VC の exp が期限切れになっていないかチェック
-    // decodeで期限切れチェックはすでに行っている
+    // `verifier nonce` で nonce を発行し、Holder に渡す
+    if command.as_deref() == Some("nonce") {
+        let nonce = generate_nonce();
+        std::fs::write(NONCE_FILE, &nonce)?;
+        println!("{nonce}");
+        return Ok(());
+    }
 
-    let holder_public_key_jwk = match vc_token.claims.get("cnf") {
-        Some(value) => {
-            let jwk = match value.get("jwk") {
-                Some(v) => v,
-                _ => panic!("there is no jwk"),
-            };
-            json_to_jwk(jwk)?
-        }
-        _ => panic!("there is no holder's public key!"),
-    };
-    let holder_decoding_key = DecodingKey::from_jwk(&holder_public_key_jwk)?;
-
-    let vp_token = match sd_jwt.key_binding_jwt {
-        Some(vp_jwt) => {
-            #[cfg(feature = "EdDSA")]
-            let mut validation = Validation::new(Algorithm::EdDSA);
-            #[cfg(feature = "ES256")]
-            let mut validation = Validation::new(Algorithm::ES256);
-            validation.set_audience(&["el-server"]);
-            jsonwebtoken::decode::<Value>(&vp_jwt, &holder_decoding_key, &validation)?
-        }
-        _ => panic!("key_binding_jwt is None"),
-    };
-    println!("kb-jwt's header={:?}", vp_token.header);
-    println!("kb-jwt's payload={:?}", vp_token.claims);
-    println!();
-
-    // VP の header の typ が kb+jwt であるかチェック
-    match vp_token.header.typ {
-        Some(v) => match v.as_str() {
-            "kb+jwt" => {}
-            _ => panic!("vc header typ is not vc+sd-jwt!"),
-        },
-        _ => panic!("vc header typ is None!"),
+    // 発行者の公開鍵 (署名アルゴリズムは VC の alg ヘッダから判定する)
+    let issuer_public_key = env::var("ISSUER_PUBLIC_KEY")
+        .unwrap_or_else(|_e| "issuer_public_key_ES256.pem".to_string());
+
+    // `verifier serve` で OID4VP の Verifier サーバとして動かす
+    // client_id と KB-JWT の aud は response_uri (redirect_uri スキーム)
+    // nonce はサーバの nonce ストアで発行し、検証に成功したら使用済みにする (VerifierServer::new)
+    if command.as_deref() == Some("serve") {
+        let env_or = |name: &str, default: &str| env::var(name).unwrap_or(default.to_string());
+        let config = VerifierServerConfig {
+            base_url: env_or("VERIFIER_URL", "http://localhost:8081"),
+            vct: env::var("VCT").ok(),
+            claims: env_or("REQUESTED_CLAIMS", "did")
+                .split(',')
+                .filter(|claim| !claim.is_empty())
+                .map(String::from)
+                .collect(),
+            query_language: env_or("QUERY_LANGUAGE", "presentation_definition").parse()?,
+            request_expires_in: Duration::from_secs(env_or("REQUEST_EXPIRES_IN", "300").parse()?),
+            max_pending_requests: match env::var("MAX_PENDING_REQUESTS") {
+                Ok(v) => v.parse()?,
+                Err(_) => DEFAULT_MAX_PENDING_REQUESTS,
+            },
+        };
+        let verifier = verifier(
+            &issuer_public_key,
+            &audiences.vc_audience,
+            &config.response_uri(),
+        )?;
+        let server = VerifierServer::new(config, verifier);
+        return server::serve(&env_or("BIND_ADDRESS", "[::]:8081"), |req| {
+            server.handle(req)
+        });
     }
 
-    // kb-jwt の sd_hash の値が一致するかチェック
-    let hasher = Sha256Hasher::new();
-    let key_binding_jwt = KeyBindingJwtClaims::new(
-        &hasher,
-        sd_jwt.jwt.clone(),
-        sd_jwt.disclosures.clone(),
-        "dummy".to_string(),
-        "dummy".to_string(),
-        0,
-    );
-    println!("sd_hash={}", key_binding_jwt.sd_hash);
-    if let Some(Value::String(sd_hash)) = vp_token.claims.get("sd_hash") {
-        if &key_binding_jwt.sd_hash != sd_hash {
-            panic!("sd_hash is not correct!");
+    // 発行済みの nonce
+    let nonce = std::fs::read_to_string(NONCE_FILE)
+        .map_err(|e| anyhow!("failed to read {NONCE_FILE} (run `verifier nonce` first): {e:?}"))?;
+
+    // `verifier mdoc` で Holder が作成した mdoc の DeviceResponse を検証する
+    if command.as_deref() == Some("mdoc") {
+        let response = std::fs::read("device_response.cbor")?;
+        let verifier = Verifier::from_pem_file(
+            &issuer_public_key,
+            &audiences.vc_audience,
+            &audiences.kb_audience,
+        )?;
+        // redirect_uri スキームなので client_id と response_uri はどちらも KB-JWT の aud
+        let documents = verifier.verify_device_response(
+            &response,
+            nonce.trim(),
+            &audiences.kb_audience,
+            &audiences.kb_audience,
+        )?;
+        for document in documents {
+            println!("docType: {}", document.doc_type);
+            println!(
+                "decoded object: {}",
+                serde_json::to_string_pretty(&document.claims)?
+            );
         }
+        std::fs::remove_file(NONCE_FILE)?;
+        return Ok(());
+    }
+
+    // Holderから提出されたVP（署名付きJWTとして）
+    let vp = std::fs::read_to_string("vp.jwt")?;
+
+    let verifier = verifier(
+        &issuer_public_key,
+        &audiences.vc_audience,
+        &audiences.kb_audience,
+    )?;
+    // W3C VCDM 2.0 の VP-JWT の場合は含まれる VC-JWT ごとに結果を表示する
+    let results = if vp.trim_end().contains('~') {
+        vec![verifier.verify_presentation(&vp, nonce.trim())?]
     } else {
-        panic!("sd_hash is not correct!");
+        verifier.verify_vp_jwt(vp.trim(), nonce.trim())?
+    };
+    for result in results {
+        println!(
+            "decoded object: {}",
+            serde_json::to_string_pretty(&result.claims)?
+        );
     }
 
-    println!("vp_token payload={:?}", vp_token.claims);
-    // Decode the payload by providing the disclosures that were parsed from the SD-JWT.
-    let decoder = SdObjectDecoder::new_with_sha256();
-    let obj = vc_token.claims.as_object().unwrap();
-    println!("obj={obj:?}");
-    println!("disclosures len={}", sd_jwt.disclosures.len());
-    let decoded = decoder.decode(obj, &sd_jwt.disclosures)?;
-    println!(
-        "decoded object: {}",
-        serde_json::to_string_pretty(&decoded)?
-    );
+    // 使用済みの nonce は削除し、同じ VP を再提示されても受け付けない
+    std::fs::remove_file(NONCE_FILE)?;
 
     Ok(())
 }
 
-/// PEMファイルの読み込み
-fn read_pem_file(file_path: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-    let mut file = File::open(file_path)?;
-    let mut contents = vec![];
-    file.read_to_end(&mut contents)?;
-    // let pem = parse(contents)?;
-    // Ok(pem.contents().to_vec())
-    Ok(contents)
+/// Verifier を作成する
+/// 環境変数 TRUST_REGISTRY (トラストレジストリの JSON ファイル) を指定した場合は、
+/// `issuer_public_key` の代わりにレジストリに登録された発行者の VC だけを受け入れる
+/// JWT_VC_ISSUERS (`iss` のカンマ区切り、`*` はすべて) を指定した場合は、
+/// 発行者メタデータ (`/.well-known/jwt-vc-issuer`) の JWKS から `kid` の鍵を取得する
+/// (`iss` が DID の場合は DID ドキュメントの鍵)
+fn verifier(issuer_public_key: &str, vc_audience: &str, kb_audience: &str) -> Result<Verifier> {
+    let verifier = match (env::var("TRUST_REGISTRY"), env::var("JWT_VC_ISSUERS")) {
+        (Ok(path), _) => Verifier::from_trust_registry(
+            Arc::new(TrustRegistry::from_file(path)?),
+            vc_audience,
+            kb_audience,
+        ),
+        (_, Ok(issuers)) => {
+            Verifier::from_issuer_metadata(issuer_key_resolver(&issuers)?, vc_audience, kb_audience)
+        }
+        _ => Verifier::from_pem_file(issuer_public_key, vc_audience, kb_audience)?,
+    };
+    let verifier = verifier
+        .with_status_lists(status_list_cache()?)
+        .with_did_resolver(DidResolver::default());
+    Ok(match type_metadata_resolver()? {
+        Some(resolver) => verifier.with_type_metadata(resolver),
+        None => verifier,
+    })
+}
+
+/// `vct` の Type Metadata の resolver
+/// FETCH_TYPE_METADATA=1 の場合は `vct` の URL から取得し、TYPE_METADATA_FILES
+/// (`<vct>=<ファイル>` のカンマ区切り) の `vct` は取得せずにファイルから読み込む
+/// どちらも設定しない場合は Type Metadata を検証しない
+fn type_metadata_resolver() -> Result<Option<Arc<TypeMetadataResolver>>> {
+    let fetch = env::var("FETCH_TYPE_METADATA").is_ok_and(|v| v == "1" || v == "true");
+    let files = env::var("TYPE_METADATA_FILES").unwrap_or_default();
+    if !fetch && files.is_empty() {
+        return Ok(None);
+    }
+    let mut resolver = TypeMetadataResolver::new(DEFAULT_TYPE_METADATA_MAX_AGE).with_fetch(fetch);
+    for entry in files.split(',').filter(|entry| !entry.is_empty()) {
+        // vct は URL なので最後の `=` で分ける
+        let (vct, path) = entry
+            .rsplit_once('=')
+            .ok_or_else(|| anyhow!("TYPE_METADATA_FILES: expected <vct>=<file>: {entry}"))?;
+        resolver = resolver.with_file(vct, path);
+    }
+    Ok(Some(Arc::new(resolver)))
 }
 
-/// JSONからJWK
-fn json_to_jwk(jwk: &Value) -> Result<Jwk> {
-    serde_json::from_value(jwk.clone()).map_err(|e| anyhow!("failed to convert jwk e={}.", e))
+/// 発行者メタデータから鍵を取得する resolver
+/// 取得した鍵は ISSUER_KEYS_MAX_AGE 秒 (既定は 1 時間) キャッシュする
+fn issuer_key_resolver(issuers: &str) -> Result<Arc<IssuerKeyResolver>> {
+    let max_age = match env::var("ISSUER_KEYS_MAX_AGE") {
+        Ok(v) => Duration::from_secs(v.parse()?),
+        Err(_) => DEFAULT_KEYS_MAX_AGE,
+    };
+    let resolver = IssuerKeyResolver::new(max_age);
+    if issuers.trim() == "*" {
+        return Ok(Arc::new(resolver));
+    }
+    let issuers = issuers
+        .split(',')
+        .map(str::trim)
+        .filter(|iss| !iss.is_empty())
+        .map(String::from)
+        .collect();
+    Ok(Arc::new(resolver.with_allowed_issuers(issuers)))
+}
+
+/// VC の `status` をチェックするためのステータスリストのキャッシュ
+/// STATUS_LIST_FILES (`<URI>=<ファイル>` のカンマ区切り) の URI は取得せずにファイルから読み込む
+fn status_list_cache() -> Result<Arc<StatusListCache>> {
+    let max_age = match env::var("STATUS_LIST_MAX_AGE") {
+        Ok(v) => Duration::from_secs(v.parse()?),
+        Err(_) => DEFAULT_CACHE_MAX_AGE,
+    };
+    let mut cache = StatusListCache::new(max_age);
+    for entry in env::var("STATUS_LIST_FILES").unwrap_or_default().split(',') {
+        if entry.is_empty() {
+            continue;
+        }
+        let (uri, path) = entry
+            .split_once('=')
+            .ok_or_else(|| anyhow!("STATUS_LIST_FILES: expected <uri>=<file>: {entry}"))?;
+        cache = cache.with_file(uri, path);
+    }
+    Ok(Arc::new(cache))
 }
@@ -0,0 +1,191 @@
+//! デプロイごとの設定 (VC と KB-JWT の `aud`)
+//!
+//! Issuer・Holder・Verifier が同じ値を使うよう、CLI フラグ > 環境変数 > 設定ファイル > 既定値
+//! の順に決定する。設定ファイルは el_issue.conf と同じ `KEY=VALUE` 形式。
+//! 各バイナリが共通で使う名前付きのフラグの読み取り (`take_flag` など) もここに置く。
+
+use anyhow::{anyhow, bail, Result};
+use std::collections::HashMap;
+
+/// VC の `aud` の既定値
+pub const DEFAULT_VC_AUDIENCE: &str = "fujita-app";
+/// KB-JWT の `aud` の既定値
+pub const DEFAULT_KB_AUDIENCE: &str = "el-server";
+/// 設定ファイルの既定のパス (存在しなければ読まない)
+pub const DEFAULT_CONFIG_FILE: &str = "vc_vp.conf";
+
+/// VC と KB-JWT の `aud`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AudienceConfig {
+    /// Issuer が VC に設定し、Holder・Verifier が期待する `aud` (環境変数 VC_AUDIENCE)
+    pub vc_audience: String,
+    /// Holder が KB-JWT に設定し、Verifier が期待する `aud` (環境変数 KB_AUDIENCE)
+    pub kb_audience: String,
+}
+
+impl Default for AudienceConfig {
+    fn default() -> Self {
+        Self {
+            vc_audience: DEFAULT_VC_AUDIENCE.to_string(),
+            kb_audience: DEFAULT_KB_AUDIENCE.to_string(),
+        }
+    }
+}
+
+impl AudienceConfig {
+    /// CLI フラグ・環境変数・設定ファイルから読み込む
+    ///
+    /// フラグは `--vc-audience`, `--kb-audience`, `--config` (設定ファイルのパス) で、
+    /// 読み取ったフラグは `args` から取り除く。残りは各バイナリのサブコマンドとフラグ。
+    /// 設定ファイルのパスは `--config` > 環境変数 VC_VP_CONFIG > `vc_vp.conf` の順。
+    pub fn load(args: &mut Vec<String>) -> Result<Self> {
+        let vc_flag = take_flag(args, "--vc-audience")?;
+        let kb_flag = take_flag(args, "--kb-audience")?;
+        let config_flag = take_flag(args, "--config")?;
+
+        let file = match config_flag.or_else(|| std::env::var("VC_VP_CONFIG").ok()) {
+            Some(path) => read_conf_file(&path)?,
+            None if std::path::Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                read_conf_file(DEFAULT_CONFIG_FILE)?
+            }
+            None => HashMap::new(),
+        };
+
+        let resolve = |flag: Option<String>, name: &str, default: &str| {
+            flag.or_else(|| std::env::var(name).ok())
+                .or_else(|| file.get(name).cloned())
+                .unwrap_or_else(|| default.to_string())
+        };
+        let config = Self {
+            vc_audience: resolve(vc_flag, "VC_AUDIENCE", DEFAULT_VC_AUDIENCE),
+            kb_audience: resolve(kb_flag, "KB_AUDIENCE", DEFAULT_KB_AUDIENCE),
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// 空の `aud` は受け付けない
+    pub fn validate(&self) -> Result<()> {
+        if self.vc_audience.trim().is_empty() {
+            bail!("VC_AUDIENCE is empty");
+        }
+        if self.kb_audience.trim().is_empty() {
+            bail!("KB_AUDIENCE is empty");
+        }
+        Ok(())
+    }
+}
+
+/// `KEY=VALUE` 形式の設定ファイルを読み込む
+/// 空行と `#` で始まる行は無視する
+pub fn read_conf_file(path: &str) -> Result<HashMap<String, String>> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| anyhow!("failed to read config file {path}: {e:?}"))?;
+    let mut values = HashMap::new();
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| anyhow!("{path}:{}: expected KEY=VALUE", i + 1))?;
+        values.insert(key.trim().to_string(), value.trim().to_string());
+    }
+    Ok(values)
+}
+
+/// `--name value` または `--name=value` を `args` から取り除いて値を返す
+pub fn take_flag(args: &mut Vec<String>, name: &str) -> Result<Option<String>> {
+    let prefix = format!("{name}=");
+    let Some(i) = args
+        .iter()
+        .position(|arg| arg == name || arg.starts_with(&prefix))
+    else {
+        return Ok(None);
+    };
+    let arg = args.remove(i);
+    if let Some(value) = arg.strip_prefix(&prefix) {
+        return Ok(Some(value.to_string()));
+    }
+    if i >= args.len() {
+        bail!("{name} requires a value");
+    }
+    Ok(Some(args.remove(i)))
+}
+
+/// `--name` を `args` から取り除き、指定されていたかを返す
+pub fn take_switch(args: &mut Vec<String>, name: &str) -> bool {
+    let len = args.len();
+    args.retain(|arg| arg != name);
+    args.len() != len
+}
+
+/// 必須のフラグの値 (ない場合は使い方を含めたエラー)
+pub fn required(value: Option<String>, flag: &str, usage: &str) -> Result<String> {
+    value.ok_or_else(|| anyhow!("{flag} is required\n\n{usage}"))
+}
+
+/// フラグを取り除いた残りの引数がちょうど `N` 個の位置引数であること
+pub fn positional<const N: usize>(args: Vec<String>, usage: &str) -> Result<[String; N]> {
+    if let Some(flag) = args.iter().find(|arg| arg.starts_with("--")) {
+        bail!("unknown option {flag}\n\n{usage}");
+    }
+    args.try_into()
+        .map_err(|_| anyhow!("expected {N} argument(s)\n\n{usage}"))
+}
+
+/// 未知のフラグや余分な引数を受け付けない
+pub fn no_more_args(args: &[String], usage: &str) -> Result<()> {
+    match args.first() {
+        Some(arg) if arg.starts_with("--") => bail!("unknown option {arg}\n\n{usage}"),
+        Some(arg) => bail!("unexpected argument {arg}\n\n{usage}"),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn flags_are_taken_from_args() {
+        let mut a = args(&[
+            "issue",
+            "--out",
+            "vc.jwt",
+            "--account-name=takehi",
+            "--force",
+        ]);
+        assert_eq!(
+            take_flag(&mut a, "--out").unwrap().as_deref(),
+            Some("vc.jwt")
+        );
+        assert_eq!(
+            take_flag(&mut a, "--account-name").unwrap().as_deref(),
+            Some("takehi")
+        );
+        assert_eq!(take_flag(&mut a, "--kid").unwrap(), None);
+        assert!(take_switch(&mut a, "--force"));
+        assert!(!take_switch(&mut a, "--force"));
+        assert_eq!(a, args(&["issue"]));
+        assert!(take_flag(&mut args(&["--out"]), "--out").is_err());
+    }
+
+    #[test]
+    fn leftover_args_are_rejected() {
+        assert!(no_more_args(&[], "usage").is_ok());
+        let err = no_more_args(&args(&["--unknown"]), "usage").unwrap_err();
+        assert!(err.to_string().starts_with("unknown option --unknown"));
+        let err = no_more_args(&args(&["takehi"]), "usage").unwrap_err();
+        assert!(err.to_string().starts_with("unexpected argument takehi"));
+        assert!(required(None, "--out", "usage").is_err());
+        assert_eq!(positional::<1>(args(&["a"]), "usage").unwrap(), ["a"]);
+        assert!(positional::<1>(args(&["a", "b"]), "usage").is_err());
+        assert!(positional::<1>(args(&["--x"]), "usage").is_err());
+    }
+}
@@ -0,0 +1,414 @@
+//! Holder がクレデンシャルを保存するストア
+//!
+//! ディレクトリに 1 つのクレデンシャルを 1 つの JSON ファイル (`<id>.json`) として保存し、
+//! 発行者・`vct`・有効期限・Holder の鍵などのメタデータと一緒に一覧・検索・削除できるようにする。
+//! `id` はクレデンシャルの SHA-256 なので、同じクレデンシャルを何度追加しても 1 つになる。
+//! SD-JWT VC (`vc+sd-jwt`・`dc+sd-jwt`) と W3C VCDM 2.0 の VC-JWT (`vc+jwt`) を保存できる。
+
+use crate::vcdm;
+use anyhow::{anyhow, bail, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use josekit::jwt;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use std::{
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// ストアのディレクトリの既定のパス
+pub const DEFAULT_WALLET_DIR: &str = "wallet";
+
+/// 保存しているクレデンシャルとメタデータ
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredCredential {
+    /// クレデンシャルの SHA-256 の Base64url
+    pub id: String,
+    /// JWS ヘッダの `typ` (`vc+sd-jwt`・`vc+jwt` など)
+    pub format: String,
+    pub credential: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub issuer: Option<String>,
+    /// SD-JWT VC の `vct`、VC-JWT の場合は `VerifiableCredential` 以外の `type`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vct: Option<String>,
+    /// `iat` (UNIX 時間)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub issued_at: Option<u64>,
+    /// `exp` (UNIX 時間)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+    /// 提示に使う Holder の秘密鍵 (ファイルのパスなど)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub holder_key: Option<String>,
+    /// 保存した時刻 (UNIX 時間)
+    pub stored_at: u64,
+}
+
+impl StoredCredential {
+    /// クレデンシャルの署名は検証せずにメタデータを読み取る
+    pub fn new(credential: &str, holder_key: Option<&str>) -> Result<Self> {
+        let credential = credential.trim();
+        let jwt = credential.split('~').next().unwrap_or_default();
+        let header = jwt::decode_header(jwt).map_err(|e| anyhow!("invalid credential: {e:?}"))?;
+        let format = header
+            .claim("typ")
+            .and_then(Value::as_str)
+            .ok_or_else(|| anyhow!("credential has no typ"))?
+            .to_string();
+        let payload = jwt
+            .split('.')
+            .nth(1)
+            .ok_or_else(|| anyhow!("invalid credential"))?;
+        let claims: Map<String, Value> = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload)?)
+            .map_err(|e| anyhow!("invalid credential payload: {e}"))?;
+
+        let (issuer, vct) = if format == vcdm::VC_JWT_TYP {
+            let vct = vcdm::types(&claims)
+                .into_iter()
+                .rfind(|t| *t != "VerifiableCredential");
+            (
+                vcdm::issuer(&claims).or(claims.get("iss").and_then(Value::as_str)),
+                vct,
+            )
+        } else if format.ends_with("+sd-jwt") {
+            (
+                claims.get("iss").and_then(Value::as_str),
+                claims.get("vct").and_then(Value::as_str),
+            )
+        } else {
+            bail!("unsupported credential format {format}");
+        };
+        Ok(Self {
+            id: credential_id(credential),
+            issuer: issuer.map(str::to_string),
+            vct: vct.map(str::to_string),
+            issued_at: claims.get("iat").and_then(Value::as_u64),
+            expires_at: claims.get("exp").and_then(Value::as_u64),
+            holder_key: holder_key.map(str::to_string),
+            stored_at: unix_time(SystemTime::now()),
+            format,
+            credential: credential.to_string(),
+        })
+    }
+
+    /// `now` に有効期限が過ぎているか
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at <= unix_time(now))
+    }
+}
+
+/// 一覧の条件 (None の条件は問わない)
+#[derive(Debug, Clone, Default)]
+pub struct CredentialFilter {
+    pub issuer: Option<String>,
+    pub vct: Option<String>,
+    pub format: Option<String>,
+    /// 期限切れのクレデンシャルも含めるか
+    pub include_expired: bool,
+}
+
+impl CredentialFilter {
+    pub fn matches(&self, credential: &StoredCredential, now: SystemTime) -> bool {
+        let eq = |expected: &Option<String>, actual: &Option<String>| {
+            expected.is_none() || expected == actual
+        };
+        eq(&self.issuer, &credential.issuer)
+            && eq(&self.vct, &credential.vct)
+            && self
+                .format
+                .as_ref()
+                .is_none_or(|format| *format == credential.format)
+            && (self.include_expired || !credential.is_expired(now))
+    }
+}
+
+/// ディレクトリに保存するクレデンシャルのストア
+pub struct CredentialStore {
+    dir: PathBuf,
+}
+
+impl CredentialStore {
+    /// ディレクトリがなければ作成する
+    pub fn open(dir: impl Into<PathBuf>) -> Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)
+            .map_err(|e| anyhow!("failed to create wallet {}: {e:?}", dir.display()))?;
+        Ok(Self { dir })
+    }
+
+    /// 環境変数 WALLET_DIR (既定は `wallet`) のストアを開く
+    pub fn from_env() -> Result<Self> {
+        let dir = std::env::var("WALLET_DIR").unwrap_or_else(|_e| DEFAULT_WALLET_DIR.to_string());
+        Self::open(dir)
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// クレデンシャルを保存する (同じクレデンシャルは上書きする)
+    pub fn add(&self, credential: &str, holder_key: Option<&str>) -> Result<StoredCredential> {
+        let stored = StoredCredential::new(credential, holder_key)?;
+        let path = self.path(&stored.id);
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_string_pretty(&stored)?)
+            .and_then(|_| std::fs::rename(&tmp, &path))
+            .map_err(|e| anyhow!("failed to write credential {}: {e:?}", path.display()))?;
+        Ok(stored)
+    }
+
+    /// `id` (一意に決まる先頭部分でもよい) のクレデンシャル
+    pub fn get(&self, id: &str) -> Result<StoredCredential> {
+        // 空の id はすべてのクレデンシャルの先頭部分になってしまう
+        if id.is_empty() {
+            bail!("credential id is empty");
+        }
+        let mut found = self
+            .all()?
+            .into_iter()
+            .filter(|credential| credential.id.starts_with(id));
+        match (found.next(), found.next()) {
+            (Some(credential), None) => Ok(credential),
+            (Some(_), Some(_)) => bail!("credential id {id} is ambiguous"),
+            (None, _) => bail!("no credential {id} in {}", self.dir.display()),
+        }
+    }
+
+    /// `filter` に合うクレデンシャル (保存した順)
+    pub fn list(&self, filter: &CredentialFilter) -> Result<Vec<StoredCredential>> {
+        let now = SystemTime::now();
+        Ok(self
+            .all()?
+            .into_iter()
+            .filter(|credential| filter.matches(credential, now))
+            .collect())
+    }
+
+    /// `id` のクレデンシャルを削除する
+    pub fn remove(&self, id: &str) -> Result<StoredCredential> {
+        let credential = self.get(id)?;
+        let path = self.path(&credential.id);
+        std::fs::remove_file(&path)
+            .map_err(|e| anyhow!("failed to remove credential {}: {e:?}", path.display()))?;
+        Ok(credential)
+    }
+
+    /// 期限切れのクレデンシャルを削除し、削除したものを返す
+    pub fn remove_expired(&self) -> Result<Vec<StoredCredential>> {
+        let now = SystemTime::now();
+        let expired: Vec<StoredCredential> = self
+            .all()?
+            .into_iter()
+            .filter(|credential| credential.is_expired(now))
+            .collect();
+        for credential in &expired {
+            let path = self.path(&credential.id);
+            std::fs::remove_file(&path)
+                .map_err(|e| anyhow!("failed to remove credential {}: {e:?}", path.display()))?;
+        }
+        Ok(expired)
+    }
+
+    /// 保存しているすべてのクレデンシャル (保存した順)
+    /// 読み込めないファイルは警告を出して飛ばし、ほかのクレデンシャルは使えるようにする
+    fn all(&self) -> Result<Vec<StoredCredential>> {
+        let entries = std::fs::read_dir(&self.dir)
+            .map_err(|e| anyhow!("failed to read wallet {}: {e:?}", self.dir.display()))?;
+        let mut credentials = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_none_or(|ext| ext != "json") {
+                continue;
+            }
+            match load(&path) {
+                Ok(credential) => credentials.push(credential),
+                Err(e) => log::warn!("skipping credential: {e}"),
+            }
+        }
+        credentials.sort_by(|a, b| a.stored_at.cmp(&b.stored_at).then(a.id.cmp(&b.id)));
+        Ok(credentials)
+    }
+
+    fn path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{id}.json"))
+    }
+}
+
+/// `path` の JSON ファイルのクレデンシャル
+fn load(path: &Path) -> Result<StoredCredential> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| anyhow!("failed to read credential {}: {e:?}", path.display()))?;
+    serde_json::from_str(&contents)
+        .map_err(|e| anyhow!("invalid credential {}: {e}", path.display()))
+}
+
+/// クレデンシャルの SHA-256 の Base64url
+fn credential_id(credential: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(credential.as_bytes()))
+}
+
+fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        alg::SigningAlgorithm,
+        issuer::{GenerateVCParams, Issuer},
+        key::{generate_key_pair, public_key_pem_to_jwk},
+    };
+    use serde_json::json;
+
+    /// テストごとの一時ディレクトリのストア
+    fn store(name: &str) -> CredentialStore {
+        let dir = std::env::temp_dir().join(format!(
+            "vc_vp_credential_store_{name}_{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        CredentialStore::open(dir).unwrap()
+    }
+
+    fn issue(iss: &str, vct: &str, vc_expires_in: u64) -> String {
+        let (issuer_private, _) = generate_key_pair(SigningAlgorithm::ES256).unwrap();
+        let (_, holder_public) = generate_key_pair(SigningAlgorithm::ES256).unwrap();
+        let claims = json!({ "account_name": "user01" });
+        Issuer::new(iss, issuer_private, "key-1")
+            .unwrap()
+            .generate_sd_jwt_vc(GenerateVCParams {
+                vct: Some(vct.to_string()),
+                vct_integrity: None,
+                holder_jwk: public_key_pem_to_jwk(&holder_public).unwrap(),
+                holder_kid: None,
+                claims: claims.as_object().unwrap().clone(),
+                disclosable: vec!["/account_name".to_string()],
+                decoys: 0,
+                audience: "https://verifier.example.com".to_string(),
+                vc_expires_in,
+                status: None,
+            })
+            .unwrap()
+    }
+
+    fn ids(credentials: &[StoredCredential]) -> Vec<&str> {
+        let mut ids: Vec<&str> = credentials.iter().map(|c| c.id.as_str()).collect();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn add_and_get() {
+        let store = store("get");
+        let vc = issue("https://issuer.example.com", "https://example.com/a", 3600);
+        let stored = store.add(&vc, Some("holder.pem")).unwrap();
+        assert_eq!(stored.format, "vc+sd-jwt");
+        assert_eq!(stored.issuer.as_deref(), Some("https://issuer.example.com"));
+        assert_eq!(stored.vct.as_deref(), Some("https://example.com/a"));
+
+        // 同じクレデンシャルは 1 つになる
+        store.add(&vc, Some("holder.pem")).unwrap();
+        assert_eq!(store.list(&CredentialFilter::default()).unwrap().len(), 1);
+
+        let found = store.get(&stored.id[..8]).unwrap();
+        assert_eq!(found.credential, vc);
+        assert_eq!(found.holder_key.as_deref(), Some("holder.pem"));
+        assert!(store.get("").unwrap_err().to_string().contains("empty"));
+        assert!(store.get("not-an-id").is_err());
+
+        assert_eq!(store.remove(&stored.id).unwrap().id, stored.id);
+        assert!(store.get(&stored.id).is_err());
+        std::fs::remove_dir_all(store.dir()).unwrap();
+    }
+
+    #[test]
+    fn list_filters_by_issuer_vct_and_expiry() {
+        let store = store("list");
+        let a = store
+            .add(
+                &issue("https://a.example.com", "https://example.com/a", 3600),
+                None,
+            )
+            .unwrap();
+        let b = store
+            .add(
+                &issue("https://b.example.com", "https://example.com/b", 3600),
+                None,
+            )
+            .unwrap();
+        let expired = store
+            .add(
+                &issue("https://a.example.com", "https://example.com/b", 0),
+                None,
+            )
+            .unwrap();
+
+        let list = |filter: CredentialFilter| store.list(&filter).unwrap();
+        let mut all = vec![a.id.as_str(), b.id.as_str()];
+        all.sort();
+        assert_eq!(ids(&list(CredentialFilter::default())), all);
+        assert_eq!(
+            ids(&list(CredentialFilter {
+                issuer: Some("https://a.example.com".to_string()),
+                ..Default::default()
+            })),
+            [a.id.as_str()]
+        );
+        assert_eq!(
+            ids(&list(CredentialFilter {
+                vct: Some("https://example.com/b".to_string()),
+                ..Default::default()
+            })),
+            [b.id.as_str()]
+        );
+        assert_eq!(
+            ids(&list(CredentialFilter {
+                vct: Some("https://example.com/b".to_string()),
+                include_expired: true,
+                ..Default::default()
+            }))
+            .len(),
+            2
+        );
+        assert!(list(CredentialFilter {
+            format: Some("vc+jwt".to_string()),
+            ..Default::default()
+        })
+        .is_empty());
+
+        assert_eq!(ids(&store.remove_expired().unwrap()), [expired.id.as_str()]);
+        assert!(store.get(&expired.id).is_err());
+        assert_eq!(
+            ids(&list(CredentialFilter {
+                include_expired: true,
+                ..Default::default()
+            })),
+            all
+        );
+        std::fs::remove_dir_all(store.dir()).unwrap();
+    }
+
+    #[test]
+    fn corrupt_files_are_skipped() {
+        let store = store("corrupt");
+        let stored = store
+            .add(
+                &issue("https://issuer.example.com", "https://example.com/a", 3600),
+                None,
+            )
+            .unwrap();
+        std::fs::write(store.dir().join("broken.json"), "{").unwrap();
+
+        let list = store.list(&CredentialFilter::default()).unwrap();
+        assert_eq!(ids(&list), [stored.id.as_str()]);
+        assert_eq!(store.get(&stored.id).unwrap().id, stored.id);
+        assert!(store.remove_expired().unwrap().is_empty());
+        std::fs::remove_dir_all(store.dir()).unwrap();
+    }
+}
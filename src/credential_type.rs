@@ -0,0 +1,428 @@
+//! 設定ファイルで定義する VC の種類
+//!
+//! VC の種類ごとに `vct`・クレームの JSON Schema・選択的開示のポリシー・有効期間・
+//! ダミーダイジェストの数を JSON ファイルで定義し、`vc_issuer` で任意の種類の VC を発行する。
+//! `name`・`description`・`display`・`claims` は Type Metadata (`type_metadata`) の表示情報になる。
+//!
+//! ```json
+//! {
+//!   "credential_types": [
+//!     {
+//!       "id": "patient_id",
+//!       "vct": "https://stg-fujita-issuer-phr.freebit.net/vc/patient-id",
+//!       "iss": "https://stg-fujita-issuer-phr.freebit.net",
+//!       "key_file": "patientid_issuer_private_key_ES256.pem",
+//!       "schema": {
+//!         "type": "object",
+//!         "required": ["patient_id", "medical_institution_code"],
+//!         "properties": { "patient_id": { "type": "string" } }
+//!       },
+//!       "disclosable": ["/patient_id", "/medical_institution_code"],
+//!       "expires_in": 3153600000,
+//!       "subject_claim": "patient_id"
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! `iss`・`key_file` (設定ファイルからの相対パス)・`kid` を省略した場合は発行者の既定値を使う。
+//! `type_metadata_file` (設定ファイルからの相対パス) を指定した場合は、`vct` の URL で公開しているその文書の
+//! バイト列から `vct#integrity` を計算する (省略した場合は定義から作成した `type_metadata` の文書)。
+//!
+//! `schema` はそのまま Type Metadata で公開し、Verifier は発行者が加えるクレーム (`ISSUER_CLAIMS`) を含む
+//! 開示後の VC を検証する。トップレベルの `additionalProperties` を指定する場合は、これらのクレームも
+//! `properties` に定義する (定義していない場合は読み込みでエラーにする)。
+
+use crate::{
+    issuer::GenerateVCParams,
+    status_list::StatusReference,
+    type_metadata::{self, ClaimMetadata, TypeMetadata},
+};
+use anyhow::{anyhow, bail, Result};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::path::{Path, PathBuf};
+
+/// 有効期間の既定値 (7 日)
+pub const DEFAULT_EXPIRES_IN: u64 = 7 * 24 * 60 * 60;
+/// ダミーダイジェストの数の既定値
+pub const DEFAULT_DECOYS: usize = 2;
+/// 設定ファイルの既定のパス
+pub const DEFAULT_CREDENTIAL_TYPES_FILE: &str = "credential_types.json";
+/// 発行者が VC に加えるクレーム (開示後の VC に含まれる)
+pub const ISSUER_CLAIMS: [&str; 8] = [
+    "iss",
+    "vct",
+    "vct#integrity",
+    "aud",
+    "iat",
+    "exp",
+    "cnf",
+    "status",
+];
+
+/// 設定ファイルの形式
+#[derive(Debug, Deserialize)]
+struct CredentialTypesFile {
+    credential_types: Vec<CredentialTypeEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CredentialTypeEntry {
+    id: String,
+    vct: String,
+    iss: Option<String>,
+    key_file: Option<String>,
+    kid: Option<String>,
+    type_metadata_file: Option<String>,
+    name: Option<String>,
+    description: Option<String>,
+    #[serde(default)]
+    display: Vec<Value>,
+    #[serde(default)]
+    claims: Vec<ClaimMetadata>,
+    schema: Option<Value>,
+    #[serde(default)]
+    disclosable: Vec<String>,
+    expires_in: Option<u64>,
+    decoys: Option<usize>,
+    subject_claim: Option<String>,
+}
+
+/// VC の種類
+#[derive(Debug)]
+pub struct CredentialType {
+    /// 種類の識別子 (`vc_issuer` の引数)
+    pub id: String,
+    pub vct: String,
+    /// `iss` (None の場合は発行者の既定値)
+    pub iss: Option<String>,
+    /// 発行者の秘密鍵のファイル (None の場合は発行者の既定値)
+    pub key_file: Option<PathBuf>,
+    /// JWS ヘッダの `kid` (None の場合は発行者の既定値)
+    pub kid: Option<String>,
+    /// `vct` の URL で公開している Type Metadata の文書 (None の場合は `type_metadata` から作成する)
+    pub type_metadata_file: Option<PathBuf>,
+    pub name: Option<String>,
+    pub description: Option<String>,
+    /// 言語ごとの表示情報 (Type Metadata の `display`)
+    pub display: Vec<Value>,
+    /// クレームのメタデータ (Type Metadata の `claims`)
+    pub claims: Vec<ClaimMetadata>,
+    /// クレームの JSON Schema
+    pub schema: Option<Value>,
+    /// 選択的開示にするクレームの JSON Pointer (`disclosure` を参照)
+    pub disclosable: Vec<String>,
+    /// 有効期間 (秒)
+    pub expires_in: u64,
+    pub decoys: usize,
+    /// ステータスリストのインデックスを割り当てるときの主体のクレーム (`account_name` など)
+    pub subject_claim: Option<String>,
+    /// `schema` をコンパイルしたもの (None の場合は検証しない)
+    validator: Option<jsonschema::Validator>,
+}
+
+impl CredentialType {
+    /// クレームを JSON Schema で検証する
+    pub fn validate(&self, claims: &Map<String, Value>) -> Result<()> {
+        let Some(validator) = &self.validator else {
+            return Ok(());
+        };
+        let instance = Value::Object(claims.clone());
+        let errors: Vec<String> = validator
+            .iter_errors(&instance)
+            .map(|e| match e.instance_path.to_string() {
+                path if path.is_empty() => e.to_string(),
+                path => format!("{path}: {e}"),
+            })
+            .collect();
+        if !errors.is_empty() {
+            bail!("claims do not match {}: {}", self.id, errors.join(", "));
+        }
+        Ok(())
+    }
+
+    /// ステータスリストの主体 (`subject_claim` の値)
+    pub fn subject(&self, claims: &Map<String, Value>) -> Result<String> {
+        let name = self
+            .subject_claim
+            .as_deref()
+            .ok_or_else(|| anyhow!("{} has no subject_claim", self.id))?;
+        match claims.get(name) {
+            Some(Value::String(subject)) => Ok(subject.clone()),
+            Some(subject) => Ok(subject.to_string()),
+            None => bail!("claims have no {name}"),
+        }
+    }
+
+    /// `vct` の Type Metadata
+    ///
+    /// `disclosable` のクレームは `sd: always` にする。スキーマは変更せずに公開する
+    /// (Verifier は `sd` が `always`・`allowed` のクレームが開示されていなくても `required` のエラーにしない)。
+    pub fn type_metadata(&self) -> TypeMetadata {
+        let disclosable: Vec<Vec<Value>> = self
+            .disclosable
+            .iter()
+            .map(|pointer| type_metadata::claim_path(pointer))
+            .collect();
+        let mut claims = self.claims.clone();
+        for claim in &mut claims {
+            if claim.sd.is_none() && disclosable.contains(&claim.path) {
+                claim.sd = Some("always".to_string());
+            }
+        }
+        for path in &disclosable {
+            if !claims.iter().any(|claim| &claim.path == path) {
+                claims.push(ClaimMetadata {
+                    path: path.clone(),
+                    sd: Some("always".to_string()),
+                    ..Default::default()
+                });
+            }
+        }
+
+        TypeMetadata {
+            vct: self.vct.clone(),
+            name: self.name.clone(),
+            description: self.description.clone(),
+            display: self.display.clone(),
+            claims,
+            schema: self.schema.clone(),
+            ..Default::default()
+        }
+    }
+
+    /// `vct` の URL で公開する Type Metadata の文書のバイト列 (`vct#integrity` はこのバイト列のハッシュ)
+    /// `type_metadata_file` を指定した場合はファイルの内容をそのまま使う
+    pub fn type_metadata_document(&self) -> Result<Vec<u8>> {
+        let Some(path) = &self.type_metadata_file else {
+            return Ok(self.type_metadata().to_document()?.into_bytes());
+        };
+        let document = std::fs::read(path)
+            .map_err(|e| anyhow!("failed to read type metadata {}: {e:?}", path.display()))?;
+        let metadata: TypeMetadata = serde_json::from_slice(&document)
+            .map_err(|e| anyhow!("invalid type metadata {}: {e}", path.display()))?;
+        if metadata.vct != self.vct {
+            bail!(
+                "type metadata {} is for {} (expected {})",
+                path.display(),
+                metadata.vct,
+                self.vct
+            );
+        }
+        Ok(document)
+    }
+
+    /// クレームを検証して発行時のパラメータを作成する
+    /// `vct#integrity` は `type_metadata_document` のバイト列のハッシュ
+    pub fn params(
+        &self,
+        claims: Map<String, Value>,
+        holder_jwk: Value,
+        audience: String,
+        status: Option<StatusReference>,
+    ) -> Result<GenerateVCParams> {
+        self.validate(&claims)?;
+        let document = self.type_metadata_document()?;
+        Ok(GenerateVCParams {
+            vct: Some(self.vct.clone()),
+            vct_integrity: Some(type_metadata::integrity(&document)),
+            holder_jwk,
+            holder_kid: None,
+            claims,
+            disclosable: self.disclosable.clone(),
+            decoys: self.decoys,
+            audience,
+            vc_expires_in: self.expires_in,
+            status,
+        })
+    }
+}
+
+/// 設定ファイルで定義した VC の種類の一覧
+#[derive(Debug)]
+pub struct CredentialTypeRegistry {
+    types: Vec<CredentialType>,
+}
+
+impl CredentialTypeRegistry {
+    /// JSON ファイルから読み込む (JSON Schema はここでコンパイルする)
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("failed to read credential types {}: {e:?}", path.display()))?;
+        let file: CredentialTypesFile = serde_json::from_str(&contents)
+            .map_err(|e| anyhow!("invalid credential types {}: {e}", path.display()))?;
+        let base = path.parent().unwrap_or(Path::new("."));
+        let types = file
+            .credential_types
+            .into_iter()
+            .map(|entry| credential_type(entry, base))
+            .collect::<Result<Vec<_>>>()?;
+        for (i, t) in types.iter().enumerate() {
+            if types[..i].iter().any(|other| other.id == t.id) {
+                bail!("duplicate credential type {}", t.id);
+            }
+        }
+        Ok(Self { types })
+    }
+
+    /// 識別子または `vct` で VC の種類を探す
+    pub fn get(&self, id_or_vct: &str) -> Option<&CredentialType> {
+        self.types
+            .iter()
+            .find(|t| t.id == id_or_vct || t.vct == id_or_vct)
+    }
+
+    pub fn types(&self) -> &[CredentialType] {
+        &self.types
+    }
+}
+
+fn credential_type(entry: CredentialTypeEntry, base: &Path) -> Result<CredentialType> {
+    let validator = entry
+        .schema
+        .as_ref()
+        .map(|schema| {
+            jsonschema::validator_for(schema)
+                .map_err(|e| anyhow!("invalid schema of {}: {e}", entry.id))
+        })
+        .transpose()?;
+    if let Some(schema) = &entry.schema {
+        check_issuer_claims(&entry.id, schema)?;
+    }
+    if let Some(pointer) = entry.disclosable.iter().find(|p| !p.starts_with('/')) {
+        bail!(
+            "disclosable of {} must be a JSON pointer: {pointer}",
+            entry.id
+        );
+    }
+    Ok(CredentialType {
+        vct: entry.vct,
+        iss: entry.iss,
+        key_file: entry.key_file.map(|file| base.join(file)),
+        kid: entry.kid,
+        type_metadata_file: entry.type_metadata_file.map(|file| base.join(file)),
+        name: entry.name,
+        description: entry.description,
+        display: entry.display,
+        claims: entry.claims,
+        schema: entry.schema,
+        disclosable: entry.disclosable,
+        expires_in: entry.expires_in.unwrap_or(DEFAULT_EXPIRES_IN),
+        decoys: entry.decoys.unwrap_or(DEFAULT_DECOYS),
+        subject_claim: entry.subject_claim,
+        validator,
+        id: entry.id,
+    })
+}
+
+/// トップレベルの `additionalProperties` が `ISSUER_CLAIMS` を拒否しないか
+fn check_issuer_claims(id: &str, schema: &Value) -> Result<()> {
+    let Some(additional) = schema.get("additionalProperties") else {
+        return Ok(());
+    };
+    if additional == &Value::Bool(true) {
+        return Ok(());
+    }
+    let properties = schema.get("properties").and_then(Value::as_object);
+    let missing: Vec<&str> = ISSUER_CLAIMS
+        .into_iter()
+        .filter(|name| !properties.is_some_and(|properties| properties.contains_key(*name)))
+        .collect();
+    if !missing.is_empty() {
+        bail!(
+            "schema of {id} restricts additionalProperties but does not define {} (the verifier validates the VC with these claims)",
+            missing.join(", ")
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn entry(schema: Value) -> CredentialTypeEntry {
+        serde_json::from_value(json!({
+            "id": "patient_id",
+            "vct": "https://issuer.example.com/vc/patient-id",
+            "schema": schema,
+            "disclosable": ["/patient_id"],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn type_metadata_publishes_schema_unchanged() {
+        let schema = json!({
+            "type": "object",
+            "required": ["patient_id"],
+            "properties": { "patient_id": { "type": "string" } },
+        });
+        let credential_type = credential_type(entry(schema.clone()), Path::new(".")).unwrap();
+        let metadata = credential_type.type_metadata();
+        assert_eq!(metadata.schema, Some(schema));
+        assert_eq!(metadata.claims[0].path, [json!("patient_id")]);
+        assert_eq!(metadata.claims[0].sd.as_deref(), Some("always"));
+    }
+
+    #[test]
+    fn additional_properties_must_allow_issuer_claims() {
+        let schema = json!({
+            "type": "object",
+            "properties": { "patient_id": { "type": "string" }, "iss": { "type": "string" } },
+            "additionalProperties": false,
+        });
+        let error = credential_type(entry(schema), Path::new(".")).unwrap_err();
+        assert!(
+            error.to_string().contains("vct, vct#integrity, aud"),
+            "{error}"
+        );
+
+        let mut properties = Map::new();
+        properties.insert("patient_id".to_string(), json!({ "type": "string" }));
+        for name in ISSUER_CLAIMS {
+            properties.insert(name.to_string(), json!({}));
+        }
+        let schema = json!({ "properties": properties, "additionalProperties": false });
+        assert!(credential_type(entry(schema), Path::new(".")).is_ok());
+    }
+
+    #[test]
+    fn integrity_uses_published_type_metadata_file() {
+        let schema = json!({ "type": "object" });
+        let generated = credential_type(entry(schema.clone()), Path::new(".")).unwrap();
+        let document = generated.type_metadata_document().unwrap();
+        assert_eq!(
+            document,
+            generated
+                .type_metadata()
+                .to_document()
+                .unwrap()
+                .into_bytes()
+        );
+
+        // 公開している文書 (空白のない JSON) のバイト列をそのまま使う
+        let published = serde_json::to_vec(&generated.type_metadata()).unwrap();
+        let path = std::env::temp_dir().join(format!(
+            "credential-type-{}-{}.json",
+            std::process::id(),
+            rand::random::<u64>()
+        ));
+        std::fs::write(&path, &published).unwrap();
+        let mut entry = entry(schema);
+        entry.type_metadata_file = Some(path.display().to_string());
+        let from_file = credential_type(entry, Path::new(".")).unwrap();
+        let params = from_file
+            .params(Map::new(), json!({}), "aud".to_string(), None)
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            params.vct_integrity,
+            Some(type_metadata::integrity(&published))
+        );
+        assert_ne!(published, document);
+    }
+}
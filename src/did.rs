@@ -0,0 +1,495 @@
+//! DID (`did:key`・`did:jwk`・`did:web`) の解決
+//!
+//! VC の `iss` や `cnf.kid` に DID (DID URL) を使えるよう、DID ドキュメントの
+//! verification method から公開鍵 (JWK) を取り出す。
+//! `did:web` の DID ドキュメントは `HttpFetcher` で取得する (テストではローカルの代わりを渡せる)。
+
+use crate::{http, jwk, key::ec_point_to_jwk};
+use anyhow::{anyhow, bail, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use p256::elliptic_curve::sec1::ToEncodedPoint as _;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+/// multicodec の ed25519-pub
+const MULTICODEC_ED25519_PUB: [u8; 2] = [0xed, 0x01];
+/// multicodec の p256-pub (圧縮ポイント)
+const MULTICODEC_P256_PUB: [u8; 2] = [0x80, 0x24];
+/// multicodec の p384-pub (圧縮ポイント)
+const MULTICODEC_P384_PUB: [u8; 2] = [0x81, 0x24];
+/// multicodec の p521-pub (圧縮ポイント)
+const MULTICODEC_P521_PUB: [u8; 2] = [0x82, 0x24];
+
+/// DID ドキュメント・発行者メタデータを取得する HTTP クライアント
+pub trait HttpFetcher: Send + Sync {
+    /// `url` を GET してボディを返す
+    fn get(&self, url: &str) -> Result<String>;
+}
+
+impl<F> HttpFetcher for F
+where
+    F: Fn(&str) -> Result<String> + Send + Sync,
+{
+    fn get(&self, url: &str) -> Result<String> {
+        self(url)
+    }
+}
+
+/// DID ドキュメント
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DidDocument {
+    pub id: String,
+    #[serde(default)]
+    pub verification_method: Vec<VerificationMethod>,
+    /// VC の署名に使える verification method (id の文字列または埋め込み)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub assertion_method: Vec<Value>,
+    /// 認証 (KB-JWT の署名など) に使える verification method
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub authentication: Vec<Value>,
+}
+
+/// DID ドキュメントの verification method
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VerificationMethod {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub method_type: String,
+    pub controller: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public_key_jwk: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public_key_multibase: Option<String>,
+}
+
+impl VerificationMethod {
+    /// 公開鍵 (JWK)。`publicKeyMultibase` は multicodec の公開鍵として読む
+    pub fn jwk(&self) -> Result<Value> {
+        match (&self.public_key_jwk, &self.public_key_multibase) {
+            (Some(jwk), _) => Ok(jwk.clone()),
+            (None, Some(multibase)) => multibase_to_jwk(multibase),
+            (None, None) => bail!("verification method {} has no public key", self.id),
+        }
+    }
+}
+
+/// verification method の用途
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Relationship {
+    /// VC の署名 (`assertionMethod`)
+    AssertionMethod,
+    /// Holder の鍵 (`authentication`)
+    Authentication,
+}
+
+impl DidDocument {
+    /// 用途に登録された verification method (埋め込みのものも含む)
+    pub fn methods_for(&self, relationship: Relationship) -> Vec<VerificationMethod> {
+        let references = match relationship {
+            Relationship::AssertionMethod => &self.assertion_method,
+            Relationship::Authentication => &self.authentication,
+        };
+        references
+            .iter()
+            .filter_map(|reference| match reference {
+                Value::String(id) => {
+                    let id = self.absolute_id(id);
+                    self.verification_method
+                        .iter()
+                        .find(|method| self.absolute_id(&method.id) == id)
+                        .cloned()
+                }
+                embedded => serde_json::from_value(embedded.clone()).ok(),
+            })
+            .map(|mut method| {
+                method.id = self.absolute_id(&method.id);
+                method
+            })
+            .collect()
+    }
+
+    /// `#key-1` のような相対の id を DID URL にする
+    fn absolute_id(&self, id: &str) -> String {
+        if id.starts_with('#') {
+            format!("{}{id}", self.id)
+        } else {
+            id.to_string()
+        }
+    }
+}
+
+/// DID を解決する
+#[derive(Clone)]
+pub struct DidResolver {
+    fetcher: Arc<dyn HttpFetcher>,
+}
+
+impl Default for DidResolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DidResolver {
+    /// `did:web` は ureq で取得する
+    pub fn new() -> Self {
+        Self::with_fetcher(Arc::new(http::get))
+    }
+
+    /// `did:web` の DID ドキュメントを取得する HTTP クライアントを指定する
+    pub fn with_fetcher(fetcher: Arc<dyn HttpFetcher>) -> Self {
+        Self { fetcher }
+    }
+
+    /// DID (DID URL の場合はフラグメントなどを除いた DID) の DID ドキュメント
+    pub fn resolve(&self, did: &str) -> Result<DidDocument> {
+        let did = did_of(did);
+        let document = match did.split(':').nth(1) {
+            Some("key") => did_key_document(did)?,
+            Some("jwk") => did_jwk_document(did)?,
+            Some("web") => {
+                let url = did_web_url(did)?;
+                let body = self.fetcher.get(&url)?;
+                let document: DidDocument = serde_json::from_str(&body)
+                    .map_err(|e| anyhow!("invalid did document from {url}: {e}"))?;
+                if document.id != did {
+                    bail!("did document id {} is not {did}", document.id);
+                }
+                document
+            }
+            _ => bail!("unsupported did method: {did}"),
+        };
+        Ok(document)
+    }
+
+    /// DID URL (`did:...#key-1`) の verification method
+    /// フラグメントがない場合は、用途に登録された最初の verification method
+    pub fn resolve_verification_method(
+        &self,
+        did_url: &str,
+        relationship: Relationship,
+    ) -> Result<VerificationMethod> {
+        let document = self.resolve(did_url)?;
+        let methods = document.methods_for(relationship);
+        let method = if did_url.contains('#') {
+            methods.into_iter().find(|method| method.id == did_url)
+        } else {
+            methods.into_iter().next()
+        };
+        method.ok_or_else(|| anyhow!("no verification method {did_url} for {relationship:?}"))
+    }
+}
+
+/// DID URL から DID だけを取り出す (パス・クエリ・フラグメントを除く)
+pub fn did_of(did_url: &str) -> &str {
+    let end = did_url.find(['#', '?', '/']).unwrap_or(did_url.len());
+    &did_url[..end]
+}
+
+/// 公開鍵 (JWK) の `did:jwk` (秘密鍵の JWK の場合は公開鍵のメンバーだけにする)
+pub fn did_jwk(jwk: &Value) -> Result<String> {
+    let public = jwk::public_jwk(jwk)?;
+    Ok(format!(
+        "did:jwk:{}",
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(&public)?)
+    ))
+}
+
+/// 公開鍵 (JWK) の `did:key` (Ed25519・P-256・P-384・P-521)
+pub fn did_key(jwk: &Value) -> Result<String> {
+    let field = |name: &str| {
+        jwk.get(name)
+            .and_then(Value::as_str)
+            .ok_or_else(|| anyhow!("jwk has no {name}"))
+    };
+    let coordinate = |name: &str| -> Result<Vec<u8>> { Ok(URL_SAFE_NO_PAD.decode(field(name)?)?) };
+    let uncompressed =
+        || -> Result<Vec<u8>> { Ok([vec![0x04], coordinate("x")?, coordinate("y")?].concat()) };
+    let (codec, key) = match (field("kty")?, field("crv")?) {
+        ("OKP", "Ed25519") => (MULTICODEC_ED25519_PUB, coordinate("x")?),
+        ("EC", "P-256") => (
+            MULTICODEC_P256_PUB,
+            p256::PublicKey::from_sec1_bytes(&uncompressed()?)?
+                .to_encoded_point(true)
+                .as_bytes()
+                .to_vec(),
+        ),
+        ("EC", "P-384") => (
+            MULTICODEC_P384_PUB,
+            p384::PublicKey::from_sec1_bytes(&uncompressed()?)?
+                .to_encoded_point(true)
+                .as_bytes()
+                .to_vec(),
+        ),
+        ("EC", "P-521") => (
+            MULTICODEC_P521_PUB,
+            p521::PublicKey::from_sec1_bytes(&uncompressed()?)?
+                .to_encoded_point(true)
+                .as_bytes()
+                .to_vec(),
+        ),
+        (kty, crv) => bail!("did:key does not support kty={kty} crv={crv}"),
+    };
+    let multibase = format!(
+        "z{}",
+        bs58::encode([&codec[..], &key].concat()).into_string()
+    );
+    Ok(format!("did:key:{multibase}"))
+}
+
+/// 公開鍵 (JWK) の `did:key` または `did:jwk` の verification method の DID URL
+/// (`iss` を DID にした場合の `kid` や、`cnf.kid` に使う)
+pub fn did_url_from_jwk(method: &str, jwk: &Value) -> Result<String> {
+    match method {
+        "key" => {
+            let did = did_key(jwk)?;
+            let multibase = did.trim_start_matches("did:key:").to_string();
+            Ok(format!("{did}#{multibase}"))
+        }
+        "jwk" => Ok(format!("{}#0", did_jwk(jwk)?)),
+        _ => bail!("unsupported did method: {method} (expected key or jwk)"),
+    }
+}
+
+/// 鍵 1 つの DID ドキュメント (`did:key`・`did:jwk`)
+fn single_key_document(did: &str, fragment: &str, method: Value) -> Result<DidDocument> {
+    let id = format!("{did}#{fragment}");
+    let mut method = method;
+    if let Some(obj) = method.as_object_mut() {
+        obj.insert("id".to_string(), Value::String(id.clone()));
+        obj.insert("controller".to_string(), Value::String(did.to_string()));
+    }
+    Ok(DidDocument {
+        id: did.to_string(),
+        verification_method: vec![serde_json::from_value(method)?],
+        assertion_method: vec![Value::String(id.clone())],
+        authentication: vec![Value::String(id)],
+    })
+}
+
+fn did_key_document(did: &str) -> Result<DidDocument> {
+    let multibase = did
+        .strip_prefix("did:key:")
+        .ok_or_else(|| anyhow!("invalid did:key: {did}"))?;
+    // 鍵の形式を確認しておく
+    multibase_to_jwk(multibase)?;
+    single_key_document(
+        did,
+        multibase,
+        json!({ "type": "Multikey", "publicKeyMultibase": multibase }),
+    )
+}
+
+fn did_jwk_document(did: &str) -> Result<DidDocument> {
+    let encoded = did
+        .strip_prefix("did:jwk:")
+        .ok_or_else(|| anyhow!("invalid did:jwk: {did}"))?;
+    let jwk: Value = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(encoded)?)
+        .map_err(|e| anyhow!("invalid did:jwk: {e}"))?;
+    if jwk.get("d").is_some() {
+        bail!("did:jwk must not contain a private key");
+    }
+    single_key_document(
+        did,
+        "0",
+        json!({ "type": "JsonWebKey2020", "publicKeyJwk": jwk }),
+    )
+}
+
+/// `did:web` の DID ドキュメントの URL
+/// (`did:web:example.com` -> `https://example.com/.well-known/did.json`、
+/// `did:web:example.com%3A8443:users:alice` -> `https://example.com:8443/users/alice/did.json`)
+pub fn did_web_url(did: &str) -> Result<String> {
+    let id = did
+        .strip_prefix("did:web:")
+        .ok_or_else(|| anyhow!("invalid did:web: {did}"))?;
+    let mut segments = id.split(':');
+    let host = segments
+        .next()
+        .filter(|host| !host.is_empty())
+        .ok_or_else(|| anyhow!("did:web has no host: {did}"))?
+        .replace("%3A", ":")
+        .replace("%3a", ":");
+    let path: Vec<&str> = segments.collect();
+    if path.iter().any(|segment| segment.is_empty()) {
+        bail!("invalid did:web path: {did}");
+    }
+    Ok(if path.is_empty() {
+        format!("https://{host}/.well-known/did.json")
+    } else {
+        format!("https://{host}/{}/did.json", path.join("/"))
+    })
+}
+
+/// multibase (base58btc) の multicodec の公開鍵を JWK にする
+fn multibase_to_jwk(multibase: &str) -> Result<Value> {
+    let encoded = multibase
+        .strip_prefix('z')
+        .ok_or_else(|| anyhow!("unsupported multibase encoding: {multibase}"))?;
+    let bytes = bs58::decode(encoded).into_vec()?;
+    if bytes.len() < 2 {
+        bail!("invalid multicodec key: {multibase}");
+    }
+    let (codec, key) = bytes.split_at(2);
+    match [codec[0], codec[1]] {
+        MULTICODEC_ED25519_PUB if key.len() == 32 => Ok(json!({
+            "kty": "OKP",
+            "crv": "Ed25519",
+            "x": URL_SAFE_NO_PAD.encode(key),
+        })),
+        MULTICODEC_P256_PUB => ec_point_to_jwk(
+            "P-256",
+            p256::PublicKey::from_sec1_bytes(key)?
+                .to_encoded_point(false)
+                .as_bytes(),
+        ),
+        MULTICODEC_P384_PUB => ec_point_to_jwk(
+            "P-384",
+            p384::PublicKey::from_sec1_bytes(key)?
+                .to_encoded_point(false)
+                .as_bytes(),
+        ),
+        MULTICODEC_P521_PUB => ec_point_to_jwk(
+            "P-521",
+            p521::PublicKey::from_sec1_bytes(key)?
+                .to_encoded_point(false)
+                .as_bytes(),
+        ),
+        _ => bail!("unsupported multicodec key: {multibase}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    // did:key の仕様のテストベクタ
+    const DID_KEY_ED25519: &str = "did:key:z6MkiTBz1ymuepAQ4HEHYSF1H8quG5GLVVQR3djdX3mDooWp";
+    const DID_KEY_P256: &str = "did:key:zDnaerDaTF5BXEavCrfRZEk316dpbLsfPDZ3WJ5hRTPFU2169";
+
+    /// 取得した URL を記録し、`document` を返す HttpFetcher
+    fn stub_resolver(document: Value) -> (DidResolver, Arc<Mutex<Vec<String>>>) {
+        let urls = Arc::new(Mutex::new(Vec::new()));
+        let recorded = urls.clone();
+        let fetcher = move |url: &str| -> Result<String> {
+            recorded.lock().unwrap().push(url.to_string());
+            Ok(document.to_string())
+        };
+        (DidResolver::with_fetcher(Arc::new(fetcher)), urls)
+    }
+
+    #[test]
+    fn did_key_ed25519() {
+        let method = DidResolver::new()
+            .resolve_verification_method(DID_KEY_ED25519, Relationship::AssertionMethod)
+            .unwrap();
+        assert_eq!(
+            method.id,
+            format!("{DID_KEY_ED25519}#z6MkiTBz1ymuepAQ4HEHYSF1H8quG5GLVVQR3djdX3mDooWp")
+        );
+        let jwk = method.jwk().unwrap();
+        assert_eq!(
+            jwk,
+            json!({
+                "kty": "OKP",
+                "crv": "Ed25519",
+                "x": "O2onvM62pC1io6jQKm8Nc2UyFXcd4kOmOsBIoYtZ2ik",
+            })
+        );
+        assert_eq!(did_key(&jwk).unwrap(), DID_KEY_ED25519);
+    }
+
+    #[test]
+    fn did_key_p256() {
+        let method = DidResolver::new()
+            .resolve_verification_method(DID_KEY_P256, Relationship::Authentication)
+            .unwrap();
+        let jwk = method.jwk().unwrap();
+        assert_eq!(jwk["kty"], "EC");
+        assert_eq!(jwk["crv"], "P-256");
+        assert_eq!(jwk["x"], "fyNYMN0976ci7xqiSdag3buk-ZCwgXU4kz9XNkBlNUI");
+        assert_eq!(jwk["y"], "hW2ojTNfH7Jbi8--CJUo3OCbH3y5n91g-IMA9MLMbTU");
+        assert_eq!(did_key(&jwk).unwrap(), DID_KEY_P256);
+    }
+
+    #[test]
+    fn did_key_rejects_unknown_multicodec() {
+        let multibase = format!("z{}", bs58::encode([0x12, 0x00, 0x01]).into_string());
+        assert!(DidResolver::new()
+            .resolve(&format!("did:key:{multibase}"))
+            .is_err());
+    }
+
+    #[test]
+    fn did_jwk_keeps_only_public_members() {
+        let jwk = json!({
+            "kty": "OKP",
+            "crv": "Ed25519",
+            "x": "O2onvM62pC1io6jQKm8Nc2UyFXcd4kOmOsBIoYtZ2ik",
+            "d": "c2VjcmV0",
+        });
+        let did = did_jwk(&jwk).unwrap();
+        let method = DidResolver::new()
+            .resolve_verification_method(&format!("{did}#0"), Relationship::AssertionMethod)
+            .unwrap();
+        let resolved = method.jwk().unwrap();
+        assert_eq!(resolved.get("d"), None);
+        assert_eq!(resolved["x"], jwk["x"]);
+    }
+
+    #[test]
+    fn did_web_url_mapping() {
+        assert_eq!(
+            did_web_url("did:web:example.com").unwrap(),
+            "https://example.com/.well-known/did.json"
+        );
+        assert_eq!(
+            did_web_url("did:web:example.com:users:alice").unwrap(),
+            "https://example.com/users/alice/did.json"
+        );
+        assert_eq!(
+            did_web_url("did:web:localhost%3A8443:issuer").unwrap(),
+            "https://localhost:8443/issuer/did.json"
+        );
+        assert!(did_web_url("did:web:").is_err());
+        assert!(did_web_url("did:web:example.com::alice").is_err());
+    }
+
+    #[test]
+    fn did_web_resolves_through_fetcher() {
+        let did = "did:web:localhost%3A8443:issuer";
+        let (resolver, urls) = stub_resolver(json!({
+            "id": did,
+            "verificationMethod": [{
+                "id": "#key-1",
+                "type": "Multikey",
+                "controller": did,
+                "publicKeyMultibase": DID_KEY_P256.trim_start_matches("did:key:"),
+            }],
+            "assertionMethod": ["#key-1"],
+        }));
+        let method = resolver
+            .resolve_verification_method(&format!("{did}#key-1"), Relationship::AssertionMethod)
+            .unwrap();
+        assert_eq!(method.id, format!("{did}#key-1"));
+        assert_eq!(method.jwk().unwrap()["crv"], "P-256");
+        assert_eq!(
+            *urls.lock().unwrap(),
+            ["https://localhost:8443/issuer/did.json"]
+        );
+        // authentication に登録していない鍵は使えない
+        assert!(resolver
+            .resolve_verification_method(&format!("{did}#key-1"), Relationship::Authentication)
+            .is_err());
+    }
+
+    #[test]
+    fn did_web_rejects_document_for_other_did() {
+        let (resolver, _urls) = stub_resolver(json!({ "id": "did:web:attacker.example" }));
+        assert!(resolver.resolve("did:web:example.com").is_err());
+    }
+}
@@ -0,0 +1,341 @@
+//! 選択的開示のポリシーと、提示する disclosure の選択
+//!
+//! ポリシーは隠すクレームの JSON Pointer の一覧で、`*` は配列のすべての要素
+//! (オブジェクトの場合はすべてのメンバー) を表す。
+//!
+//! - `/route_networks`: 配列全体を 1 つの disclosure にする
+//! - `/route_networks/*`: 配列の要素ごとに disclosure にする
+//! - `/address/street`: ネストしたオブジェクトのメンバーを disclosure にする
+//!
+//! 親と子の両方を指定した場合は、子を隠したうえで親も隠す (親を開示しても子は開示されない)。
+
+use anyhow::{anyhow, bail, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use sd_jwt_payload::{Disclosure, Hasher, Sha256Hasher};
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// 配列の要素の disclosure のダイジェストを入れるキー
+const ARRAY_DIGEST_KEY: &str = "...";
+/// オブジェクトの disclosure のダイジェストを入れるキー
+const DIGESTS_KEY: &str = "_sd";
+
+/// ポリシーの JSON Pointer の `*` を `claims` に合わせて展開する
+/// 子を先に隠す必要があるため、深いパスから順に並べて返す
+pub fn resolve_policy(claims: &Value, policy: &[String]) -> Result<Vec<String>> {
+    let mut resolved = Vec::new();
+    for pointer in policy {
+        let segments = pointer
+            .strip_prefix('/')
+            .ok_or_else(|| anyhow!("disclosure policy must be a JSON pointer: {pointer}"))?;
+        let segments: Vec<&str> = segments.split('/').collect();
+        expand(claims, "", &segments, pointer, &mut resolved)?;
+    }
+    resolved.sort_by_key(|path: &String| std::cmp::Reverse(path.matches('/').count()));
+    Ok(resolved)
+}
+
+/// `segments` をたどって、見つかったクレームの JSON Pointer を `resolved` に加える
+fn expand(
+    value: &Value,
+    prefix: &str,
+    segments: &[&str],
+    pointer: &str,
+    resolved: &mut Vec<String>,
+) -> Result<()> {
+    let Some((segment, rest)) = segments.split_first() else {
+        if !resolved.iter().any(|path| path == prefix) {
+            resolved.push(prefix.to_string());
+        }
+        return Ok(());
+    };
+    if *segment == "*" {
+        match value {
+            Value::Array(items) => {
+                for (i, item) in items.iter().enumerate() {
+                    expand(item, &format!("{prefix}/{i}"), rest, pointer, resolved)?;
+                }
+            }
+            Value::Object(members) => {
+                for (name, member) in members {
+                    let path = format!("{prefix}/{}", escape(name));
+                    expand(member, &path, rest, pointer, resolved)?;
+                }
+            }
+            _ => bail!("{pointer}: {prefix} is not an array or object"),
+        }
+        return Ok(());
+    }
+    let path = format!("{prefix}/{segment}");
+    let child = value
+        .pointer(&format!("/{segment}"))
+        .ok_or_else(|| anyhow!("{pointer}: {path} does not exist"))?;
+    expand(child, &path, rest, pointer, resolved)
+}
+
+/// SD-JWT の disclosure と、それが隠しているクレームの JSON Pointer の組
+/// 配列の要素のインデックスは、未開示の要素も含めた発行時の位置
+pub fn disclosure_paths(jwt: &str, disclosures: &[String]) -> Result<Vec<(String, String)>> {
+    let payload = jwt
+        .split('.')
+        .nth(1)
+        .ok_or_else(|| anyhow!("jwt has no payload"))?;
+    let payload: Value = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload)?)?;
+
+    let hasher = Sha256Hasher::new();
+    let by_digest = disclosures
+        .iter()
+        .map(|encoded| {
+            let disclosure = Disclosure::parse(encoded.clone())
+                .map_err(|e| anyhow!("invalid disclosure: {e}"))?;
+            Ok((hasher.encoded_digest(encoded), disclosure))
+        })
+        .collect::<Result<HashMap<_, _>>>()?;
+
+    let mut paths = Vec::new();
+    walk(&payload, "", &by_digest, &mut paths);
+    Ok(paths)
+}
+
+/// `value` の中のダイジェストを disclosure に対応付け、開示された値の中もたどる
+fn walk(
+    value: &Value,
+    path: &str,
+    by_digest: &HashMap<String, Disclosure>,
+    paths: &mut Vec<(String, String)>,
+) {
+    match value {
+        Value::Object(members) => {
+            for (name, member) in members {
+                if name != DIGESTS_KEY {
+                    walk(
+                        member,
+                        &format!("{path}/{}", escape(name)),
+                        by_digest,
+                        paths,
+                    );
+                    continue;
+                }
+                for digest in member.as_array().into_iter().flatten() {
+                    let Some(disclosure) = digest.as_str().and_then(|d| by_digest.get(d)) else {
+                        continue;
+                    };
+                    let Some(claim_name) = &disclosure.claim_name else {
+                        continue;
+                    };
+                    let path = format!("{path}/{}", escape(claim_name));
+                    paths.push((path.clone(), disclosure.disclosure.clone()));
+                    walk(&disclosure.claim_value, &path, by_digest, paths);
+                }
+            }
+        }
+        Value::Array(items) => {
+            for (i, item) in items.iter().enumerate() {
+                let path = format!("{path}/{i}");
+                match array_element_disclosure(item, by_digest) {
+                    Some(disclosure) => {
+                        paths.push((path.clone(), disclosure.disclosure.clone()));
+                        walk(&disclosure.claim_value, &path, by_digest, paths);
+                    }
+                    None => walk(item, &path, by_digest, paths),
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// 配列の要素が `{"...": <digest>}` の場合、その disclosure
+fn array_element_disclosure<'a>(
+    item: &Value,
+    by_digest: &'a HashMap<String, Disclosure>,
+) -> Option<&'a Disclosure> {
+    let item = item.as_object().filter(|item| item.len() == 1)?;
+    let disclosure = by_digest.get(item.get(ARRAY_DIGEST_KEY)?.as_str()?)?;
+    disclosure.claim_name.is_none().then_some(disclosure)
+}
+
+/// `selectors` で選んだクレームを開示する disclosure
+///
+/// セレクタは JSON Pointer (`/route_networks/1`) またはクレーム名 (`route_networks`、
+/// ネストしたクレームも含む)。選んだクレームの中の disclosure と、
+/// そこに至るまでの親の disclosure も含める。
+pub fn select(paths: &[(String, String)], selectors: &[&str]) -> Vec<String> {
+    let mut targets = Vec::new();
+    for selector in selectors {
+        if selector.starts_with('/') {
+            targets.push(selector.to_string());
+            continue;
+        }
+        let suffix = format!("/{}", escape(selector));
+        targets.extend(
+            paths
+                .iter()
+                .filter(|(path, _)| path.ends_with(&suffix))
+                .map(|(path, _)| path.clone()),
+        );
+        targets.push(suffix);
+    }
+    paths
+        .iter()
+        .filter(|(path, _)| {
+            targets
+                .iter()
+                .any(|target| is_within(path, target) || is_within(target, path))
+        })
+        .map(|(_, disclosure)| disclosure.clone())
+        .collect()
+}
+
+/// `path` が `ancestor` 自身またはその中のクレームか
+fn is_within(path: &str, ancestor: &str) -> bool {
+    path.strip_prefix(ancestor)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// JSON Pointer のトークンのエスケープ (RFC 6901)
+fn escape(token: &str) -> String {
+    token.replace('~', "~0").replace('/', "~1")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sd_jwt_payload::SdObjectEncoder;
+    use serde_json::json;
+
+    fn claims() -> Value {
+        json!({
+            "address": { "locality": "Toyoake", "street": "Dengakugakubo 1-98" },
+            "ip_addresses": ["10.0.0.1", "10.0.0.2"],
+            "name": "takehi",
+        })
+    }
+
+    fn policy(pointers: &[&str]) -> Vec<String> {
+        pointers.iter().map(|pointer| pointer.to_string()).collect()
+    }
+
+    /// `claims` の `pointers` を隠した JWT (署名は検証しないので空) と、JSON Pointer ごとの disclosure
+    fn conceal(claims: Value, pointers: &[String]) -> (String, HashMap<String, String>) {
+        let mut encoder: SdObjectEncoder = claims.try_into().unwrap();
+        let disclosures = pointers
+            .iter()
+            .map(|path| {
+                let disclosure = encoder.conceal(path, None).unwrap();
+                (path.clone(), disclosure.to_string())
+            })
+            .collect();
+        let payload =
+            URL_SAFE_NO_PAD.encode(serde_json::to_vec(encoder.object().unwrap()).unwrap());
+        (format!("e30.{payload}."), disclosures)
+    }
+
+    #[test]
+    fn resolve_policy_expands_wildcards_deepest_first() {
+        let resolved = resolve_policy(
+            &claims(),
+            &policy(&[
+                "/ip_addresses",
+                "/ip_addresses/*",
+                "/address/*",
+                "/name",
+                "/name",
+            ]),
+        )
+        .unwrap();
+        assert_eq!(
+            resolved,
+            [
+                "/ip_addresses/0",
+                "/ip_addresses/1",
+                "/address/locality",
+                "/address/street",
+                "/ip_addresses",
+                "/name",
+            ]
+        );
+    }
+
+    #[test]
+    fn resolve_policy_rejects_missing_claims() {
+        assert!(resolve_policy(&claims(), &policy(&["/missing"])).is_err());
+        assert!(resolve_policy(&claims(), &policy(&["/name/*"])).is_err());
+        assert!(resolve_policy(&claims(), &policy(&["name"])).is_err());
+    }
+
+    #[test]
+    fn disclosure_paths_of_nested_arrays() {
+        let pointers = resolve_policy(
+            &claims(),
+            &policy(&["/ip_addresses/*", "/ip_addresses", "/name"]),
+        )
+        .unwrap();
+        let (jwt, by_path) = conceal(claims(), &pointers);
+        let disclosures: Vec<String> = by_path.values().cloned().collect();
+
+        let mut paths = disclosure_paths(&jwt, &disclosures).unwrap();
+        paths.sort();
+        let mut expected: Vec<(String, String)> = by_path.into_iter().collect();
+        expected.sort();
+        assert_eq!(paths, expected);
+        assert_eq!(
+            paths
+                .iter()
+                .map(|(path, _)| path.as_str())
+                .collect::<Vec<_>>(),
+            [
+                "/ip_addresses",
+                "/ip_addresses/0",
+                "/ip_addresses/1",
+                "/name"
+            ]
+        );
+    }
+
+    #[test]
+    fn select_by_pointer_and_name() {
+        let pointers = resolve_policy(
+            &claims(),
+            &policy(&["/ip_addresses/*", "/ip_addresses", "/address/*", "/name"]),
+        )
+        .unwrap();
+        let (jwt, by_path) = conceal(claims(), &pointers);
+        let disclosures: Vec<String> = by_path.values().cloned().collect();
+        let paths = disclosure_paths(&jwt, &disclosures).unwrap();
+
+        let selected = |selectors: &[&str]| {
+            let mut selected = select(&paths, selectors);
+            selected.sort();
+            selected
+        };
+        let expected = |pointers: &[&str]| {
+            let mut expected: Vec<String> = pointers
+                .iter()
+                .map(|pointer| by_path[*pointer].clone())
+                .collect();
+            expected.sort();
+            expected
+        };
+
+        // 配列の要素を選ぶと、配列自体の disclosure も必要
+        assert_eq!(
+            selected(&["/ip_addresses/1"]),
+            expected(&["/ip_addresses", "/ip_addresses/1"])
+        );
+        // 配列を選ぶと、要素の disclosure もすべて含める
+        assert_eq!(
+            selected(&["/ip_addresses"]),
+            expected(&["/ip_addresses", "/ip_addresses/0", "/ip_addresses/1"])
+        );
+        // クレーム名はネストしたクレームも選ぶ
+        assert_eq!(selected(&["ip_addresses"]), selected(&["/ip_addresses"]));
+        assert_eq!(selected(&["street"]), expected(&["/address/street"]));
+        assert_eq!(
+            selected(&["name", "/address/locality"]),
+            expected(&["/name", "/address/locality"])
+        );
+        assert!(selected(&[]).is_empty());
+        assert!(selected(&["/missing", "missing"]).is_empty());
+    }
+}
@@ -0,0 +1,295 @@
+//! 検証エラーと発行エラー
+//!
+//! ゲートウェイなどで HTTP レスポンスやメトリクスに振り分けられるよう、
+//! 失敗の理由ごとにバリアントを分けている。
+
+use crate::alg::SigningAlgorithm;
+use std::fmt;
+
+/// 検証対象の JWT の種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JwtKind {
+    /// 発行者が署名した SD-JWT VC の JWT 部分
+    Credential,
+    /// Holder が署名した KB-JWT
+    KeyBinding,
+    /// 発行者が署名したステータスリストの JWT
+    StatusList,
+    /// Holder が署名した VP-JWT
+    Presentation,
+    /// 認証サーバが署名した JWT Bearer Grant のアサーション (OID4VCI)
+    Assertion,
+    /// 発行サーバが署名したアクセストークン (OID4VCI)
+    AccessToken,
+    /// Holder が署名した proof JWT (OID4VCI)
+    Proof,
+}
+
+impl fmt::Display for JwtKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Credential => "vc",
+            Self::KeyBinding => "kb-jwt",
+            Self::StatusList => "status-list",
+            Self::Presentation => "vp-jwt",
+            Self::Assertion => "assertion",
+            Self::AccessToken => "access token",
+            Self::Proof => "proof",
+        })
+    }
+}
+
+/// VC / VP の検証エラー
+#[derive(Debug, thiserror::Error)]
+pub enum VerificationError {
+    /// SD-JWT として解析できない
+    #[error("malformed sd-jwt: {0}")]
+    MalformedSdJwt(String),
+    /// JWT のヘッダやペイロードが解析できない
+    #[error("malformed {token}: {reason}")]
+    MalformedJwt { token: JwtKind, reason: String },
+    /// JWS ヘッダの `alg` が未対応
+    #[error("unsupported {token} alg: {alg}")]
+    UnsupportedAlgorithm { token: JwtKind, alg: String },
+    /// JWS ヘッダの `alg` が検証に使う鍵の種類と合わない
+    #[error("{token} alg {alg} does not match {key_alg} key")]
+    AlgorithmMismatch {
+        token: JwtKind,
+        alg: SigningAlgorithm,
+        key_alg: SigningAlgorithm,
+    },
+    /// ヘッダの `typ` が期待する値ではない
+    #[error("{token} typ is not {expected}: {actual:?}")]
+    InvalidTyp {
+        token: JwtKind,
+        expected: &'static str,
+        actual: Option<String>,
+    },
+    /// 発行者の公開鍵が読み込めない
+    #[error("invalid issuer key: {0}")]
+    InvalidIssuerKey(String),
+    /// 発行者の署名が正しくない
+    #[error("bad issuer signature")]
+    BadIssuerSignature,
+    /// `exp` がない
+    #[error("{0} has no exp")]
+    MissingExp(JwtKind),
+    /// 有効期限切れ
+    #[error("{0} is expired")]
+    Expired(JwtKind),
+    /// `aud` に期待する値が含まれていない
+    #[error("{token} aud does not contain {expected}")]
+    AudienceMismatch { token: JwtKind, expected: String },
+    /// VC に `cnf.jwk` (または解決できる `cnf.kid`) がない
+    #[error("vc has no cnf.jwk")]
+    MissingCnf,
+    /// `cnf.jwk` が検証に使えない
+    #[error("invalid holder key: {0}")]
+    InvalidHolderKey(String),
+    /// VP に KB-JWT がない
+    #[error("kb-jwt is missing")]
+    KbJwtMissing,
+    /// KB-JWT の署名が正しくない
+    #[error("bad kb-jwt signature")]
+    BadKbJwtSignature,
+    /// KB-JWT の `sd_hash` が SD-JWT と一致しない
+    #[error("sd_hash mismatch")]
+    SdHashMismatch,
+    /// KB-JWT の `nonce` が期待する値ではない
+    #[error("nonce mismatch")]
+    NonceMismatch,
+    /// nonce が未発行・使用済み・期限切れ
+    #[error("nonce is unknown, already used or expired")]
+    NonceReplayed,
+    /// `iat` がない
+    #[error("{0} has no iat")]
+    MissingIat(JwtKind),
+    /// `iat` が許容範囲外 (古すぎる、または未来)
+    #[error("{0} iat is out of the allowed window")]
+    IatOutOfWindow(JwtKind),
+    /// `state` に対応する Authorization Request がない (期限切れ・検証済みを含む)
+    #[error("unknown or expired state")]
+    UnknownState,
+    /// 要求した `vct` のクレデンシャルではない
+    #[error("vct is not {expected}: {actual:?}")]
+    VctMismatch {
+        expected: String,
+        actual: Option<String>,
+    },
+    /// 要求したクレームが開示されていない
+    #[error("requested claim {0} is not disclosed")]
+    MissingRequestedClaim(String),
+    /// disclosure が不正で、クレームを復元できない
+    #[error("invalid disclosure: {0}")]
+    InvalidDisclosure(String),
+    /// `iss` がトラストレジストリに登録されていない
+    #[error("untrusted issuer: {0}")]
+    UntrustedIssuer(String),
+    /// JWS ヘッダの `kid` の鍵が発行者に登録されていない
+    #[error("unknown key for {iss}: kid={kid:?}")]
+    UnknownIssuerKey { iss: String, kid: Option<String> },
+    /// 発行者に許可されていない署名アルゴリズム
+    #[error("{alg} is not allowed for {iss}")]
+    AlgorithmNotAllowed { iss: String, alg: SigningAlgorithm },
+    /// 発行者に許可されていない `vct`
+    #[error("vct {vct:?} is not allowed for {iss}")]
+    VctNotAllowed { iss: String, vct: Option<String> },
+    /// ステータスリストで失効している
+    #[error("credential is revoked")]
+    Revoked,
+    /// ステータスリストで一時停止されている
+    #[error("credential is suspended")]
+    Suspended,
+    /// ステータスリストの値が VALID 以外 (アプリケーション固有の値)
+    #[error("credential status is {0:#04x}")]
+    UnknownStatus(u8),
+    /// ステータスリストが取得できない
+    #[error("status list is unavailable: {0}")]
+    StatusListUnavailable(String),
+    /// ステータスリストや `status` クレームが不正
+    #[error("invalid status list: {0}")]
+    InvalidStatusList(String),
+    /// VP-JWT の署名が正しくない
+    #[error("bad vp-jwt signature")]
+    BadPresentationSignature,
+    /// VCDM の VC / VP として正しくない
+    #[error("invalid credential: {0}")]
+    InvalidCredential(String),
+    /// mdoc (DeviceResponse・MSO) として正しくない
+    #[error("invalid mdoc: {0}")]
+    InvalidMdoc(String),
+    /// mdoc の deviceSignature が正しくない
+    #[error("bad device signature")]
+    BadDeviceSignature,
+    /// 発行者メタデータ・JWKS が取得できない
+    #[error("issuer metadata is unavailable: {0}")]
+    IssuerMetadataUnavailable(String),
+    /// 発行者メタデータ・JWKS が不正
+    #[error("invalid issuer metadata: {0}")]
+    InvalidIssuerMetadata(String),
+    /// `vct` の Type Metadata が取得できない
+    #[error("type metadata is unavailable: {0}")]
+    TypeMetadataUnavailable(String),
+    /// Type Metadata が不正、または `vct#integrity` と一致しない
+    #[error("invalid type metadata: {0}")]
+    InvalidTypeMetadata(String),
+    /// 開示されたクレームが Type Metadata のスキーマに合わない
+    #[error("claims do not match the type metadata schema: {0}")]
+    SchemaMismatch(String),
+}
+
+impl VerificationError {
+    /// メトリクスのラベルなどに使う固定の識別子
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::MalformedSdJwt(_) => "malformed_sd_jwt",
+            Self::MalformedJwt { .. } => "malformed_jwt",
+            Self::UnsupportedAlgorithm { .. } => "unsupported_algorithm",
+            Self::AlgorithmMismatch { .. } => "algorithm_mismatch",
+            Self::InvalidTyp { .. } => "invalid_typ",
+            Self::InvalidIssuerKey(_) => "invalid_issuer_key",
+            Self::BadIssuerSignature => "bad_issuer_signature",
+            Self::MissingExp(_) => "missing_exp",
+            Self::Expired(_) => "expired",
+            Self::AudienceMismatch { .. } => "audience_mismatch",
+            Self::MissingCnf => "missing_cnf",
+            Self::InvalidHolderKey(_) => "invalid_holder_key",
+            Self::KbJwtMissing => "kb_jwt_missing",
+            Self::BadKbJwtSignature => "bad_kb_jwt_signature",
+            Self::SdHashMismatch => "sd_hash_mismatch",
+            Self::NonceMismatch => "nonce_mismatch",
+            Self::NonceReplayed => "nonce_replayed",
+            Self::MissingIat(_) => "missing_iat",
+            Self::IatOutOfWindow(_) => "iat_out_of_window",
+            Self::UnknownState => "unknown_state",
+            Self::VctMismatch { .. } => "vct_mismatch",
+            Self::MissingRequestedClaim(_) => "missing_requested_claim",
+            Self::InvalidDisclosure(_) => "invalid_disclosure",
+            Self::UntrustedIssuer(_) => "untrusted_issuer",
+            Self::UnknownIssuerKey { .. } => "unknown_issuer_key",
+            Self::AlgorithmNotAllowed { .. } => "algorithm_not_allowed",
+            Self::VctNotAllowed { .. } => "vct_not_allowed",
+            Self::Revoked => "revoked",
+            Self::Suspended => "suspended",
+            Self::UnknownStatus(_) => "unknown_status",
+            Self::StatusListUnavailable(_) => "status_list_unavailable",
+            Self::InvalidStatusList(_) => "invalid_status_list",
+            Self::BadPresentationSignature => "bad_presentation_signature",
+            Self::InvalidCredential(_) => "invalid_credential",
+            Self::InvalidMdoc(_) => "invalid_mdoc",
+            Self::BadDeviceSignature => "bad_device_signature",
+            Self::IssuerMetadataUnavailable(_) => "issuer_metadata_unavailable",
+            Self::InvalidIssuerMetadata(_) => "invalid_issuer_metadata",
+            Self::TypeMetadataUnavailable(_) => "type_metadata_unavailable",
+            Self::InvalidTypeMetadata(_) => "invalid_type_metadata",
+            Self::SchemaMismatch(_) => "schema_mismatch",
+        }
+    }
+}
+
+/// OID4VCI の発行エラー
+/// `code()` は OAuth 2.0 / OID4VCI のエラーレスポンスの `error` の値
+#[derive(Debug, thiserror::Error)]
+pub enum IssuanceError {
+    /// リクエストのパラメータが足りない、または不正
+    #[error("invalid request: {0}")]
+    InvalidRequest(String),
+    /// 未対応の `grant_type`
+    #[error("unsupported grant_type: {0}")]
+    UnsupportedGrantType(String),
+    /// `client_id` が登録されたものではない
+    #[error("invalid client_id: {0}")]
+    InvalidClient(String),
+    /// 認証のアサーションが検証できない
+    #[error("invalid grant: {0}")]
+    InvalidGrant(String),
+    /// `authorization_details` が発行できるクレデンシャルと合わない
+    #[error("invalid authorization_details: {0}")]
+    InvalidAuthorizationDetails(String),
+    /// アクセストークンが検証できない
+    #[error("invalid access token: {0}")]
+    InvalidToken(String),
+    /// 未対応の `format`
+    #[error("unsupported credential format: {0}")]
+    UnsupportedCredentialFormat(String),
+    /// 未対応の `vct`
+    #[error("unsupported credential type: {0}")]
+    UnsupportedCredentialType(String),
+    /// Holder の proof が不正
+    #[error("invalid proof: {0}")]
+    InvalidProof(String),
+    /// proof の `nonce` が発行した `c_nonce` ではない
+    #[error("invalid nonce: {0}")]
+    InvalidNonce(String),
+    /// 発行側の内部エラー
+    #[error("server error: {0}")]
+    ServerError(String),
+}
+
+impl IssuanceError {
+    /// エラーレスポンスの `error` の値
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::InvalidRequest(_) => "invalid_request",
+            Self::UnsupportedGrantType(_) => "unsupported_grant_type",
+            Self::InvalidClient(_) => "invalid_client",
+            Self::InvalidGrant(_) => "invalid_grant",
+            Self::InvalidAuthorizationDetails(_) => "invalid_authorization_details",
+            Self::InvalidToken(_) => "invalid_token",
+            Self::UnsupportedCredentialFormat(_) => "unsupported_credential_format",
+            Self::UnsupportedCredentialType(_) => "unsupported_credential_type",
+            Self::InvalidProof(_) => "invalid_proof",
+            Self::InvalidNonce(_) => "invalid_nonce",
+            Self::ServerError(_) => "server_error",
+        }
+    }
+
+    /// エラーレスポンスの HTTP ステータス
+    pub fn status(&self) -> u16 {
+        match self {
+            Self::InvalidClient(_) | Self::InvalidToken(_) => 401,
+            Self::ServerError(_) => 500,
+            _ => 400,
+        }
+    }
+}
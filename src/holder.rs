@@ -0,0 +1,206 @@
+//! SD-JWT VC から VP (SD-JWT + KB-JWT) を作成
+
+use crate::{
+    alg::SigningAlgorithm,
+    signer::{JwsSignerAdapter, PemSigner, Signer},
+};
+use anyhow::{anyhow, bail, Result};
+use josekit::{
+    jws::JwsHeader,
+    jwt::{self, JwtPayload},
+};
+use serde_json::Value;
+use std::sync::Arc;
+
+/// VC を保持して提示する Holder
+pub struct Holder {
+    /// Holder の秘密鍵で署名するバックエンド (KB-JWT の署名に使う)
+    signer: Arc<dyn Signer>,
+    /// 秘密鍵の種類から決まる署名アルゴリズム
+    alg: SigningAlgorithm,
+}
+
+impl Holder {
+    /// 署名アルゴリズムは秘密鍵の種類から判定する
+    pub fn new(private_key: Vec<u8>) -> Result<Self> {
+        Ok(Self::from_signer(Arc::new(PemSigner::new(&private_key)?)))
+    }
+
+    /// 署名のバックエンド (暗号化した鍵、PKCS#11 のトークンなど) で署名する Holder を作成
+    pub fn from_signer(signer: Arc<dyn Signer>) -> Self {
+        Self {
+            alg: signer.algorithm(),
+            signer,
+        }
+    }
+
+    /// 鍵の指定 (PEMファイルのパス、または PKCS#11 URI、`signer::open` を参照) から Holder を作成
+    pub fn from_key(key: &str) -> Result<Self> {
+        Ok(Self::from_signer(crate::signer::open(key)?))
+    }
+
+    /// PEMファイルから秘密鍵を読み込んで Holder を作成
+    pub fn from_pem_file(file_path: &str) -> Result<Self> {
+        let private_key = std::fs::read(file_path)
+            .map_err(|e| anyhow!("failed to read private key file {file_path}: {e:?}"))?;
+        Self::new(private_key)
+    }
+
+    /// 署名アルゴリズムを明示的に指定する (RSA 鍵で PS256 を使う場合など)
+    pub fn with_algorithm(mut self, alg: SigningAlgorithm) -> Result<Self> {
+        if !alg.is_compatible_with(self.alg) {
+            bail!("{alg} cannot be used with {} key", self.alg);
+        }
+        self.alg = alg;
+        Ok(self)
+    }
+
+    /// Holder の公開鍵 (JWK)
+    pub fn public_jwk(&self) -> Result<Value> {
+        self.signer.public_jwk()
+    }
+
+    /// OID4VCI の Credential Request に付ける proof JWT (`openid4vci-proof+jwt`) を作成
+    /// `c_nonce` は発行者のトークンエンドポイントなどから受け取った値
+    pub fn proof_jwt(
+        &self,
+        credential_issuer: &str,
+        c_nonce: &str,
+        client_id: Option<&str>,
+    ) -> Result<String> {
+        let mut header = JwsHeader::new();
+        header.set_token_type("openid4vci-proof+jwt");
+        header.set_algorithm(self.alg.name());
+        header.set_claim("jwk", Some(self.public_jwk()?))?;
+
+        let mut payload = JwtPayload::new();
+        if let Some(client_id) = client_id {
+            payload.set_issuer(client_id);
+        }
+        payload.set_audience(vec![credential_issuer]);
+        payload.set_claim("nonce", Some(Value::String(c_nonce.to_string())))?;
+        payload.set_issued_at(&std::time::SystemTime::now());
+
+        let signer = JwsSignerAdapter::new(self.signer.clone(), self.alg)?;
+        jwt::encode_with_signer(&payload, &header, &signer)
+            .map_err(|e| anyhow!("failed to encode with signer: {e:?}"))
+    }
+
+    /// disclosures の中から、`selectors` で選んだクレームを開示するものを選別する
+    /// セレクタはクレーム名、または配列の要素やネストしたクレームを指す JSON Pointer (`/route_networks/0`)
+    pub fn select_disclosures(
+        jwt: &str,
+        disclosures: &[String],
+        selectors: &[&str],
+    ) -> Result<Vec<String>> {
+        let paths = crate::disclosure::disclosure_paths(jwt, disclosures)?;
+        Ok(crate::disclosure::select(&paths, selectors))
+    }
+
+    /// VC から開示するクレームを選び、KB-JWT を付与した VP を作成
+    /// `selectors` はクレーム名または JSON Pointer (`select_disclosures` を参照)
+    /// `nonce` には Verifier から受け取った値をそのまま渡す
+    pub fn present(
+        &self,
+        vc: &str,
+        selectors: &[&str],
+        nonce: &str,
+        audience: &str,
+    ) -> Result<String> {
+        if nonce.is_empty() {
+            bail!("nonce is empty");
+        }
+        let sd_jwt = crate::sd_jwt::parse(vc)?;
+        let disclosures = Self::select_disclosures(&sd_jwt.jwt, &sd_jwt.disclosures, selectors)?;
+
+        let key_binding_jwt = self.key_binding_jwt(&sd_jwt.jwt, &disclosures, nonce, audience)?;
+        Ok(crate::sd_jwt::presentation(
+            &sd_jwt.jwt,
+            &disclosures,
+            Some(&key_binding_jwt),
+        ))
+    }
+
+    /// KB-JWT を作成
+    fn key_binding_jwt(
+        &self,
+        jwt: &str,
+        disclosures: &[String],
+        nonce: &str,
+        audience: &str,
+    ) -> Result<String> {
+        let mut header = JwsHeader::new();
+        header.set_token_type("kb+jwt");
+        header.set_algorithm(self.alg.name());
+
+        let mut payload = JwtPayload::new();
+        payload.set_claim("nonce", Some(Value::String(nonce.to_string())))?;
+        let sd_hash = crate::sd_jwt::sd_hash(jwt, disclosures);
+        payload.set_claim("sd_hash", Some(Value::String(sd_hash)))?;
+        payload.set_audience(vec![audience]);
+        let now = std::time::SystemTime::now();
+        let expires_at = now + std::time::Duration::from_secs(60);
+        payload.set_issued_at(&now);
+        payload.set_expires_at(&expires_at);
+
+        let signer = JwsSignerAdapter::new(self.signer.clone(), self.alg)?;
+        jwt::encode_with_signer(&payload, &header, &signer)
+            .map_err(|e| anyhow!("failed to encode with signer: {e:?}"))
+    }
+
+    /// VC-JWT (`vc+jwt`) を入れた VP-JWT (`vp+jwt`) を作成
+    /// `holder` は VP の `holder` (Holder の DID など、None の場合は設定しない)
+    pub fn present_vc_jwt(
+        &self,
+        vc_jwts: &[&str],
+        holder: Option<&str>,
+        nonce: &str,
+        audience: &str,
+    ) -> Result<String> {
+        if nonce.is_empty() {
+            bail!("nonce is empty");
+        }
+        let mut header = JwsHeader::new();
+        header.set_token_type(crate::vcdm::VP_JWT_TYP);
+        header.set_algorithm(self.alg.name());
+
+        let mut payload = JwtPayload::from_map(crate::vcdm::presentation(vc_jwts, holder))?;
+        if let Some(holder) = holder {
+            payload.set_issuer(holder);
+        }
+        payload.set_claim("nonce", Some(Value::String(nonce.to_string())))?;
+        payload.set_audience(vec![audience]);
+        let now = std::time::SystemTime::now();
+        let expires_at = now + std::time::Duration::from_secs(60);
+        payload.set_issued_at(&now);
+        payload.set_expires_at(&expires_at);
+
+        let signer = JwsSignerAdapter::new(self.signer.clone(), self.alg)?;
+        jwt::encode_with_signer(&payload, &header, &signer)
+            .map_err(|e| anyhow!("failed to encode with signer: {e:?}"))
+    }
+
+    /// mdoc (`IssuerSigned` の CBOR) から `elements` の要素だけを開示した DeviceResponse を作成
+    /// `nonce`・Verifier の `client_id`・`response_uri` は SessionTranscript に入れて deviceSignature で署名する
+    /// (redirect_uri スキームでは `client_id` と `response_uri` は同じ値)
+    pub fn present_mdoc(
+        &self,
+        issuer_signed: &[u8],
+        elements: &[&str],
+        nonce: &str,
+        client_id: &str,
+        response_uri: &str,
+    ) -> Result<Vec<u8>> {
+        if nonce.is_empty() {
+            bail!("nonce is empty");
+        }
+        let signer = JwsSignerAdapter::new(self.signer.clone(), self.alg)?;
+        crate::mdoc::device_response(
+            issuer_signed,
+            elements,
+            &crate::mdoc::session_transcript(client_id, nonce, response_uri)?,
+            self.alg,
+            &signer,
+        )
+    }
+}
@@ -0,0 +1,52 @@
+//! HTTP クライアントの共通部分 (ureq)
+
+use anyhow::{anyhow, bail, Result};
+use serde_json::Value;
+use std::io::Read as _;
+
+/// `get_bytes` で読み込むボディの最大サイズ
+pub const MAX_RESPONSE_BYTES: u64 = 10 * 1024 * 1024;
+
+/// `url` を GET してボディを返す
+pub fn get(url: &str) -> Result<String> {
+    let response = ureq::get(url)
+        .call()
+        .map_err(|e| anyhow!("GET {url} failed: {e}"))?;
+    response
+        .into_string()
+        .map_err(|e| anyhow!("failed to read response from {url}: {e}"))
+}
+
+/// `url` を GET してボディのバイト列をそのまま返す (文字コードを変換しない、ハッシュを確認する文書など)
+pub fn get_bytes(url: &str) -> Result<Vec<u8>> {
+    let response = ureq::get(url)
+        .call()
+        .map_err(|e| anyhow!("GET {url} failed: {e}"))?;
+    let mut body = Vec::new();
+    response
+        .into_reader()
+        .take(MAX_RESPONSE_BYTES + 1)
+        .read_to_end(&mut body)
+        .map_err(|e| anyhow!("failed to read response from {url}: {e}"))?;
+    if body.len() as u64 > MAX_RESPONSE_BYTES {
+        bail!("response from {url} exceeds {MAX_RESPONSE_BYTES} bytes");
+    }
+    Ok(body)
+}
+
+/// `url` に `application/x-www-form-urlencoded` で POST し、JSON のレスポンスを返す
+/// エラーのステータスの場合も、OAuth 形式のエラーをメッセージに含める
+pub fn post_form(url: &str, params: &[(&str, &str)]) -> Result<Value> {
+    let body = match ureq::post(url).send_form(params) {
+        Ok(response) => response.into_string()?,
+        Err(ureq::Error::Status(status, response)) => {
+            let body = response.into_string().unwrap_or_default();
+            return Err(anyhow!("POST {url} returned {status}: {body}"));
+        }
+        Err(e) => return Err(anyhow!("POST {url} failed: {e}")),
+    };
+    if body.trim().is_empty() {
+        return Ok(Value::Object(Default::default()));
+    }
+    serde_json::from_str(&body).map_err(|e| anyhow!("invalid json from {url}: {e}"))
+}
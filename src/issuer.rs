@@ -0,0 +1,289 @@
+//! SD-JWT VC の発行
+
+use crate::{
+    alg::SigningAlgorithm,
+    signer::{JwsSignerAdapter, PemSigner, Signer},
+    status_list::StatusReference,
+};
+use anyhow::{anyhow, bail, Result};
+use josekit::{
+    jws::JwsHeader,
+    jwt::{self, JwtPayload},
+};
+use rand::seq::SliceRandom;
+use sd_jwt_payload::{Disclosure, SdObjectEncoder};
+use serde_json::{Map, Value};
+use std::sync::Arc;
+
+/// SD-JWT VC の発行者
+#[derive(Clone)]
+pub struct Issuer {
+    /// `iss` に設定する発行者の識別子
+    issuer: String,
+    /// 発行者の秘密鍵で署名するバックエンド
+    signer: Arc<dyn Signer>,
+    /// 秘密鍵の種類から決まる署名アルゴリズム
+    alg: SigningAlgorithm,
+    /// JWS ヘッダの `kid`
+    key_id: String,
+    /// 署名には使わず JWKS で公開する他の公開鍵 (鍵のローテーション前後の鍵)
+    verification_keys: Vec<Value>,
+}
+
+/// SD-JWT VC 発行時のパラメータ
+pub struct GenerateVCParams {
+    /// `vct` (None の場合は設定しない)
+    pub vct: Option<String>,
+    /// `vct` の Type Metadata のハッシュ (`vct#integrity`、None の場合は設定しない)
+    pub vct_integrity: Option<String>,
+    /// `cnf` に埋め込む Holder の公開鍵 (JWK)
+    pub holder_jwk: Value,
+    /// Holder の鍵の DID URL (指定した場合は `cnf.jwk` の代わりに `cnf.kid` にする)
+    pub holder_kid: Option<String>,
+    /// VC に含めるクレーム
+    pub claims: Map<String, Value>,
+    /// 選択的開示にするクレームの JSON Pointer (`*` で配列の要素ごと、`disclosure` を参照)
+    pub disclosable: Vec<String>,
+    /// トップレベルに追加するダミーダイジェストの数
+    pub decoys: usize,
+    /// `aud`
+    pub audience: String,
+    /// 有効期間 (秒)
+    pub vc_expires_in: u64,
+    /// ステータスリストで割り当てたインデックス (None の場合は `status` を設定しない)
+    pub status: Option<StatusReference>,
+}
+
+impl Issuer {
+    /// 署名アルゴリズムは秘密鍵の種類から判定する
+    pub fn new(
+        issuer: impl Into<String>,
+        private_key: Vec<u8>,
+        key_id: impl Into<String>,
+    ) -> Result<Self> {
+        Ok(Self::from_signer(
+            issuer,
+            Arc::new(PemSigner::new(&private_key)?),
+            key_id,
+        ))
+    }
+
+    /// 署名のバックエンド (暗号化した鍵、PKCS#11 のトークンなど) で署名する発行者を作成
+    pub fn from_signer(
+        issuer: impl Into<String>,
+        signer: Arc<dyn Signer>,
+        key_id: impl Into<String>,
+    ) -> Self {
+        Self {
+            issuer: issuer.into(),
+            alg: signer.algorithm(),
+            signer,
+            key_id: key_id.into(),
+            verification_keys: Vec::new(),
+        }
+    }
+
+    /// 鍵の指定 (PEMファイルのパス、または PKCS#11 URI、`signer::open` を参照) から発行者を作成
+    pub fn from_key(
+        issuer: impl Into<String>,
+        key: &str,
+        key_id: impl Into<String>,
+    ) -> Result<Self> {
+        Ok(Self::from_signer(issuer, crate::signer::open(key)?, key_id))
+    }
+
+    /// 署名鍵に加えて JWKS で公開する公開鍵 (JWK、`kid` 付き) を設定する
+    pub fn with_verification_keys(mut self, keys: Vec<Value>) -> Result<Self> {
+        if keys
+            .iter()
+            .any(|jwk| jwk.get("kid").and_then(Value::as_str).is_none())
+        {
+            bail!("verification keys must have kid");
+        }
+        self.verification_keys = keys;
+        Ok(self)
+    }
+
+    /// 署名アルゴリズムを明示的に指定する (RSA 鍵で PS256 を使う場合など)
+    pub fn with_algorithm(mut self, alg: SigningAlgorithm) -> Result<Self> {
+        if !alg.is_compatible_with(self.alg) {
+            bail!("{alg} cannot be used with {} key", self.alg);
+        }
+        self.alg = alg;
+        Ok(self)
+    }
+
+    /// 署名アルゴリズム
+    pub fn algorithm(&self) -> SigningAlgorithm {
+        self.alg
+    }
+
+    /// PEMファイルから秘密鍵を読み込んで発行者を作成
+    pub fn from_pem_file(
+        issuer: impl Into<String>,
+        file_path: &str,
+        key_id: impl Into<String>,
+    ) -> Result<Self> {
+        let private_key = std::fs::read(file_path)
+            .map_err(|e| anyhow!("failed to read private key file {file_path}: {e:?}"))?;
+        Self::new(issuer, private_key, key_id)
+    }
+
+    /// 発行者の公開鍵 (JWK)
+    pub fn public_jwk(&self) -> Result<Value> {
+        self.signer.public_jwk()
+    }
+
+    /// 発行者の公開鍵の JWKS (`kid` は JWS ヘッダと同じ発行者の kid)
+    /// `with_verification_keys` で設定した公開鍵も含める
+    pub fn jwks(&self) -> Result<Value> {
+        let mut jwk = self.public_jwk()?;
+        if let Some(obj) = jwk.as_object_mut() {
+            obj.insert("kid".to_string(), Value::String(self.key_id.clone()));
+            obj.insert(
+                "alg".to_string(),
+                Value::String(self.alg.name().to_string()),
+            );
+            obj.insert("use".to_string(), Value::String("sig".to_string()));
+        }
+        let mut keys = vec![jwk];
+        keys.extend(
+            self.verification_keys
+                .iter()
+                .filter(|key| key.get("kid") != Some(&Value::String(self.key_id.clone())))
+                .cloned(),
+        );
+        Ok(serde_json::json!({ "keys": keys }))
+    }
+
+    /// JWS ヘッダの `kid`
+    pub fn key_id(&self) -> &str {
+        &self.key_id
+    }
+
+    /// 発行者の鍵で JWT に署名する (ヘッダの `typ` は `typ`、`kid` は発行者の kid)
+    pub fn sign_jwt(&self, typ: &str, payload: &JwtPayload) -> Result<String> {
+        let mut header = JwsHeader::new();
+        header.set_token_type(typ);
+        header.set_algorithm(self.alg.name());
+        header.set_key_id(&self.key_id);
+
+        let signer = JwsSignerAdapter::new(self.signer.clone(), self.alg)?;
+        jwt::encode_with_signer(payload, &header, &signer)
+            .map_err(|e| anyhow!("failed to encode with signer: {e:?}"))
+    }
+
+    /// `iss` に設定する発行者の識別子
+    pub fn issuer(&self) -> &str {
+        &self.issuer
+    }
+
+    /// SD-JWT形式のVCを生成
+    pub fn generate_sd_jwt_vc(&self, params: GenerateVCParams) -> Result<String> {
+        let mut object = Value::Object(params.claims);
+        let disclosable = crate::disclosure::resolve_policy(&object, &params.disclosable)?;
+
+        let mut cnf = Map::new();
+        match params.holder_kid {
+            Some(kid) => cnf.insert("kid".to_string(), Value::String(kid)),
+            None => cnf.insert("jwk".to_string(), params.holder_jwk),
+        };
+        if let Value::Object(ref mut map) = object {
+            map.insert("cnf".to_string(), Value::Object(cnf));
+        }
+
+        let mut encoder: SdObjectEncoder = object.try_into()?;
+        let disclosures = disclosable
+            .iter()
+            .map(|path| encoder.conceal(path, None))
+            .collect::<Result<Vec<Disclosure>, _>>()?;
+
+        encoder.add_decoys("", params.decoys)?; // Add decoys to the top level.
+
+        // Use the encoded object as a payload for the JWT.
+        let mut payload = JwtPayload::from_map(encoder.object()?.clone())?;
+        payload.set_issuer(&self.issuer);
+        if let Some(vct) = params.vct {
+            payload.set_claim("vct", Some(Value::from(vct)))?;
+        }
+        if let Some(integrity) = params.vct_integrity {
+            payload.set_claim(
+                crate::type_metadata::VCT_INTEGRITY_CLAIM,
+                Some(Value::from(integrity)),
+            )?;
+        }
+        payload.set_audience(vec![params.audience]);
+        if let Some(status) = params.status {
+            payload.set_claim("status", Some(status.to_claim()))?;
+        }
+        let now = std::time::SystemTime::now();
+        let expires_at = now + std::time::Duration::from_secs(params.vc_expires_in);
+        payload.set_issued_at(&now);
+        payload.set_expires_at(&expires_at);
+
+        // Create the JWT.
+        // Creating JWTs is outside the scope of this library, josekit is used here as an example.
+        let jwt = self.sign_jwt(&format!("vc+{}", sd_jwt_payload::HEADER_TYP), &payload)?;
+
+        // Create an SD_JWT by collecting the disclosures and creating an `SdJwt` instance.
+        let mut disclosures: Vec<String> = disclosures
+            .into_iter()
+            .map(|disclosure| disclosure.to_string())
+            .collect();
+
+        // disclosures の配列の中身をランダムに並べ替える
+        disclosures.shuffle(&mut rand::rng());
+
+        // disclosure が 0 個でも `<jwt>~` になるよう sd_jwt で組み立てる
+        Ok(crate::sd_jwt::issuer_signed_part(&jwt, &disclosures))
+    }
+
+    /// W3C VCDM 2.0 の VC-JWT (`vc+jwt`) を生成
+    /// `claims` を `credentialSubject` にし、`vct` は `type` に加える
+    /// (選択的開示はしないため `disclosable` と `decoys` は使わない)
+    pub fn generate_vc_jwt(&self, params: GenerateVCParams) -> Result<String> {
+        let now = std::time::SystemTime::now();
+        let expires_at = now + std::time::Duration::from_secs(params.vc_expires_in);
+
+        let mut subject = params.claims;
+        let mut cnf = Map::new();
+        match params.holder_kid {
+            // Holder の DID を credentialSubject の id にする
+            Some(kid) => {
+                subject.insert(
+                    "id".to_string(),
+                    Value::String(crate::did::did_of(&kid).to_string()),
+                );
+                cnf.insert("kid".to_string(), Value::String(kid));
+            }
+            None => {
+                cnf.insert("jwk".to_string(), params.holder_jwk);
+            }
+        }
+        let credential = crate::vcdm::credential(
+            &self.issuer,
+            params.vct.as_deref(),
+            subject,
+            now,
+            expires_at,
+        );
+
+        let mut payload = JwtPayload::from_map(credential)?;
+        payload.set_issuer(&self.issuer);
+        payload.set_claim("cnf", Some(Value::Object(cnf)))?;
+        payload.set_audience(vec![params.audience]);
+        if let Some(status) = params.status {
+            payload.set_claim("status", Some(status.to_claim()))?;
+        }
+        payload.set_issued_at(&now);
+        payload.set_expires_at(&expires_at);
+        self.sign_jwt(crate::vcdm::VC_JWT_TYP, &payload)
+    }
+
+    /// ISO 18013-5 の mdoc (`IssuerSigned` の CBOR) を生成
+    /// MSO は JWS と同じ発行者の鍵で COSE_Sign1 に署名し、`kid` を入れる
+    pub fn generate_mdoc(&self, params: crate::mdoc::MdocParams) -> Result<Vec<u8>> {
+        let signer = JwsSignerAdapter::new(self.signer.clone(), self.alg)?;
+        crate::mdoc::issuer_signed(params, self.alg, &self.key_id, &signer)
+    }
+}
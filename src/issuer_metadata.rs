@@ -0,0 +1,471 @@
+//! SD-JWT VC の発行者メタデータ (`/.well-known/jwt-vc-issuer`) と JWKS による鍵の解決
+//!
+//! Verifier は VC の `iss` からメタデータの URL を作り、`jwks_uri` または `jwks` の鍵のうち
+//! JWS ヘッダの `kid` に一致するものを使う。取得した鍵は発行者ごとにキャッシュし、
+//! 知らない `kid` が来た場合 (鍵のローテーション) は取得し直す。
+//! `iss` が DID の場合は DID ドキュメントの `assertionMethod` の鍵を使う。
+//! メタデータと JWKS は `HttpFetcher` で取得する (テストではローカルの代わりを渡せる)。
+
+use crate::{
+    alg::SigningAlgorithm,
+    did::{DidResolver, HttpFetcher, Relationship},
+    error::VerificationError,
+    http,
+    trust::TrustedIssuer,
+};
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+/// 発行者メタデータの well-known のパス
+pub const WELL_KNOWN_JWT_VC_ISSUER: &str = "/.well-known/jwt-vc-issuer";
+/// 取得した鍵をキャッシュする時間の既定値
+pub const DEFAULT_KEYS_MAX_AGE: Duration = Duration::from_secs(60 * 60);
+/// 知らない `kid` で取得し直す間隔の下限 (同じ発行者に何度も取りに行かない)
+const REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// `/.well-known/jwt-vc-issuer` のメタデータ
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JwtVcIssuerMetadata {
+    /// VC の `iss` と一致しなければならない
+    pub issuer: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jwks_uri: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jwks: Option<Value>,
+}
+
+/// `iss` のホストとパスの間に well-known を挟んだメタデータの URL
+/// (`https://example.com/tenant` -> `https://example.com/.well-known/jwt-vc-issuer/tenant`)
+pub fn metadata_url(iss: &str) -> Result<String> {
+    let (origin, path) = split_issuer_url(iss)?;
+    Ok(format!("{origin}{WELL_KNOWN_JWT_VC_ISSUER}{path}"))
+}
+
+/// 発行者が `iss` のメタデータを公開するパス
+pub fn metadata_path(iss: &str) -> Result<String> {
+    let (_, path) = split_issuer_url(iss)?;
+    Ok(format!("{WELL_KNOWN_JWT_VC_ISSUER}{path}"))
+}
+
+/// `iss` を `scheme://host` と末尾の `/` を除いたパスに分ける
+fn split_issuer_url(iss: &str) -> Result<(&str, &str)> {
+    let (scheme, rest) = iss
+        .split_once("://")
+        .ok_or_else(|| anyhow!("iss is not an http(s) url: {iss}"))?;
+    if scheme != "https" && scheme != "http" {
+        bail!("iss is not an http(s) url: {iss}");
+    }
+    let host_len = rest.find('/').unwrap_or(rest.len());
+    if host_len == 0 || iss.contains(['?', '#']) {
+        bail!("invalid iss url: {iss}");
+    }
+    let (origin, path) = iss.split_at(scheme.len() + 3 + host_len);
+    Ok((origin, path.trim_end_matches('/')))
+}
+
+/// キャッシュした発行者の鍵
+struct CachedKeys {
+    keys: Vec<Value>,
+    fetched_at: SystemTime,
+}
+
+/// 発行者メタデータから発行者の鍵を解決する
+pub struct IssuerKeyResolver {
+    /// 受け入れる `iss` (None の場合は制限しない)
+    allowed_issuers: Option<Vec<String>>,
+    max_age: Duration,
+    cache: Mutex<HashMap<String, CachedKeys>>,
+    /// `iss` が DID の場合に DID ドキュメントを解決する
+    did_resolver: DidResolver,
+    /// メタデータと JWKS を取得する HTTP クライアント
+    fetcher: Arc<dyn HttpFetcher>,
+}
+
+impl Default for IssuerKeyResolver {
+    fn default() -> Self {
+        Self::new(DEFAULT_KEYS_MAX_AGE)
+    }
+}
+
+impl IssuerKeyResolver {
+    pub fn new(max_age: Duration) -> Self {
+        Self {
+            allowed_issuers: None,
+            max_age,
+            cache: Mutex::new(HashMap::new()),
+            did_resolver: DidResolver::default(),
+            fetcher: Arc::new(http::get),
+        }
+    }
+
+    /// メタデータと JWKS を取得する HTTP クライアントを指定する
+    pub fn with_fetcher(mut self, fetcher: Arc<dyn HttpFetcher>) -> Self {
+        self.fetcher = fetcher;
+        self
+    }
+
+    /// `iss` が DID の場合に使う DID resolver を指定する
+    pub fn with_did_resolver(mut self, did_resolver: DidResolver) -> Self {
+        self.did_resolver = did_resolver;
+        self
+    }
+
+    /// メタデータを取得してよい `iss` を制限する
+    /// 制限しない場合、`iss` の URL に鍵を置ける者は誰でも VC を発行できることになる
+    pub fn with_allowed_issuers(mut self, issuers: Vec<String>) -> Self {
+        self.allowed_issuers = Some(issuers);
+        self
+    }
+
+    /// `iss` の鍵を `TrustedIssuer` として返す
+    /// キャッシュに `kid` の鍵がなければメタデータを取得し直す
+    pub fn trusted_issuer(
+        &self,
+        iss: &str,
+        kid: Option<&str>,
+    ) -> Result<TrustedIssuer, VerificationError> {
+        if let Some(allowed) = &self.allowed_issuers {
+            if !allowed.iter().any(|a| a == iss) {
+                return Err(VerificationError::UntrustedIssuer(iss.to_string()));
+            }
+        }
+
+        let now = SystemTime::now();
+        let cached = {
+            let cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+            cache.get(iss).map(|c| {
+                let age = now.duration_since(c.fetched_at).unwrap_or_default();
+                (c.keys.clone(), age)
+            })
+        };
+        let keys = match cached {
+            Some((keys, age))
+                if age < self.max_age
+                    && (age < REFRESH_INTERVAL
+                        || kid.is_none_or(|kid| has_kid(&keys, iss, kid))) =>
+            {
+                keys
+            }
+            _ => {
+                let keys = if iss.starts_with("did:") {
+                    self.did_issuer_keys(iss)?
+                } else {
+                    fetch_issuer_keys(self.fetcher.as_ref(), iss)?
+                };
+                let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+                cache.insert(
+                    iss.to_string(),
+                    CachedKeys {
+                        keys: keys.clone(),
+                        fetched_at: now,
+                    },
+                );
+                keys
+            }
+        };
+
+        Ok(TrustedIssuer {
+            iss: iss.to_string(),
+            keys: keys
+                .into_iter()
+                .map(|jwk| crate::trust::TrustedKey {
+                    kid: jwk.get("kid").and_then(Value::as_str).map(str::to_string),
+                    jwk,
+                })
+                .collect(),
+            vct: None,
+            algorithms: None,
+            kid_fallback: false,
+        })
+    }
+
+    /// DID ドキュメントの `assertionMethod` の鍵 (`kid` は verification method の DID URL)
+    fn did_issuer_keys(&self, iss: &str) -> Result<Vec<Value>, VerificationError> {
+        let document = self
+            .did_resolver
+            .resolve(iss)
+            .map_err(|e| VerificationError::IssuerMetadataUnavailable(e.to_string()))?;
+        let keys = document
+            .methods_for(Relationship::AssertionMethod)
+            .into_iter()
+            .filter_map(|method| {
+                let mut jwk = method.jwk().ok()?;
+                jwk.as_object_mut()?
+                    .insert("kid".to_string(), Value::String(method.id));
+                SigningAlgorithm::from_jwk(&jwk).is_ok().then_some(jwk)
+            })
+            .collect::<Vec<_>>();
+        if keys.is_empty() {
+            return Err(VerificationError::InvalidIssuerMetadata(format!(
+                "{iss} has no usable assertionMethod"
+            )));
+        }
+        Ok(keys)
+    }
+}
+
+/// キャッシュした鍵に `kid` があるか (`#key-1` は `iss` からの DID URL として比べる)
+fn has_kid(keys: &[Value], iss: &str, kid: &str) -> bool {
+    let absolute = format!("{iss}{kid}");
+    keys.iter().any(|key| {
+        key.get("kid")
+            .and_then(Value::as_str)
+            .is_some_and(|k| k == kid || (kid.starts_with('#') && k == absolute))
+    })
+}
+
+/// メタデータと JWKS を取得して、署名の検証に使える鍵を返す
+fn fetch_issuer_keys(
+    fetcher: &dyn HttpFetcher,
+    iss: &str,
+) -> Result<Vec<Value>, VerificationError> {
+    let unavailable =
+        |e: anyhow::Error| VerificationError::IssuerMetadataUnavailable(e.to_string());
+    let invalid = |reason: String| VerificationError::InvalidIssuerMetadata(reason);
+
+    let url = metadata_url(iss).map_err(|e| invalid(e.to_string()))?;
+    let metadata: JwtVcIssuerMetadata =
+        serde_json::from_str(&fetcher.get(&url).map_err(unavailable)?)
+            .map_err(|e| invalid(e.to_string()))?;
+    if metadata.issuer != iss {
+        return Err(invalid(format!("issuer is not {iss}")));
+    }
+    let jwks = match (metadata.jwks, metadata.jwks_uri) {
+        (Some(jwks), None) => jwks,
+        (None, Some(jwks_uri)) => {
+            serde_json::from_str(&fetcher.get(&jwks_uri).map_err(unavailable)?)
+                .map_err(|e| invalid(format!("invalid jwks: {e}")))?
+        }
+        _ => return Err(invalid("either jwks or jwks_uri is required".to_string())),
+    };
+    let keys: Vec<Value> = jwks
+        .get("keys")
+        .and_then(Value::as_array)
+        .ok_or_else(|| invalid("jwks has no keys".to_string()))?
+        .iter()
+        // 暗号化用の鍵と、対応していない種類の鍵は使わない
+        .filter(|key| key.get("use").and_then(Value::as_str) != Some("enc"))
+        .filter(|key| SigningAlgorithm::from_jwk(key).is_ok())
+        .cloned()
+        .collect();
+    if keys.is_empty() {
+        return Err(invalid("jwks has no usable keys".to_string()));
+    }
+    Ok(keys)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key::{generate_key_pair, public_key_pem_to_jwk};
+    use serde_json::json;
+
+    const ISS: &str = "https://issuer.example.com/tenant";
+    const METADATA_URL: &str = "https://issuer.example.com/.well-known/jwt-vc-issuer/tenant";
+
+    /// URL ごとのボディを返し、取得した URL を記録する HttpFetcher
+    /// (ボディはテストの途中で差し替えられる)
+    struct StubFetcher {
+        bodies: Mutex<HashMap<String, String>>,
+        urls: Mutex<Vec<String>>,
+    }
+
+    impl StubFetcher {
+        fn new() -> Arc<Self> {
+            Arc::new(Self {
+                bodies: Mutex::new(HashMap::new()),
+                urls: Mutex::new(Vec::new()),
+            })
+        }
+
+        fn set(&self, url: &str, body: Value) {
+            let mut bodies = self.bodies.lock().unwrap();
+            bodies.insert(url.to_string(), body.to_string());
+        }
+
+        fn fetched(&self) -> usize {
+            self.urls.lock().unwrap().len()
+        }
+    }
+
+    impl HttpFetcher for StubFetcher {
+        fn get(&self, url: &str) -> Result<String> {
+            self.urls.lock().unwrap().push(url.to_string());
+            let bodies = self.bodies.lock().unwrap();
+            bodies
+                .get(url)
+                .cloned()
+                .ok_or_else(|| anyhow!("404: {url}"))
+        }
+    }
+
+    fn jwk(kid: &str) -> Value {
+        let (_, public) = generate_key_pair(SigningAlgorithm::ES256).unwrap();
+        let mut jwk = public_key_pem_to_jwk(&public).unwrap();
+        jwk["kid"] = json!(kid);
+        jwk
+    }
+
+    fn metadata(keys: &[Value]) -> Value {
+        json!({ "issuer": ISS, "jwks": { "keys": keys } })
+    }
+
+    fn resolver(fetcher: &Arc<StubFetcher>, max_age: Duration) -> IssuerKeyResolver {
+        IssuerKeyResolver::new(max_age).with_fetcher(fetcher.clone())
+    }
+
+    fn kids(issuer: &TrustedIssuer) -> Vec<&str> {
+        issuer
+            .keys
+            .iter()
+            .filter_map(|k| k.kid.as_deref())
+            .collect()
+    }
+
+    /// キャッシュした鍵を `age` だけ前に取得したことにする
+    fn age_cache(resolver: &IssuerKeyResolver, age: Duration) {
+        let mut cache = resolver.cache.lock().unwrap();
+        let cached = cache.get_mut(ISS).unwrap();
+        cached.fetched_at = SystemTime::now() - age;
+    }
+
+    #[test]
+    fn metadata_url_inserts_well_known_after_host() {
+        for (iss, url) in [
+            (
+                "https://h/tenant",
+                "https://h/.well-known/jwt-vc-issuer/tenant",
+            ),
+            (
+                "https://h/tenant/",
+                "https://h/.well-known/jwt-vc-issuer/tenant",
+            ),
+            ("https://h", "https://h/.well-known/jwt-vc-issuer"),
+            ("https://h/", "https://h/.well-known/jwt-vc-issuer"),
+            (
+                "https://h:8443/a/b",
+                "https://h:8443/.well-known/jwt-vc-issuer/a/b",
+            ),
+            (
+                "http://localhost:8080",
+                "http://localhost:8080/.well-known/jwt-vc-issuer",
+            ),
+        ] {
+            assert_eq!(metadata_url(iss).unwrap(), url, "{iss}");
+        }
+        assert_eq!(
+            metadata_path("https://h:8443/tenant/").unwrap(),
+            "/.well-known/jwt-vc-issuer/tenant"
+        );
+    }
+
+    #[test]
+    fn metadata_url_rejects_non_http_issuers() {
+        for iss in [
+            "issuer.example.com",
+            "ftp://h/tenant",
+            "https:///tenant",
+            "https://h/tenant?x=1",
+            "https://h/tenant#x",
+        ] {
+            assert!(metadata_url(iss).is_err(), "{iss}");
+        }
+    }
+
+    #[test]
+    fn keys_are_fetched_from_jwks_uri() {
+        let fetcher = StubFetcher::new();
+        fetcher.set(
+            METADATA_URL,
+            json!({ "issuer": ISS, "jwks_uri": "https://issuer.example.com/jwks" }),
+        );
+        let mut enc = jwk("enc");
+        enc["use"] = json!("enc");
+        fetcher.set(
+            "https://issuer.example.com/jwks",
+            json!({ "keys": [jwk("k1"), enc] }),
+        );
+        let issuer = resolver(&fetcher, DEFAULT_KEYS_MAX_AGE)
+            .trusted_issuer(ISS, Some("k1"))
+            .unwrap();
+        assert_eq!(kids(&issuer), ["k1"]);
+        assert_eq!(
+            *fetcher.urls.lock().unwrap(),
+            [METADATA_URL, "https://issuer.example.com/jwks"]
+        );
+    }
+
+    #[test]
+    fn metadata_for_another_issuer_is_rejected() {
+        let fetcher = StubFetcher::new();
+        fetcher.set(
+            METADATA_URL,
+            json!({ "issuer": "https://issuer.example.com", "jwks": { "keys": [jwk("k1")] } }),
+        );
+        let error = resolver(&fetcher, DEFAULT_KEYS_MAX_AGE)
+            .trusted_issuer(ISS, Some("k1"))
+            .unwrap_err();
+        assert!(matches!(error, VerificationError::InvalidIssuerMetadata(_)));
+    }
+
+    #[test]
+    fn keys_are_cached_until_max_age() {
+        let fetcher = StubFetcher::new();
+        fetcher.set(METADATA_URL, metadata(&[jwk("k1")]));
+        let resolver = resolver(&fetcher, Duration::from_secs(60));
+
+        resolver.trusted_issuer(ISS, Some("k1")).unwrap();
+        resolver.trusted_issuer(ISS, Some("k1")).unwrap();
+        assert_eq!(fetcher.fetched(), 1);
+
+        // max_age を過ぎると取得し直す
+        fetcher.set(METADATA_URL, metadata(&[jwk("k2")]));
+        age_cache(&resolver, Duration::from_secs(61));
+        let issuer = resolver.trusted_issuer(ISS, None).unwrap();
+        assert_eq!(fetcher.fetched(), 2);
+        assert_eq!(kids(&issuer), ["k2"]);
+    }
+
+    #[test]
+    fn unknown_kid_refreshes_cached_keys() {
+        let fetcher = StubFetcher::new();
+        fetcher.set(METADATA_URL, metadata(&[jwk("k1")]));
+        let resolver = resolver(&fetcher, DEFAULT_KEYS_MAX_AGE);
+        resolver.trusted_issuer(ISS, Some("k1")).unwrap();
+
+        // 鍵のローテーション
+        fetcher.set(METADATA_URL, metadata(&[jwk("k1"), jwk("k2")]));
+
+        // 取得した直後は知らない kid でも取得し直さない
+        let issuer = resolver.trusted_issuer(ISS, Some("k2")).unwrap();
+        assert_eq!(fetcher.fetched(), 1);
+        assert_eq!(kids(&issuer), ["k1"]);
+
+        // REFRESH_INTERVAL を過ぎていれば取得し直す
+        age_cache(&resolver, REFRESH_INTERVAL + Duration::from_secs(1));
+        let issuer = resolver.trusted_issuer(ISS, Some("k2")).unwrap();
+        assert_eq!(fetcher.fetched(), 2);
+        assert_eq!(kids(&issuer), ["k1", "k2"]);
+
+        // 知っている kid なら max_age まではキャッシュを使う
+        age_cache(&resolver, REFRESH_INTERVAL + Duration::from_secs(1));
+        resolver.trusted_issuer(ISS, Some("k1")).unwrap();
+        assert_eq!(fetcher.fetched(), 2);
+    }
+
+    #[test]
+    fn issuers_outside_the_allow_list_are_not_fetched() {
+        let fetcher = StubFetcher::new();
+        let resolver = resolver(&fetcher, DEFAULT_KEYS_MAX_AGE)
+            .with_allowed_issuers(vec!["https://issuer.example.com/other".to_string()]);
+        let error = resolver.trusted_issuer(ISS, Some("k1")).unwrap_err();
+        assert!(matches!(error, VerificationError::UntrustedIssuer(_)));
+        assert_eq!(fetcher.fetched(), 0);
+    }
+}
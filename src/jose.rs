@@ -0,0 +1,149 @@
+//! JWT の署名・クレームの検証の共通部分
+//!
+//! Verifier (VC・KB-JWT・VP-JWT) と OID4VCI の発行サーバ (アサーション・アクセストークン・proof JWT) で
+//! 同じ検証を使う。エラーは `VerificationError` で返し、発行サーバでは `IssuanceError` に変換する。
+
+use crate::{
+    alg::SigningAlgorithm,
+    error::{JwtKind, VerificationError},
+};
+use josekit::{
+    jws::{JwsHeader, JwsVerifier},
+    jwt::{self, JwtPayload},
+    JoseError,
+};
+use std::time::{Duration, SystemTime};
+
+/// JWS ヘッダの `alg` が検証に使う鍵の種類と合っているかチェックする
+pub(crate) fn check_key_algorithm(
+    token: JwtKind,
+    alg: SigningAlgorithm,
+    key_alg: SigningAlgorithm,
+) -> Result<(), VerificationError> {
+    if !alg.is_compatible_with(key_alg) {
+        return Err(VerificationError::AlgorithmMismatch {
+            token,
+            alg,
+            key_alg,
+        });
+    }
+    Ok(())
+}
+
+/// ヘッダの `typ` が `expected` であるかチェックする
+pub(crate) fn check_typ(
+    header: &JwsHeader,
+    token: JwtKind,
+    expected: &'static str,
+) -> Result<(), VerificationError> {
+    match header.token_type() {
+        Some(v) if v == expected => Ok(()),
+        actual => Err(VerificationError::InvalidTyp {
+            token,
+            expected,
+            actual: actual.map(str::to_string),
+        }),
+    }
+}
+
+/// `iat` が現在時刻から `skew` 以内であるかチェックする
+pub(crate) fn check_iat(
+    payload: &JwtPayload,
+    token: JwtKind,
+    skew: Duration,
+) -> Result<(), VerificationError> {
+    let iat = payload
+        .issued_at()
+        .ok_or(VerificationError::MissingIat(token))?;
+    let now = SystemTime::now();
+    let diff = iat
+        .duration_since(now)
+        .or_else(|_| now.duration_since(iat))
+        .unwrap_or_default();
+    if diff > skew {
+        return Err(VerificationError::IatOutOfWindow(token));
+    }
+    Ok(())
+}
+
+/// JWT のヘッダの `alg` から署名アルゴリズムを判定
+pub(crate) fn jwt_algorithm(
+    jwt: &str,
+    token: JwtKind,
+) -> Result<SigningAlgorithm, VerificationError> {
+    let header = jwt::decode_header(jwt).map_err(|e| VerificationError::MalformedJwt {
+        token,
+        reason: e.to_string(),
+    })?;
+    let alg = header
+        .claim("alg")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| VerificationError::MalformedJwt {
+            token,
+            reason: "jws header has no alg".to_string(),
+        })?;
+    alg.parse()
+        .map_err(|_| VerificationError::UnsupportedAlgorithm {
+            token,
+            alg: alg.to_string(),
+        })
+}
+
+/// JWT の署名を検証し、`exp` と `aud` をチェックする
+/// `exp` は VC・アサーション・アクセストークンでは必須、それ以外では含まれている場合だけチェックする
+/// `audience` が None の場合は `aud` をチェックしない
+/// 検証器はヘッダの `alg` から判定したアルゴリズムで `verifier_for` が作成する
+pub(crate) fn decode_jwt(
+    jwt: &str,
+    token: JwtKind,
+    audience: Option<&str>,
+    verifier_for: impl FnOnce(SigningAlgorithm) -> Result<Box<dyn JwsVerifier>, VerificationError>,
+) -> Result<(JwtPayload, JwsHeader), VerificationError> {
+    let verifier = verifier_for(jwt_algorithm(jwt, token)?)?;
+    let (payload, header) =
+        jwt::decode_with_verifier(jwt, verifier.as_ref()).map_err(|e| match e {
+            JoseError::InvalidSignature(_) => match token {
+                JwtKind::Credential => VerificationError::BadIssuerSignature,
+                JwtKind::KeyBinding => VerificationError::BadKbJwtSignature,
+                JwtKind::Presentation => VerificationError::BadPresentationSignature,
+                JwtKind::StatusList => {
+                    VerificationError::InvalidStatusList("bad signature".to_string())
+                }
+                JwtKind::Assertion | JwtKind::AccessToken | JwtKind::Proof => {
+                    VerificationError::MalformedJwt {
+                        token,
+                        reason: "bad signature".to_string(),
+                    }
+                }
+            },
+            e => VerificationError::MalformedJwt {
+                token,
+                reason: e.to_string(),
+            },
+        })?;
+
+    let requires_exp = matches!(
+        token,
+        JwtKind::Credential | JwtKind::Assertion | JwtKind::AccessToken
+    );
+    match payload.expires_at() {
+        Some(exp) if exp > SystemTime::now() => {}
+        Some(_) => return Err(VerificationError::Expired(token)),
+        None if !requires_exp => {}
+        None => return Err(VerificationError::MissingExp(token)),
+    }
+
+    if let Some(audience) = audience {
+        if !payload
+            .audience()
+            .is_some_and(|aud| aud.contains(&audience))
+        {
+            return Err(VerificationError::AudienceMismatch {
+                token,
+                expected: audience.to_string(),
+            });
+        }
+    }
+
+    Ok((payload, header))
+}
@@ -0,0 +1,300 @@
+//! JWK (RFC 7517) の読み込みと JWK Thumbprint (RFC 7638・RFC 9278)
+//!
+//! 鍵のファイルは PEM のほかに JWK (または鍵が 1 つの JWKS) の JSON でもよい。秘密鍵の JWK (`d` を含む) は
+//! PKCS#8 の PEM に、公開鍵の JWK は SubjectPublicKeyInfo の PEM に変換して PEM の鍵と同じように扱う。
+//! 対応する鍵は EC (P-256・P-384・P-521)・OKP (Ed25519)・RSA。
+
+use crate::alg::SigningAlgorithm;
+use anyhow::{anyhow, bail, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use josekit::jwk::{
+    alg::{ec::EcKeyPair, ed::EdKeyPair, rsa::RsaKeyPair},
+    KeyPair as _,
+};
+use openssl::{
+    bn::BigNum,
+    ec::{EcGroup, EcKey},
+    nid::Nid,
+    pkey::{Id, PKey},
+    rsa::Rsa,
+};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+
+/// RFC 9278 の SHA-256 の JWK Thumbprint URI の接頭辞
+pub const JWK_THUMBPRINT_URI_PREFIX: &str = "urn:ietf:params:oauth:jwk-thumbprint:sha-256:";
+
+/// RFC 7638 JWK Thumbprint (SHA-256, Base64URL, no padding) を算出
+/// EC鍵では "crv","kty","x","y"、OKP鍵 (Ed25519) では "crv","kty","x"、RSA鍵では "e","kty","n" を
+/// 辞書順で並べた JSON をハッシュ対象にする
+pub fn jwk_thumbprint_sha256(jwk: &Value) -> Result<String> {
+    use sha2::{Digest, Sha256};
+
+    // 必要フィールドを取り出し、辞書順(BTreeMap)で整形
+    let kty = field(jwk, "kty")?;
+    let mut bmap = BTreeMap::new();
+    bmap.insert("kty", kty);
+    for &name in public_members(kty)? {
+        bmap.insert(name, field(jwk, name)?);
+    }
+
+    // 余計な空白なしのJSONにシリアライズ（serde_json::to_string はデフォルトで緊縮表現）
+    let canon = serde_json::to_string(&bmap)?;
+
+    // SHA-256 -> Base64URL(no padding)
+    let digest = Sha256::digest(canon.as_bytes());
+    Ok(URL_SAFE_NO_PAD.encode(digest))
+}
+
+/// RFC 9278 の JWK Thumbprint URI (`urn:ietf:params:oauth:jwk-thumbprint:sha-256:<thumbprint>`)
+pub fn jwk_thumbprint_uri(jwk: &Value) -> Result<String> {
+    Ok(format!(
+        "{JWK_THUMBPRINT_URI_PREFIX}{}",
+        jwk_thumbprint_sha256(jwk)?
+    ))
+}
+
+/// JWK Thumbprint URI から SHA-256 の JWK Thumbprint を取り出す (他のハッシュ関数の URI は `None`)
+pub fn parse_jwk_thumbprint_uri(uri: &str) -> Option<&str> {
+    uri.strip_prefix(JWK_THUMBPRINT_URI_PREFIX)
+        .filter(|thumbprint| !thumbprint.is_empty())
+}
+
+/// `kid` が JWK Thumbprint URI の場合に、`jwk` の Thumbprint と一致するか
+pub fn matches_thumbprint_uri(jwk: &Value, kid: &str) -> bool {
+    parse_jwk_thumbprint_uri(kid)
+        .is_some_and(|thumbprint| jwk_thumbprint_sha256(jwk).is_ok_and(|t| t == thumbprint))
+}
+
+/// JSON の JWK を josekit の JWK に変換
+pub fn json_to_jwk(jwk: &Value) -> Result<josekit::jwk::Jwk> {
+    let map = jwk
+        .as_object()
+        .ok_or_else(|| anyhow!("jwk is not a JSON object"))?;
+    josekit::jwk::Jwk::from_map(map.clone()).map_err(|e| anyhow!("failed to convert jwk e={e:?}"))
+}
+
+/// 鍵のファイルの内容が JWK (JSON) か
+pub fn is_jwk(data: &[u8]) -> bool {
+    data.trim_ascii_start().starts_with(b"{")
+}
+
+/// JWK、または鍵が 1 つの JWKS の JSON を読み込む
+pub fn parse_jwk(data: &[u8]) -> Result<Value> {
+    let value: Value =
+        serde_json::from_slice(data).map_err(|e| anyhow!("failed to parse jwk e={e:?}"))?;
+    let jwk = match value.get("keys").and_then(Value::as_array) {
+        Some(keys) if keys.len() == 1 => keys[0].clone(),
+        Some(keys) => bail!("JWKS must have exactly one key (found {})", keys.len()),
+        None => value,
+    };
+    if !jwk.is_object() {
+        bail!("jwk is not a JSON object");
+    }
+    field(&jwk, "kty")?;
+    Ok(jwk)
+}
+
+/// JWK ファイルを読み込む
+pub fn read_jwk_file(file_path: &str) -> Result<Value> {
+    let data = std::fs::read(file_path)
+        .map_err(|e| anyhow!("failed to read jwk file {file_path}: {e:?}"))?;
+    parse_jwk(&data)
+}
+
+/// 秘密鍵の JWK (`d` を含む) か
+pub fn is_private_jwk(jwk: &Value) -> bool {
+    jwk.get("d").is_some()
+}
+
+/// JWK から秘密鍵のメンバーを除いた公開鍵の JWK
+/// `alg`・`use`・`kid` がなければ、鍵の種類から決まる `alg`・`sig`・JWK Thumbprint を付与する
+pub fn public_jwk(jwk: &Value) -> Result<Value> {
+    let kty = field(jwk, "kty")?;
+    let mut public = Map::new();
+    for name in ["kty", "alg", "use", "kid"]
+        .iter()
+        .chain(public_members(kty)?)
+    {
+        if let Some(value) = jwk.get(*name) {
+            public.insert(name.to_string(), value.clone());
+        }
+    }
+    let mut public = Value::Object(public);
+
+    let alg = SigningAlgorithm::from_jwk(&public)?;
+    let kid = jwk_thumbprint_sha256(&public)?;
+    if let Some(obj) = public.as_object_mut() {
+        obj.entry("alg")
+            .or_insert_with(|| Value::String(alg.name().to_string()));
+        obj.entry("use")
+            .or_insert_with(|| Value::String("sig".to_string()));
+        obj.entry("kid").or_insert_with(|| Value::String(kid));
+    }
+    Ok(public)
+}
+
+/// 秘密鍵の JWK を PKCS#8 の PEM にする
+pub fn private_jwk_to_pem(jwk: &Value) -> Result<Vec<u8>> {
+    if !is_private_jwk(jwk) {
+        bail!("jwk has no private key (d)");
+    }
+    // josekit は `alg`・`use` などで鍵の用途を制限するので、鍵のメンバーだけで変換する
+    let kty = field(jwk, "kty")?;
+    let mut key = Map::new();
+    for name in ["kty", "d", "p", "q", "dp", "dq", "qi"]
+        .iter()
+        .chain(public_members(kty)?)
+    {
+        if let Some(value) = jwk.get(*name) {
+            key.insert(name.to_string(), value.clone());
+        }
+    }
+    let key = json_to_jwk(&Value::Object(key))?;
+    let pem = match kty {
+        "EC" => EcKeyPair::from_jwk(&key)?.to_pem_private_key(),
+        "OKP" => EdKeyPair::from_jwk(&key)?.to_pem_private_key(),
+        "RSA" => RsaKeyPair::from_jwk(&key)?.to_pem_private_key(),
+        _ => unreachable!("public_members rejects other key types"),
+    };
+    Ok(pem)
+}
+
+/// 公開鍵の JWK を SubjectPublicKeyInfo の PEM にする
+pub fn public_jwk_to_pem(jwk: &Value) -> Result<Vec<u8>> {
+    let decode = |name: &str| -> Result<Vec<u8>> {
+        URL_SAFE_NO_PAD
+            .decode(field(jwk, name)?)
+            .map_err(|e| anyhow!("invalid jwk {name}: {e:?}"))
+    };
+    let key = match (field(jwk, "kty")?, jwk.get("crv").and_then(Value::as_str)) {
+        ("EC", Some(crv)) => {
+            let nid = match crv {
+                "P-256" => Nid::X9_62_PRIME256V1,
+                "P-384" => Nid::SECP384R1,
+                "P-521" => Nid::SECP521R1,
+                _ => bail!("unsupported jwk crv {crv}"),
+            };
+            let group = EcGroup::from_curve_name(nid)?;
+            let x = BigNum::from_slice(&decode("x")?)?;
+            let y = BigNum::from_slice(&decode("y")?)?;
+            // 曲線上の点でなければエラーになる
+            let key = EcKey::from_public_key_affine_coordinates(&group, &x, &y)
+                .map_err(|e| anyhow!("invalid jwk public key for {crv}: {e}"))?;
+            PKey::from_ec_key(key)?
+        }
+        ("OKP", Some("Ed25519")) => PKey::public_key_from_raw_bytes(&decode("x")?, Id::ED25519)?,
+        ("RSA", _) => {
+            let n = BigNum::from_slice(&decode("n")?)?;
+            let e = BigNum::from_slice(&decode("e")?)?;
+            PKey::from_rsa(Rsa::from_public_components(n, e)?)?
+        }
+        (kty, crv) => bail!("unsupported jwk kty={kty} crv={crv:?}"),
+    };
+    Ok(key.public_key_to_pem()?)
+}
+
+/// 鍵の種類ごとの公開鍵のメンバー (RFC 7638 の JWK Thumbprint の対象から `kty` を除いたもの)
+fn public_members(kty: &str) -> Result<&'static [&'static str]> {
+    match kty {
+        "EC" => Ok(&["crv", "x", "y"]),
+        "OKP" => Ok(&["crv", "x"]),
+        "RSA" => Ok(&["e", "n"]),
+        _ => bail!("unsupported kty {kty}"),
+    }
+}
+
+fn field<'a>(jwk: &'a Value, name: &str) -> Result<&'a str> {
+    jwk.get(name)
+        .and_then(Value::as_str)
+        .ok_or_else(|| anyhow!("missing {name}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// RFC 7638 3.1 の RSA 鍵 (Thumbprint の対象外の `alg`・`kid` を含む)
+    fn rfc7638_rsa_jwk() -> Value {
+        json!({
+            "kty": "RSA",
+            "n": "0vx7agoebGcQSuuPiLJXZptN9nndrQmbXEps2aiAFbWhM78LhWx4cbbfAAtVT86zwu1RK7aPFFxuhDR1L6tSoc_BJECPebWKRXjBZCiFV4n3oknjhMstn64tZ_2W-5JsGY4Hc5n9yBXArwl93lqt7_RN5w6Cf0h4QyQ5v-65YGjQR0_FDW2QvzqY368QQMicAtaSqzs8KJZgnYb9c7d0zgdAZHzu6qMQvRL5hajrn1n91CbOpbISD08qNLyrdkt-bFTWhAI4vMQFh6WeZu0fM4lFd2NcRwr3XPksINHaQ-G_xBniIqbw0Ls1jF44-csFCur-kEgU8awapJzKnqDKgw",
+            "e": "AQAB",
+            "alg": "RS256",
+            "kid": "2011-04-29"
+        })
+    }
+
+    #[test]
+    fn rsa_thumbprint_matches_rfc7638() {
+        assert_eq!(
+            jwk_thumbprint_sha256(&rfc7638_rsa_jwk()).unwrap(),
+            "NzbLsXh8uDCcd-6MNwXF4W_7noWXFZAfHkxZsRGC9Xs"
+        );
+        // RFC 9278 3 の例
+        assert_eq!(
+            jwk_thumbprint_uri(&rfc7638_rsa_jwk()).unwrap(),
+            "urn:ietf:params:oauth:jwk-thumbprint:sha-256:NzbLsXh8uDCcd-6MNwXF4W_7noWXFZAfHkxZsRGC9Xs"
+        );
+    }
+
+    #[test]
+    fn okp_thumbprint_matches_rfc8037() {
+        // RFC 8037 A.3
+        let jwk = json!({
+            "kty": "OKP",
+            "crv": "Ed25519",
+            "x": "11qYAYKxCrfVS_7TyWQHOg7hcvPapiMlrwIaaPcHURo"
+        });
+        assert_eq!(
+            jwk_thumbprint_sha256(&jwk).unwrap(),
+            "kPrK_qmxVWaYVA9wwBF6Iuo3vVzz7TxHCTwXBygrS4k"
+        );
+    }
+
+    #[test]
+    fn ec_thumbprint_uses_required_members_only() {
+        // RFC 7517 A.1 の P-256 鍵
+        let jwk = json!({
+            "kty": "EC",
+            "crv": "P-256",
+            "x": "MKBCTNIcKUSDii11ySs3526iDZ8AiTo7Tu6KPAqv7D4",
+            "y": "4Etl6SRW2YiLUrN5vfvVHuhp7x8PxltmWWlbbM4IFyM",
+            "use": "enc",
+            "kid": "1"
+        });
+        assert_eq!(
+            jwk_thumbprint_sha256(&jwk).unwrap(),
+            "cn-I_WNMClehiVp51i_0VpOENW1upEerA8sEam5hn-s"
+        );
+    }
+
+    #[test]
+    fn thumbprint_uri_round_trip() {
+        let jwk = rfc7638_rsa_jwk();
+        let uri = jwk_thumbprint_uri(&jwk).unwrap();
+        assert_eq!(
+            parse_jwk_thumbprint_uri(&uri),
+            Some("NzbLsXh8uDCcd-6MNwXF4W_7noWXFZAfHkxZsRGC9Xs")
+        );
+        assert!(matches_thumbprint_uri(&jwk, &uri));
+
+        let other = json!({
+            "kty": "OKP",
+            "crv": "Ed25519",
+            "x": "11qYAYKxCrfVS_7TyWQHOg7hcvPapiMlrwIaaPcHURo"
+        });
+        assert!(!matches_thumbprint_uri(&other, &uri));
+        // Thumbprint そのものや他のハッシュ関数の URI は kid として一致させない
+        assert!(!matches_thumbprint_uri(
+            &jwk,
+            "NzbLsXh8uDCcd-6MNwXF4W_7noWXFZAfHkxZsRGC9Xs"
+        ));
+        assert!(!matches_thumbprint_uri(
+            &jwk,
+            "urn:ietf:params:oauth:jwk-thumbprint:sha-384:NzbLsXh8uDCcd-6MNwXF4W_7noWXFZAfHkxZsRGC9Xs"
+        ));
+        assert_eq!(parse_jwk_thumbprint_uri(JWK_THUMBPRINT_URI_PREFIX), None);
+    }
+}
@@ -0,0 +1,330 @@
+//! 鍵ファイルの読み込みと JWK 変換
+//!
+//! JWK Thumbprint と JWK の鍵ファイルは `jwk` モジュール。
+
+use crate::{alg::SigningAlgorithm, jwk::jwk_thumbprint_sha256};
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use josekit::jwk::{
+    alg::{ec::EcKeyPair, ed::EdKeyPair, rsa::RsaKeyPair, rsapss::RsaPssKeyPair},
+    KeyPair as _,
+};
+use serde_json::{json, Value};
+use std::path::Path;
+
+/// PEMファイルから鍵を取り出す
+pub fn read_pem_file(file_path: &str) -> Result<Vec<u8>> {
+    let pem = pem::parse(std::fs::read(file_path)?)?;
+    Ok(pem.contents().to_vec())
+}
+
+/// PEM形式の秘密鍵を PKCS#8 (`PRIVATE KEY`) にする
+///
+/// SEC1 (`EC PRIVATE KEY`) の EC 鍵は PKCS#8 に変換し、`openssl ecparam -genkey` が出力する
+/// `EC PARAMETERS` などの秘密鍵以外のブロックは読み飛ばす。それ以外の秘密鍵はそのまま返す。
+pub fn private_key_to_pkcs8_pem(pem: &[u8]) -> Result<Vec<u8>> {
+    let blocks = pem::parse_many(pem)?;
+    let block = blocks
+        .iter()
+        .find(|block| block.tag().ends_with("PRIVATE KEY"))
+        .ok_or_else(|| anyhow!("PEM has no private key"))?;
+    let encoded = pem::encode(block).into_bytes();
+    match block.tag() {
+        "EC PRIVATE KEY" => Ok(EcKeyPair::from_pem(&encoded, None)
+            .map_err(|e| anyhow!("invalid EC private key e={e:?}"))?
+            .to_pem_private_key()),
+        _ => Ok(encoded),
+    }
+}
+
+/// `alg` の鍵ペアを生成し、PEM形式の秘密鍵 (PKCS#8) と公開鍵 (SubjectPublicKeyInfo) を返す
+pub fn generate_key_pair(alg: SigningAlgorithm) -> Result<(Vec<u8>, Vec<u8>)> {
+    use josekit::{
+        jwk::alg::ed::EdCurve,
+        jws::{EdDSA, ES256, ES384, ES512, RS256},
+    };
+
+    let (private_key, public_key) = match alg {
+        SigningAlgorithm::ES256 => {
+            let key_pair = ES256.generate_key_pair()?;
+            (key_pair.to_pem_private_key(), key_pair.to_pem_public_key())
+        }
+        SigningAlgorithm::ES384 => {
+            let key_pair = ES384.generate_key_pair()?;
+            (key_pair.to_pem_private_key(), key_pair.to_pem_public_key())
+        }
+        SigningAlgorithm::ES512 => {
+            let key_pair = ES512.generate_key_pair()?;
+            (key_pair.to_pem_private_key(), key_pair.to_pem_public_key())
+        }
+        SigningAlgorithm::EdDSA => {
+            let key_pair = EdDSA.generate_key_pair(EdCurve::Ed25519)?;
+            (key_pair.to_pem_private_key(), key_pair.to_pem_public_key())
+        }
+        // PS256 も rsaEncryption の RSA 鍵で署名できる
+        SigningAlgorithm::RS256 | SigningAlgorithm::PS256 => {
+            let key_pair = RS256.generate_key_pair(2048)?;
+            (key_pair.to_pem_private_key(), key_pair.to_pem_public_key())
+        }
+    };
+    Ok((private_key, public_key))
+}
+
+/// 秘密鍵を所有者だけが読めるファイル (0600) に書き出す (ディレクトリがなければ作成する)
+///
+/// `overwrite` の場合は既存のファイルも書き込む前に 0600 にしてから置き換え、
+/// そうでない場合は既存のファイルがあればエラーにする。
+pub fn write_private_key(
+    path: impl AsRef<Path>,
+    private_key: &[u8],
+    overwrite: bool,
+) -> Result<()> {
+    let path = path.as_ref();
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true);
+    if overwrite {
+        options.create(true);
+    } else {
+        options.create_new(true);
+    }
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options
+        .open(path)
+        .map_err(|e| anyhow!("failed to write {}: {e:?}", path.display()))?;
+    // mode は新しく作成した場合にしか効かないので、既存のファイルは中身を置き換える前に変更する
+    #[cfg(unix)]
+    file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+    file.set_len(0)?;
+    std::io::Write::write_all(&mut file, private_key)?;
+    Ok(())
+}
+
+/// 鍵ファイル (公開鍵・秘密鍵の PEM または JWK) から公開鍵をJWK形式に変換する
+/// 暗号化した秘密鍵と PKCS#11 URI の鍵は `signer::open` で開いて公開鍵を取り出す
+pub fn public_key_to_jwk(file_path: &str) -> Result<Value> {
+    if file_path.starts_with("pkcs11:") {
+        return crate::signer::open(file_path)?.public_jwk();
+    }
+    let pem = std::fs::read(file_path)?;
+    if crate::jwk::is_jwk(&pem) {
+        return crate::jwk::public_jwk(&crate::jwk::parse_jwk(&pem)?);
+    }
+    if crate::signer::is_encrypted_pem(&pem) {
+        return crate::signer::open(file_path)?.public_jwk();
+    }
+    public_key_pem_to_jwk(&pem)
+}
+
+/// PEM形式の鍵から公開鍵をJWK形式に変換する
+/// 秘密鍵が渡された場合は公開鍵部分を取り出す
+pub fn public_key_pem_to_jwk(pem: &[u8]) -> Result<Value> {
+    let parsed = pem::parse(pem)?;
+
+    let (alg, jwk) = if parsed.tag().ends_with("PUBLIC KEY") {
+        let alg = SigningAlgorithm::from_pem(pem)?;
+        (alg, public_key_der_to_jwk(alg, parsed.contents())?)
+    } else {
+        // 秘密鍵から公開鍵部分を取り出す (SEC1 の EC 鍵も読めるよう PKCS#8 にする)
+        let pem = &private_key_to_pkcs8_pem(pem)?;
+        let alg = SigningAlgorithm::from_pem(pem)?;
+        let jwk = match alg {
+            SigningAlgorithm::ES256 | SigningAlgorithm::ES384 | SigningAlgorithm::ES512 => {
+                EcKeyPair::from_pem(pem, None)?.to_jwk_public_key()
+            }
+            SigningAlgorithm::EdDSA => EdKeyPair::from_pem(pem)?.to_jwk_public_key(),
+            SigningAlgorithm::RS256 => RsaKeyPair::from_pem(pem)?.to_jwk_public_key(),
+            SigningAlgorithm::PS256 => {
+                RsaPssKeyPair::from_pem(pem, None, None, None)?.to_jwk_public_key()
+            }
+        };
+        (alg, Value::Object(jwk.into()))
+    };
+
+    jwk_with_metadata(alg, jwk)
+}
+
+/// 公開鍵の JWK に `alg`・`use` と `kid` (JWK Thumbprint) を付与する
+pub(crate) fn jwk_with_metadata(alg: SigningAlgorithm, mut jwk: Value) -> Result<Value> {
+    if let Some(obj) = jwk.as_object_mut() {
+        obj.insert("alg".to_string(), Value::String(alg.name().to_string()));
+        obj.insert("use".to_string(), Value::String("sig".to_string()));
+    }
+
+    // 一般的な kid（JWK Thumbprint RFC7638のSHA-256, Base64URL）を付与
+    let kid = jwk_thumbprint_sha256(&jwk)?;
+    if let Some(obj) = jwk.as_object_mut() {
+        obj.insert("kid".to_string(), Value::String(kid));
+    }
+
+    Ok(jwk)
+}
+
+/// PEM形式の鍵 (秘密鍵の場合は公開鍵部分) の RFC 7638 JWK Thumbprint
+/// JWS ヘッダの `kid` を指定しない場合の既定値
+pub fn pem_thumbprint(pem: &[u8]) -> Result<String> {
+    jwk_thumbprint_sha256(&public_key_pem_to_jwk(pem)?)
+}
+
+/// DER形式の公開鍵から必須フィールドだけの JWK を作成
+/// RSA 鍵は SubjectPublicKeyInfo と PKCS#1 (RSA PUBLIC KEY) のどちらでもよい
+fn public_key_der_to_jwk(alg: SigningAlgorithm, der: &[u8]) -> Result<Value> {
+    use p256::{
+        elliptic_curve::sec1::ToEncodedPoint as _,
+        pkcs8::{spki::SubjectPublicKeyInfoRef, DecodePublicKey},
+    };
+
+    match alg {
+        SigningAlgorithm::ES256 => {
+            let public_key = p256::PublicKey::from_public_key_der(der)
+                .map_err(|e| anyhow!("failed convert public key from pem e={e:?}"))?;
+            ec_point_to_jwk("P-256", public_key.to_encoded_point(false).as_bytes())
+        }
+        SigningAlgorithm::ES384 => {
+            let public_key = p384::PublicKey::from_public_key_der(der)
+                .map_err(|e| anyhow!("failed convert public key from pem e={e:?}"))?;
+            ec_point_to_jwk("P-384", public_key.to_encoded_point(false).as_bytes())
+        }
+        SigningAlgorithm::ES512 => {
+            let public_key = p521::PublicKey::from_public_key_der(der)
+                .map_err(|e| anyhow!("failed convert public key from pem e={e:?}"))?;
+            ec_point_to_jwk("P-521", public_key.to_encoded_point(false).as_bytes())
+        }
+        SigningAlgorithm::EdDSA => {
+            // Ed25519 の公開鍵は subjectPublicKey の 32 バイトそのもの
+            let spki = SubjectPublicKeyInfoRef::try_from(der)
+                .map_err(|e| anyhow!("failed to parse public key e={e:?}"))?;
+            let public_key_bytes = spki.subject_public_key.raw_bytes();
+            Ok(json!({
+                "kty": "OKP",
+                "crv": "Ed25519",
+                "x": URL_SAFE_NO_PAD.encode(public_key_bytes),
+            }))
+        }
+        SigningAlgorithm::RS256 | SigningAlgorithm::PS256 => {
+            use rsa::{pkcs1::DecodeRsaPublicKey as _, traits::PublicKeyParts as _};
+
+            let public_key = rsa::RsaPublicKey::from_public_key_der(der)
+                .or_else(|_| rsa::RsaPublicKey::from_pkcs1_der(der))
+                .or_else(|_| {
+                    // id-RSASSA-PSS の SubjectPublicKeyInfo は中身が PKCS#1 の公開鍵
+                    let spki = SubjectPublicKeyInfoRef::try_from(der)
+                        .map_err(|e| anyhow!("failed to parse public key e={e:?}"))?;
+                    rsa::RsaPublicKey::from_pkcs1_der(spki.subject_public_key.raw_bytes())
+                        .map_err(|e| anyhow!("failed convert public key from pem e={e:?}"))
+                })?;
+            Ok(json!({
+                "kty": "RSA",
+                "n": URL_SAFE_NO_PAD.encode(public_key.n().to_bytes_be()),
+                "e": URL_SAFE_NO_PAD.encode(public_key.e().to_bytes_be()),
+            }))
+        }
+    }
+}
+
+/// 非圧縮ポイント (0x04 || X || Y) から EC の JWK を作成
+pub(crate) fn ec_point_to_jwk(crv: &str, encoded_point: &[u8]) -> Result<Value> {
+    let coordinates = match encoded_point.split_first() {
+        Some((0x04, coordinates)) if coordinates.len() % 2 == 0 => coordinates,
+        _ => return Err(anyhow!("Failed to get X, Y coordinates")),
+    };
+    let (x_bytes, y_bytes) = coordinates.split_at(coordinates.len() / 2);
+
+    // Base64URL(=paddingなし)でエンコード
+    Ok(json!({
+        "kty": "EC",
+        "crv": crv,
+        "x": URL_SAFE_NO_PAD.encode(x_bytes),
+        "y": URL_SAFE_NO_PAD.encode(y_bytes),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// openssl ecparam -genkey が出力する形式 (EC PARAMETERS + SEC1)
+    fn sec1_private_key() -> (Vec<u8>, Vec<u8>) {
+        let group =
+            openssl::ec::EcGroup::from_curve_name(openssl::nid::Nid::X9_62_PRIME256V1).unwrap();
+        let key = openssl::ec::EcKey::generate(&group).unwrap();
+        let mut pem =
+            b"-----BEGIN EC PARAMETERS-----\nBggqhkjOPQMBBw==\n-----END EC PARAMETERS-----\n"
+                .to_vec();
+        pem.extend(key.private_key_to_pem().unwrap());
+        (pem, key.public_key_to_pem().unwrap())
+    }
+
+    #[test]
+    fn sec1_private_key_is_converted_to_pkcs8() {
+        let (sec1, public_key) = sec1_private_key();
+        assert!(String::from_utf8_lossy(&sec1).contains("EC PRIVATE KEY"));
+        let pkcs8 = private_key_to_pkcs8_pem(&sec1).unwrap();
+        assert_eq!(pem::parse(&pkcs8).unwrap().tag(), "PRIVATE KEY");
+        assert_eq!(
+            SigningAlgorithm::from_pem(&pkcs8).unwrap(),
+            SigningAlgorithm::ES256
+        );
+        assert_eq!(
+            public_key_pem_to_jwk(&sec1).unwrap(),
+            public_key_pem_to_jwk(&public_key).unwrap()
+        );
+    }
+
+    #[test]
+    fn public_jwk_from_private_and_public_key() {
+        for alg in [
+            SigningAlgorithm::ES256,
+            SigningAlgorithm::ES384,
+            SigningAlgorithm::ES512,
+            SigningAlgorithm::EdDSA,
+            SigningAlgorithm::RS256,
+        ] {
+            let (private_key, public_key) = generate_key_pair(alg).unwrap();
+            let jwk = public_key_pem_to_jwk(&public_key).unwrap();
+            assert_eq!(public_key_pem_to_jwk(&private_key).unwrap(), jwk, "{alg}");
+            assert_eq!(jwk["alg"], alg.name());
+            assert_eq!(jwk["use"], "sig");
+            assert_eq!(jwk["kid"], pem_thumbprint(&private_key).unwrap());
+            assert!(jwk.get("d").is_none());
+            assert_eq!(SigningAlgorithm::from_jwk(&jwk).unwrap(), alg);
+        }
+    }
+
+    #[test]
+    fn pkcs8_key_is_unchanged() {
+        let (private_key, _) = generate_key_pair(SigningAlgorithm::EdDSA).unwrap();
+        let pkcs8 = private_key_to_pkcs8_pem(&private_key).unwrap();
+        assert_eq!(
+            pem::parse(pkcs8).unwrap(),
+            pem::parse(&private_key).unwrap()
+        );
+        assert!(private_key_to_pkcs8_pem(
+            b"-----BEGIN PUBLIC KEY-----\nAA==\n-----END PUBLIC KEY-----\n"
+        )
+        .is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn write_private_key_restricts_existing_file() {
+        use std::os::unix::fs::PermissionsExt as _;
+
+        let dir = std::env::temp_dir().join(format!("vc_vp_key_{}", std::process::id()));
+        let path = dir.join("nested").join("private.pem");
+        write_private_key(&path, b"first", false).unwrap();
+        let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&path), 0o600);
+        assert!(write_private_key(&path, b"second", false).is_err());
+
+        // --force で上書きする既存のファイルが 0644 でも 0600 にする
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        write_private_key(&path, b"second", true).unwrap();
+        assert_eq!(mode(&path), 0o600);
+        assert_eq!(std::fs::read(&path).unwrap(), b"second");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
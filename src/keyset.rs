@@ -0,0 +1,508 @@
+//! 発行者の署名鍵のキーセット (鍵のローテーション)
+//!
+//! JSON ファイル (既定は `issuer_keys.json`) に発行者の秘密鍵を並べ、`not_before` を過ぎた最も新しい鍵で
+//! 署名する。署名に使わなくなった鍵も `not_after` までは JWKS で公開し、Verifier が以前の鍵で署名された
+//! VC を有効期限まで検証できるようにする。`kid` は公開鍵の JWK Thumbprint (RFC 7638)。
+//! `file` には暗号化した秘密鍵や PKCS#11 URI も指定できる (`signer::open` を参照)。
+//!
+//! ```json
+//! {
+//!   "keys": [
+//!     { "file": "el_issuer_private_key_ES256.pem", "kid": "VCVk4e6-...", "not_before": 0, "not_after": 1790000000 },
+//!     { "file": "issuer_keys/3xVn...pem", "kid": "3xVn...", "not_before": 1789395200 }
+//!   ]
+//! }
+//! ```
+
+use crate::{
+    alg::SigningAlgorithm,
+    issuer::Issuer,
+    jwk::jwk_thumbprint_sha256,
+    key::{generate_key_pair, pem_thumbprint, write_private_key},
+    signer::{self, Signer},
+};
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// キーセットのファイルの既定値
+pub const DEFAULT_KEY_SET_FILE: &str = "issuer_keys.json";
+/// `rotate` で生成した秘密鍵を置くディレクトリ (キーセットのファイルと同じディレクトリからの相対)
+pub const GENERATED_KEY_DIR: &str = "issuer_keys";
+
+/// キーセットの鍵
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IssuerKeyEntry {
+    /// PEM形式の秘密鍵のファイル (相対パスはキーセットのファイルのディレクトリから)、または PKCS#11 URI
+    pub file: PathBuf,
+    /// JWS ヘッダの `kid` (省略した場合は公開鍵の JWK Thumbprint)
+    #[serde(default)]
+    pub kid: String,
+    /// 署名に使い始める時刻 (UNIX 時間)
+    #[serde(default)]
+    pub not_before: u64,
+    /// JWKS での公開をやめる時刻 (UNIX 時間、None の場合は公開し続ける)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub not_after: Option<u64>,
+}
+
+/// ある時刻でのキーセットの鍵の状態
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyState {
+    /// 署名に使う鍵
+    Active,
+    /// `not_before` 前で、JWKS で先に公開している鍵
+    Pending,
+    /// 署名には使わず、JWKS で公開して検証だけに使う鍵
+    Retired,
+    /// `not_after` を過ぎて JWKS からも外した鍵
+    Expired,
+}
+
+impl fmt::Display for KeyState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Active => "active",
+            Self::Pending => "pending",
+            Self::Retired => "retired",
+            Self::Expired => "expired",
+        })
+    }
+}
+
+/// ファイルに保存する内容
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+struct KeySetState {
+    keys: Vec<IssuerKeyEntry>,
+}
+
+/// 発行者の署名鍵のキーセット
+///
+/// 操作のたびにファイルの更新時刻を確認し、変わっていれば読み直すので、サーバの実行中に別のプロセスで
+/// ローテーションしても反映される。
+pub struct IssuerKeySet {
+    path: PathBuf,
+    /// 同じプロセス内での読み書きを直列にし、`kid` を補った内容をファイルの更新時刻とともにキャッシュする
+    /// (`kid` を省略した鍵の JWK Thumbprint を、発行や JWKS のたびに鍵を開いて計算し直さない)
+    cache: Mutex<Option<(SystemTime, KeySetState)>>,
+    /// 開いた鍵 (`file` ごと)。暗号化した鍵の復号や PKCS#11 のログインを発行や JWKS のたびに
+    /// やり直さないよう、キーセットのファイルを読み直すまで使い回す
+    signers: Mutex<HashMap<PathBuf, Arc<dyn Signer>>>,
+}
+
+impl IssuerKeySet {
+    /// ファイルがあれば開き、なければ空のキーセットを作成する
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let key_set = Self {
+            path: path.into(),
+            cache: Mutex::new(None),
+            signers: Mutex::new(HashMap::new()),
+        };
+        if key_set.path.exists() {
+            key_set.load()?;
+        } else {
+            key_set.save(&KeySetState::default())?;
+        }
+        Ok(key_set)
+    }
+
+    /// 環境変数 ISSUER_KEY_SET が設定されていれば、そのキーセットを開く
+    pub fn from_env() -> Result<Option<Self>> {
+        match std::env::var("ISSUER_KEY_SET") {
+            Ok(path) => Self::open(path).map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// キーセットのファイルのパス
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// すべての鍵と `now` での状態 (登録した順)
+    pub fn keys(&self, now: SystemTime) -> Result<Vec<(IssuerKeyEntry, KeyState)>> {
+        let state = self.read()?;
+        let now = unix_time(now);
+        let active = active_index(&state.keys, now);
+        Ok(state
+            .keys
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| (entry.clone(), key_state(entry, active == Some(i), now)))
+            .collect())
+    }
+
+    /// 現在の署名鍵の発行者
+    /// JWKS には署名鍵に加えて、公開中の他の鍵 (ローテーション前後の鍵) も含める
+    pub fn issuer(&self, iss: impl Into<String>) -> Result<Issuer> {
+        let state = self.read()?;
+        let now = unix_time(SystemTime::now());
+        let active = active_index(&state.keys, now)
+            .ok_or_else(|| anyhow!("no active key in {}", self.path.display()))?;
+        let entry = &state.keys[active];
+        let signer = self.signer(entry)?;
+        let verification_keys = state
+            .keys
+            .iter()
+            .enumerate()
+            .filter(|(i, entry)| *i != active && is_published(entry, now))
+            .map(|(_, entry)| self.public_jwk(entry))
+            .collect::<Result<Vec<_>>>()?;
+        Issuer::from_signer(iss, signer, &entry.kid).with_verification_keys(verification_keys)
+    }
+
+    /// 公開中のすべての鍵 (署名鍵・切り替え前の鍵・切り替え後の検証用の鍵) の JWKS
+    pub fn jwks(&self) -> Result<Value> {
+        let state = self.read()?;
+        let now = unix_time(SystemTime::now());
+        let keys = state
+            .keys
+            .iter()
+            .filter(|entry| is_published(entry, now))
+            .map(|entry| self.public_jwk(entry))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(json!({ "keys": keys }))
+    }
+
+    /// 既存の秘密鍵を `not_before` から署名に使う鍵として追加する (`kid` の既定値は JWK Thumbprint)
+    pub fn add(
+        &self,
+        file: impl Into<PathBuf>,
+        kid: Option<String>,
+        not_before: SystemTime,
+    ) -> Result<IssuerKeyEntry> {
+        let mut entry = IssuerKeyEntry {
+            file: file.into(),
+            kid: kid.unwrap_or_default(),
+            not_before: unix_time(not_before),
+            not_after: None,
+        };
+        self.update(|state| {
+            self.resolve_kid(&mut entry)?;
+            if state.keys.iter().any(|e| e.kid == entry.kid) {
+                bail!("key {} is already in {}", entry.kid, self.path.display());
+            }
+            state.keys.push(entry.clone());
+            Ok(entry)
+        })
+    }
+
+    /// `alg` の鍵を生成して `activate_at` から署名に使い、それまでの鍵は `retire_after` 後
+    /// (切り替え直前に署名した VC が期限切れになる時刻) に JWKS から外すよう `not_after` を設定する
+    ///
+    /// 生成した鍵は切り替えまでの間も JWKS で公開するので、Verifier は先に新しい鍵を取得できる。
+    /// 環境変数 PRIVATE_KEY_PASSWORD が設定されていれば、生成した鍵はそのパスワードで暗号化する。
+    pub fn rotate(
+        &self,
+        alg: SigningAlgorithm,
+        activate_at: SystemTime,
+        retire_after: Duration,
+    ) -> Result<IssuerKeyEntry> {
+        let (private_key, public_key) = generate_key_pair(alg)?;
+        let kid = pem_thumbprint(&public_key)?;
+        let file = Path::new(GENERATED_KEY_DIR).join(format!("{kid}.pem"));
+        let private_key = match signer::password_from_env()? {
+            Some(password) => signer::encrypt_private_key_pem(&private_key, password.as_bytes())?,
+            None => private_key,
+        };
+        write_private_key(self.resolve(&file), &private_key, false)?;
+
+        let not_before = unix_time(activate_at);
+        let not_after = not_before + retire_after.as_secs();
+        self.update(|state| {
+            for entry in &mut state.keys {
+                if entry.not_before <= not_before {
+                    entry.not_after = Some(entry.not_after.map_or(not_after, |t| t.min(not_after)));
+                }
+            }
+            let entry = IssuerKeyEntry {
+                file,
+                kid,
+                not_before,
+                not_after: None,
+            };
+            state.keys.push(entry.clone());
+            Ok(entry)
+        })
+    }
+
+    /// JWKS からも外した (`not_after` を過ぎた) 鍵をキーセットから削除し、削除したものを返す
+    /// 秘密鍵のファイルは削除しない
+    pub fn remove_expired(&self) -> Result<Vec<IssuerKeyEntry>> {
+        let now = unix_time(SystemTime::now());
+        self.update(|state| {
+            let (expired, keys) = std::mem::take(&mut state.keys)
+                .into_iter()
+                .partition(|entry| entry.not_after.is_some_and(|t| t <= now));
+            state.keys = keys;
+            Ok(expired)
+        })
+    }
+
+    /// `kid` が空の鍵は JWK Thumbprint にする
+    fn resolve_kid(&self, entry: &mut IssuerKeyEntry) -> Result<()> {
+        if entry.kid.is_empty() {
+            entry.kid = jwk_thumbprint_sha256(&self.signer(entry)?.public_jwk()?)?;
+        }
+        Ok(())
+    }
+
+    /// 鍵の公開鍵 (`kid` はキーセットの kid)
+    fn public_jwk(&self, entry: &IssuerKeyEntry) -> Result<Value> {
+        let mut jwk = self.signer(entry)?.public_jwk()?;
+        jwk["kid"] = Value::String(entry.kid.clone());
+        Ok(jwk)
+    }
+
+    /// 鍵で署名するバックエンド (開いたものはキャッシュする)
+    fn signer(&self, entry: &IssuerKeyEntry) -> Result<Arc<dyn Signer>> {
+        if let Some(signer) = self.cached_signers().get(&entry.file) {
+            return Ok(signer.clone());
+        }
+        let file = entry.file.to_string_lossy();
+        let signer = if file.starts_with("pkcs11:") {
+            signer::open(&file)?
+        } else {
+            signer::open(&self.resolve(&entry.file).to_string_lossy())?
+        };
+        self.cached_signers()
+            .insert(entry.file.clone(), signer.clone());
+        Ok(signer)
+    }
+
+    fn cached_signers(&self) -> MutexGuard<'_, HashMap<PathBuf, Arc<dyn Signer>>> {
+        self.signers.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// キーセットのファイルのディレクトリからの相対パスを解決する
+    fn resolve(&self, file: &Path) -> PathBuf {
+        match self.path.parent() {
+            Some(dir) if file.is_relative() => dir.join(file),
+            _ => file.to_path_buf(),
+        }
+    }
+
+    /// ファイルを読み込み、省略された `kid` を補う
+    fn read(&self) -> Result<KeySetState> {
+        let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+        self.resolved(&mut cache)
+    }
+
+    /// ファイルを読み込んで `f` で変更し、保存する
+    fn update<T>(&self, f: impl FnOnce(&mut KeySetState) -> Result<T>) -> Result<T> {
+        let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+        let mut state = self.resolved(&mut cache)?;
+        let value = f(&mut state)?;
+        self.save(&state)?;
+        *cache = Some((self.modified()?, state));
+        Ok(value)
+    }
+
+    /// `kid` を補った内容 (ファイルの更新時刻が変わっていなければキャッシュ)
+    fn resolved(&self, cache: &mut Option<(SystemTime, KeySetState)>) -> Result<KeySetState> {
+        let modified = self.modified()?;
+        if let Some((cached, state)) = cache.as_ref() {
+            if *cached == modified {
+                return Ok(state.clone());
+            }
+        }
+        // ファイルが変わったら鍵も開き直す
+        self.cached_signers().clear();
+        let mut state = self.load()?;
+        for entry in &mut state.keys {
+            self.resolve_kid(entry)?;
+        }
+        *cache = Some((modified, state.clone()));
+        Ok(state)
+    }
+
+    /// キーセットのファイルの更新時刻
+    fn modified(&self) -> Result<SystemTime> {
+        std::fs::metadata(&self.path)
+            .and_then(|metadata| metadata.modified())
+            .map_err(|e| anyhow!("failed to read key set {}: {e:?}", self.path.display()))
+    }
+
+    fn load(&self) -> Result<KeySetState> {
+        let contents = std::fs::read_to_string(&self.path)
+            .map_err(|e| anyhow!("failed to read key set {}: {e:?}", self.path.display()))?;
+        serde_json::from_str(&contents)
+            .map_err(|e| anyhow!("invalid key set {}: {e}", self.path.display()))
+    }
+
+    /// 読み込み中のプロセスが書きかけのファイルを読まないよう、一時ファイルから rename する
+    fn save(&self, state: &KeySetState) -> Result<()> {
+        let contents = serde_json::to_string_pretty(state)?;
+        let tmp = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp, contents)
+            .and_then(|_| std::fs::rename(&tmp, &self.path))
+            .map_err(|e| anyhow!("failed to write key set {}: {e:?}", self.path.display()))
+    }
+}
+
+/// `now` に署名に使う鍵 (`not_before` を過ぎて公開中の鍵のうち最も新しいもの) の位置
+fn active_index(keys: &[IssuerKeyEntry], now: u64) -> Option<usize> {
+    keys.iter()
+        .enumerate()
+        .filter(|(_, entry)| entry.not_before <= now && is_published(entry, now))
+        .max_by_key(|(i, entry)| (entry.not_before, *i))
+        .map(|(i, _)| i)
+}
+
+fn key_state(entry: &IssuerKeyEntry, active: bool, now: u64) -> KeyState {
+    if active {
+        KeyState::Active
+    } else if !is_published(entry, now) {
+        KeyState::Expired
+    } else if entry.not_before > now {
+        KeyState::Pending
+    } else {
+        KeyState::Retired
+    }
+}
+
+/// `now` に JWKS で公開する鍵か
+fn is_published(entry: &IssuerKeyEntry, now: u64) -> bool {
+    entry.not_after.is_none_or(|t| now < t)
+}
+
+fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("vc_vp_{name}_{}", std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn set_modified(path: &Path, time: SystemTime) {
+        std::fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(time)
+            .unwrap();
+    }
+
+    #[test]
+    fn resolved_kid_is_cached_until_the_file_changes() {
+        let dir = TempDir::new("keyset_cache");
+        let (first, first_public) = generate_key_pair(SigningAlgorithm::ES256).unwrap();
+        let (second, second_public) = generate_key_pair(SigningAlgorithm::ES256).unwrap();
+        std::fs::write(dir.0.join("issuer.pem"), first).unwrap();
+        let path = dir.0.join("keys.json");
+        std::fs::write(&path, r#"{"keys":[{"file":"issuer.pem"}]}"#).unwrap();
+        let key_set = IssuerKeySet::open(&path).unwrap();
+        let kid =
+            |key_set: &IssuerKeySet| key_set.keys(SystemTime::now()).unwrap()[0].0.kid.clone();
+        assert_eq!(kid(&key_set), pem_thumbprint(&first_public).unwrap());
+
+        // キーセットのファイルが変わらなければ鍵を開き直さない
+        let modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+        std::fs::write(dir.0.join("issuer.pem"), second).unwrap();
+        assert_eq!(kid(&key_set), pem_thumbprint(&first_public).unwrap());
+
+        set_modified(&path, modified + Duration::from_secs(10));
+        assert_eq!(kid(&key_set), pem_thumbprint(&second_public).unwrap());
+    }
+
+    #[test]
+    fn opened_keys_are_cached_until_the_file_changes() {
+        let dir = TempDir::new("keyset_signers");
+        let (first, first_public) = generate_key_pair(SigningAlgorithm::ES256).unwrap();
+        let (second, second_public) = generate_key_pair(SigningAlgorithm::ES256).unwrap();
+        std::fs::write(dir.0.join("issuer.pem"), first).unwrap();
+        let path = dir.0.join("keys.json");
+        std::fs::write(&path, r#"{"keys":[{"file":"issuer.pem","kid":"key-1"}]}"#).unwrap();
+        let key_set = IssuerKeySet::open(&path).unwrap();
+        let x = |public: &[u8]| crate::key::public_key_pem_to_jwk(public).unwrap()["x"].clone();
+        let jwks_x = |key_set: &IssuerKeySet| key_set.jwks().unwrap()["keys"][0]["x"].clone();
+        key_set.issuer("https://issuer.example.com").unwrap();
+        assert_eq!(jwks_x(&key_set), x(&first_public));
+
+        // 鍵のファイルを差し替えても、キーセットのファイルが変わらなければ開いた鍵を使う
+        let modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+        std::fs::write(dir.0.join("issuer.pem"), second).unwrap();
+        key_set.issuer("https://issuer.example.com").unwrap();
+        assert_eq!(jwks_x(&key_set), x(&first_public));
+
+        set_modified(&path, modified + Duration::from_secs(10));
+        assert_eq!(jwks_x(&key_set), x(&second_public));
+    }
+
+    #[test]
+    fn rotate_publishes_the_next_key_and_retires_the_previous_one() {
+        let dir = TempDir::new("keyset_rotate");
+        let key_set = IssuerKeySet::open(dir.0.join("keys.json")).unwrap();
+        let now = SystemTime::now();
+        let first = key_set
+            .rotate(SigningAlgorithm::ES256, now, Duration::from_secs(60))
+            .unwrap();
+        let activate_at = now + Duration::from_secs(3600);
+        let second = key_set
+            .rotate(
+                SigningAlgorithm::EdDSA,
+                activate_at,
+                Duration::from_secs(60),
+            )
+            .unwrap();
+
+        let states = |time: SystemTime| -> Vec<(String, KeyState)> {
+            key_set
+                .keys(time)
+                .unwrap()
+                .into_iter()
+                .map(|(entry, state)| (entry.kid, state))
+                .collect()
+        };
+        assert_eq!(
+            states(now),
+            [
+                (first.kid.clone(), KeyState::Active),
+                (second.kid.clone(), KeyState::Pending)
+            ]
+        );
+        assert_eq!(
+            states(activate_at),
+            [
+                (first.kid.clone(), KeyState::Retired),
+                (second.kid.clone(), KeyState::Active)
+            ]
+        );
+        assert_eq!(
+            states(activate_at + Duration::from_secs(60)),
+            [
+                (first.kid.clone(), KeyState::Expired),
+                (second.kid.clone(), KeyState::Active)
+            ]
+        );
+        // 生成した鍵は切り替え前から JWKS で公開する
+        let jwks = key_set.jwks().unwrap();
+        assert_eq!(jwks["keys"].as_array().unwrap().len(), 2);
+        assert_eq!(key_set.issuer("iss").unwrap().key_id(), first.kid);
+    }
+}
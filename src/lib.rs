@@ -0,0 +1,40 @@
+//! SD-JWT VC の発行 (Issuer)・提示 (Holder)・検証 (Verifier) を行うライブラリ
+//!
+//! `src/bin/` 以下のバイナリはこのライブラリを使った CLI のサンプル。
+
+pub mod alg;
+pub mod config;
+pub mod credential_store;
+pub mod credential_type;
+pub mod did;
+pub mod disclosure;
+pub mod error;
+pub mod holder;
+pub mod http;
+pub mod issuer;
+pub mod issuer_metadata;
+mod jose;
+pub mod jwk;
+pub mod key;
+pub mod keyset;
+pub mod mdoc;
+pub mod nonce;
+pub mod oid4vci;
+pub mod oid4vp;
+#[cfg(feature = "pkcs11")]
+pub mod pkcs11;
+pub mod sd_jwt;
+pub mod server;
+pub mod signer;
+pub mod status_list;
+pub mod trust;
+pub mod type_metadata;
+pub mod vcdm;
+pub mod verifier;
+pub mod wallet;
+
+pub use alg::SigningAlgorithm;
+pub use error::{IssuanceError, VerificationError};
+pub use holder::Holder;
+pub use issuer::{GenerateVCParams, Issuer};
+pub use verifier::{VerificationResult, Verifier};
@@ -0,0 +1,884 @@
+//! ISO/IEC 18013-5 の mdoc (`mso_mdoc`)
+//!
+//! - 発行: クレームを `IssuerSignedItem` にし、そのダイジェストを並べた MSO (Mobile Security Object) を
+//!   発行者の鍵で COSE_Sign1 に署名する (`IssuerSigned`)
+//! - 提示: Holder は開示する要素だけを選び、SessionTranscript を含む DeviceAuthentication に
+//!   MSO の `deviceKey` で署名して DeviceResponse を作る
+//! - 検証: `issuerAuth` の署名・要素のダイジェスト・有効期間・`deviceSignature` を確認する
+//!
+//! SessionTranscript は OpenID4VP 1.0 (Appendix B.2.6.1) の `OpenID4VPHandover` (リダイレクトのフロー)。
+//!
+//! ISO 18013-5 とは異なるこのサンプルの独自のプロファイル: 署名は JWS と同じ鍵 (ES256 など) を使い、
+//! `issuerAuth` には発行者の証明書 (`x5chain`、IACA が発行した Document Signer 証明書) の代わりに `kid` を入れる。
+//! そのため ISO 18013-5 の Verifier は発行者を検証できず、このクレートの Verifier は発行者の公開鍵 (PEM) で検証する。
+
+use crate::{alg::SigningAlgorithm, error::VerificationError, vcdm};
+use anyhow::{anyhow, bail, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use ciborium::Value as Cbor;
+use josekit::jws::{JwsSigner, JwsVerifier};
+use rand::{seq::SliceRandom as _, RngCore as _};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    time::{Duration, SystemTime},
+};
+
+/// mDL の docType
+pub const MDL_DOCTYPE: &str = "org.iso.18013.5.1.mDL";
+/// mDL の名前空間
+pub const MDL_NAMESPACE: &str = "org.iso.18013.5.1";
+/// OID4VP などで使う mdoc の形式の識別子
+pub const MSO_MDOC_FORMAT: &str = "mso_mdoc";
+
+/// COSE ヘッダの `alg`
+const COSE_HEADER_ALG: i64 = 1;
+/// COSE ヘッダの `kid`
+const COSE_HEADER_KID: i64 = 4;
+/// 埋め込み CBOR (`#6.24(bstr)`) のタグ
+const TAG_ENCODED_CBOR: u64 = 24;
+/// `tdate` のタグ
+const TAG_TDATE: u64 = 0;
+
+/// mdoc の発行時のパラメータ
+pub struct MdocParams {
+    /// `docType` (mDL の場合は `MDL_DOCTYPE`)
+    pub doc_type: String,
+    /// 名前空間ごとの要素 (要素名と値)
+    pub namespaces: BTreeMap<String, Map<String, Value>>,
+    /// MSO の `deviceKey` にする Holder の公開鍵 (JWK)
+    pub holder_jwk: Value,
+    /// 有効期間
+    pub valid_for: Duration,
+}
+
+/// DeviceResponse の検証結果 (ドキュメントごと)
+#[derive(Debug, Clone)]
+pub struct MdocDocument {
+    pub doc_type: String,
+    /// 開示された要素 (名前空間ごと)
+    pub claims: Map<String, Value>,
+    /// MSO の `deviceKey` (JWK)
+    pub device_jwk: Value,
+    /// MSO の `validityInfo.validUntil`
+    pub valid_until: SystemTime,
+}
+
+/// OpenID4VP 1.0 の SessionTranscript (`[null, null, OpenID4VPHandover]`)
+///
+/// `OpenID4VPHandover` は `["OpenID4VPHandover", sha256(OpenID4VPHandoverInfo)]` で、
+/// `OpenID4VPHandoverInfo` は `[client_id, nonce, jwkThumbprint, response_uri]` の CBOR。
+/// レスポンスを暗号化しないので `jwkThumbprint` は null
+pub fn session_transcript(client_id: &str, nonce: &str, response_uri: &str) -> Result<Vec<u8>> {
+    let handover_info = encode(&Cbor::Array(vec![
+        text(client_id),
+        text(nonce),
+        Cbor::Null,
+        text(response_uri),
+    ]))?;
+    encode(&Cbor::Array(vec![
+        Cbor::Null,
+        Cbor::Null,
+        Cbor::Array(vec![
+            text("OpenID4VPHandover"),
+            Cbor::Bytes(Sha256::digest(handover_info).to_vec()),
+        ]),
+    ]))
+}
+
+/// `IssuerSigned` (`nameSpaces` と `issuerAuth`) を作成して CBOR で返す
+pub(crate) fn issuer_signed(
+    params: MdocParams,
+    alg: SigningAlgorithm,
+    kid: &str,
+    signer: &dyn JwsSigner,
+) -> Result<Vec<u8>> {
+    let mut rng = rand::rng();
+    let mut namespaces = Vec::new();
+    let mut value_digests = Vec::new();
+    for (namespace, elements) in params.namespaces {
+        // digestID は要素の並びから推測されないよう並べ替える
+        let mut digest_ids: Vec<u64> = (0..elements.len() as u64).collect();
+        digest_ids.shuffle(&mut rng);
+
+        let mut items = Vec::new();
+        let mut digests = Vec::new();
+        for ((identifier, value), digest_id) in elements.into_iter().zip(digest_ids) {
+            let mut random = [0u8; 16];
+            rng.fill_bytes(&mut random);
+            let item = Cbor::Map(vec![
+                (text("digestID"), Cbor::Integer(digest_id.into())),
+                (text("random"), Cbor::Bytes(random.to_vec())),
+                (text("elementIdentifier"), text(&identifier)),
+                (text("elementValue"), json_to_cbor(&value)),
+            ]);
+            let item = encoded_cbor(&item)?;
+            digests.push((
+                Cbor::Integer(digest_id.into()),
+                Cbor::Bytes(Sha256::digest(encode(&item)?).to_vec()),
+            ));
+            items.push(item);
+        }
+        namespaces.push((text(&namespace), Cbor::Array(items)));
+        value_digests.push((text(&namespace), Cbor::Map(digests)));
+    }
+
+    let now = SystemTime::now();
+    let tdate =
+        |time: SystemTime| Cbor::Tag(TAG_TDATE, Box::new(text(&vcdm::format_datetime(time))));
+    let mso = Cbor::Map(vec![
+        (text("version"), text("1.0")),
+        (text("digestAlgorithm"), text("SHA-256")),
+        (text("valueDigests"), Cbor::Map(value_digests)),
+        (
+            text("deviceKeyInfo"),
+            Cbor::Map(vec![(text("deviceKey"), cose_key(&params.holder_jwk)?)]),
+        ),
+        (text("docType"), text(&params.doc_type)),
+        (
+            text("validityInfo"),
+            Cbor::Map(vec![
+                (text("signed"), tdate(now)),
+                (text("validFrom"), tdate(now)),
+                (text("validUntil"), tdate(now + params.valid_for)),
+            ]),
+        ),
+    ]);
+    let payload = encode(&encoded_cbor(&mso)?)?;
+    let issuer_auth = sign1(alg, Some(kid), Some(payload), &[], signer)?;
+
+    encode(&Cbor::Map(vec![
+        (text("nameSpaces"), Cbor::Map(namespaces)),
+        (text("issuerAuth"), issuer_auth),
+    ]))
+}
+
+/// `IssuerSigned` から `elements` の要素だけを開示した DeviceResponse を作成する
+/// `elements` は要素名 (すべての名前空間から探す)
+pub(crate) fn device_response(
+    issuer_signed: &[u8],
+    elements: &[&str],
+    session_transcript: &[u8],
+    alg: SigningAlgorithm,
+    signer: &dyn JwsSigner,
+) -> Result<Vec<u8>> {
+    let issuer_signed = decode(issuer_signed)?;
+    let issuer_auth = get(&issuer_signed, "issuerAuth")
+        .ok_or_else(|| anyhow!("issuerSigned has no issuerAuth"))?
+        .clone();
+    let mso = mso_of(&issuer_auth)?;
+    let doc_type = get(&mso, "docType")
+        .and_then(Cbor::as_text)
+        .ok_or_else(|| anyhow!("mso has no docType"))?
+        .to_string();
+
+    let mut namespaces = Vec::new();
+    if let Some(Cbor::Map(entries)) = get(&issuer_signed, "nameSpaces") {
+        for (namespace, items) in entries {
+            let selected: Vec<Cbor> = items
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter(|item| {
+                    decode_encoded_cbor(item)
+                        .ok()
+                        .and_then(|item| {
+                            get(&item, "elementIdentifier")
+                                .and_then(Cbor::as_text)
+                                .map(|id| elements.contains(&id))
+                        })
+                        .unwrap_or(false)
+                })
+                .cloned()
+                .collect();
+            if !selected.is_empty() {
+                namespaces.push((namespace.clone(), Cbor::Array(selected)));
+            }
+        }
+    }
+
+    // DeviceNameSpaces は空 (Holder が追加する要素はない)
+    let device_namespaces = encoded_cbor(&Cbor::Map(vec![]))?;
+    let device_authentication =
+        device_authentication(&decode(session_transcript)?, &doc_type, &device_namespaces)?;
+    let device_signature = sign1(alg, None, None, &device_authentication, signer)?;
+
+    let document = Cbor::Map(vec![
+        (text("docType"), text(&doc_type)),
+        (
+            text("issuerSigned"),
+            Cbor::Map(vec![
+                (text("nameSpaces"), Cbor::Map(namespaces)),
+                (text("issuerAuth"), issuer_auth),
+            ]),
+        ),
+        (
+            text("deviceSigned"),
+            Cbor::Map(vec![
+                (text("nameSpaces"), device_namespaces),
+                (
+                    text("deviceAuth"),
+                    Cbor::Map(vec![(text("deviceSignature"), device_signature)]),
+                ),
+            ]),
+        ),
+    ]);
+    encode(&Cbor::Map(vec![
+        (text("version"), text("1.0")),
+        (text("documents"), Cbor::Array(vec![document])),
+        (text("status"), Cbor::Integer(0.into())),
+    ]))
+}
+
+/// DeviceResponse を検証する
+/// `issuer_verifier_for` はヘッダの `alg` と `kid` から発行者の署名の検証器を作る
+pub(crate) fn verify_device_response(
+    response: &[u8],
+    session_transcript: &[u8],
+    issuer_verifier_for: impl Fn(
+        SigningAlgorithm,
+        Option<&str>,
+    ) -> Result<Box<dyn JwsVerifier>, VerificationError>,
+) -> Result<Vec<MdocDocument>, VerificationError> {
+    let response = decode(response).map_err(invalid)?;
+    match get(&response, "status").and_then(|v| v.as_integer()) {
+        Some(status) if status == 0.into() => {}
+        status => return Err(invalid(anyhow!("device response status is {status:?}"))),
+    }
+    let documents = get(&response, "documents")
+        .and_then(Cbor::as_array)
+        .filter(|documents| !documents.is_empty())
+        .ok_or_else(|| invalid(anyhow!("device response has no documents")))?;
+    let session_transcript = decode(session_transcript).map_err(invalid)?;
+    documents
+        .iter()
+        .map(|document| verify_document(document, &session_transcript, &issuer_verifier_for))
+        .collect()
+}
+
+fn verify_document(
+    document: &Cbor,
+    session_transcript: &Cbor,
+    issuer_verifier_for: &impl Fn(
+        SigningAlgorithm,
+        Option<&str>,
+    ) -> Result<Box<dyn JwsVerifier>, VerificationError>,
+) -> Result<MdocDocument, VerificationError> {
+    let field = |value: &Cbor, name: &str| {
+        get(value, name)
+            .cloned()
+            .ok_or_else(|| invalid(anyhow!("{name} is missing")))
+    };
+    let doc_type = field(document, "docType")?
+        .into_text()
+        .map_err(|_| invalid(anyhow!("docType is not a text")))?;
+    let issuer_signed = field(document, "issuerSigned")?;
+    let issuer_auth = field(&issuer_signed, "issuerAuth")?;
+
+    // issuerAuth の署名
+    let payload = verify_sign1(&issuer_auth, None, issuer_verifier_for)
+        .map_err(|e| match e {
+            SignatureError::Invalid => VerificationError::BadIssuerSignature,
+            SignatureError::Other(e) => e,
+        })?
+        .ok_or_else(|| invalid(anyhow!("issuerAuth has no payload")))?;
+    let mso = decode_encoded_cbor(&decode(&payload).map_err(invalid)?).map_err(invalid)?;
+    if get(&mso, "docType").and_then(Cbor::as_text) != Some(doc_type.as_str()) {
+        return Err(invalid(anyhow!("mso docType is not {doc_type}")));
+    }
+    if get(&mso, "digestAlgorithm").and_then(Cbor::as_text) != Some("SHA-256") {
+        return Err(invalid(anyhow!("unsupported digestAlgorithm")));
+    }
+
+    // 有効期間
+    let validity = field(&mso, "validityInfo")?;
+    let tdate = |name: &str| -> Result<SystemTime, VerificationError> {
+        match get(&validity, name) {
+            Some(Cbor::Tag(TAG_TDATE, value)) => value
+                .as_text()
+                .ok_or_else(|| invalid(anyhow!("{name} is not a tdate")))
+                .and_then(|value| vcdm::parse_datetime(value).map_err(invalid)),
+            _ => Err(invalid(anyhow!("validityInfo has no {name}"))),
+        }
+    };
+    let now = SystemTime::now();
+    if tdate("validFrom")? > now {
+        return Err(invalid(anyhow!("mdoc is not yet valid")));
+    }
+    let valid_until = tdate("validUntil")?;
+    if valid_until <= now {
+        return Err(VerificationError::Expired(
+            crate::error::JwtKind::Credential,
+        ));
+    }
+
+    // 開示された要素のダイジェストが MSO の valueDigests と一致するか
+    let value_digests = field(&mso, "valueDigests")?;
+    let mut claims = Map::new();
+    if let Some(Cbor::Map(entries)) = get(&issuer_signed, "nameSpaces") {
+        for (namespace, items) in entries {
+            let namespace = namespace
+                .as_text()
+                .ok_or_else(|| invalid(anyhow!("namespace is not a text")))?;
+            let digests = get(&value_digests, namespace)
+                .ok_or_else(|| invalid(anyhow!("no digests for {namespace}")))?;
+            let mut elements = Map::new();
+            for item_bytes in items.as_array().into_iter().flatten() {
+                let item = decode_encoded_cbor(item_bytes).map_err(invalid)?;
+                let identifier = get(&item, "elementIdentifier")
+                    .and_then(Cbor::as_text)
+                    .ok_or_else(|| invalid(anyhow!("item has no elementIdentifier")))?;
+                let digest_id = get(&item, "digestID")
+                    .and_then(Cbor::as_integer)
+                    .ok_or_else(|| invalid(anyhow!("item has no digestID")))?;
+                let expected = digests.as_map().and_then(|digests| {
+                    digests
+                        .iter()
+                        .find(|(id, _)| id.as_integer() == Some(digest_id))
+                        .and_then(|(_, digest)| digest.as_bytes())
+                });
+                let actual = Sha256::digest(encode(item_bytes).map_err(invalid)?);
+                if expected.map(Vec::as_slice) != Some(&actual[..]) {
+                    return Err(invalid(anyhow!(
+                        "digest mismatch for {namespace}/{identifier}"
+                    )));
+                }
+                let value = get(&item, "elementValue")
+                    .ok_or_else(|| invalid(anyhow!("item has no elementValue")))?;
+                elements.insert(identifier.to_string(), cbor_to_json(value));
+            }
+            claims.insert(namespace.to_string(), Value::Object(elements));
+        }
+    }
+
+    // deviceSignature (SessionTranscript を含む DeviceAuthentication に Holder の鍵で署名したもの)
+    let device_key = get(&mso, "deviceKeyInfo")
+        .and_then(|info| get(info, "deviceKey"))
+        .ok_or_else(|| invalid(anyhow!("mso has no deviceKey")))?;
+    let device_jwk = jwk_from_cose_key(device_key).map_err(invalid)?;
+    let device_signed = field(document, "deviceSigned")?;
+    let device_namespaces = field(&device_signed, "nameSpaces")?;
+    let device_signature = get(&device_signed, "deviceAuth")
+        .and_then(|auth| get(auth, "deviceSignature"))
+        .ok_or_else(|| invalid(anyhow!("deviceAuth has no deviceSignature")))?;
+    let device_authentication =
+        device_authentication(session_transcript, &doc_type, &device_namespaces)
+            .map_err(invalid)?;
+    verify_sign1(device_signature, Some(&device_authentication), |alg, _| {
+        let key_alg = SigningAlgorithm::from_jwk(&device_jwk)
+            .map_err(|e| VerificationError::InvalidHolderKey(e.to_string()))?;
+        if !alg.is_compatible_with(key_alg) {
+            return Err(VerificationError::InvalidHolderKey(format!(
+                "{alg} cannot be used with {key_alg} device key"
+            )));
+        }
+        alg.verifier_from_jwk(&device_jwk)
+            .map_err(|e| VerificationError::InvalidHolderKey(e.to_string()))
+    })
+    .map_err(|e| match e {
+        SignatureError::Invalid => VerificationError::BadDeviceSignature,
+        SignatureError::Other(e) => e,
+    })?;
+
+    Ok(MdocDocument {
+        doc_type,
+        claims,
+        device_jwk,
+        valid_until,
+    })
+}
+
+/// COSE_Sign1 の検証の失敗
+enum SignatureError {
+    /// 署名が正しくない
+    Invalid,
+    Other(VerificationError),
+}
+
+/// DeviceAuthenticationBytes (`#6.24(bstr .cbor ["DeviceAuthentication", SessionTranscript, DocType, DeviceNameSpacesBytes])`)
+fn device_authentication(
+    session_transcript: &Cbor,
+    doc_type: &str,
+    device_namespaces: &Cbor,
+) -> Result<Vec<u8>> {
+    encode(&encoded_cbor(&Cbor::Array(vec![
+        text("DeviceAuthentication"),
+        session_transcript.clone(),
+        text(doc_type),
+        device_namespaces.clone(),
+    ]))?)
+}
+
+/// COSE_Sign1 を作成する (`payload` が None の場合は detached で `detached_payload` に署名する)
+fn sign1(
+    alg: SigningAlgorithm,
+    kid: Option<&str>,
+    payload: Option<Vec<u8>>,
+    detached_payload: &[u8],
+    signer: &dyn JwsSigner,
+) -> Result<Cbor> {
+    let protected = encode(&Cbor::Map(vec![(
+        Cbor::Integer(COSE_HEADER_ALG.into()),
+        Cbor::Integer(cose_algorithm(alg).into()),
+    )]))?;
+    let signed = payload.as_deref().unwrap_or(detached_payload);
+    let signature = signer.sign(&sig_structure(&protected, signed)?)?;
+    let unprotected = kid
+        .map(|kid| {
+            vec![(
+                Cbor::Integer(COSE_HEADER_KID.into()),
+                Cbor::Bytes(kid.as_bytes().to_vec()),
+            )]
+        })
+        .unwrap_or_default();
+    Ok(Cbor::Array(vec![
+        Cbor::Bytes(protected),
+        Cbor::Map(unprotected),
+        payload.map_or(Cbor::Null, Cbor::Bytes),
+        Cbor::Bytes(signature),
+    ]))
+}
+
+/// COSE_Sign1 の署名を検証し、payload を返す
+fn verify_sign1(
+    sign1: &Cbor,
+    detached_payload: Option<&[u8]>,
+    verifier_for: impl FnOnce(
+        SigningAlgorithm,
+        Option<&str>,
+    ) -> Result<Box<dyn JwsVerifier>, VerificationError>,
+) -> Result<Option<Vec<u8>>, SignatureError> {
+    let malformed = |reason: &str| SignatureError::Other(invalid(anyhow!("{reason}")));
+    let [protected, unprotected, payload, signature] =
+        sign1.as_array().map(Vec::as_slice).unwrap_or_default()
+    else {
+        return Err(malformed("COSE_Sign1 must be an array of 4"));
+    };
+    let protected = protected
+        .as_bytes()
+        .ok_or_else(|| malformed("protected header is not a bstr"))?;
+    let header = decode(protected).map_err(|_| malformed("invalid protected header"))?;
+    let alg = get_int(&header, COSE_HEADER_ALG)
+        .and_then(|alg| alg.as_integer())
+        .and_then(|alg| i64::try_from(alg).ok())
+        .and_then(algorithm_from_cose)
+        .ok_or_else(|| malformed("unsupported alg in protected header"))?;
+    let kid = get_int(unprotected, COSE_HEADER_KID)
+        .and_then(Cbor::as_bytes)
+        .and_then(|kid| std::str::from_utf8(kid).ok());
+    let payload = match payload {
+        Cbor::Bytes(payload) => Some(payload.clone()),
+        Cbor::Null => None,
+        _ => return Err(malformed("payload is not a bstr")),
+    };
+    let signed = payload
+        .as_deref()
+        .or(detached_payload)
+        .ok_or_else(|| malformed("payload is detached"))?;
+    let signature = signature
+        .as_bytes()
+        .ok_or_else(|| malformed("signature is not a bstr"))?;
+
+    let verifier = verifier_for(alg, kid).map_err(SignatureError::Other)?;
+    let message =
+        sig_structure(protected, signed).map_err(|e| SignatureError::Other(invalid(e)))?;
+    verifier
+        .verify(&message, signature)
+        .map_err(|_| SignatureError::Invalid)?;
+    Ok(payload)
+}
+
+/// COSE_Sign1 の Sig_structure (`["Signature1", protected, external_aad, payload]`)
+fn sig_structure(protected: &[u8], payload: &[u8]) -> Result<Vec<u8>> {
+    encode(&Cbor::Array(vec![
+        text("Signature1"),
+        Cbor::Bytes(protected.to_vec()),
+        Cbor::Bytes(vec![]),
+        Cbor::Bytes(payload.to_vec()),
+    ]))
+}
+
+/// `issuerAuth` の payload の MSO (署名は検証しない)
+fn mso_of(issuer_auth: &Cbor) -> Result<Cbor> {
+    let payload = issuer_auth
+        .as_array()
+        .and_then(|sign1| sign1.get(2))
+        .and_then(Cbor::as_bytes)
+        .ok_or_else(|| anyhow!("issuerAuth has no payload"))?;
+    decode_encoded_cbor(&decode(payload)?)
+}
+
+/// COSE のアルゴリズムの識別子
+fn cose_algorithm(alg: SigningAlgorithm) -> i64 {
+    match alg {
+        SigningAlgorithm::ES256 => -7,
+        SigningAlgorithm::ES384 => -35,
+        SigningAlgorithm::ES512 => -36,
+        SigningAlgorithm::EdDSA => -8,
+        SigningAlgorithm::PS256 => -37,
+        SigningAlgorithm::RS256 => -257,
+    }
+}
+
+fn algorithm_from_cose(alg: i64) -> Option<SigningAlgorithm> {
+    [
+        SigningAlgorithm::ES256,
+        SigningAlgorithm::ES384,
+        SigningAlgorithm::ES512,
+        SigningAlgorithm::EdDSA,
+        SigningAlgorithm::PS256,
+        SigningAlgorithm::RS256,
+    ]
+    .into_iter()
+    .find(|candidate| cose_algorithm(*candidate) == alg)
+}
+
+/// JWK を COSE_Key にする (EC2 と OKP)
+fn cose_key(jwk: &Value) -> Result<Cbor> {
+    let field = |name: &str| -> Result<Cbor> {
+        let value = jwk
+            .get(name)
+            .and_then(Value::as_str)
+            .ok_or_else(|| anyhow!("jwk has no {name}"))?;
+        Ok(Cbor::Bytes(URL_SAFE_NO_PAD.decode(value)?))
+    };
+    let int = |v: i64| Cbor::Integer(v.into());
+    let kty = jwk.get("kty").and_then(Value::as_str);
+    let crv = jwk.get("crv").and_then(Value::as_str);
+    Ok(match (kty, crv) {
+        (Some("EC"), Some(crv)) => {
+            let crv = match crv {
+                "P-256" => 1,
+                "P-384" => 2,
+                "P-521" => 3,
+                _ => bail!("unsupported crv for COSE_Key: {crv}"),
+            };
+            Cbor::Map(vec![
+                (int(1), int(2)),
+                (int(-1), int(crv)),
+                (int(-2), field("x")?),
+                (int(-3), field("y")?),
+            ])
+        }
+        (Some("OKP"), Some("Ed25519")) => Cbor::Map(vec![
+            (int(1), int(1)),
+            (int(-1), int(6)),
+            (int(-2), field("x")?),
+        ]),
+        _ => bail!("unsupported jwk for COSE_Key: kty={kty:?} crv={crv:?}"),
+    })
+}
+
+/// COSE_Key を JWK にする
+fn jwk_from_cose_key(key: &Cbor) -> Result<Value> {
+    let int = |label: i64| {
+        get_int(key, label)
+            .and_then(Cbor::as_integer)
+            .and_then(|v| i64::try_from(v).ok())
+    };
+    let bytes = |label: i64| -> Result<String> {
+        get_int(key, label)
+            .and_then(Cbor::as_bytes)
+            .map(|b| URL_SAFE_NO_PAD.encode(b))
+            .ok_or_else(|| anyhow!("COSE_Key has no {label}"))
+    };
+    match (int(1), int(-1)) {
+        (Some(2), Some(crv)) => {
+            let crv = match crv {
+                1 => "P-256",
+                2 => "P-384",
+                3 => "P-521",
+                _ => bail!("unsupported COSE_Key crv: {crv}"),
+            };
+            Ok(serde_json::json!({ "kty": "EC", "crv": crv, "x": bytes(-2)?, "y": bytes(-3)? }))
+        }
+        (Some(1), Some(6)) => {
+            Ok(serde_json::json!({ "kty": "OKP", "crv": "Ed25519", "x": bytes(-2)? }))
+        }
+        (kty, crv) => bail!("unsupported COSE_Key kty={kty:?} crv={crv:?}"),
+    }
+}
+
+fn json_to_cbor(value: &Value) -> Cbor {
+    match value {
+        Value::Null => Cbor::Null,
+        Value::Bool(b) => Cbor::Bool(*b),
+        Value::Number(n) => match (n.as_i64(), n.as_u64()) {
+            (Some(i), _) => Cbor::Integer(i.into()),
+            (None, Some(u)) => Cbor::Integer(u.into()),
+            _ => Cbor::Float(n.as_f64().unwrap_or_default()),
+        },
+        Value::String(s) => text(s),
+        Value::Array(values) => Cbor::Array(values.iter().map(json_to_cbor).collect()),
+        Value::Object(map) => Cbor::Map(
+            map.iter()
+                .map(|(k, v)| (text(k), json_to_cbor(v)))
+                .collect(),
+        ),
+    }
+}
+
+/// CBOR の値を JSON にする (bstr は base64url、タグは中身の値)
+fn cbor_to_json(value: &Cbor) -> Value {
+    match value {
+        Cbor::Null => Value::Null,
+        Cbor::Bool(b) => Value::Bool(*b),
+        Cbor::Integer(i) => i64::try_from(*i)
+            .map(Value::from)
+            .unwrap_or_else(|_| Value::from(i128::from(*i) as f64)),
+        Cbor::Float(f) => Value::from(*f),
+        Cbor::Text(s) => Value::String(s.clone()),
+        Cbor::Bytes(b) => Value::String(URL_SAFE_NO_PAD.encode(b)),
+        Cbor::Array(values) => Value::Array(values.iter().map(cbor_to_json).collect()),
+        Cbor::Map(entries) => Value::Object(
+            entries
+                .iter()
+                .map(|(k, v)| {
+                    let key = match k {
+                        Cbor::Text(s) => s.clone(),
+                        k => cbor_to_json(k).to_string(),
+                    };
+                    (key, cbor_to_json(v))
+                })
+                .collect(),
+        ),
+        Cbor::Tag(_, value) => cbor_to_json(value),
+        _ => Value::Null,
+    }
+}
+
+fn text(s: &str) -> Cbor {
+    Cbor::Text(s.to_string())
+}
+
+/// テキストのキーの値
+fn get<'a>(map: &'a Cbor, key: &str) -> Option<&'a Cbor> {
+    map.as_map()?
+        .iter()
+        .find(|(k, _)| k.as_text() == Some(key))
+        .map(|(_, v)| v)
+}
+
+/// 整数のキー (COSE のラベル) の値
+fn get_int(map: &Cbor, label: i64) -> Option<&Cbor> {
+    map.as_map()?
+        .iter()
+        .find(|(k, _)| k.as_integer() == Some(label.into()))
+        .map(|(_, v)| v)
+}
+
+fn encode(value: &Cbor) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    ciborium::into_writer(value, &mut bytes).map_err(|e| anyhow!("failed to encode cbor: {e}"))?;
+    Ok(bytes)
+}
+
+fn decode(bytes: &[u8]) -> Result<Cbor> {
+    ciborium::from_reader(bytes).map_err(|e| anyhow!("invalid cbor: {e}"))
+}
+
+/// 埋め込み CBOR (`#6.24(bstr .cbor value)`)
+fn encoded_cbor(value: &Cbor) -> Result<Cbor> {
+    Ok(Cbor::Tag(
+        TAG_ENCODED_CBOR,
+        Box::new(Cbor::Bytes(encode(value)?)),
+    ))
+}
+
+fn decode_encoded_cbor(value: &Cbor) -> Result<Cbor> {
+    match value {
+        Cbor::Tag(TAG_ENCODED_CBOR, bytes) => match bytes.as_ref() {
+            Cbor::Bytes(bytes) => decode(bytes),
+            _ => bail!("tag 24 does not contain a bstr"),
+        },
+        _ => bail!("expected an embedded cbor (tag 24)"),
+    }
+}
+
+fn invalid(e: anyhow::Error) -> VerificationError {
+    VerificationError::InvalidMdoc(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key::{generate_key_pair, public_key_pem_to_jwk};
+    use serde_json::json;
+
+    const CLIENT_ID: &str = "https://verifier.example.com/response";
+
+    struct Keys {
+        issuer_private: Vec<u8>,
+        issuer_public: Vec<u8>,
+        holder_private: Vec<u8>,
+        holder_jwk: Value,
+    }
+
+    fn keys() -> Keys {
+        let (issuer_private, issuer_public) = generate_key_pair(SigningAlgorithm::ES256).unwrap();
+        let (holder_private, holder_public) = generate_key_pair(SigningAlgorithm::EdDSA).unwrap();
+        Keys {
+            issuer_private,
+            issuer_public,
+            holder_private,
+            holder_jwk: public_key_pem_to_jwk(&holder_public).unwrap(),
+        }
+    }
+
+    fn issue(keys: &Keys) -> Vec<u8> {
+        let elements = json!({
+            "family_name": "Fujita",
+            "given_name": "Taro",
+            "age_over_18": true,
+        });
+        let params = MdocParams {
+            doc_type: MDL_DOCTYPE.to_string(),
+            namespaces: BTreeMap::from([(
+                MDL_NAMESPACE.to_string(),
+                elements.as_object().unwrap().clone(),
+            )]),
+            holder_jwk: keys.holder_jwk.clone(),
+            valid_for: Duration::from_secs(3600),
+        };
+        let signer = SigningAlgorithm::ES256
+            .signer_from_pem(&keys.issuer_private)
+            .unwrap();
+        issuer_signed(
+            params,
+            SigningAlgorithm::ES256,
+            "issuer-key",
+            signer.as_ref(),
+        )
+        .unwrap()
+    }
+
+    fn present(keys: &Keys, issuer_signed: &[u8], nonce: &str) -> Vec<u8> {
+        let signer = SigningAlgorithm::EdDSA
+            .signer_from_pem(&keys.holder_private)
+            .unwrap();
+        device_response(
+            issuer_signed,
+            &["given_name", "age_over_18"],
+            &session_transcript(CLIENT_ID, nonce, CLIENT_ID).unwrap(),
+            SigningAlgorithm::EdDSA,
+            signer.as_ref(),
+        )
+        .unwrap()
+    }
+
+    fn verify(
+        keys: &Keys,
+        response: &[u8],
+        nonce: &str,
+    ) -> Result<Vec<MdocDocument>, VerificationError> {
+        verify_device_response(
+            response,
+            &session_transcript(CLIENT_ID, nonce, CLIENT_ID).unwrap(),
+            |alg, kid| {
+                assert_eq!(kid, Some("issuer-key"));
+                Ok(alg.verifier_from_pem(&keys.issuer_public).unwrap())
+            },
+        )
+    }
+
+    #[test]
+    fn session_transcript_is_openid4vp_handover() {
+        let transcript =
+            decode(&session_transcript(CLIENT_ID, "n-0S6_WzA2Mj", CLIENT_ID).unwrap()).unwrap();
+        let [Cbor::Null, Cbor::Null, Cbor::Array(handover)] =
+            transcript.as_array().unwrap().as_slice()
+        else {
+            panic!("unexpected session transcript {transcript:?}");
+        };
+        let info = encode(&Cbor::Array(vec![
+            text(CLIENT_ID),
+            text("n-0S6_WzA2Mj"),
+            Cbor::Null,
+            text(CLIENT_ID),
+        ]))
+        .unwrap();
+        assert_eq!(
+            handover,
+            &[
+                text("OpenID4VPHandover"),
+                Cbor::Bytes(Sha256::digest(info).to_vec())
+            ]
+        );
+    }
+
+    #[test]
+    fn issue_present_and_verify() {
+        let keys = keys();
+        let response = present(&keys, &issue(&keys), "nonce-1");
+        let documents = verify(&keys, &response, "nonce-1").unwrap();
+        assert_eq!(documents.len(), 1);
+        assert_eq!(documents[0].doc_type, MDL_DOCTYPE);
+        assert_eq!(
+            documents[0].claims,
+            *json!({ MDL_NAMESPACE: { "given_name": "Taro", "age_over_18": true } })
+                .as_object()
+                .unwrap()
+        );
+        assert_eq!(documents[0].device_jwk["x"], keys.holder_jwk["x"]);
+
+        // 別の nonce (SessionTranscript) では deviceSignature を検証できない
+        assert!(matches!(
+            verify(&keys, &response, "nonce-2"),
+            Err(VerificationError::BadDeviceSignature)
+        ));
+    }
+
+    #[test]
+    fn rejects_tampered_element() {
+        let keys = keys();
+        let mut response = decode(&present(&keys, &issue(&keys), "nonce-1")).unwrap();
+
+        // 開示された要素の値を書き換える (MSO の valueDigests と一致しなくなる)
+        let Cbor::Map(entries) = &mut response else {
+            panic!("device response is not a map");
+        };
+        let document = &mut entries
+            .iter_mut()
+            .find(|(k, _)| k.as_text() == Some("documents"))
+            .unwrap()
+            .1
+            .as_array_mut()
+            .unwrap()[0];
+        let items = document
+            .as_map_mut()
+            .unwrap()
+            .iter_mut()
+            .find(|(k, _)| k.as_text() == Some("issuerSigned"))
+            .unwrap()
+            .1
+            .as_map_mut()
+            .unwrap()
+            .iter_mut()
+            .find(|(k, _)| k.as_text() == Some("nameSpaces"))
+            .unwrap()
+            .1
+            .as_map_mut()
+            .unwrap()[0]
+            .1
+            .as_array_mut()
+            .unwrap();
+        for item in items.iter_mut() {
+            let Cbor::Map(mut fields) = decode_encoded_cbor(item).unwrap() else {
+                panic!("item is not a map");
+            };
+            for (name, value) in fields.iter_mut() {
+                if name.as_text() == Some("elementValue") && value.as_bool().is_some() {
+                    *value = Cbor::Bool(false);
+                }
+            }
+            *item = encoded_cbor(&Cbor::Map(fields)).unwrap();
+        }
+
+        let error = verify(&keys, &encode(&response).unwrap(), "nonce-1").unwrap_err();
+        assert!(
+            matches!(&error, VerificationError::InvalidMdoc(reason) if reason.contains("digest mismatch")),
+            "{error:?}"
+        );
+    }
+}
@@ -0,0 +1,82 @@
+//! KB-JWT の `nonce` の発行と使用済み管理
+//!
+//! Verifier は発行した nonce をストアに登録し、VP の検証に成功したら使用済みにする。
+//! 同じ nonce の VP をもう一度提示されても受け付けない。
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use rand::RngCore as _;
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+/// ランダムな nonce (128 bit, Base64URL) を生成
+pub fn generate_nonce() -> String {
+    let mut bytes = [0u8; 16];
+    rand::rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// 発行した nonce を管理するストア
+/// 複数プロセスで共有する場合は Redis などで実装する
+pub trait NonceStore: Send + Sync {
+    /// 発行した nonce を有効期限付きで登録する
+    fn insert(&self, nonce: &str, expires_at: SystemTime);
+
+    /// nonce を使用済みにする
+    /// 未登録・使用済み・期限切れの場合は false を返す
+    fn consume(&self, nonce: &str) -> bool;
+
+    /// 一度しか使えない値 (アサーションの `jti` など) を有効期限まで記録する
+    /// 記録済み (期限内) の場合は記録せずに false を返す
+    fn insert_new(&self, value: &str, expires_at: SystemTime) -> bool;
+
+    /// nonce を生成して `ttl` の間だけ有効なものとして登録する
+    fn issue(&self, ttl: Duration) -> String {
+        let nonce = generate_nonce();
+        self.insert(&nonce, SystemTime::now() + ttl);
+        nonce
+    }
+}
+
+/// プロセス内のメモリに nonce を保持するストア
+#[derive(Default)]
+pub struct InMemoryNonceStore {
+    /// nonce -> 有効期限
+    nonces: Mutex<HashMap<String, SystemTime>>,
+}
+
+impl InMemoryNonceStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl NonceStore for InMemoryNonceStore {
+    fn insert(&self, nonce: &str, expires_at: SystemTime) {
+        let mut nonces = self.nonces.lock().unwrap_or_else(|e| e.into_inner());
+        // 期限切れのものは登録のついでに捨てる
+        let now = SystemTime::now();
+        nonces.retain(|_, expires_at| *expires_at > now);
+        nonces.insert(nonce.to_string(), expires_at);
+    }
+
+    fn consume(&self, nonce: &str) -> bool {
+        let mut nonces = self.nonces.lock().unwrap_or_else(|e| e.into_inner());
+        nonces
+            .remove(nonce)
+            .is_some_and(|expires_at| expires_at > SystemTime::now())
+    }
+
+    fn insert_new(&self, value: &str, expires_at: SystemTime) -> bool {
+        let mut nonces = self.nonces.lock().unwrap_or_else(|e| e.into_inner());
+        let now = SystemTime::now();
+        nonces.retain(|_, expires_at| *expires_at > now);
+        if nonces.contains_key(value) {
+            return false;
+        }
+        nonces.insert(value.to_string(), expires_at);
+        true
+    }
+}
@@ -0,0 +1,943 @@
+//! OpenID for Verifiable Credential Issuance (OID4VCI) の発行サーバ
+//!
+//! - `GET /.well-known/openid-credential-issuer` : Credential Issuer Metadata
+//! - `GET /.well-known/oauth-authorization-server` : Authorization Server Metadata
+//! - `POST /token` : 認証サーバが署名したアサーション (JWT Bearer Grant, RFC 7523) と引き換えにアクセストークンを発行
+//!   (アサーションの `iss` は `client_id`、`jti` は一度しか使えない)
+//! - `POST /credential` : アクセストークンと Holder の proof JWT を受け取り、SD-JWT VC を発行
+//! - `GET /status-list` : 署名したステータスリスト (`with_status_list` を指定した場合)
+//! - `GET /.well-known/jwt-vc-issuer` : SD-JWT VC の発行者メタデータ (`jwks_uri`)
+//! - `GET /jwks.json` : VC を検証する発行者の公開鍵 (JWKS)
+//!
+//! `with_key_set` を指定した場合はリクエストごとにキーセットの現在の署名鍵で署名し、
+//! JWKS ではローテーション前後の鍵も公開する。
+//!
+//! proof JWT は `aud`・`nonce` (`c_nonce`)・`iat` と署名を検証し、ヘッダの `jwk` を VC の `cnf` にする。
+
+use crate::{
+    alg::SigningAlgorithm,
+    error::{IssuanceError, JwtKind, VerificationError},
+    issuer::{GenerateVCParams, Issuer},
+    issuer_metadata,
+    jose::{check_iat, check_key_algorithm, decode_jwt},
+    keyset::IssuerKeySet,
+    nonce::{generate_nonce, InMemoryNonceStore, NonceStore},
+    server::{Request, Response},
+    status_list::{self, StatusListRegistry, STATUS_LIST_CONTENT_TYPE},
+    verifier::DEFAULT_IAT_SKEW,
+};
+use anyhow::{anyhow, Result};
+use josekit::jwt::{self, JwtPayload};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::{
+    borrow::Cow,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+/// JWT Bearer Grant の `grant_type`
+pub const GRANT_TYPE_JWT_BEARER: &str = "urn:ietf:params:oauth:grant-type:jwt-bearer";
+/// アクセストークンの `typ` (RFC 9068)
+pub const ACCESS_TOKEN_TYP: &str = "at+jwt";
+/// proof JWT の `typ`
+pub const PROOF_TYP: &str = "openid4vci-proof+jwt";
+/// `c_nonce` の有効期間 (秒)
+pub const C_NONCE_EXPIRES_IN: u64 = 300;
+/// ステータスリストを公開するパス
+pub const STATUS_LIST_PATH: &str = "/status-list";
+/// 発行者の JWKS を公開するパス
+pub const JWKS_PATH: &str = "/jwks.json";
+
+/// 発行サーバの設定 (el_issue.conf の各項目)
+#[derive(Debug, Clone)]
+pub struct CredentialIssuerConfig {
+    /// Credential Issuer Identifier (`ISSUER`)。VC の `iss` にもなる
+    pub credential_issuer: String,
+    /// Credential Issuer Metadata で公開するクレデンシャルの識別子
+    pub credential_configuration_id: String,
+    /// VC の `vct` (`VCT`)
+    pub vct: String,
+    /// クレデンシャルの形式 (`VC_FORMAT`)
+    pub format: String,
+    /// アクセストークンの `aud` (`ACCESS_TOKEN_AUDIENCE`)
+    pub access_token_audience: String,
+    /// アクセストークンの `scope` (`ACCESS_TOKEN_SCOPE`)
+    pub access_token_scope: String,
+    /// トークンを要求できる `client_id` (`CLIENT_ID`)
+    pub client_id: String,
+    /// `authorization_details` の `type` (`AUTHORIZATION_DETAILS_TYPE`)
+    pub authorization_details_type: String,
+    /// 受け付ける proof の `proof_type` (`PROOF_TYPE`)
+    pub proof_type: String,
+    /// アクセストークンの有効期間 (秒, `TOKEN_EXPIRES_IN`)
+    pub token_expires_in: u64,
+    /// VC の有効期間 (秒, `VC_EXPIRES_IN`)
+    pub vc_expires_in: u64,
+    /// VC の `aud`
+    pub vc_audience: String,
+    /// アサーションの中でアカウント名が入っているクレーム (`ACCOUNT_NAME_KEY`)
+    pub account_name_key: String,
+    /// 選択的開示にするクレームの JSON Pointer (`*` で配列の要素ごと)
+    pub disclosable: Vec<String>,
+    /// トップレベルに追加するダミーダイジェストの数
+    pub decoys: usize,
+}
+
+/// アカウント名から VC に含めるクレームを作る関数
+pub type ClaimsProvider = Box<dyn Fn(&str) -> Result<Map<String, Value>> + Send + Sync>;
+
+/// OID4VCI の発行サーバ
+pub struct CredentialIssuerServer {
+    config: CredentialIssuerConfig,
+    issuer: Issuer,
+    /// アサーションを検証する認証サーバの公開鍵 (PEM, `AUTHENTICATION_PARAM_FILE`)
+    authentication_key: Vec<u8>,
+    claims_for: ClaimsProvider,
+    /// 発行した `c_nonce`
+    c_nonces: InMemoryNonceStore,
+    /// 使用済みのアサーションの `jti` (有効期限まで記録し、同じアサーションの再利用を拒否する)
+    assertion_ids: InMemoryNonceStore,
+    /// VC に `status` を入れる場合のステータスリスト
+    status_list: Option<Arc<StatusListRegistry>>,
+    /// 署名鍵をローテーションする場合のキーセット (`issuer` の鍵の代わりに使う)
+    key_set: Option<Arc<IssuerKeySet>>,
+}
+
+/// Credential Request のボディ
+#[derive(Debug, Deserialize)]
+struct CredentialRequest {
+    format: Option<String>,
+    vct: Option<String>,
+    proof: Option<Proof>,
+}
+
+/// Credential Request の `proof`
+#[derive(Debug, Deserialize)]
+struct Proof {
+    proof_type: String,
+    jwt: Option<String>,
+}
+
+impl CredentialIssuerServer {
+    pub fn new(
+        config: CredentialIssuerConfig,
+        issuer: Issuer,
+        authentication_key: Vec<u8>,
+        claims_for: ClaimsProvider,
+    ) -> Self {
+        Self {
+            config,
+            issuer,
+            authentication_key,
+            claims_for,
+            c_nonces: InMemoryNonceStore::new(),
+            assertion_ids: InMemoryNonceStore::new(),
+            status_list: None,
+            key_set: None,
+        }
+    }
+
+    /// 発行する VC にステータスリストのインデックスを割り当て、
+    /// `GET /status-list` で署名したステータスリストを公開する
+    pub fn with_status_list(mut self, status_list: Arc<StatusListRegistry>) -> Self {
+        self.status_list = Some(status_list);
+        self
+    }
+
+    /// `issuer` の鍵の代わりにキーセットの現在の署名鍵で署名する
+    pub fn with_key_set(mut self, key_set: Arc<IssuerKeySet>) -> Self {
+        self.key_set = Some(key_set);
+        self
+    }
+
+    /// 現在の署名鍵の発行者
+    fn issuer(&self) -> Result<Cow<'_, Issuer>> {
+        match &self.key_set {
+            Some(key_set) => Ok(Cow::Owned(key_set.issuer(self.issuer.issuer())?)),
+            None => Ok(Cow::Borrowed(&self.issuer)),
+        }
+    }
+
+    /// リクエストをエンドポイントに振り分ける
+    pub fn handle(&self, req: &Request) -> Response {
+        let result = match (req.method.as_str(), req.path.as_str()) {
+            ("GET", "/.well-known/openid-credential-issuer") => Ok(self.metadata()),
+            ("GET", "/.well-known/oauth-authorization-server") => {
+                Ok(self.authorization_server_metadata())
+            }
+            ("POST", "/token") => self.token(req),
+            ("POST", "/credential") => self.credential(req),
+            ("GET", STATUS_LIST_PATH) => return self.status_list_token(),
+            ("GET", JWKS_PATH) => self
+                .issuer()
+                .and_then(|issuer| issuer.jwks())
+                .map_err(|e| IssuanceError::ServerError(e.to_string())),
+            ("GET", path) if self.is_jwt_vc_issuer_metadata_path(path) => {
+                Ok(self.jwt_vc_issuer_metadata())
+            }
+            _ => return Response::not_found(),
+        };
+        match result {
+            Ok(body) => Response::json(200, &body).with_header("Cache-Control", "no-store"),
+            Err(e) => {
+                let response = Response::oauth_error(e.status(), e.code(), &e.to_string());
+                match e {
+                    IssuanceError::InvalidToken(_) => response
+                        .with_header("WWW-Authenticate", format!("Bearer error=\"{}\"", e.code())),
+                    // 新しい c_nonce を返して proof を作り直してもらう
+                    IssuanceError::InvalidNonce(_) => Response::json(
+                        e.status(),
+                        &json!({
+                            "error": e.code(),
+                            "error_description": e.to_string(),
+                            "c_nonce": self.issue_c_nonce(),
+                            "c_nonce_expires_in": C_NONCE_EXPIRES_IN,
+                        }),
+                    ),
+                    _ => response,
+                }
+            }
+        }
+    }
+
+    /// 署名したステータスリストの JWT
+    fn status_list_token(&self) -> Response {
+        let Some(status_list) = &self.status_list else {
+            return Response::not_found();
+        };
+        let token = self.issuer().and_then(|issuer| {
+            status_list.token(
+                &issuer,
+                status_list::DEFAULT_TTL,
+                status_list::DEFAULT_EXPIRES_IN,
+            )
+        });
+        match token {
+            Ok(token) => Response::new(200, STATUS_LIST_CONTENT_TYPE, token).with_header(
+                "Cache-Control",
+                format!("max-age={}", status_list::DEFAULT_TTL.as_secs()),
+            ),
+            Err(e) => Response::oauth_error(500, "server_error", &e.to_string()),
+        }
+    }
+
+    fn issue_c_nonce(&self) -> String {
+        self.c_nonces.issue(Duration::from_secs(C_NONCE_EXPIRES_IN))
+    }
+
+    fn endpoint(&self, path: &str) -> String {
+        format!(
+            "{}{path}",
+            self.config.credential_issuer.trim_end_matches('/')
+        )
+    }
+
+    /// Credential Issuer Metadata
+    pub fn metadata(&self) -> Value {
+        let config = &self.config;
+        // ローテーションで鍵の種類が変わった場合は現在の署名鍵のアルゴリズム
+        let alg = self
+            .issuer()
+            .map_or(self.issuer.algorithm(), |issuer| issuer.algorithm());
+        let mut credential_configurations = Map::new();
+        credential_configurations.insert(
+            config.credential_configuration_id.clone(),
+            json!({
+                "format": config.format,
+                "vct": config.vct,
+                "scope": config.access_token_scope,
+                "cryptographic_binding_methods_supported": ["jwk"],
+                "credential_signing_alg_values_supported": [alg.name()],
+                "proof_types_supported": {
+                    config.proof_type.clone(): {
+                        "proof_signing_alg_values_supported": supported_algorithm_names(),
+                    },
+                },
+            }),
+        );
+        json!({
+            "credential_issuer": config.credential_issuer,
+            "authorization_servers": [config.credential_issuer],
+            "credential_endpoint": self.endpoint("/credential"),
+            "credential_configurations_supported": credential_configurations,
+        })
+    }
+
+    /// SD-JWT VC の発行者メタデータ (`iss` に `/.well-known/jwt-vc-issuer` を挟んだパスで公開する)
+    pub fn jwt_vc_issuer_metadata(&self) -> Value {
+        json!({
+            "issuer": self.config.credential_issuer,
+            "jwks_uri": self.endpoint(JWKS_PATH),
+        })
+    }
+
+    fn is_jwt_vc_issuer_metadata_path(&self, path: &str) -> bool {
+        issuer_metadata::metadata_path(&self.config.credential_issuer)
+            .is_ok_and(|metadata_path| metadata_path == path)
+    }
+
+    /// Authorization Server Metadata (RFC 8414)
+    pub fn authorization_server_metadata(&self) -> Value {
+        json!({
+            "issuer": self.config.credential_issuer,
+            "token_endpoint": self.endpoint("/token"),
+            "grant_types_supported": [GRANT_TYPE_JWT_BEARER],
+            "authorization_details_types_supported": [self.config.authorization_details_type],
+            "scopes_supported": [self.config.access_token_scope],
+        })
+    }
+
+    /// トークンエンドポイント
+    fn token(&self, req: &Request) -> Result<Value, IssuanceError> {
+        let config = &self.config;
+        let form = req.form();
+        let param = |name: &str| {
+            form.get(name)
+                .map(String::as_str)
+                .ok_or_else(|| IssuanceError::InvalidRequest(format!("{name} is required")))
+        };
+
+        let grant_type = param("grant_type")?;
+        if grant_type != GRANT_TYPE_JWT_BEARER {
+            return Err(IssuanceError::UnsupportedGrantType(grant_type.to_string()));
+        }
+        let client_id = param("client_id")?;
+        if client_id != config.client_id {
+            return Err(IssuanceError::InvalidClient(client_id.to_string()));
+        }
+        let authorization_details = match form.get("authorization_details") {
+            Some(v) => Some(self.check_authorization_details(v)?),
+            None => None,
+        };
+
+        // 認証サーバが署名したアサーションからアカウント名を取り出す
+        let token = JwtKind::Assertion;
+        let (assertion, _) = decode_jwt(
+            param("assertion")?,
+            token,
+            Some(&config.credential_issuer),
+            |alg| {
+                let key_alg = SigningAlgorithm::from_pem(&self.authentication_key)
+                    .map_err(|e| VerificationError::InvalidIssuerKey(e.to_string()))?;
+                check_key_algorithm(token, alg, key_alg)?;
+                alg.verifier_from_pem(&self.authentication_key)
+                    .map_err(|e| VerificationError::InvalidIssuerKey(e.to_string()))
+            },
+        )
+        .map_err(|e| IssuanceError::InvalidGrant(e.to_string()))?;
+        if assertion.issuer() != Some(client_id) {
+            return Err(IssuanceError::InvalidGrant(format!(
+                "assertion iss is not {client_id}"
+            )));
+        }
+        let account_name = assertion
+            .claim(&config.account_name_key)
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| {
+                IssuanceError::InvalidGrant(format!("assertion has no {}", config.account_name_key))
+            })?;
+        // 同じアサーションで何度もアクセストークンを取得できないよう、`jti` を有効期限まで記録する
+        let jti = assertion
+            .jwt_id()
+            .ok_or_else(|| IssuanceError::InvalidGrant("assertion has no jti".to_string()))?;
+        let expires_at = assertion.expires_at().unwrap_or_else(SystemTime::now);
+        if !self.assertion_ids.insert_new(jti, expires_at) {
+            return Err(IssuanceError::InvalidGrant(
+                "assertion has already been used".to_string(),
+            ));
+        }
+
+        // アクセストークン (JWT) を発行
+        let mut payload = JwtPayload::new();
+        let now = SystemTime::now();
+        payload.set_issuer(&config.credential_issuer);
+        payload.set_subject(account_name);
+        payload.set_audience(vec![config.access_token_audience.clone()]);
+        payload.set_issued_at(&now);
+        payload.set_expires_at(&(now + Duration::from_secs(config.token_expires_in)));
+        payload.set_jwt_id(generate_nonce());
+        let claims = [
+            ("client_id", Some(json!(config.client_id))),
+            ("scope", Some(json!(config.access_token_scope))),
+            ("authorization_details", authorization_details.clone()),
+        ];
+        for (name, value) in claims {
+            payload
+                .set_claim(name, value)
+                .map_err(|e| IssuanceError::ServerError(e.to_string()))?;
+        }
+        let access_token = self
+            .issuer()
+            .and_then(|issuer| issuer.sign_jwt(ACCESS_TOKEN_TYP, &payload))
+            .map_err(|e| IssuanceError::ServerError(e.to_string()))?;
+
+        let mut response = json!({
+            "access_token": access_token,
+            "token_type": "Bearer",
+            "expires_in": config.token_expires_in,
+            "scope": config.access_token_scope,
+            "c_nonce": self.issue_c_nonce(),
+            "c_nonce_expires_in": C_NONCE_EXPIRES_IN,
+        });
+        if let Some(authorization_details) = authorization_details {
+            response["authorization_details"] = authorization_details;
+        }
+        Ok(response)
+    }
+
+    /// `authorization_details` (JSON の配列) が発行できるクレデンシャルを指しているかチェックする
+    fn check_authorization_details(&self, value: &str) -> Result<Value, IssuanceError> {
+        let invalid = |reason: String| IssuanceError::InvalidAuthorizationDetails(reason);
+        let details: Value = serde_json::from_str(value).map_err(|e| invalid(e.to_string()))?;
+        let entries = details
+            .as_array()
+            .filter(|entries| !entries.is_empty())
+            .ok_or_else(|| invalid("must be a non-empty array".to_string()))?;
+        for entry in entries {
+            let field = |name: &str| entry.get(name).and_then(|v| v.as_str());
+            if field("type") != Some(self.config.authorization_details_type.as_str()) {
+                return Err(invalid(format!("unsupported type {:?}", field("type"))));
+            }
+            match field("credential_configuration_id") {
+                Some(id) if id != self.config.credential_configuration_id => {
+                    return Err(invalid(format!("unknown credential_configuration_id {id}")));
+                }
+                _ => {}
+            }
+        }
+        Ok(details)
+    }
+
+    /// クレデンシャルエンドポイント
+    fn credential(&self, req: &Request) -> Result<Value, IssuanceError> {
+        let config = &self.config;
+
+        // アクセストークンを検証
+        let access_token = req
+            .bearer_token()
+            .ok_or_else(|| IssuanceError::InvalidToken("bearer token is required".to_string()))?;
+        let issuer = self
+            .issuer()
+            .map_err(|e| IssuanceError::ServerError(e.to_string()))?;
+        // ローテーション前の鍵で署名したアクセストークンも受け付けるよう、JWKS から `kid` の鍵を探す
+        let issuer_jwk = access_token_key(&issuer, access_token)
+            .map_err(|e| IssuanceError::InvalidToken(e.to_string()))?;
+        let (token, header) = decode_jwt(
+            access_token,
+            JwtKind::AccessToken,
+            Some(&config.access_token_audience),
+            |alg| {
+                alg.verifier_from_jwk(&issuer_jwk)
+                    .map_err(|e| VerificationError::InvalidIssuerKey(e.to_string()))
+            },
+        )
+        .map_err(|e| IssuanceError::InvalidToken(e.to_string()))?;
+        if header.token_type() != Some(ACCESS_TOKEN_TYP)
+            || token.issuer() != Some(config.credential_issuer.as_str())
+        {
+            return Err(IssuanceError::InvalidToken(
+                "not an access token of this issuer".to_string(),
+            ));
+        }
+        let account_name = token
+            .subject()
+            .ok_or_else(|| IssuanceError::InvalidToken("access token has no sub".to_string()))?;
+
+        let request: CredentialRequest = req
+            .json()
+            .map_err(|e| IssuanceError::InvalidRequest(e.to_string()))?;
+        if let Some(format) = request.format.filter(|f| *f != config.format) {
+            return Err(IssuanceError::UnsupportedCredentialFormat(format));
+        }
+        if let Some(vct) = request.vct.filter(|v| *v != config.vct) {
+            return Err(IssuanceError::UnsupportedCredentialType(vct));
+        }
+
+        // Holder が鍵を持っていることを proof で確認し、その公開鍵を cnf にする
+        let proof = request
+            .proof
+            .ok_or_else(|| IssuanceError::InvalidProof("proof is required".to_string()))?;
+        if proof.proof_type != config.proof_type {
+            return Err(IssuanceError::InvalidProof(format!(
+                "unsupported proof_type {}",
+                proof.proof_type
+            )));
+        }
+        let proof_jwt = proof
+            .jwt
+            .ok_or_else(|| IssuanceError::InvalidProof("proof has no jwt".to_string()))?;
+        let client_id = token.claim("client_id").and_then(|v| v.as_str());
+        let holder_jwk = verify_proof_jwt(
+            &proof_jwt,
+            &config.credential_issuer,
+            client_id,
+            &self.c_nonces,
+            DEFAULT_IAT_SKEW,
+        )?;
+
+        let claims = (self.claims_for)(account_name)
+            .map_err(|e| IssuanceError::ServerError(e.to_string()))?;
+        let status = self
+            .status_list
+            .as_ref()
+            .map(|status_list| status_list.allocate(account_name))
+            .transpose()
+            .map_err(|e| IssuanceError::ServerError(e.to_string()))?;
+        let vc = issuer
+            .generate_sd_jwt_vc(GenerateVCParams {
+                vct: Some(config.vct.clone()),
+                vct_integrity: None,
+                holder_jwk,
+                holder_kid: None,
+                claims,
+                disclosable: config.disclosable.clone(),
+                decoys: config.decoys,
+                audience: config.vc_audience.clone(),
+                vc_expires_in: config.vc_expires_in,
+                status,
+            })
+            .map_err(|e| IssuanceError::ServerError(e.to_string()))?;
+
+        Ok(json!({
+            "credential": vc,
+            "c_nonce": self.issue_c_nonce(),
+            "c_nonce_expires_in": C_NONCE_EXPIRES_IN,
+        }))
+    }
+}
+
+/// Holder の proof JWT (`openid4vci-proof+jwt`) を検証し、ヘッダの `jwk` (Holder の公開鍵) を返す
+///
+/// - 署名はヘッダの `jwk` で検証する (`jwk` に秘密鍵が含まれていたら拒否)
+/// - `aud` は `credential_issuer`、`iss` がある場合は `client_id` と一致すること
+/// - `nonce` は `c_nonces` で発行済み・未使用であること (検証に成功したら使用済みにする)
+/// - `iat` は現在時刻から `iat_skew` 以内であること
+pub fn verify_proof_jwt(
+    proof_jwt: &str,
+    credential_issuer: &str,
+    client_id: Option<&str>,
+    c_nonces: &dyn NonceStore,
+    iat_skew: Duration,
+) -> Result<Value, IssuanceError> {
+    let invalid = |reason: &str| IssuanceError::InvalidProof(reason.to_string());
+    let token = JwtKind::Proof;
+
+    let header = jwt::decode_header(proof_jwt).map_err(|e| invalid(&e.to_string()))?;
+    if header.claim("typ").and_then(|v| v.as_str()) != Some(PROOF_TYP) {
+        return Err(invalid("proof typ is not openid4vci-proof+jwt"));
+    }
+    let jwk = header
+        .claim("jwk")
+        .cloned()
+        .ok_or_else(|| invalid("proof header has no jwk"))?;
+    if jwk.get("d").is_some() {
+        return Err(invalid("proof jwk contains a private key"));
+    }
+
+    let (payload, _) = decode_jwt(proof_jwt, token, Some(credential_issuer), |alg| {
+        let key_alg = SigningAlgorithm::from_jwk(&jwk)
+            .map_err(|e| VerificationError::InvalidHolderKey(e.to_string()))?;
+        check_key_algorithm(token, alg, key_alg)?;
+        alg.verifier_from_jwk(&jwk)
+            .map_err(|e| VerificationError::InvalidHolderKey(e.to_string()))
+    })
+    .map_err(|e| invalid(&e.to_string()))?;
+
+    if let (Some(iss), Some(client_id)) = (payload.issuer(), client_id) {
+        if iss != client_id {
+            return Err(invalid(&format!("proof iss {iss} is not {client_id}")));
+        }
+    }
+    check_iat(&payload, token, iat_skew).map_err(|e| invalid(&e.to_string()))?;
+
+    // すべての検証に成功してから c_nonce を使用済みにする
+    let nonce = payload
+        .claim("nonce")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| IssuanceError::InvalidNonce("proof has no nonce".to_string()))?;
+    if !c_nonces.consume(nonce) {
+        return Err(IssuanceError::InvalidNonce(
+            "c_nonce is unknown, already used or expired".to_string(),
+        ));
+    }
+
+    Ok(jwk)
+}
+
+/// アクセストークンを検証する発行者の公開鍵 (ヘッダの `kid` の鍵、`kid` がなければ署名鍵)
+fn access_token_key(issuer: &Issuer, access_token: &str) -> Result<Value> {
+    let header =
+        jwt::decode_header(access_token).map_err(|e| anyhow!("invalid access token: {e:?}"))?;
+    let Some(kid) = header.claim("kid").and_then(Value::as_str) else {
+        return issuer.public_jwk();
+    };
+    let jwks = issuer.jwks()?;
+    jwks["keys"]
+        .as_array()
+        .and_then(|keys| {
+            keys.iter()
+                .find(|key| key.get("kid").and_then(Value::as_str) == Some(kid))
+        })
+        .cloned()
+        .ok_or_else(|| anyhow!("unknown access token kid {kid}"))
+}
+
+/// 対応している署名アルゴリズムの名前
+fn supported_algorithm_names() -> Vec<&'static str> {
+    use SigningAlgorithm::*;
+    [ES256, ES384, ES512, EdDSA, RS256, PS256]
+        .iter()
+        .map(SigningAlgorithm::name)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        key::{generate_key_pair, public_key_pem_to_jwk},
+        Holder, Verifier,
+    };
+    use josekit::jws::JwsHeader;
+
+    const CREDENTIAL_ISSUER: &str = "https://issuer.example.com";
+    const CLIENT_ID: &str = "fujita-app";
+
+    struct Setup {
+        server: CredentialIssuerServer,
+        issuer_public: Vec<u8>,
+        /// アサーションに署名する認証サーバの秘密鍵
+        authentication_private: Vec<u8>,
+        holder: Holder,
+    }
+
+    fn setup() -> Setup {
+        let (issuer_private, issuer_public) = generate_key_pair(SigningAlgorithm::ES256).unwrap();
+        let (authentication_private, authentication_public) =
+            generate_key_pair(SigningAlgorithm::ES256).unwrap();
+        let (holder_private, _) = generate_key_pair(SigningAlgorithm::ES256).unwrap();
+        let config = CredentialIssuerConfig {
+            credential_issuer: CREDENTIAL_ISSUER.to_string(),
+            credential_configuration_id: "test_credential".to_string(),
+            vct: "https://credentials.example.com/test_credential".to_string(),
+            format: "vc+sd-jwt".to_string(),
+            access_token_audience: CREDENTIAL_ISSUER.to_string(),
+            access_token_scope: "test".to_string(),
+            client_id: CLIENT_ID.to_string(),
+            authorization_details_type: "openid_credential".to_string(),
+            proof_type: "jwt".to_string(),
+            token_expires_in: 600,
+            vc_expires_in: 3600,
+            vc_audience: "fujita-app".to_string(),
+            account_name_key: "did".to_string(),
+            disclosable: vec!["/account_name".to_string()],
+            decoys: 0,
+        };
+        let issuer = Issuer::new(CREDENTIAL_ISSUER, issuer_private, "key-1").unwrap();
+        let claims_for: ClaimsProvider = Box::new(|account_name| {
+            Ok(json!({ "account_name": account_name })
+                .as_object()
+                .unwrap()
+                .clone())
+        });
+        Setup {
+            server: CredentialIssuerServer::new(config, issuer, authentication_public, claims_for),
+            issuer_public,
+            authentication_private,
+            holder: Holder::new(holder_private).unwrap(),
+        }
+    }
+
+    /// 認証サーバが署名したアサーション
+    fn assertion(setup: &Setup, iss: &str, jti: Option<&str>) -> String {
+        let mut payload = JwtPayload::new();
+        let now = SystemTime::now();
+        payload.set_issuer(iss);
+        payload.set_audience(vec![CREDENTIAL_ISSUER]);
+        payload.set_issued_at(&now);
+        payload.set_expires_at(&(now + Duration::from_secs(60)));
+        if let Some(jti) = jti {
+            payload.set_jwt_id(jti);
+        }
+        payload.set_claim("did", Some(json!("user01"))).unwrap();
+        let mut header = JwsHeader::new();
+        header.set_token_type("JWT");
+        let signer = SigningAlgorithm::ES256
+            .signer_from_pem(&setup.authentication_private)
+            .unwrap();
+        jwt::encode_with_signer(&payload, &header, signer.as_ref()).unwrap()
+    }
+
+    fn token_request(assertion: &str) -> Request {
+        let body = form_urlencoded::Serializer::new(String::new())
+            .append_pair("grant_type", GRANT_TYPE_JWT_BEARER)
+            .append_pair("client_id", CLIENT_ID)
+            .append_pair("assertion", assertion)
+            .finish();
+        Request {
+            method: "POST".to_string(),
+            path: "/token".to_string(),
+            query: String::new(),
+            headers: vec![],
+            body: body.into_bytes(),
+        }
+    }
+
+    fn credential_request(access_token: &str, proof_jwt: &str) -> Request {
+        let body = json!({
+            "format": "vc+sd-jwt",
+            "proof": { "proof_type": "jwt", "jwt": proof_jwt },
+        });
+        Request {
+            method: "POST".to_string(),
+            path: "/credential".to_string(),
+            query: String::new(),
+            headers: vec![(
+                "Authorization".to_string(),
+                format!("Bearer {access_token}"),
+            )],
+            body: body.to_string().into_bytes(),
+        }
+    }
+
+    fn body(response: &Response) -> Value {
+        serde_json::from_slice(&response.body).unwrap()
+    }
+
+    #[test]
+    fn token_and_credential_are_issued() {
+        let setup = setup();
+        let response =
+            setup
+                .server
+                .handle(&token_request(&assertion(&setup, CLIENT_ID, Some("jti-1"))));
+        assert_eq!(response.status, 200);
+        let token = body(&response);
+        let access_token = token["access_token"].as_str().unwrap();
+        let c_nonce = token["c_nonce"].as_str().unwrap();
+
+        let proof_jwt = setup
+            .holder
+            .proof_jwt(CREDENTIAL_ISSUER, c_nonce, Some(CLIENT_ID))
+            .unwrap();
+        let response = setup
+            .server
+            .handle(&credential_request(access_token, &proof_jwt));
+        assert_eq!(response.status, 200);
+        let credential = body(&response);
+        let sd_jwt = crate::sd_jwt::parse(credential["credential"].as_str().unwrap()).unwrap();
+        let verifier = Verifier::new(setup.issuer_public.clone(), "fujita-app", "unused");
+        let (_, claims) = verifier.verify_credential(&sd_jwt.jwt).unwrap();
+        assert_eq!(claims["iss"], CREDENTIAL_ISSUER);
+        assert_eq!(claims["cnf"]["jwk"], setup.holder.public_jwk().unwrap());
+
+        // c_nonce は一度しか使えない
+        let response = setup
+            .server
+            .handle(&credential_request(access_token, &proof_jwt));
+        assert_eq!(response.status, 400);
+        assert_eq!(body(&response)["error"], "invalid_nonce");
+        assert!(body(&response)["c_nonce"].is_string());
+    }
+
+    #[test]
+    fn replayed_assertion_is_rejected() {
+        let setup = setup();
+        let assertion = assertion(&setup, CLIENT_ID, Some("jti-1"));
+        assert_eq!(setup.server.handle(&token_request(&assertion)).status, 200);
+        let response = setup.server.handle(&token_request(&assertion));
+        assert_eq!(response.status, 400);
+        assert_eq!(body(&response)["error"], "invalid_grant");
+    }
+
+    #[test]
+    fn assertion_needs_client_id_iss_and_jti() {
+        let setup = setup();
+        for assertion in [
+            assertion(&setup, "other-client", Some("jti-1")),
+            assertion(&setup, CLIENT_ID, None),
+        ] {
+            let response = setup.server.handle(&token_request(&assertion));
+            assert_eq!(response.status, 400);
+            assert_eq!(body(&response)["error"], "invalid_grant");
+        }
+    }
+
+    #[test]
+    fn credential_requires_access_token() {
+        let setup = setup();
+        let response = setup
+            .server
+            .handle(&credential_request("invalid", "invalid"));
+        assert_eq!(response.status, 401);
+        assert_eq!(body(&response)["error"], "invalid_token");
+    }
+
+    /// `signing_key` で署名した proof JWT (ヘッダの `jwk` は `jwk`)
+    /// `edit` でヘッダとペイロードを書き換える
+    fn proof_jwt(
+        signing_key: &[u8],
+        jwk: &Value,
+        nonce: &str,
+        edit: impl FnOnce(&mut JwsHeader, &mut JwtPayload),
+    ) -> String {
+        let mut header = JwsHeader::new();
+        header.set_token_type(PROOF_TYP);
+        header.set_algorithm("ES256");
+        header.set_claim("jwk", Some(jwk.clone())).unwrap();
+        let mut payload = JwtPayload::new();
+        payload.set_issuer(CLIENT_ID);
+        payload.set_audience(vec![CREDENTIAL_ISSUER]);
+        payload.set_claim("nonce", Some(json!(nonce))).unwrap();
+        payload.set_issued_at(&SystemTime::now());
+        edit(&mut header, &mut payload);
+        let signer = SigningAlgorithm::ES256
+            .signer_from_pem(signing_key)
+            .unwrap();
+        jwt::encode_with_signer(&payload, &header, signer.as_ref()).unwrap()
+    }
+
+    struct Proof {
+        holder_private: Vec<u8>,
+        holder_jwk: Value,
+        c_nonces: InMemoryNonceStore,
+        c_nonce: String,
+    }
+
+    fn proof_setup() -> Proof {
+        let (holder_private, holder_public) = generate_key_pair(SigningAlgorithm::ES256).unwrap();
+        let c_nonces = InMemoryNonceStore::new();
+        let c_nonce = c_nonces.issue(Duration::from_secs(60));
+        Proof {
+            holder_private,
+            holder_jwk: public_key_pem_to_jwk(&holder_public).unwrap(),
+            c_nonces,
+            c_nonce,
+        }
+    }
+
+    fn verify(proof: &Proof, proof_jwt: &str) -> Result<Value, IssuanceError> {
+        verify_proof_jwt(
+            proof_jwt,
+            CREDENTIAL_ISSUER,
+            Some(CLIENT_ID),
+            &proof.c_nonces,
+            DEFAULT_IAT_SKEW,
+        )
+    }
+
+    /// 不正な proof は `InvalidProof` になり、c_nonce は使用済みにならない
+    fn assert_invalid_proof(proof: &Proof, proof_jwt: &str) {
+        assert!(matches!(
+            verify(proof, proof_jwt),
+            Err(IssuanceError::InvalidProof(_))
+        ));
+        assert!(proof.c_nonces.consume(&proof.c_nonce));
+    }
+
+    #[test]
+    fn proof_returns_holder_jwk_and_consumes_c_nonce() {
+        let proof = proof_setup();
+        let jwt = proof_jwt(
+            &proof.holder_private,
+            &proof.holder_jwk,
+            &proof.c_nonce,
+            |_, _| {},
+        );
+        assert_eq!(verify(&proof, &jwt).unwrap(), proof.holder_jwk);
+        assert!(matches!(
+            verify(&proof, &jwt),
+            Err(IssuanceError::InvalidNonce(_))
+        ));
+    }
+
+    #[test]
+    fn proof_with_wrong_typ_is_rejected() {
+        let proof = proof_setup();
+        let jwt = proof_jwt(
+            &proof.holder_private,
+            &proof.holder_jwk,
+            &proof.c_nonce,
+            |header, _| header.set_token_type("JWT"),
+        );
+        assert_invalid_proof(&proof, &jwt);
+    }
+
+    #[test]
+    fn proof_jwk_with_private_key_is_rejected() {
+        let proof = proof_setup();
+        let mut jwk = proof.holder_jwk.clone();
+        jwk["d"] = json!("AAAA");
+        let jwt = proof_jwt(&proof.holder_private, &jwk, &proof.c_nonce, |_, _| {});
+        assert_invalid_proof(&proof, &jwt);
+    }
+
+    #[test]
+    fn proof_signed_by_another_key_is_rejected() {
+        let proof = proof_setup();
+        let (other_private, _) = generate_key_pair(SigningAlgorithm::ES256).unwrap();
+        let jwt = proof_jwt(&other_private, &proof.holder_jwk, &proof.c_nonce, |_, _| {});
+        assert_invalid_proof(&proof, &jwt);
+    }
+
+    #[test]
+    fn proof_with_wrong_aud_is_rejected() {
+        let proof = proof_setup();
+        let jwt = proof_jwt(
+            &proof.holder_private,
+            &proof.holder_jwk,
+            &proof.c_nonce,
+            |_, payload| payload.set_audience(vec!["https://other.example.com"]),
+        );
+        assert_invalid_proof(&proof, &jwt);
+    }
+
+    #[test]
+    fn proof_iss_must_be_client_id() {
+        let proof = proof_setup();
+        let jwt = proof_jwt(
+            &proof.holder_private,
+            &proof.holder_jwk,
+            &proof.c_nonce,
+            |_, payload| payload.set_issuer("other-client"),
+        );
+        assert_invalid_proof(&proof, &jwt);
+    }
+
+    #[test]
+    fn proof_iat_outside_the_window_is_rejected() {
+        let proof = proof_setup();
+        for iat in [
+            SystemTime::now() - Duration::from_secs(600),
+            SystemTime::now() + Duration::from_secs(600),
+        ] {
+            let jwt = proof_jwt(
+                &proof.holder_private,
+                &proof.holder_jwk,
+                &proof.c_nonce,
+                |_, payload| payload.set_issued_at(&iat),
+            );
+            assert!(matches!(
+                verify(&proof, &jwt),
+                Err(IssuanceError::InvalidProof(_))
+            ));
+        }
+        assert!(proof.c_nonces.consume(&proof.c_nonce));
+    }
+
+    #[test]
+    fn proof_with_unknown_nonce_is_rejected() {
+        let proof = proof_setup();
+        let jwt = proof_jwt(
+            &proof.holder_private,
+            &proof.holder_jwk,
+            "unknown",
+            |_, _| {},
+        );
+        assert!(matches!(
+            verify(&proof, &jwt),
+            Err(IssuanceError::InvalidNonce(_))
+        ));
+    }
+}
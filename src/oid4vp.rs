@@ -0,0 +1,795 @@
+//! OpenID for Verifiable Presentations (OID4VP) の Verifier サーバ
+//!
+//! - `POST /request` : Authorization Request を作成し、`state` と Wallet に渡す URL を返す
+//!   (有効期限内のリクエストが `max_pending_requests` に達している場合は 503)
+//! - `GET /request/{state}` : `request_uri` で参照される Request Object (署名なし JWT)
+//! - `POST /response` : Wallet からの `direct_post` (`vp_token`, `state`) を受け取り、VP を検証
+//! - `GET /result/{state}` : 検証結果
+//!
+//! VP の検証は `Verifier::verify_presentation` で行い、KB-JWT の `nonce` と `aud` は
+//! Authorization Request の `nonce` と `client_id` と一致しなければならない。
+//! `nonce` はサーバの nonce ストアで発行し、検証に成功したら使用済みにする。
+//!
+//! Request Object は署名しないので、`client_id` は `redirect_uri` スキーム (`client_id` が
+//! `response_uri` そのもの) にする。Wallet は `client_id` と `response_uri` が一致することを確認でき、
+//! 別の Verifier 宛ての VP を `response_uri` で横取りされることはない。
+
+use crate::{
+    error::VerificationError,
+    nonce::{generate_nonce, InMemoryNonceStore, NonceStore},
+    server::{Request, Response},
+    verifier::{VerificationResult, Verifier},
+};
+use josekit::{jws::JwsHeader, jwt, jwt::JwtPayload};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+/// Request Object の `typ`
+pub const REQUEST_OBJECT_TYP: &str = "oauth-authz-req+jwt";
+/// `client_id` が `response_uri` である Client Identifier Scheme
+pub const CLIENT_ID_SCHEME_REDIRECT_URI: &str = "redirect_uri";
+/// Presentation Definition / DCQL で要求するクレデンシャルの ID
+pub const CREDENTIAL_QUERY_ID: &str = "credential";
+/// DCQL で要求する SD-JWT VC の形式 (OpenID4VP 1.0)
+pub const DCQL_SD_JWT_FORMAT: &str = "dc+sd-jwt";
+/// 保持する Authorization Request の数の上限の既定値
+pub const DEFAULT_MAX_PENDING_REQUESTS: usize = 1000;
+
+/// 要求するクレデンシャルの指定方法
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueryLanguage {
+    /// DIF Presentation Exchange の `presentation_definition`
+    PresentationDefinition,
+    /// `dcql_query`
+    Dcql,
+}
+
+impl std::str::FromStr for QueryLanguage {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "presentation_definition" => Ok(Self::PresentationDefinition),
+            "dcql" => Ok(Self::Dcql),
+            _ => anyhow::bail!("unsupported query language {s}"),
+        }
+    }
+}
+
+/// OID4VP の Authorization Request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuthorizationRequest {
+    pub client_id: String,
+    /// Client Identifier Scheme (省略時は `client_id` の接頭辞、なければ `redirect_uri` とみなす)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id_scheme: Option<String>,
+    pub response_type: String,
+    pub response_mode: String,
+    pub response_uri: String,
+    pub nonce: String,
+    pub state: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub presentation_definition: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dcql_query: Option<Value>,
+    /// Wallet が `client_id` を確認したか (`wallet::resolve_request` が設定する)
+    #[serde(skip)]
+    pub(crate) client_id_verified: bool,
+}
+
+/// Authorization Request で要求されたクレデンシャル
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CredentialQuery {
+    /// input descriptor または DCQL のクレデンシャルの ID
+    pub id: String,
+    /// 受け付ける `vct` (空の場合は問わない)
+    pub vct_values: Vec<String>,
+    /// 開示を要求するクレーム
+    pub claims: Vec<String>,
+}
+
+impl AuthorizationRequest {
+    /// `presentation_definition` または `dcql_query` から要求されたクレデンシャルを取り出す
+    /// 要求できるクレデンシャルは 1 つだけ
+    pub fn credential_query(&self) -> anyhow::Result<CredentialQuery> {
+        match (&self.presentation_definition, &self.dcql_query) {
+            (Some(definition), None) => parse_presentation_definition(definition),
+            (None, Some(query)) => parse_dcql_query(query),
+            (None, None) => anyhow::bail!("request has no presentation_definition or dcql_query"),
+            (Some(_), Some(_)) => {
+                anyhow::bail!("request has both presentation_definition and dcql_query")
+            }
+        }
+    }
+}
+
+/// Verifier サーバの設定
+#[derive(Debug, Clone)]
+pub struct VerifierServerConfig {
+    /// Verifier の外部から見た URL (`response_uri` と `request_uri` の基点)
+    pub base_url: String,
+    /// 要求するクレデンシャルの `vct` (None の場合は問わない)
+    pub vct: Option<String>,
+    /// 開示を要求するクレーム
+    pub claims: Vec<String>,
+    pub query_language: QueryLanguage,
+    /// Authorization Request の有効期間
+    pub request_expires_in: Duration,
+    /// 保持する Authorization Request (検証結果を含む) の数の上限
+    pub max_pending_requests: usize,
+}
+
+impl VerifierServerConfig {
+    /// `direct_post` を受け取る URL
+    /// `redirect_uri` スキームの `client_id` で、KB-JWT の `aud` になる
+    pub fn response_uri(&self) -> String {
+        format!("{}/response", self.base_url.trim_end_matches('/'))
+    }
+}
+
+/// Authorization Request に対する検証の状態
+#[derive(Debug, Clone)]
+enum Status {
+    Pending,
+    /// `direct_post` を受け取って検証中
+    Verifying,
+    Verified(VerificationResult),
+    Failed(&'static str),
+}
+
+/// 発行した Authorization Request と、その検証の状態
+struct RequestContext {
+    request: AuthorizationRequest,
+    expires_at: SystemTime,
+    status: Status,
+}
+
+/// OID4VP の Verifier サーバ
+pub struct VerifierServer {
+    config: VerifierServerConfig,
+    /// KB-JWT の `aud` には `client_id` (`response_uri`) を期待する
+    verifier: Verifier,
+    /// Authorization Request の `nonce` (`verifier` と共有し、検証に成功したら使用済みにする)
+    nonces: Arc<dyn NonceStore>,
+    /// `state` -> 発行した Authorization Request
+    requests: Mutex<HashMap<String, RequestContext>>,
+}
+
+impl VerifierServer {
+    /// `verifier` の KB-JWT の `aud` は `config.response_uri()` にしておく
+    /// `verifier` にはサーバの nonce ストアを設定し、同じ `nonce` の VP の再提示を拒否する
+    pub fn new(config: VerifierServerConfig, verifier: Verifier) -> Self {
+        let nonces: Arc<dyn NonceStore> = Arc::new(InMemoryNonceStore::new());
+        Self {
+            config,
+            verifier: verifier.with_nonce_store(nonces.clone()),
+            nonces,
+            requests: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// リクエストをエンドポイントに振り分ける
+    pub fn handle(&self, req: &Request) -> Response {
+        let path = req.path.as_str();
+        match (req.method.as_str(), path) {
+            ("POST", "/request") => match self.create_request() {
+                Some(response) => Response::json(200, &response),
+                None => Response::oauth_error(
+                    503,
+                    "temporarily_unavailable",
+                    "too many pending requests",
+                ),
+            },
+            ("GET", _) if path.starts_with("/request/") => {
+                match self.request_object(&path["/request/".len()..]) {
+                    Some(jwt) => Response::new(200, "application/oauth-authz-req+jwt", jwt),
+                    None => Response::not_found(),
+                }
+            }
+            ("POST", "/response") => {
+                let form = req.form();
+                let result = match (form.get("vp_token"), form.get("state")) {
+                    (Some(vp_token), Some(state)) => self.verify_response(vp_token, state),
+                    _ => {
+                        return Response::oauth_error(
+                            400,
+                            "invalid_request",
+                            "vp_token and state are required",
+                        )
+                    }
+                };
+                match result {
+                    Ok(_) => Response::json(200, &json!({})),
+                    Err(e) => Response::oauth_error(400, e.code(), &e.to_string()),
+                }
+            }
+            ("GET", _) if path.starts_with("/result/") => {
+                match self.result(&path["/result/".len()..]) {
+                    Some(result) => Response::json(200, &result),
+                    None => Response::not_found(),
+                }
+            }
+            _ => Response::not_found(),
+        }
+    }
+
+    /// Authorization Request を作成して保存する
+    /// Wallet には `request` (値渡し) か `request_uri` (参照渡し) のどちらかを渡す
+    /// 有効期限内のリクエストが `max_pending_requests` に達している場合は None
+    pub fn create_request(&self) -> Option<Value> {
+        let now = SystemTime::now();
+        let mut requests = self.requests.lock().unwrap_or_else(|e| e.into_inner());
+        requests.retain(|_, context| context.expires_at > now);
+        if requests.len() >= self.config.max_pending_requests {
+            return None;
+        }
+
+        let state = generate_nonce();
+        let base_url = self.config.base_url.trim_end_matches('/');
+        let (presentation_definition, dcql_query) = match self.config.query_language {
+            QueryLanguage::PresentationDefinition => (Some(self.presentation_definition()), None),
+            QueryLanguage::Dcql => (None, Some(self.dcql_query())),
+        };
+        let response_uri = self.config.response_uri();
+        let request = AuthorizationRequest {
+            client_id: response_uri.clone(),
+            client_id_scheme: Some(CLIENT_ID_SCHEME_REDIRECT_URI.to_string()),
+            response_type: "vp_token".to_string(),
+            response_mode: "direct_post".to_string(),
+            response_uri,
+            nonce: self.nonces.issue(self.config.request_expires_in),
+            state: state.clone(),
+            presentation_definition,
+            dcql_query,
+            client_id_verified: false,
+        };
+        let request_uri = format!("{base_url}/request/{state}");
+        let wallet_url = format!(
+            "openid4vp://?{}",
+            form_urlencoded::Serializer::new(String::new())
+                .append_pair("client_id", &request.client_id)
+                .append_pair("request_uri", &request_uri)
+                .finish()
+        );
+        let response = json!({
+            "state": state,
+            "request": request,
+            "request_uri": request_uri,
+            "wallet_url": wallet_url,
+        });
+
+        requests.insert(
+            state,
+            RequestContext {
+                request,
+                expires_at: now + self.config.request_expires_in,
+                status: Status::Pending,
+            },
+        );
+        Some(response)
+    }
+
+    /// `request_uri` で返す Request Object (署名なし JWT、`client_id` は `response_uri`)
+    fn request_object(&self, state: &str) -> Option<String> {
+        let requests = self.requests.lock().unwrap_or_else(|e| e.into_inner());
+        let context = requests
+            .get(state)
+            .filter(|context| context.expires_at > SystemTime::now())?;
+        let claims = serde_json::to_value(&context.request).ok()?;
+        let payload = JwtPayload::from_map(claims.as_object()?.clone()).ok()?;
+        let mut header = JwsHeader::new();
+        header.set_token_type(REQUEST_OBJECT_TYP);
+        jwt::encode_unsecured(&payload, &header).ok()
+    }
+
+    /// Presentation Definition (DIF Presentation Exchange)
+    fn presentation_definition(&self) -> Value {
+        let mut fields: Vec<Value> = self
+            .config
+            .claims
+            .iter()
+            .map(|claim| json!({ "path": [format!("$.{claim}")] }))
+            .collect();
+        if let Some(vct) = &self.config.vct {
+            fields.push(json!({
+                "path": ["$.vct"],
+                "filter": { "type": "string", "const": vct },
+            }));
+        }
+        json!({
+            "id": generate_nonce(),
+            "input_descriptors": [{
+                "id": CREDENTIAL_QUERY_ID,
+                "format": { "vc+sd-jwt": {} },
+                "constraints": {
+                    "limit_disclosure": "required",
+                    "fields": fields,
+                },
+            }],
+        })
+    }
+
+    /// DCQL のクエリ
+    fn dcql_query(&self) -> Value {
+        let claims: Vec<Value> = self
+            .config
+            .claims
+            .iter()
+            .map(|claim| json!({ "path": [claim] }))
+            .collect();
+        let mut credential = json!({
+            "id": CREDENTIAL_QUERY_ID,
+            "format": DCQL_SD_JWT_FORMAT,
+            "claims": claims,
+        });
+        if let Some(vct) = &self.config.vct {
+            credential["meta"] = json!({ "vct_values": [vct] });
+        }
+        json!({ "credentials": [credential] })
+    }
+
+    /// `direct_post` で受け取った `vp_token` を、`state` の Authorization Request に対して検証する
+    /// 1 つの Authorization Request に対して検証できるのは 1 回だけ
+    pub fn verify_response(
+        &self,
+        vp_token: &str,
+        state: &str,
+    ) -> Result<VerificationResult, VerificationError> {
+        let nonce = {
+            let mut requests = self.requests.lock().unwrap_or_else(|e| e.into_inner());
+            let context = requests
+                .get_mut(state)
+                .filter(|context| context.expires_at > SystemTime::now())
+                .filter(|context| matches!(context.status, Status::Pending))
+                .ok_or(VerificationError::UnknownState)?;
+            // 検証中に同じ state で送られても受け付けない
+            context.status = Status::Verifying;
+            context.request.nonce.clone()
+        };
+
+        let result = extract_vp(vp_token)
+            .and_then(|vp| self.verifier.verify_presentation(&vp, &nonce))
+            .and_then(|result| self.check_requested(result));
+
+        let mut requests = self.requests.lock().unwrap_or_else(|e| e.into_inner());
+        // 期限切れのリクエストは検証のついでにも捨てる
+        let now = SystemTime::now();
+        requests.retain(|_, context| context.expires_at > now);
+        if let Some(context) = requests.get_mut(state) {
+            context.status = match &result {
+                Ok(result) => Status::Verified(result.clone()),
+                Err(e) => Status::Failed(e.code()),
+            };
+        }
+        result
+    }
+
+    /// 要求したクレームが開示されているか、`vct` が一致するかチェックする
+    fn check_requested(
+        &self,
+        result: VerificationResult,
+    ) -> Result<VerificationResult, VerificationError> {
+        if let Some(vct) = &self.config.vct {
+            if result.vct.as_ref() != Some(vct) {
+                return Err(VerificationError::VctMismatch {
+                    expected: vct.clone(),
+                    actual: result.vct,
+                });
+            }
+        }
+        if let Some(claim) = self
+            .config
+            .claims
+            .iter()
+            .find(|claim| !result.claims.contains_key(claim.as_str()))
+        {
+            return Err(VerificationError::MissingRequestedClaim(claim.clone()));
+        }
+        Ok(result)
+    }
+
+    /// `state` の Authorization Request の検証結果
+    pub fn result(&self, state: &str) -> Option<Value> {
+        let requests = self.requests.lock().unwrap_or_else(|e| e.into_inner());
+        let context = requests.get(state)?;
+        Some(match &context.status {
+            Status::Pending => json!({ "status": "pending" }),
+            Status::Verifying => json!({ "status": "verifying" }),
+            Status::Verified(result) => json!({
+                "status": "verified",
+                "iss": result.issuer,
+                "vct": result.vct,
+                "claims": disclosed_claims(&result.claims),
+            }),
+            Status::Failed(code) => json!({ "status": "failed", "error": code }),
+        })
+    }
+}
+
+/// `vp_token` から SD-JWT の VP を取り出す
+/// DCQL の場合は `{ "<クレデンシャルの ID>": "<VP>" }` の JSON になっている
+fn extract_vp(vp_token: &str) -> Result<String, VerificationError> {
+    match serde_json::from_str::<Value>(vp_token) {
+        Ok(Value::Object(map)) => {
+            let vp = match map.get(CREDENTIAL_QUERY_ID) {
+                Some(Value::String(vp)) => Some(vp.clone()),
+                // 1 つのクエリに複数の VP を返す形式の場合は先頭を使う
+                Some(Value::Array(vps)) => vps.first().and_then(|v| v.as_str()).map(str::to_string),
+                _ => None,
+            };
+            vp.ok_or_else(|| {
+                VerificationError::MalformedSdJwt("vp_token has no credential".to_string())
+            })
+        }
+        Ok(Value::String(vp)) => Ok(vp),
+        _ => Ok(vp_token.to_string()),
+    }
+}
+
+/// 検証結果のクレームから SD-JWT の管理用のクレームを除く
+fn disclosed_claims(claims: &Map<String, Value>) -> Map<String, Value> {
+    const RESERVED: [&str; 8] = ["iss", "aud", "iat", "exp", "nbf", "cnf", "vct", "_sd_alg"];
+    claims
+        .iter()
+        .filter(|(name, _)| !RESERVED.contains(&name.as_str()))
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect()
+}
+
+/// SD-JWT VC のクレデンシャルの形式 (以前の `vc+sd-jwt` と OpenID4VP 1.0 の `dc+sd-jwt`)
+const SD_JWT_FORMATS: [&str; 2] = ["vc+sd-jwt", DCQL_SD_JWT_FORMAT];
+
+/// Presentation Definition の input descriptor から要求を取り出す
+/// `$.vct` の `filter` は `vct` の条件、それ以外のフィールドは開示を要求するクレームとして扱う
+fn parse_presentation_definition(definition: &Value) -> anyhow::Result<CredentialQuery> {
+    let descriptor = match definition
+        .get("input_descriptors")
+        .and_then(Value::as_array)
+    {
+        Some(descriptors) if descriptors.len() == 1 => &descriptors[0],
+        _ => anyhow::bail!("exactly one input descriptor is supported"),
+    };
+    if let Some(format) = descriptor.get("format").and_then(Value::as_object) {
+        if !SD_JWT_FORMATS.iter().any(|f| format.contains_key(*f)) {
+            anyhow::bail!("unsupported credential format");
+        }
+    }
+    let id = descriptor
+        .get("id")
+        .and_then(Value::as_str)
+        .ok_or_else(|| anyhow::anyhow!("input descriptor has no id"))?;
+
+    let mut query = CredentialQuery {
+        id: id.to_string(),
+        vct_values: Vec::new(),
+        claims: Vec::new(),
+    };
+    let fields = descriptor
+        .pointer("/constraints/fields")
+        .and_then(Value::as_array)
+        .cloned()
+        .unwrap_or_default();
+    for field in &fields {
+        let Some(path) = field.pointer("/path/0").and_then(Value::as_str) else {
+            anyhow::bail!("field has no path");
+        };
+        // `$.address.street` の場合は `address` を開示する
+        let Some(claim) = path
+            .strip_prefix("$.")
+            .and_then(|path| path.split('.').next())
+        else {
+            anyhow::bail!("unsupported field path {path}");
+        };
+        if claim == "vct" {
+            let filter = field.get("filter");
+            if let Some(vct) = filter.and_then(|f| f.get("const")).and_then(Value::as_str) {
+                query.vct_values.push(vct.to_string());
+            }
+            if let Some(values) = filter.and_then(|f| f.get("enum")).and_then(Value::as_array) {
+                query
+                    .vct_values
+                    .extend(values.iter().filter_map(Value::as_str).map(str::to_string));
+            }
+        } else if !query.claims.iter().any(|c| c == claim) {
+            query.claims.push(claim.to_string());
+        }
+    }
+    Ok(query)
+}
+
+/// DCQL のクエリから要求を取り出す
+fn parse_dcql_query(query: &Value) -> anyhow::Result<CredentialQuery> {
+    let credential = match query.get("credentials").and_then(Value::as_array) {
+        Some(credentials) if credentials.len() == 1 => &credentials[0],
+        _ => anyhow::bail!("exactly one credential query is supported"),
+    };
+    let format = credential.get("format").and_then(Value::as_str);
+    if !format.is_some_and(|format| SD_JWT_FORMATS.contains(&format)) {
+        anyhow::bail!("unsupported credential format");
+    }
+    let id = credential
+        .get("id")
+        .and_then(Value::as_str)
+        .ok_or_else(|| anyhow::anyhow!("credential query has no id"))?;
+    let vct_values = credential
+        .pointer("/meta/vct_values")
+        .and_then(Value::as_array)
+        .map(|values| {
+            values
+                .iter()
+                .filter_map(Value::as_str)
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let mut claims: Vec<String> = Vec::new();
+    for claim in credential
+        .get("claims")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+    {
+        let Some(name) = claim.pointer("/path/0").and_then(Value::as_str) else {
+            anyhow::bail!("claim query has no path");
+        };
+        if !claims.iter().any(|c| c == name) {
+            claims.push(name.to_string());
+        }
+    }
+    Ok(CredentialQuery {
+        id: id.to_string(),
+        vct_values,
+        claims,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        issuer::{GenerateVCParams, Issuer},
+        key::{generate_key_pair, public_key_pem_to_jwk},
+        Holder, SigningAlgorithm,
+    };
+
+    const BASE_URL: &str = "https://verifier.example.com";
+    const VC_AUDIENCE: &str = "fujita-app";
+    const VCT: &str = "https://credentials.example.com/test";
+
+    struct Setup {
+        server: VerifierServer,
+        issuer: Issuer,
+        holder: Holder,
+        holder_jwk: Value,
+    }
+
+    fn config(query_language: QueryLanguage) -> VerifierServerConfig {
+        VerifierServerConfig {
+            base_url: BASE_URL.to_string(),
+            vct: Some(VCT.to_string()),
+            claims: vec!["account_name".to_string()],
+            query_language,
+            request_expires_in: Duration::from_secs(60),
+            max_pending_requests: DEFAULT_MAX_PENDING_REQUESTS,
+        }
+    }
+
+    fn setup(config: VerifierServerConfig) -> Setup {
+        let (issuer_private, issuer_public) = generate_key_pair(SigningAlgorithm::ES256).unwrap();
+        let (holder_private, holder_public) = generate_key_pair(SigningAlgorithm::ES256).unwrap();
+        let verifier = Verifier::new(issuer_public, VC_AUDIENCE, config.response_uri());
+        Setup {
+            server: VerifierServer::new(config, verifier),
+            issuer: Issuer::new("https://issuer.example.com", issuer_private, "key-1").unwrap(),
+            holder: Holder::new(holder_private).unwrap(),
+            holder_jwk: public_key_pem_to_jwk(&holder_public).unwrap(),
+        }
+    }
+
+    fn issue(setup: &Setup) -> String {
+        let claims = json!({ "account_name": "user01", "group_name": "staff" });
+        setup
+            .issuer
+            .generate_sd_jwt_vc(GenerateVCParams {
+                vct: Some(VCT.to_string()),
+                vct_integrity: None,
+                holder_jwk: setup.holder_jwk.clone(),
+                holder_kid: None,
+                claims: claims.as_object().unwrap().clone(),
+                disclosable: vec!["/account_name".to_string(), "/group_name".to_string()],
+                decoys: 0,
+                audience: VC_AUDIENCE.to_string(),
+                vc_expires_in: 3600,
+                status: None,
+            })
+            .unwrap()
+    }
+
+    fn http_request(method: &str, path: &str, body: Vec<u8>) -> Request {
+        Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            query: String::new(),
+            headers: vec![],
+            body,
+        }
+    }
+
+    /// `POST /request` で作成した Authorization Request
+    fn authorization_request(setup: &Setup) -> AuthorizationRequest {
+        let response = setup
+            .server
+            .handle(&http_request("POST", "/request", vec![]));
+        assert_eq!(response.status, 200);
+        let body: Value = serde_json::from_slice(&response.body).unwrap();
+        serde_json::from_value(body["request"].clone()).unwrap()
+    }
+
+    fn direct_post(setup: &Setup, vp_token: &str, state: &str) -> Response {
+        let body = form_urlencoded::Serializer::new(String::new())
+            .append_pair("vp_token", vp_token)
+            .append_pair("state", state)
+            .finish();
+        setup
+            .server
+            .handle(&http_request("POST", "/response", body.into_bytes()))
+    }
+
+    fn result(setup: &Setup, state: &str) -> Value {
+        let response =
+            setup
+                .server
+                .handle(&http_request("GET", &format!("/result/{state}"), vec![]));
+        assert_eq!(response.status, 200);
+        serde_json::from_slice(&response.body).unwrap()
+    }
+
+    #[test]
+    fn vp_token_forms_are_extracted() {
+        assert_eq!(extract_vp("a.b.c~d~e.f.g").unwrap(), "a.b.c~d~e.f.g");
+        assert_eq!(extract_vp(r#""a.b.c~""#).unwrap(), "a.b.c~");
+        assert_eq!(extract_vp(r#"{"credential":"a.b.c~"}"#).unwrap(), "a.b.c~");
+        assert_eq!(
+            extract_vp(r#"{"credential":["a.b.c~","d.e.f~"]}"#).unwrap(),
+            "a.b.c~"
+        );
+        for vp_token in [r#"{"other":"a.b.c~"}"#, r#"{"credential":[]}"#] {
+            assert!(matches!(
+                extract_vp(vp_token),
+                Err(VerificationError::MalformedSdJwt(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn presentation_definition_request_is_parsed() {
+        let setup = setup(config(QueryLanguage::PresentationDefinition));
+        let request = authorization_request(&setup);
+        assert_eq!(request.client_id, format!("{BASE_URL}/response"));
+        assert_eq!(request.client_id, request.response_uri);
+        assert!(request.dcql_query.is_none());
+        let query = request.credential_query().unwrap();
+        assert_eq!(query.id, CREDENTIAL_QUERY_ID);
+        assert_eq!(query.vct_values, vec![VCT.to_string()]);
+        assert_eq!(query.claims, vec!["account_name".to_string()]);
+    }
+
+    #[test]
+    fn dcql_request_uses_dc_sd_jwt() {
+        let setup = setup(config(QueryLanguage::Dcql));
+        let request = authorization_request(&setup);
+        let dcql_query = request.dcql_query.clone().unwrap();
+        assert_eq!(dcql_query["credentials"][0]["format"], "dc+sd-jwt");
+        let query = request.credential_query().unwrap();
+        assert_eq!(query.vct_values, vec![VCT.to_string()]);
+        assert_eq!(query.claims, vec!["account_name".to_string()]);
+    }
+
+    #[test]
+    fn both_sd_jwt_formats_are_accepted() {
+        for format in ["vc+sd-jwt", "dc+sd-jwt"] {
+            let query = json!({
+                "credentials": [{ "id": "pid", "format": format, "claims": [{ "path": ["a"] }] }],
+            });
+            assert_eq!(parse_dcql_query(&query).unwrap().id, "pid");
+            let definition = json!({
+                "input_descriptors": [{
+                    "id": "pid",
+                    "format": { format: {} },
+                    "constraints": { "fields": [{ "path": ["$.a.b"] }] },
+                }],
+            });
+            let query = parse_presentation_definition(&definition).unwrap();
+            assert_eq!(query.claims, vec!["a".to_string()]);
+        }
+        let query = json!({ "credentials": [{ "id": "pid", "format": "jwt_vc_json" }] });
+        assert!(parse_dcql_query(&query).is_err());
+        let definition = json!({
+            "input_descriptors": [{ "id": "pid", "format": { "jwt_vc_json": {} } }],
+        });
+        assert!(parse_presentation_definition(&definition).is_err());
+    }
+
+    #[test]
+    fn direct_post_is_verified_once() {
+        let setup = setup(config(QueryLanguage::Dcql));
+        let request = authorization_request(&setup);
+        assert_eq!(result(&setup, &request.state)["status"], "pending");
+
+        let vp = setup
+            .holder
+            .present(
+                &issue(&setup),
+                &["account_name"],
+                &request.nonce,
+                &request.client_id,
+            )
+            .unwrap();
+        let vp_token = json!({ CREDENTIAL_QUERY_ID: vp }).to_string();
+        assert_eq!(direct_post(&setup, &vp_token, &request.state).status, 200);
+        let verified = result(&setup, &request.state);
+        assert_eq!(verified["status"], "verified");
+        assert_eq!(verified["claims"], json!({ "account_name": "user01" }));
+
+        // 同じ state には二度と応答できない
+        let response = direct_post(&setup, &vp_token, &request.state);
+        assert_eq!(response.status, 400);
+        let body: Value = serde_json::from_slice(&response.body).unwrap();
+        assert_eq!(body["error"], "unknown_state");
+    }
+
+    #[test]
+    fn direct_post_failures_are_recorded() {
+        let setup = setup(config(QueryLanguage::PresentationDefinition));
+        let request = authorization_request(&setup);
+        // 要求したクレームを開示していない
+        let vp = setup
+            .holder
+            .present(&issue(&setup), &[], &request.nonce, &request.client_id)
+            .unwrap();
+        let response = direct_post(&setup, &vp, &request.state);
+        assert_eq!(response.status, 400);
+        assert_eq!(
+            result(&setup, &request.state),
+            json!({ "status": "failed", "error": "missing_requested_claim" })
+        );
+
+        let response = setup.server.handle(&http_request(
+            "POST",
+            "/response",
+            b"vp_token=a.b.c~".to_vec(),
+        ));
+        assert_eq!(response.status, 400);
+        let body: Value = serde_json::from_slice(&response.body).unwrap();
+        assert_eq!(body["error"], "invalid_request");
+    }
+
+    #[test]
+    fn pending_requests_are_capped() {
+        let mut config = config(QueryLanguage::Dcql);
+        config.max_pending_requests = 2;
+        let setup = setup(config);
+        authorization_request(&setup);
+        authorization_request(&setup);
+        let response = setup
+            .server
+            .handle(&http_request("POST", "/request", vec![]));
+        assert_eq!(response.status, 503);
+    }
+
+    #[test]
+    fn expired_requests_do_not_count_towards_the_cap() {
+        let mut config = config(QueryLanguage::Dcql);
+        config.max_pending_requests = 1;
+        config.request_expires_in = Duration::ZERO;
+        let setup = setup(config);
+        authorization_request(&setup);
+        authorization_request(&setup);
+    }
+}
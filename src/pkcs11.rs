@@ -0,0 +1,884 @@
+//! PKCS#11 のトークン (HSM・SoftHSM) の鍵で署名する (`pkcs11` feature)
+//!
+//! 鍵は RFC 7512 の PKCS#11 URI で指定する。秘密鍵はトークンの外に出さず、署名だけをトークンで行う。
+//!
+//! ```text
+//! pkcs11:token=vc-vp;object=issuer-key?module-path=/usr/lib/softhsm/libsofthsm2.so&pin-value=1234
+//! ```
+//!
+//! - パス部分の `token` (トークンのラベル)・`slot-id`・`object` (鍵のラベル)・`id` (鍵の CKA_ID) で鍵を選ぶ
+//! - `module-path` を省略した場合は環境変数 PKCS11_MODULE
+//! - PIN は `pin-value`、`pin-source` (PIN のファイル)、環境変数 PKCS11_PIN の順
+//!
+//! EC (P-256・P-384・P-521) は CKM_ECDSA (ハッシュはこちらで計算)、Ed25519 は CKM_EDDSA、
+//! RSA は CKM_SHA256_RSA_PKCS (RS256)・CKM_SHA256_RSA_PKCS_PSS (PS256) で署名する。
+//!
+//! モジュールはプロセス内の署名器で共有し、最後の署名器を閉じたときに C_Finalize を呼ぶ。
+
+use crate::{alg::SigningAlgorithm, signer::Signer};
+use anyhow::{anyhow, bail, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256, Sha384, Sha512};
+use std::{
+    collections::BTreeMap,
+    ffi::c_void,
+    os::raw::{c_uchar, c_ulong},
+    ptr,
+    sync::Mutex,
+};
+
+type CkUlong = c_ulong;
+type CkRv = CkUlong;
+type CkSessionHandle = CkUlong;
+type CkObjectHandle = CkUlong;
+type CkSlotId = CkUlong;
+
+const CKR_OK: CkRv = 0;
+const CKR_USER_ALREADY_LOGGED_IN: CkRv = 0x100;
+const CKR_CRYPTOKI_ALREADY_INITIALIZED: CkRv = 0x191;
+
+const CKF_OS_LOCKING_OK: CkUlong = 0x2;
+const CKF_SERIAL_SESSION: CkUlong = 0x4;
+const CKU_USER: CkUlong = 1;
+
+const CKA_CLASS: CkUlong = 0x0;
+const CKA_LABEL: CkUlong = 0x3;
+const CKA_KEY_TYPE: CkUlong = 0x100;
+const CKA_ID: CkUlong = 0x102;
+const CKA_MODULUS: CkUlong = 0x120;
+const CKA_PUBLIC_EXPONENT: CkUlong = 0x122;
+const CKA_EC_PARAMS: CkUlong = 0x180;
+const CKA_EC_POINT: CkUlong = 0x181;
+
+const CKO_PUBLIC_KEY: CkUlong = 2;
+const CKO_PRIVATE_KEY: CkUlong = 3;
+
+const CKK_RSA: CkUlong = 0x0;
+const CKK_EC: CkUlong = 0x3;
+const CKK_EC_EDWARDS: CkUlong = 0x40;
+
+const CKM_SHA256_RSA_PKCS: CkUlong = 0x40;
+const CKM_SHA256_RSA_PKCS_PSS: CkUlong = 0x43;
+const CKM_ECDSA: CkUlong = 0x1041;
+const CKM_EDDSA: CkUlong = 0x1057;
+const CKM_SHA256: CkUlong = 0x250;
+const CKG_MGF1_SHA256: CkUlong = 0x2;
+
+/// 曲線の OID (CKA_EC_PARAMS の DER)
+const OID_P256: &[u8] = &[0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+const OID_P384: &[u8] = &[0x06, 0x05, 0x2b, 0x81, 0x04, 0x00, 0x22];
+const OID_P521: &[u8] = &[0x06, 0x05, 0x2b, 0x81, 0x04, 0x00, 0x23];
+const OID_ED25519: &[u8] = &[0x06, 0x03, 0x2b, 0x65, 0x70];
+/// PrintableString "edwards25519" (PKCS#11 3.0 より前の表現)
+const NAME_ED25519: &[u8] = b"\x13\x0cedwards25519";
+
+#[repr(C)]
+struct CkAttribute {
+    attr_type: CkUlong,
+    value: *mut c_void,
+    value_len: CkUlong,
+}
+
+#[repr(C)]
+struct CkMechanism {
+    mechanism: CkUlong,
+    parameter: *mut c_void,
+    parameter_len: CkUlong,
+}
+
+#[repr(C)]
+struct CkRsaPkcsPssParams {
+    hash_alg: CkUlong,
+    mgf: CkUlong,
+    salt_len: CkUlong,
+}
+
+#[repr(C)]
+struct CkCInitializeArgs {
+    create_mutex: *mut c_void,
+    destroy_mutex: *mut c_void,
+    lock_mutex: *mut c_void,
+    unlock_mutex: *mut c_void,
+    flags: CkUlong,
+    reserved: *mut c_void,
+}
+
+#[repr(C)]
+struct CkVersion {
+    major: c_uchar,
+    minor: c_uchar,
+}
+
+#[repr(C)]
+struct CkTokenInfo {
+    label: [c_uchar; 32],
+    manufacturer_id: [c_uchar; 32],
+    model: [c_uchar; 16],
+    serial_number: [c_uchar; 16],
+    flags: CkUlong,
+    max_session_count: CkUlong,
+    session_count: CkUlong,
+    max_rw_session_count: CkUlong,
+    rw_session_count: CkUlong,
+    max_pin_len: CkUlong,
+    min_pin_len: CkUlong,
+    total_public_memory: CkUlong,
+    free_public_memory: CkUlong,
+    total_private_memory: CkUlong,
+    free_private_memory: CkUlong,
+    hardware_version: CkVersion,
+    firmware_version: CkVersion,
+    utc_time: [c_uchar; 16],
+}
+
+/// 使う PKCS#11 の関数 (モジュールから読み込む)
+struct Functions {
+    initialize: unsafe extern "C" fn(*mut c_void) -> CkRv,
+    finalize: unsafe extern "C" fn(*mut c_void) -> CkRv,
+    get_slot_list: unsafe extern "C" fn(c_uchar, *mut CkSlotId, *mut CkUlong) -> CkRv,
+    get_token_info: unsafe extern "C" fn(CkSlotId, *mut CkTokenInfo) -> CkRv,
+    open_session: unsafe extern "C" fn(
+        CkSlotId,
+        CkUlong,
+        *mut c_void,
+        *mut c_void,
+        *mut CkSessionHandle,
+    ) -> CkRv,
+    close_session: unsafe extern "C" fn(CkSessionHandle) -> CkRv,
+    login: unsafe extern "C" fn(CkSessionHandle, CkUlong, *const c_uchar, CkUlong) -> CkRv,
+    find_objects_init: unsafe extern "C" fn(CkSessionHandle, *mut CkAttribute, CkUlong) -> CkRv,
+    find_objects:
+        unsafe extern "C" fn(CkSessionHandle, *mut CkObjectHandle, CkUlong, *mut CkUlong) -> CkRv,
+    find_objects_final: unsafe extern "C" fn(CkSessionHandle) -> CkRv,
+    get_attribute_value:
+        unsafe extern "C" fn(CkSessionHandle, CkObjectHandle, *mut CkAttribute, CkUlong) -> CkRv,
+    sign_init: unsafe extern "C" fn(CkSessionHandle, *mut CkMechanism, CkObjectHandle) -> CkRv,
+    sign: unsafe extern "C" fn(
+        CkSessionHandle,
+        *const c_uchar,
+        CkUlong,
+        *mut c_uchar,
+        *mut CkUlong,
+    ) -> CkRv,
+}
+
+impl Functions {
+    fn load(library: &libloading::Library) -> Result<Self> {
+        // SAFETY: 関数の型は PKCS#11 (v2.40) の定義に合わせている
+        unsafe {
+            Ok(Self {
+                initialize: *library.get(b"C_Initialize\0")?,
+                finalize: *library.get(b"C_Finalize\0")?,
+                get_slot_list: *library.get(b"C_GetSlotList\0")?,
+                get_token_info: *library.get(b"C_GetTokenInfo\0")?,
+                open_session: *library.get(b"C_OpenSession\0")?,
+                close_session: *library.get(b"C_CloseSession\0")?,
+                login: *library.get(b"C_Login\0")?,
+                find_objects_init: *library.get(b"C_FindObjectsInit\0")?,
+                find_objects: *library.get(b"C_FindObjects\0")?,
+                find_objects_final: *library.get(b"C_FindObjectsFinal\0")?,
+                get_attribute_value: *library.get(b"C_GetAttributeValue\0")?,
+                sign_init: *library.get(b"C_SignInit\0")?,
+                sign: *library.get(b"C_Sign\0")?,
+            })
+        }
+    }
+}
+
+/// PKCS#11 の関数の戻り値をチェックする
+fn check(rv: CkRv, function: &str) -> Result<()> {
+    if rv != CKR_OK {
+        bail!("{function} failed: CKR 0x{rv:x}");
+    }
+    Ok(())
+}
+
+/// このプロセスで C_Initialize したモジュールのパスと、使っている署名器の数
+static INITIALIZED: Mutex<BTreeMap<String, usize>> = Mutex::new(BTreeMap::new());
+
+/// 読み込んで初期化した PKCS#11 モジュール
+///
+/// 同じモジュールを使う署名器の数を数え、最後の署名器を閉じたときに C_Finalize を呼ぶ。
+/// このプロセスの他のコードが初期化したモジュールは終了処理をしない。
+struct Module {
+    functions: Functions,
+    path: String,
+    /// C_Finalize の対象として数えているか
+    counted: bool,
+    /// モジュールは使い終わるまで読み込んだままにする (C_Finalize の後に解放する)
+    _library: libloading::Library,
+}
+
+impl Module {
+    fn open(path: &str) -> Result<Self> {
+        // SAFETY: PKCS#11 モジュールの読み込み (初期化処理は C_Initialize で行う)
+        let library = unsafe { libloading::Library::new(path) }
+            .map_err(|e| anyhow!("failed to load PKCS#11 module {path}: {e}"))?;
+        let functions = Functions::load(&library)?;
+
+        let mut args = CkCInitializeArgs {
+            create_mutex: ptr::null_mut(),
+            destroy_mutex: ptr::null_mut(),
+            lock_mutex: ptr::null_mut(),
+            unlock_mutex: ptr::null_mut(),
+            flags: CKF_OS_LOCKING_OK,
+            reserved: ptr::null_mut(),
+        };
+        let mut initialized = INITIALIZED.lock().unwrap_or_else(|e| e.into_inner());
+        // SAFETY: args は呼び出しの間有効
+        let rv = unsafe { (functions.initialize)(&mut args as *mut _ as *mut c_void) };
+        let counted = match rv {
+            CKR_OK => {
+                initialized.insert(path.to_string(), 1);
+                true
+            }
+            CKR_CRYPTOKI_ALREADY_INITIALIZED => match initialized.get_mut(path) {
+                Some(count) => {
+                    *count += 1;
+                    true
+                }
+                None => false,
+            },
+            _ => bail!("C_Initialize failed: CKR 0x{rv:x}"),
+        };
+        Ok(Self {
+            functions,
+            path: path.to_string(),
+            counted,
+            _library: library,
+        })
+    }
+}
+
+impl Drop for Module {
+    fn drop(&mut self) {
+        if !self.counted {
+            return;
+        }
+        let mut initialized = INITIALIZED.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(count) = initialized.get_mut(&self.path) else {
+            return;
+        };
+        *count -= 1;
+        if *count == 0 {
+            initialized.remove(&self.path);
+            // SAFETY: このモジュールのセッションはすべて閉じている
+            unsafe { (self.functions.finalize)(ptr::null_mut()) };
+        }
+    }
+}
+
+/// PKCS#11 URI で指定した鍵
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Pkcs11Uri {
+    pub token: Option<String>,
+    pub slot_id: Option<c_ulong>,
+    /// 鍵のラベル (CKA_LABEL)
+    pub object: Option<String>,
+    /// 鍵の CKA_ID
+    pub id: Option<Vec<u8>>,
+    pub module_path: Option<String>,
+    pub pin_value: Option<String>,
+    /// PIN を読み込むファイル
+    pub pin_source: Option<String>,
+}
+
+impl std::str::FromStr for Pkcs11Uri {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let rest = s
+            .strip_prefix("pkcs11:")
+            .ok_or_else(|| anyhow!("not a PKCS#11 URI: {s}"))?;
+        let (path, query) = rest.split_once('?').unwrap_or((rest, ""));
+        let mut uri = Self::default();
+        let attributes = path.split(';').chain(query.split('&'));
+        for attr in attributes.filter(|attr| !attr.is_empty()) {
+            let (name, value) = attr
+                .split_once('=')
+                .ok_or_else(|| anyhow!("invalid PKCS#11 URI attribute {attr}"))?;
+            let value = percent_decode(value)?;
+            let text = || String::from_utf8(value.clone()).map_err(|e| anyhow!("{name}: {e}"));
+            match name {
+                "token" => uri.token = Some(text()?),
+                "slot-id" => uri.slot_id = Some(text()?.parse()?),
+                "object" => uri.object = Some(text()?),
+                "id" => uri.id = Some(value),
+                "module-path" => uri.module_path = Some(text()?),
+                "pin-value" => uri.pin_value = Some(text()?),
+                "pin-source" => {
+                    let source = text()?;
+                    uri.pin_source = Some(source.strip_prefix("file:").unwrap_or(&source).into());
+                }
+                // type=private などそれ以外の属性は使わない
+                _ => {}
+            }
+        }
+        if uri.object.is_none() && uri.id.is_none() {
+            bail!("PKCS#11 URI must have object or id");
+        }
+        Ok(uri)
+    }
+}
+
+impl Pkcs11Uri {
+    /// ログインの PIN (`pin-value`、`pin-source`、環境変数 PKCS11_PIN の順)
+    fn pin(&self) -> Result<Option<String>> {
+        if let Some(pin) = &self.pin_value {
+            return Ok(Some(pin.clone()));
+        }
+        if let Some(file) = &self.pin_source {
+            let pin = std::fs::read_to_string(file)
+                .map_err(|e| anyhow!("failed to read PIN file {file}: {e:?}"))?;
+            return Ok(Some(pin.trim_end_matches(['\r', '\n']).to_string()));
+        }
+        Ok(std::env::var("PKCS11_PIN").ok())
+    }
+}
+
+/// URI のパーセントエンコーディングを復号する
+fn percent_decode(value: &str) -> Result<Vec<u8>> {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = value
+                .get(i + 1..i + 3)
+                .ok_or_else(|| anyhow!("invalid percent encoding in {value}"))?;
+            decoded.push(u8::from_str_radix(hex, 16)?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    Ok(decoded)
+}
+
+/// PKCS#11 のトークンの鍵で署名する
+pub struct Pkcs11Signer {
+    module: Module,
+    session: Mutex<CkSessionHandle>,
+    private_key: CkObjectHandle,
+    alg: SigningAlgorithm,
+    /// 公開鍵 (JWK)
+    public_jwk: Value,
+}
+
+impl Pkcs11Signer {
+    /// PKCS#11 URI の鍵を開く (トークンにログインし、署名できることを確認する)
+    pub fn from_uri(uri: &str) -> Result<Self> {
+        let uri: Pkcs11Uri = uri.parse()?;
+        let module_path = match &uri.module_path {
+            Some(path) => path.clone(),
+            None => std::env::var("PKCS11_MODULE")
+                .map_err(|_| anyhow!("module-path or PKCS11_MODULE must be set"))?,
+        };
+        let module = Module::open(&module_path)?;
+        let functions = &module.functions;
+
+        let slot = find_slot(functions, &uri)?;
+        let mut session = 0;
+        // SAFETY: session は呼び出しの間有効
+        check(
+            unsafe {
+                (functions.open_session)(
+                    slot,
+                    CKF_SERIAL_SESSION,
+                    ptr::null_mut(),
+                    ptr::null_mut(),
+                    &mut session,
+                )
+            },
+            "C_OpenSession",
+        )?;
+        Self::open_key(module, session, &uri)
+    }
+
+    /// ログインして鍵を探す (失敗した場合はセッションを閉じる)
+    fn open_key(module: Module, session: CkSessionHandle, uri: &Pkcs11Uri) -> Result<Self> {
+        let functions = &module.functions;
+        let result = (|| {
+            if let Some(pin) = uri.pin()? {
+                // SAFETY: pin は呼び出しの間有効
+                let rv = unsafe {
+                    (functions.login)(session, CKU_USER, pin.as_ptr(), pin.len() as CkUlong)
+                };
+                if rv != CKR_USER_ALREADY_LOGGED_IN {
+                    check(rv, "C_Login")?;
+                }
+            }
+            let private_key = find_object(functions, session, CKO_PRIVATE_KEY, uri)?
+                .ok_or_else(|| anyhow!("private key not found in PKCS#11 token"))?;
+            // 公開鍵は公開鍵のオブジェクト (なければ秘密鍵) の属性から作る
+            let public_key = find_object(functions, session, CKO_PUBLIC_KEY, uri)?;
+            let (alg, jwk) = public_key_jwk(functions, session, public_key.unwrap_or(private_key))?;
+            Ok((private_key, alg, crate::key::jwk_with_metadata(alg, jwk)?))
+        })();
+        match result {
+            Ok((private_key, alg, public_jwk)) => Ok(Self {
+                module,
+                session: Mutex::new(session),
+                private_key,
+                alg,
+                public_jwk,
+            }),
+            Err(e) => {
+                // SAFETY: 開いたセッションを閉じる (モジュールは module の drop で終了する)
+                unsafe { (functions.close_session)(session) };
+                Err(e)
+            }
+        }
+    }
+}
+
+impl Drop for Pkcs11Signer {
+    fn drop(&mut self) {
+        let session = *self.session.lock().unwrap_or_else(|e| e.into_inner());
+        // SAFETY: 開いたセッションを閉じる (C_Finalize はその後の module の drop で呼ぶ)
+        unsafe { (self.module.functions.close_session)(session) };
+    }
+}
+
+impl Signer for Pkcs11Signer {
+    fn algorithm(&self) -> SigningAlgorithm {
+        self.alg
+    }
+
+    fn public_jwk(&self) -> Result<Value> {
+        Ok(self.public_jwk.clone())
+    }
+
+    fn sign(&self, alg: SigningAlgorithm, message: &[u8]) -> Result<Vec<u8>> {
+        if !alg.is_compatible_with(self.alg) {
+            bail!("{alg} cannot be used with {} key", self.alg);
+        }
+        let mut pss_params = CkRsaPkcsPssParams {
+            hash_alg: CKM_SHA256,
+            mgf: CKG_MGF1_SHA256,
+            salt_len: 32,
+        };
+        // ECDSA はハッシュ値に署名する
+        let (mechanism, data) = match alg {
+            SigningAlgorithm::ES256 => (CKM_ECDSA, Sha256::digest(message).to_vec()),
+            SigningAlgorithm::ES384 => (CKM_ECDSA, Sha384::digest(message).to_vec()),
+            SigningAlgorithm::ES512 => (CKM_ECDSA, Sha512::digest(message).to_vec()),
+            SigningAlgorithm::EdDSA => (CKM_EDDSA, message.to_vec()),
+            SigningAlgorithm::RS256 => (CKM_SHA256_RSA_PKCS, message.to_vec()),
+            SigningAlgorithm::PS256 => (CKM_SHA256_RSA_PKCS_PSS, message.to_vec()),
+        };
+        let mut mechanism = CkMechanism {
+            mechanism,
+            parameter: ptr::null_mut(),
+            parameter_len: 0,
+        };
+        if alg == SigningAlgorithm::PS256 {
+            mechanism.parameter = &mut pss_params as *mut _ as *mut c_void;
+            mechanism.parameter_len = std::mem::size_of::<CkRsaPkcsPssParams>() as CkUlong;
+        }
+
+        // 1 つのセッションで同時に署名しないよう、署名が終わるまでロックする
+        let guard = self.session.lock().unwrap_or_else(|e| e.into_inner());
+        let session = *guard;
+        let functions = &self.module.functions;
+        // SAFETY: mechanism・data・signature は呼び出しの間有効
+        unsafe {
+            check(
+                (functions.sign_init)(session, &mut mechanism, self.private_key),
+                "C_SignInit",
+            )?;
+            let mut len: CkUlong = 0;
+            check(
+                (functions.sign)(
+                    session,
+                    data.as_ptr(),
+                    data.len() as CkUlong,
+                    ptr::null_mut(),
+                    &mut len,
+                ),
+                "C_Sign",
+            )?;
+            let mut signature = vec![0u8; len as usize];
+            check(
+                (functions.sign)(
+                    session,
+                    data.as_ptr(),
+                    data.len() as CkUlong,
+                    signature.as_mut_ptr(),
+                    &mut len,
+                ),
+                "C_Sign",
+            )?;
+            signature.truncate(len as usize);
+            Ok(signature)
+        }
+    }
+}
+
+// SAFETY: セッションは Mutex で直列にして使い、モジュールは CKF_OS_LOCKING_OK で初期化している
+unsafe impl Send for Pkcs11Signer {}
+unsafe impl Sync for Pkcs11Signer {}
+
+/// URI の `slot-id` または `token` (ラベル) のスロット (どちらもなければ最初のトークン)
+fn find_slot(functions: &Functions, uri: &Pkcs11Uri) -> Result<CkSlotId> {
+    let mut count: CkUlong = 0;
+    // SAFETY: count は呼び出しの間有効
+    check(
+        unsafe { (functions.get_slot_list)(1, ptr::null_mut(), &mut count) },
+        "C_GetSlotList",
+    )?;
+    let mut slots = vec![0; count as usize];
+    // SAFETY: slots は count 個の要素を持つ
+    check(
+        unsafe { (functions.get_slot_list)(1, slots.as_mut_ptr(), &mut count) },
+        "C_GetSlotList",
+    )?;
+    slots.truncate(count as usize);
+
+    for slot in slots {
+        if uri.slot_id.is_some_and(|id| id != slot) {
+            continue;
+        }
+        if let Some(token) = &uri.token {
+            // SAFETY: CK_TOKEN_INFO はすべてのビットが 0 でもよい構造体
+            let mut info: CkTokenInfo = unsafe { std::mem::zeroed() };
+            // SAFETY: info は呼び出しの間有効
+            check(
+                unsafe { (functions.get_token_info)(slot, &mut info) },
+                "C_GetTokenInfo",
+            )?;
+            // ラベルは 32 バイトの空白埋め
+            let label = String::from_utf8_lossy(&info.label);
+            if label.trim_end() != token {
+                continue;
+            }
+        }
+        return Ok(slot);
+    }
+    bail!("PKCS#11 token not found")
+}
+
+/// URI の `object` と `id` に一致する `class` のオブジェクト (複数一致する場合はエラー)
+fn find_object(
+    functions: &Functions,
+    session: CkSessionHandle,
+    class: CkUlong,
+    uri: &Pkcs11Uri,
+) -> Result<Option<CkObjectHandle>> {
+    let mut class = class;
+    let mut template = vec![CkAttribute {
+        attr_type: CKA_CLASS,
+        value: &mut class as *mut _ as *mut c_void,
+        value_len: std::mem::size_of::<CkUlong>() as CkUlong,
+    }];
+    let mut label = uri.object.clone().unwrap_or_default().into_bytes();
+    if uri.object.is_some() {
+        template.push(CkAttribute {
+            attr_type: CKA_LABEL,
+            value: label.as_mut_ptr() as *mut c_void,
+            value_len: label.len() as CkUlong,
+        });
+    }
+    let mut id = uri.id.clone().unwrap_or_default();
+    if uri.id.is_some() {
+        template.push(CkAttribute {
+            attr_type: CKA_ID,
+            value: id.as_mut_ptr() as *mut c_void,
+            value_len: id.len() as CkUlong,
+        });
+    }
+
+    // 2 つ目が見つかれば URI があいまい
+    let mut objects = [0; 2];
+    let mut count: CkUlong = 0;
+    // SAFETY: template が指す値と objects・count は呼び出しの間有効
+    unsafe {
+        check(
+            (functions.find_objects_init)(
+                session,
+                template.as_mut_ptr(),
+                template.len() as CkUlong,
+            ),
+            "C_FindObjectsInit",
+        )?;
+        let rv = (functions.find_objects)(
+            session,
+            objects.as_mut_ptr(),
+            objects.len() as CkUlong,
+            &mut count,
+        );
+        check(
+            (functions.find_objects_final)(session),
+            "C_FindObjectsFinal",
+        )?;
+        check(rv, "C_FindObjects")?;
+    }
+    match count {
+        0 => Ok(None),
+        1 => Ok(Some(objects[0])),
+        _ => bail!("multiple PKCS#11 objects match the URI (specify both object and id)"),
+    }
+}
+
+/// オブジェクトの属性の値
+fn attribute(
+    functions: &Functions,
+    session: CkSessionHandle,
+    object: CkObjectHandle,
+    attr_type: CkUlong,
+) -> Result<Vec<u8>> {
+    let mut template = CkAttribute {
+        attr_type,
+        value: ptr::null_mut(),
+        value_len: 0,
+    };
+    // SAFETY: 1 回目で長さを取得し、その長さのバッファで 2 回目を呼ぶ
+    unsafe {
+        check(
+            (functions.get_attribute_value)(session, object, &mut template, 1),
+            "C_GetAttributeValue",
+        )?;
+        let mut value = vec![0u8; template.value_len as usize];
+        template.value = value.as_mut_ptr() as *mut c_void;
+        check(
+            (functions.get_attribute_value)(session, object, &mut template, 1),
+            "C_GetAttributeValue",
+        )?;
+        value.truncate(template.value_len as usize);
+        Ok(value)
+    }
+}
+
+/// 鍵のオブジェクトの属性から署名アルゴリズムと公開鍵の JWK を作る
+fn public_key_jwk(
+    functions: &Functions,
+    session: CkSessionHandle,
+    object: CkObjectHandle,
+) -> Result<(SigningAlgorithm, Value)> {
+    let key_type = attribute(functions, session, object, CKA_KEY_TYPE)?;
+    let key_type = CkUlong::from_ne_bytes(
+        key_type
+            .as_slice()
+            .try_into()
+            .map_err(|_| anyhow!("invalid CKA_KEY_TYPE"))?,
+    );
+    match key_type {
+        CKK_EC | CKK_EC_EDWARDS => {
+            let params = attribute(functions, session, object, CKA_EC_PARAMS)?;
+            let point = attribute(functions, session, object, CKA_EC_POINT)?;
+            let (alg, crv, len) = match params.as_slice() {
+                OID_P256 => (SigningAlgorithm::ES256, "P-256", 65),
+                OID_P384 => (SigningAlgorithm::ES384, "P-384", 97),
+                OID_P521 => (SigningAlgorithm::ES512, "P-521", 133),
+                OID_ED25519 | NAME_ED25519 => {
+                    let jwk = json!({
+                        "kty": "OKP",
+                        "crv": "Ed25519",
+                        "x": URL_SAFE_NO_PAD.encode(ec_point(&point, 32)?),
+                    });
+                    return Ok((SigningAlgorithm::EdDSA, jwk));
+                }
+                _ => bail!("unsupported EC curve of PKCS#11 key"),
+            };
+            let point = ec_point(&point, len)?;
+            if point[0] != 0x04 {
+                bail!("CKA_EC_POINT is not an uncompressed point");
+            }
+            Ok((alg, crate::key::ec_point_to_jwk(crv, point)?))
+        }
+        CKK_RSA => {
+            let n = attribute(functions, session, object, CKA_MODULUS)?;
+            let e = attribute(functions, session, object, CKA_PUBLIC_EXPONENT)?;
+            let jwk = json!({
+                "kty": "RSA",
+                "n": URL_SAFE_NO_PAD.encode(n),
+                "e": URL_SAFE_NO_PAD.encode(e),
+            });
+            Ok((SigningAlgorithm::RS256, jwk))
+        }
+        _ => bail!("unsupported PKCS#11 key type 0x{key_type:x}"),
+    }
+}
+
+/// CKA_EC_POINT の値 (DER の OCTET STRING) から `len` バイトの点を取り出す
+///
+/// PKCS#11 では OCTET STRING で包むが、包まずに点そのものを返す古いモジュールもあるので、
+/// DER として全体を読めて中身が `len` バイトの場合はその中身、そうでなく値そのものが `len` バイトの場合は値を使う。
+fn ec_point(value: &[u8], len: usize) -> Result<&[u8]> {
+    use p256::pkcs8::der::{asn1::OctetStringRef, Decode as _};
+
+    match OctetStringRef::from_der(value) {
+        Ok(octets) if octets.as_bytes().len() == len => Ok(octets.as_bytes()),
+        _ if value.len() == len => Ok(value),
+        Ok(octets) => bail!(
+            "CKA_EC_POINT has {} bytes (expected {len})",
+            octets.as_bytes().len()
+        ),
+        Err(e) => bail!("invalid CKA_EC_POINT: {e}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key::{generate_key_pair, public_key_pem_to_jwk};
+
+    #[test]
+    fn pkcs11_uri_is_parsed() {
+        let uri: Pkcs11Uri =
+            "pkcs11:token=vc%20vp;object=issuer-key;id=%01%02;type=private?module-path=/usr/lib/softhsm/libsofthsm2.so&pin-source=file:/run/pin"
+                .parse()
+                .unwrap();
+        assert_eq!(
+            uri,
+            Pkcs11Uri {
+                token: Some("vc vp".to_string()),
+                slot_id: None,
+                object: Some("issuer-key".to_string()),
+                id: Some(vec![1, 2]),
+                module_path: Some("/usr/lib/softhsm/libsofthsm2.so".to_string()),
+                pin_value: None,
+                pin_source: Some("/run/pin".to_string()),
+            }
+        );
+        assert!("pkcs11:token=vc-vp".parse::<Pkcs11Uri>().is_err());
+        assert!("pkcs11:object=%zz".parse::<Pkcs11Uri>().is_err());
+        assert!("file:key.pem".parse::<Pkcs11Uri>().is_err());
+    }
+
+    #[test]
+    fn ec_point_is_der_decoded() {
+        let mut p256 = vec![0x04];
+        p256.extend([0xab; 64]);
+        let mut der = vec![0x04, 65];
+        der.extend(&p256);
+        assert_eq!(ec_point(&der, 65).unwrap(), p256);
+        // 包まずに返すモジュールの値
+        assert_eq!(ec_point(&p256, 65).unwrap(), p256);
+
+        // P-521 の点は長さが長形式 (0x81 0x85)
+        let mut p521 = vec![0x04];
+        p521.extend([0xcd; 132]);
+        let mut der = vec![0x04, 0x81, 0x85];
+        der.extend(&p521);
+        assert_eq!(ec_point(&der, 133).unwrap(), p521);
+
+        // OCTET STRING のタグと長さに見える先頭の Ed25519 の公開鍵も削らない
+        let mut ed25519 = vec![0x04, 30];
+        ed25519.extend([0xef; 30]);
+        assert_eq!(ec_point(&ed25519, 32).unwrap(), ed25519);
+
+        assert!(ec_point(&der, 65).is_err());
+        let mut trailing = vec![0x04, 65];
+        trailing.extend(&p256);
+        trailing.push(0);
+        assert!(ec_point(&trailing, 65).is_err());
+    }
+
+    /// SoftHSM のトークンに softhsm2-util で鍵をインポートして署名を確かめる
+    /// `--ignored` で実行し、環境変数 SOFTHSM2_MODULE (libsofthsm2.so のパス) が必要
+    /// (黙ってスキップしないよう、設定されていない・モジュールがない場合は失敗する)
+    #[test]
+    #[ignore = "requires SoftHSM: set SOFTHSM2_MODULE and run with --ignored"]
+    fn softhsm_signs_with_imported_keys() {
+        let module = std::env::var("SOFTHSM2_MODULE")
+            .expect("SOFTHSM2_MODULE must be set to the path of libsofthsm2.so");
+        assert!(
+            std::path::Path::new(&module).exists(),
+            "SOFTHSM2_MODULE {module} does not exist"
+        );
+        let dir = std::env::temp_dir().join(format!("vc_vp_softhsm_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("tokens")).unwrap();
+        let conf = dir.join("softhsm2.conf");
+        std::fs::write(
+            &conf,
+            format!(
+                "directories.tokendir = {}\nobjectstore.backend = file\n",
+                dir.join("tokens").display()
+            ),
+        )
+        .unwrap();
+        // モジュールは C_Initialize のときに SOFTHSM2_CONF を読む
+        std::env::set_var("SOFTHSM2_CONF", &conf);
+        let softhsm = |args: &[&str]| {
+            let status = std::process::Command::new("softhsm2-util")
+                .args(args)
+                .status()
+                .expect("failed to run softhsm2-util");
+            assert!(status.success(), "softhsm2-util {args:?}");
+        };
+        softhsm(&[
+            "--init-token",
+            "--free",
+            "--label",
+            "vc-vp-test",
+            "--so-pin",
+            "0000",
+            "--pin",
+            "1234",
+        ]);
+        let import = |alg: SigningAlgorithm, label: &str, id: &str| {
+            let (private_key, public_key) = generate_key_pair(alg).unwrap();
+            let file = dir.join(format!("{id}.pem"));
+            std::fs::write(&file, private_key).unwrap();
+            softhsm(&[
+                "--import",
+                file.to_str().unwrap(),
+                "--token",
+                "vc-vp-test",
+                "--label",
+                label,
+                "--id",
+                id,
+                "--pin",
+                "1234",
+            ]);
+            public_key
+        };
+        let uri = |path: &str| {
+            format!("pkcs11:token=vc-vp-test;{path}?module-path={module}&pin-value=1234")
+        };
+
+        let mut signers = Vec::new();
+        for (alg, id) in [
+            (SigningAlgorithm::ES256, "01"),
+            (SigningAlgorithm::ES384, "02"),
+            (SigningAlgorithm::RS256, "03"),
+        ] {
+            let public_key = import(alg, &format!("key-{id}"), id);
+            let signer = Pkcs11Signer::from_uri(&uri(&format!("object=key-{id}"))).unwrap();
+            assert_eq!(signer.algorithm(), alg);
+            assert_eq!(
+                signer.public_jwk().unwrap(),
+                public_key_pem_to_jwk(&public_key).unwrap()
+            );
+            let signature = signer.sign(alg, b"message").unwrap();
+            let verifier: Box<dyn josekit::jws::JwsVerifier> = match alg {
+                SigningAlgorithm::ES256 => {
+                    Box::new(josekit::jws::ES256.verifier_from_pem(&public_key).unwrap())
+                }
+                SigningAlgorithm::ES384 => {
+                    Box::new(josekit::jws::ES384.verifier_from_pem(&public_key).unwrap())
+                }
+                _ => Box::new(josekit::jws::RS256.verifier_from_pem(&public_key).unwrap()),
+            };
+            verifier.verify(b"message", &signature).unwrap();
+            signers.push(signer);
+        }
+
+        // 同じラベルの鍵が 2 つあればラベルだけでは選ばない
+        import(SigningAlgorithm::ES256, "key-01", "04");
+        let err = Pkcs11Signer::from_uri(&uri("object=key-01")).err().unwrap();
+        assert!(err.to_string().contains("multiple"), "{err}");
+        assert!(Pkcs11Signer::from_uri(&uri("object=key-01;id=%04")).is_ok());
+
+        // 最後の署名器を閉じると C_Finalize し、次に開くときに初期化し直す
+        drop(signers);
+        assert!(!INITIALIZED.lock().unwrap().contains_key(&module));
+        assert!(Pkcs11Signer::from_uri(&uri("id=%02")).is_ok());
+        assert!(!INITIALIZED.lock().unwrap().contains_key(&module));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,46 @@
+//! SD-JWT の組み立てと分解
+//!
+//! sd-jwt-payload の `SdJwt` は disclosure が 0 個のとき `<jwt>~~<kb-jwt>` を作り、
+//! 仕様どおりの `<jwt>~<kb-jwt>` を読めないため、VP の文字列の形式はここで扱う。
+
+use anyhow::{bail, Result};
+use sd_jwt_payload::{Hasher, SdJwt, Sha256Hasher};
+
+/// `<jwt>~<disclosure>~...~<kb-jwt>` を分解する
+/// KB-JWT がない場合は `~` で終わる
+pub fn parse(sd_jwt: &str) -> Result<SdJwt> {
+    let segments: Vec<&str> = sd_jwt.split('~').collect();
+    if segments.len() < 2 || segments[0].is_empty() {
+        bail!("SD-JWT format is invalid");
+    }
+    let disclosures = &segments[1..segments.len() - 1];
+    if disclosures.iter().any(|disclosure| disclosure.is_empty()) {
+        bail!("SD-JWT has an empty disclosure");
+    }
+    let key_binding_jwt = segments[segments.len() - 1];
+    Ok(SdJwt::new(
+        segments[0].to_string(),
+        disclosures.iter().map(|d| d.to_string()).collect(),
+        (!key_binding_jwt.is_empty()).then(|| key_binding_jwt.to_string()),
+    ))
+}
+
+/// KB-JWT を除いた `<jwt>~<disclosure>~...~` の文字列
+pub fn issuer_signed_part(jwt: &str, disclosures: &[String]) -> String {
+    let mut sd_jwt = format!("{jwt}~");
+    for disclosure in disclosures {
+        sd_jwt.push_str(disclosure);
+        sd_jwt.push('~');
+    }
+    sd_jwt
+}
+
+/// KB-JWT の `sd_hash` (`<jwt>~<disclosure>~...~` の SHA-256 の Base64url)
+pub fn sd_hash(jwt: &str, disclosures: &[String]) -> String {
+    Sha256Hasher::new().encoded_digest(&issuer_signed_part(jwt, disclosures))
+}
+
+/// VP の文字列 (`<jwt>~<disclosure>~...~<kb-jwt>`)
+pub fn presentation(jwt: &str, disclosures: &[String], key_binding_jwt: Option<&str>) -> String {
+    issuer_signed_part(jwt, disclosures) + key_binding_jwt.unwrap_or("")
+}
@@ -0,0 +1,158 @@
+//! HTTP サーバの共通部分
+//!
+//! 各エンドポイントの処理は `Request` を受け取って `Response` を返す関数として書き、
+//! tiny_http とのやり取りはここにまとめる。
+//! 待ち受けのアドレスとアクセスログは `log` で出力する (出力先は bin で設定する)。
+
+use anyhow::{anyhow, Result};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use std::{collections::HashMap, io::Read as _};
+
+/// 受け付けるリクエストのボディの最大サイズ (超える場合は 413)
+pub const MAX_BODY_BYTES: u64 = 1024 * 1024;
+
+/// HTTP リクエスト
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: String,
+    /// クエリを除いたパス
+    pub path: String,
+    /// `?` 以降のクエリ文字列
+    pub query: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// ヘッダの値 (名前の大文字・小文字は区別しない)
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// `Authorization: Bearer <token>` のトークン
+    pub fn bearer_token(&self) -> Option<&str> {
+        let value = self.header("Authorization")?;
+        let (scheme, token) = value.split_once(' ')?;
+        scheme
+            .eq_ignore_ascii_case("Bearer")
+            .then_some(token.trim())
+    }
+
+    /// クエリ文字列のパラメータ
+    pub fn query_params(&self) -> HashMap<String, String> {
+        form_urlencoded::parse(self.query.as_bytes())
+            .into_owned()
+            .collect()
+    }
+
+    /// `application/x-www-form-urlencoded` のボディのパラメータ
+    pub fn form(&self) -> HashMap<String, String> {
+        form_urlencoded::parse(&self.body).into_owned().collect()
+    }
+
+    /// JSON のボディ
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T> {
+        serde_json::from_slice(&self.body).map_err(|e| anyhow!("invalid json body: {e}"))
+    }
+}
+
+/// HTTP レスポンス
+#[derive(Debug, Clone)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: u16, content_type: &str, body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            status,
+            headers: vec![("Content-Type".to_string(), content_type.to_string())],
+            body: body.into(),
+        }
+    }
+
+    /// JSON のレスポンス
+    pub fn json(status: u16, body: &Value) -> Self {
+        Self::new(status, "application/json", body.to_string())
+    }
+
+    /// OAuth 2.0 形式のエラーレスポンス (`error`, `error_description`)
+    pub fn oauth_error(status: u16, error: &str, description: &str) -> Self {
+        Self::json(
+            status,
+            &json!({ "error": error, "error_description": description }),
+        )
+    }
+
+    pub fn not_found() -> Self {
+        Self::oauth_error(404, "not_found", "not found")
+    }
+
+    /// ヘッダを追加する
+    pub fn with_header(mut self, name: &str, value: impl Into<String>) -> Self {
+        self.headers.push((name.to_string(), value.into()));
+        self
+    }
+}
+
+/// `bind_address` で待ち受け、リクエストごとに `handler` を呼び出す
+pub fn serve(bind_address: &str, handler: impl Fn(&Request) -> Response) -> Result<()> {
+    let server = tiny_http::Server::http(bind_address)
+        .map_err(|e| anyhow!("failed to bind {bind_address}: {e}"))?;
+    log::info!("listening on {bind_address}");
+
+    for mut request in server.incoming_requests() {
+        let (path, query) = match request.url().split_once('?') {
+            Some((path, query)) => (path.to_string(), query.to_string()),
+            None => (request.url().to_string(), String::new()),
+        };
+        let mut body = Vec::new();
+        let read = request
+            .as_reader()
+            .take(MAX_BODY_BYTES + 1)
+            .read_to_end(&mut body);
+        let response = match read {
+            Ok(_) if body.len() as u64 > MAX_BODY_BYTES => Response::oauth_error(
+                413,
+                "invalid_request",
+                &format!("request body exceeds {MAX_BODY_BYTES} bytes"),
+            ),
+            Ok(_) => handler(&Request {
+                method: request.method().as_str().to_string(),
+                path,
+                query,
+                headers: request
+                    .headers()
+                    .iter()
+                    .map(|h| (h.field.as_str().to_string(), h.value.as_str().to_string()))
+                    .collect(),
+                body,
+            }),
+            Err(e) => Response::oauth_error(400, "invalid_request", &e.to_string()),
+        };
+        log::info!(
+            "{} {} -> {}",
+            request.method(),
+            request.url(),
+            response.status
+        );
+
+        let mut reply = tiny_http::Response::from_data(response.body)
+            .with_status_code(tiny_http::StatusCode(response.status));
+        for (name, value) in &response.headers {
+            if let Ok(header) = tiny_http::Header::from_bytes(name.as_bytes(), value.as_bytes()) {
+                reply.add_header(header);
+            }
+        }
+        if let Err(e) = request.respond(reply) {
+            log::warn!("failed to send response: {e:?}");
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,236 @@
+//! JWS・COSE の署名に使う秘密鍵のバックエンド
+//!
+//! 発行者 (`Issuer`) と Holder (`Holder`) は `Signer` で署名するので、秘密鍵をプロセスのメモリに
+//! 読み込まないバックエンド (PKCS#11 のトークン) も使える。
+//!
+//! - `PemSigner` : PEM形式の秘密鍵 (PKCS#8・SEC1、パスワードで暗号化した PKCS#8) と秘密鍵の JWK
+//! - `pkcs11::Pkcs11Signer` : PKCS#11 のトークン (HSM・SoftHSM) の鍵 (`pkcs11` feature)
+//!
+//! `open` は鍵の指定 (PEM・JWK のファイルのパス、または `pkcs11:` で始まる RFC 7512 の PKCS#11 URI) から
+//! どちらかを作成する。暗号化した鍵のパスワードは環境変数 PRIVATE_KEY_PASSWORD
+//! (または PRIVATE_KEY_PASSWORD_FILE のファイル) で指定する。
+
+use crate::alg::SigningAlgorithm;
+use anyhow::{anyhow, bail, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use josekit::{
+    jws::{EdDSA, JwsAlgorithm, JwsSigner, ES256, ES384, ES512, PS256, RS256},
+    JoseError,
+};
+use serde_json::Value;
+use std::sync::Arc;
+
+/// パスワードで暗号化した PKCS#8 の PEM のタグ
+pub const ENCRYPTED_PRIVATE_KEY_TAG: &str = "ENCRYPTED PRIVATE KEY";
+
+/// 秘密鍵で署名するバックエンド
+pub trait Signer: Send + Sync {
+    /// 鍵の種類から決まる署名アルゴリズム
+    fn algorithm(&self) -> SigningAlgorithm;
+
+    /// 公開鍵 (JWK、`alg`・`use` と JWK Thumbprint の `kid` 付き)
+    fn public_jwk(&self) -> Result<Value>;
+
+    /// `alg` (鍵の種類と互換のもの) で `message` に署名し、JWS の署名値 (EC 鍵の場合は R || S) を返す
+    fn sign(&self, alg: SigningAlgorithm, message: &[u8]) -> Result<Vec<u8>>;
+}
+
+/// PEM形式の秘密鍵で署名する
+pub struct PemSigner {
+    /// PKCS#8 の秘密鍵
+    private_key: Vec<u8>,
+    alg: SigningAlgorithm,
+}
+
+impl PemSigner {
+    /// SEC1 (`EC PRIVATE KEY`) の EC 鍵も PKCS#8 にして扱う
+    pub fn new(private_key: &[u8]) -> Result<Self> {
+        let private_key = crate::key::private_key_to_pkcs8_pem(private_key)?;
+        let alg = SigningAlgorithm::from_pem(&private_key)?;
+        Ok(Self { private_key, alg })
+    }
+
+    /// パスワードで暗号化した PKCS#8 (`ENCRYPTED PRIVATE KEY`) を復号して読み込む
+    pub fn from_encrypted_pem(private_key: &[u8], password: &[u8]) -> Result<Self> {
+        Self::new(&decrypt_private_key_pem(private_key, password)?)
+    }
+
+    /// 秘密鍵の JWK (`d` を含むもの) を読み込む
+    /// JWK に `alg` があればそのアルゴリズムで署名する (RSA 鍵の PS256 など)
+    pub fn from_jwk(jwk: &Value) -> Result<Self> {
+        let mut signer = Self::new(&crate::jwk::private_jwk_to_pem(jwk)?)?;
+        if jwk.get("alg").is_some() {
+            let alg = SigningAlgorithm::from_jwk(jwk)?;
+            if !alg.is_compatible_with(signer.alg) {
+                bail!("jwk alg {alg} cannot be used with {} key", signer.alg);
+            }
+            signer.alg = alg;
+        }
+        Ok(signer)
+    }
+
+    /// PEM・JWK のファイルから秘密鍵を読み込む (暗号化されている場合は `password` で復号する)
+    pub fn from_file(file_path: &str, password: Option<&[u8]>) -> Result<Self> {
+        let private_key = std::fs::read(file_path)
+            .map_err(|e| anyhow!("failed to read private key file {file_path}: {e:?}"))?;
+        if crate::jwk::is_jwk(&private_key) {
+            return Self::from_jwk(&crate::jwk::parse_jwk(&private_key)?)
+                .map_err(|e| anyhow!("invalid private key {file_path}: {e}"));
+        }
+        if !is_encrypted_pem(&private_key) {
+            return Self::new(&private_key);
+        }
+        let password = password.ok_or_else(|| {
+            anyhow!("private key {file_path} is encrypted (set PRIVATE_KEY_PASSWORD)")
+        })?;
+        Self::from_encrypted_pem(&private_key, password)
+    }
+}
+
+impl Signer for PemSigner {
+    fn algorithm(&self) -> SigningAlgorithm {
+        self.alg
+    }
+
+    fn public_jwk(&self) -> Result<Value> {
+        let mut jwk = crate::key::public_key_pem_to_jwk(&self.private_key)?;
+        jwk["alg"] = Value::String(self.alg.name().to_string());
+        Ok(jwk)
+    }
+
+    fn sign(&self, alg: SigningAlgorithm, message: &[u8]) -> Result<Vec<u8>> {
+        alg.signer_from_pem(&self.private_key)?
+            .sign(message)
+            .map_err(|e| anyhow!("failed to sign: {e:?}"))
+    }
+}
+
+/// 鍵の指定から署名のバックエンドを作成する
+///
+/// - `pkcs11:` で始まる場合は PKCS#11 URI (`pkcs11` feature が必要)
+/// - それ以外は PEM・JWK のファイルのパス (暗号化されている場合のパスワードは PRIVATE_KEY_PASSWORD)
+pub fn open(key: &str) -> Result<Arc<dyn Signer>> {
+    if key.starts_with("pkcs11:") {
+        #[cfg(feature = "pkcs11")]
+        return Ok(Arc::new(crate::pkcs11::Pkcs11Signer::from_uri(key)?));
+        #[cfg(not(feature = "pkcs11"))]
+        bail!("PKCS#11 keys require the pkcs11 feature");
+    }
+    let password = password_from_env()?;
+    Ok(Arc::new(PemSigner::from_file(
+        key,
+        password.as_deref().map(str::as_bytes),
+    )?))
+}
+
+/// 環境変数 PRIVATE_KEY_PASSWORD、または PRIVATE_KEY_PASSWORD_FILE のファイルのパスワード
+pub fn password_from_env() -> Result<Option<String>> {
+    if let Ok(password) = std::env::var("PRIVATE_KEY_PASSWORD") {
+        return Ok(Some(password));
+    }
+    match std::env::var("PRIVATE_KEY_PASSWORD_FILE") {
+        Ok(file) => {
+            let password = std::fs::read_to_string(&file)
+                .map_err(|e| anyhow!("failed to read password file {file}: {e:?}"))?;
+            Ok(Some(password.trim_end_matches(['\r', '\n']).to_string()))
+        }
+        Err(_) => Ok(None),
+    }
+}
+
+/// PEM がパスワードで暗号化した PKCS#8 か
+pub fn is_encrypted_pem(pem: &[u8]) -> bool {
+    pem::parse_many(pem).is_ok_and(|blocks| {
+        blocks
+            .iter()
+            .any(|block| block.tag() == ENCRYPTED_PRIVATE_KEY_TAG)
+    })
+}
+
+/// パスワードで暗号化した PKCS#8 を復号し、PKCS#8 の PEM にする
+pub fn decrypt_private_key_pem(private_key: &[u8], password: &[u8]) -> Result<Vec<u8>> {
+    let key = openssl::pkey::PKey::private_key_from_pem_passphrase(private_key, password)
+        .map_err(|_e| anyhow!("failed to decrypt private key (wrong password?)"))?;
+    Ok(key.private_key_to_pem_pkcs8()?)
+}
+
+/// PEM形式の秘密鍵を `password` で暗号化した PKCS#8 (PBES2, AES-256-CBC) にする
+pub fn encrypt_private_key_pem(private_key: &[u8], password: &[u8]) -> Result<Vec<u8>> {
+    let private_key = crate::key::private_key_to_pkcs8_pem(private_key)?;
+    let key = openssl::pkey::PKey::private_key_from_pem(&private_key)?;
+    Ok(key.private_key_to_pem_pkcs8_passphrase(openssl::symm::Cipher::aes_256_cbc(), password)?)
+}
+
+/// `Signer` を josekit の `JwsSigner` として使う
+#[derive(Clone)]
+pub(crate) struct JwsSignerAdapter {
+    signer: Arc<dyn Signer>,
+    alg: SigningAlgorithm,
+    signature_len: usize,
+}
+
+impl JwsSignerAdapter {
+    pub(crate) fn new(signer: Arc<dyn Signer>, alg: SigningAlgorithm) -> Result<Self> {
+        if !alg.is_compatible_with(signer.algorithm()) {
+            bail!("{alg} cannot be used with {} key", signer.algorithm());
+        }
+        let signature_len = match alg {
+            SigningAlgorithm::ES256 | SigningAlgorithm::EdDSA => 64,
+            SigningAlgorithm::ES384 => 96,
+            SigningAlgorithm::ES512 => 132,
+            // RSA の署名は法 (n) と同じ長さ
+            SigningAlgorithm::RS256 | SigningAlgorithm::PS256 => {
+                let jwk = signer.public_jwk()?;
+                let n = jwk
+                    .get("n")
+                    .and_then(Value::as_str)
+                    .ok_or_else(|| anyhow!("RSA public key has no n"))?;
+                URL_SAFE_NO_PAD.decode(n)?.len()
+            }
+        };
+        Ok(Self {
+            signer,
+            alg,
+            signature_len,
+        })
+    }
+}
+
+impl std::fmt::Debug for JwsSignerAdapter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JwsSignerAdapter")
+            .field("alg", &self.alg)
+            .finish_non_exhaustive()
+    }
+}
+
+impl JwsSigner for JwsSignerAdapter {
+    fn algorithm(&self) -> &dyn JwsAlgorithm {
+        match self.alg {
+            SigningAlgorithm::ES256 => &ES256,
+            SigningAlgorithm::ES384 => &ES384,
+            SigningAlgorithm::ES512 => &ES512,
+            SigningAlgorithm::EdDSA => &EdDSA,
+            SigningAlgorithm::RS256 => &RS256,
+            SigningAlgorithm::PS256 => &PS256,
+        }
+    }
+
+    fn key_id(&self) -> Option<&str> {
+        None
+    }
+
+    fn signature_len(&self) -> usize {
+        self.signature_len
+    }
+
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, JoseError> {
+        self.signer
+            .sign(self.alg, message)
+            .map_err(JoseError::InvalidSignature)
+    }
+
+    fn box_clone(&self) -> Box<dyn JwsSigner> {
+        Box::new(self.clone())
+    }
+}
@@ -0,0 +1,611 @@
+//! Token Status List (draft-ietf-oauth-status-list)
+//!
+//! 発行者は VC ごとにステータスリストのインデックスを割り当てて `status` クレームに入れ、
+//! 圧縮したステータスリストを署名付き JWT (`statuslistjwt+jwt`) として公開する。
+//! Verifier はリストを取得 (またはローカルのファイルから読み込み) してキャッシュし、
+//! 失効・一時停止された VC を拒否する。
+
+use crate::{error::VerificationError, http, issuer::Issuer};
+use anyhow::{anyhow, bail, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use fs2::FileExt as _;
+use josekit::jwt::JwtPayload;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    io::{Read, Write},
+    path::PathBuf,
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+/// ステータスリストの JWT の `typ`
+pub const STATUS_LIST_TYP: &str = "statuslistjwt+jwt";
+/// ステータスリストの JWT の Content-Type
+pub const STATUS_LIST_CONTENT_TYPE: &str = "application/statuslist+jwt";
+/// 1 つのステータスに使うビット数の既定値 (VALID / INVALID / SUSPENDED を表せる)
+pub const DEFAULT_BITS: u8 = 2;
+/// ステータスリストの大きさの既定値
+pub const DEFAULT_SIZE: usize = 1 << 16;
+/// 展開したステータスリストの最大サイズ (取得したリストの zip bomb を防ぐ)
+pub const MAX_STATUS_LIST_BYTES: u64 = 16 * 1024 * 1024;
+/// 発行者側のステータスリストを保存するファイルの既定値
+pub const DEFAULT_REGISTRY_FILE: &str = "status_list.json";
+/// ステータスリストの JWT の `ttl` の既定値
+pub const DEFAULT_TTL: Duration = Duration::from_secs(5 * 60);
+/// ステータスリストの JWT の有効期間の既定値
+pub const DEFAULT_EXPIRES_IN: Duration = Duration::from_secs(24 * 60 * 60);
+/// Verifier がステータスリストをキャッシュする時間の既定値 (`ttl` や `exp` が短ければそちら)
+pub const DEFAULT_CACHE_MAX_AGE: Duration = Duration::from_secs(5 * 60);
+
+/// VC のステータス
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Valid,
+    /// 失効
+    Invalid,
+    /// 一時停止
+    Suspended,
+    /// アプリケーション固有の値
+    Other(u8),
+}
+
+impl Status {
+    pub fn value(self) -> u8 {
+        match self {
+            Self::Valid => 0x00,
+            Self::Invalid => 0x01,
+            Self::Suspended => 0x02,
+            Self::Other(v) => v,
+        }
+    }
+
+    pub fn from_value(value: u8) -> Self {
+        match value {
+            0x00 => Self::Valid,
+            0x01 => Self::Invalid,
+            0x02 => Self::Suspended,
+            v => Self::Other(v),
+        }
+    }
+}
+
+impl std::str::FromStr for Status {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "valid" => Ok(Self::Valid),
+            "invalid" | "revoked" => Ok(Self::Invalid),
+            "suspended" => Ok(Self::Suspended),
+            _ => bail!("unknown status {s} (valid, revoked or suspended)"),
+        }
+    }
+}
+
+/// ステータスリスト (1 つのステータスを `bits` ビットで詰めたバイト列)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatusList {
+    bits: u8,
+    bytes: Vec<u8>,
+}
+
+impl StatusList {
+    /// `size` 個のステータスがすべて VALID のリスト
+    pub fn new(bits: u8, size: usize) -> Result<Self> {
+        if ![1, 2, 4, 8].contains(&bits) {
+            bail!("bits must be 1, 2, 4 or 8");
+        }
+        let len = (size * bits as usize).div_ceil(8);
+        Ok(Self {
+            bits,
+            bytes: vec![0; len],
+        })
+    }
+
+    pub fn bits(&self) -> u8 {
+        self.bits
+    }
+
+    /// 格納できるステータスの数
+    pub fn len(&self) -> usize {
+        self.bytes.len() * 8 / self.bits as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// `idx` のステータス (範囲外の場合は None)
+    pub fn get(&self, idx: usize) -> Option<Status> {
+        let (byte, shift) = self.position(idx)?;
+        Some(Status::from_value(
+            (self.bytes[byte] >> shift) & self.mask(),
+        ))
+    }
+
+    /// `idx` のステータスを設定する
+    pub fn set(&mut self, idx: usize, status: Status) -> Result<()> {
+        let (byte, shift) = self
+            .position(idx)
+            .ok_or_else(|| anyhow!("status index {idx} is out of range"))?;
+        let mask = self.mask();
+        if status.value() > mask {
+            bail!("status {status:?} does not fit in {} bits", self.bits);
+        }
+        self.bytes[byte] = (self.bytes[byte] & !(mask << shift)) | (status.value() << shift);
+        Ok(())
+    }
+
+    fn mask(&self) -> u8 {
+        ((1u16 << self.bits) - 1) as u8
+    }
+
+    /// `idx` のステータスが入っているバイトの位置とシフト量 (下位ビットから詰める)
+    fn position(&self, idx: usize) -> Option<(usize, usize)> {
+        let bit = idx.checked_mul(self.bits as usize)?;
+        (bit / 8 < self.bytes.len()).then_some((bit / 8, bit % 8))
+    }
+
+    /// `{"bits": .., "lst": <zlib で圧縮して Base64url>}`
+    pub fn to_json(&self) -> Result<Value> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(&self.bytes)?;
+        let compressed = encoder.finish()?;
+        Ok(json!({ "bits": self.bits, "lst": URL_SAFE_NO_PAD.encode(compressed) }))
+    }
+
+    /// `to_json` の形式から復元する
+    /// 展開後に `MAX_STATUS_LIST_BYTES` を超えるリストはエラーにする
+    pub fn from_json(value: &Value) -> Result<Self> {
+        let bits = value
+            .get("bits")
+            .and_then(Value::as_u64)
+            .ok_or_else(|| anyhow!("status_list has no bits"))?;
+        let lst = value
+            .get("lst")
+            .and_then(Value::as_str)
+            .ok_or_else(|| anyhow!("status_list has no lst"))?;
+        let compressed = URL_SAFE_NO_PAD
+            .decode(lst)
+            .map_err(|e| anyhow!("lst is not base64url: {e}"))?;
+        let mut bytes = Vec::new();
+        ZlibDecoder::new(compressed.as_slice())
+            .take(MAX_STATUS_LIST_BYTES + 1)
+            .read_to_end(&mut bytes)
+            .map_err(|e| anyhow!("failed to decompress lst: {e}"))?;
+        if bytes.len() as u64 > MAX_STATUS_LIST_BYTES {
+            bail!("status list exceeds {MAX_STATUS_LIST_BYTES} bytes");
+        }
+        let mut list = Self::new(u8::try_from(bits)?, 0)?;
+        list.bytes = bytes;
+        Ok(list)
+    }
+}
+
+/// VC の `status.status_list` (ステータスリストの URI とインデックス)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StatusReference {
+    pub idx: usize,
+    pub uri: String,
+}
+
+impl StatusReference {
+    /// VC に入れる `status` クレーム
+    pub fn to_claim(&self) -> Value {
+        json!({ "status_list": self })
+    }
+
+    /// VC のクレームの `status.status_list` (`status` がない場合は None)
+    pub fn from_claims(claims: &Map<String, Value>) -> Option<Result<Self>> {
+        let status = claims.get("status")?;
+        let status_list = status.get("status_list")?;
+        Some(
+            serde_json::from_value(status_list.clone())
+                .map_err(|e| anyhow!("invalid status_list reference: {e}")),
+        )
+    }
+}
+
+/// ステータスリストの JWT を発行者の鍵で署名する
+/// `ttl` は Verifier がキャッシュしてよい時間
+pub fn sign_status_list(
+    issuer: &Issuer,
+    uri: &str,
+    list: &StatusList,
+    ttl: Duration,
+    expires_in: Duration,
+) -> Result<String> {
+    let mut payload = JwtPayload::new();
+    payload.set_issuer(issuer.issuer());
+    payload.set_subject(uri);
+    let now = SystemTime::now();
+    payload.set_issued_at(&now);
+    payload.set_expires_at(&(now + expires_in));
+    payload.set_claim("ttl", Some(Value::from(ttl.as_secs())))?;
+    payload.set_claim("status_list", Some(list.to_json()?))?;
+    issuer.sign_jwt(STATUS_LIST_TYP, &payload)
+}
+
+/// 発行者側のステータスリスト (JSON ファイルに保存する)
+///
+/// インデックスは先頭から順に割り当て、どのアカウントに割り当てたかも記録して
+/// アカウント単位で失効できるようにする。
+/// 発行サーバの動作中に CLI で失効させられるよう、操作のたびにファイルを読み直す。
+/// 読み込みから保存までは `<ファイル>.lock` の排他ロックを取り、別のプロセスの割り当て・失効と
+/// 混ざって変更が失われたり、同じインデックスを割り当てたりしないようにする。
+pub struct StatusListRegistry {
+    path: PathBuf,
+    uri: String,
+}
+
+/// ファイルに保存する内容
+#[derive(Debug, Serialize, Deserialize)]
+struct RegistryState {
+    uri: String,
+    next_index: usize,
+    /// インデックス -> 割り当てたアカウント
+    subjects: BTreeMap<usize, String>,
+    status_list: Value,
+}
+
+impl StatusListRegistry {
+    /// ファイルがあれば開き、なければ `uri` の空のリストを作成する
+    pub fn open(path: impl Into<PathBuf>, uri: &str) -> Result<Self> {
+        let registry = Self {
+            path: path.into(),
+            uri: uri.to_string(),
+        };
+        let _lock = registry.lock()?;
+        if registry.path.exists() {
+            let state = registry.load()?;
+            if state.uri != uri {
+                bail!(
+                    "status list {} is for {}",
+                    registry.path.display(),
+                    state.uri
+                );
+            }
+        } else {
+            registry.save(&RegistryState {
+                uri: uri.to_string(),
+                next_index: 0,
+                subjects: BTreeMap::new(),
+                status_list: StatusList::new(DEFAULT_BITS, DEFAULT_SIZE)?.to_json()?,
+            })?;
+        }
+        Ok(registry)
+    }
+
+    /// 環境変数 STATUS_LIST_URI が設定されていれば、STATUS_LIST_FILE
+    /// (既定は `status_list.json`) のステータスリストを開く
+    pub fn from_env() -> Result<Option<Self>> {
+        let Ok(uri) = std::env::var("STATUS_LIST_URI") else {
+            return Ok(None);
+        };
+        let path = std::env::var("STATUS_LIST_FILE")
+            .unwrap_or_else(|_e| DEFAULT_REGISTRY_FILE.to_string());
+        Self::open(path, &uri).map(Some)
+    }
+
+    /// ステータスリストの URI
+    pub fn uri(&self) -> &str {
+        &self.uri
+    }
+
+    /// `subject` (アカウント名) の VC にインデックスを割り当てる
+    pub fn allocate(&self, subject: &str) -> Result<StatusReference> {
+        let idx = self.update(|state| {
+            let list = StatusList::from_json(&state.status_list)?;
+            let idx = state.next_index;
+            if idx >= list.len() {
+                bail!("status list {} is full", state.uri);
+            }
+            state.next_index += 1;
+            state.subjects.insert(idx, subject.to_string());
+            Ok(idx)
+        })?;
+        Ok(StatusReference {
+            idx,
+            uri: self.uri.clone(),
+        })
+    }
+
+    /// `idx` のステータスを変更する
+    pub fn set_status(&self, idx: usize, status: Status) -> Result<()> {
+        self.update(|state| {
+            if idx >= state.next_index {
+                bail!("status index {idx} is not allocated");
+            }
+            let mut list = StatusList::from_json(&state.status_list)?;
+            list.set(idx, status)?;
+            state.status_list = list.to_json()?;
+            Ok(())
+        })
+    }
+
+    /// `subject` に割り当てたすべてのインデックスのステータスを変更し、変更したインデックスを返す
+    pub fn set_subject_status(&self, subject: &str, status: Status) -> Result<Vec<usize>> {
+        self.update(|state| {
+            let indexes: Vec<usize> = state
+                .subjects
+                .iter()
+                .filter(|(_, s)| *s == subject)
+                .map(|(idx, _)| *idx)
+                .collect();
+            let mut list = StatusList::from_json(&state.status_list)?;
+            for idx in &indexes {
+                list.set(*idx, status)?;
+            }
+            state.status_list = list.to_json()?;
+            Ok(indexes)
+        })
+    }
+
+    /// 現在のステータスリスト
+    /// (ファイルは rename で置き換えるので、ロックを取らずに読める)
+    pub fn status_list(&self) -> Result<StatusList> {
+        StatusList::from_json(&self.load()?.status_list)
+    }
+
+    /// 署名したステータスリストの JWT
+    pub fn token(&self, issuer: &Issuer, ttl: Duration, expires_in: Duration) -> Result<String> {
+        sign_status_list(issuer, &self.uri, &self.status_list()?, ttl, expires_in)
+    }
+
+    /// ファイルを読み込んで `f` で変更し、保存する
+    fn update<T>(&self, f: impl FnOnce(&mut RegistryState) -> Result<T>) -> Result<T> {
+        let _lock = self.lock()?;
+        let mut state = self.load()?;
+        let value = f(&mut state)?;
+        self.save(&state)?;
+        Ok(value)
+    }
+
+    /// `<ファイル>.lock` の排他ロック (返した `File` を drop すると解放される)
+    /// ステータスリストのファイルは rename で置き換わるので、別のファイルをロックする
+    fn lock(&self) -> Result<File> {
+        let path = self.path.with_extension("json.lock");
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .map_err(|e| anyhow!("failed to open {}: {e:?}", path.display()))?;
+        file.lock_exclusive()
+            .map_err(|e| anyhow!("failed to lock {}: {e:?}", path.display()))?;
+        Ok(file)
+    }
+
+    fn load(&self) -> Result<RegistryState> {
+        let contents = std::fs::read_to_string(&self.path)
+            .map_err(|e| anyhow!("failed to read status list {}: {e:?}", self.path.display()))?;
+        serde_json::from_str(&contents)
+            .map_err(|e| anyhow!("invalid status list {}: {e}", self.path.display()))
+    }
+
+    /// 読み込み中のプロセスが書きかけのファイルを読まないよう、一時ファイルから rename する
+    fn save(&self, state: &RegistryState) -> Result<()> {
+        let contents = serde_json::to_string_pretty(state)?;
+        let tmp = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp, contents)
+            .and_then(|_| std::fs::rename(&tmp, &self.path))
+            .map_err(|e| anyhow!("failed to write status list {}: {e:?}", self.path.display()))
+    }
+}
+
+/// Verifier 側のステータスリストのキャッシュ
+///
+/// URI ごとに検証済みのリストを `ttl`・`exp`・`max_age` のうち最も早い時刻まで保持する。
+pub struct StatusListCache {
+    /// URI -> 取得する代わりに読み込むファイル
+    files: HashMap<String, PathBuf>,
+    max_age: Duration,
+    entries: Mutex<HashMap<String, (StatusList, SystemTime)>>,
+}
+
+/// 検証済みのステータスリストの JWT の内容
+pub struct VerifiedStatusList {
+    pub list: StatusList,
+    pub expires_at: Option<SystemTime>,
+    pub ttl: Option<Duration>,
+}
+
+impl Default for StatusListCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_CACHE_MAX_AGE)
+    }
+}
+
+impl StatusListCache {
+    pub fn new(max_age: Duration) -> Self {
+        Self {
+            files: HashMap::new(),
+            max_age,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// `uri` のステータスリストは HTTP で取得せずに `path` から読み込む
+    pub fn with_file(mut self, uri: impl Into<String>, path: impl Into<PathBuf>) -> Self {
+        self.files.insert(uri.into(), path.into());
+        self
+    }
+
+    /// `reference` のステータスを返す
+    /// キャッシュにない場合はリストを取得し、`verify` で署名などを検証してからキャッシュする
+    pub fn status(
+        &self,
+        reference: &StatusReference,
+        verify: impl FnOnce(&str) -> Result<VerifiedStatusList, VerificationError>,
+    ) -> Result<Status, VerificationError> {
+        let now = SystemTime::now();
+        let cached = {
+            let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+            entries
+                .get(&reference.uri)
+                .filter(|(_, expires_at)| *expires_at > now)
+                .map(|(list, _)| list.clone())
+        };
+        let list = match cached {
+            Some(list) => list,
+            None => {
+                let verified = verify(&self.fetch(&reference.uri)?)?;
+                let mut expires_at = now + self.max_age;
+                if let Some(ttl) = verified.ttl {
+                    expires_at = expires_at.min(now + ttl);
+                }
+                if let Some(exp) = verified.expires_at {
+                    expires_at = expires_at.min(exp);
+                }
+                let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+                entries.insert(reference.uri.clone(), (verified.list.clone(), expires_at));
+                verified.list
+            }
+        };
+        list.get(reference.idx).ok_or_else(|| {
+            VerificationError::InvalidStatusList(format!("index {} is out of range", reference.idx))
+        })
+    }
+
+    /// ステータスリストの JWT をファイルまたは HTTP で取得する
+    fn fetch(&self, uri: &str) -> Result<String, VerificationError> {
+        let token = match self.files.get(uri) {
+            Some(path) => std::fs::read_to_string(path).map_err(|e| {
+                VerificationError::StatusListUnavailable(format!("{}: {e}", path.display()))
+            })?,
+            None => http::get(uri)
+                .map_err(|e| VerificationError::StatusListUnavailable(e.to_string()))?,
+        };
+        Ok(token.trim().to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list_with(bits: u8, statuses: &[u8]) -> StatusList {
+        let mut list = StatusList::new(bits, statuses.len()).unwrap();
+        for (idx, &value) in statuses.iter().enumerate() {
+            list.set(idx, Status::from_value(value)).unwrap();
+        }
+        list
+    }
+
+    #[test]
+    fn packs_one_bit_statuses() {
+        // draft-ietf-oauth-status-list の 1 ビットの例
+        let statuses = [1, 0, 0, 1, 1, 1, 0, 1, 1, 1, 0, 0, 0, 1, 0, 1];
+        let list = list_with(1, &statuses);
+        assert_eq!(list.bytes, [0xb9, 0xa3]);
+        assert_eq!(list.len(), 16);
+        for (idx, &value) in statuses.iter().enumerate() {
+            assert_eq!(list.get(idx).unwrap().value(), value);
+        }
+    }
+
+    #[test]
+    fn packs_two_bit_statuses() {
+        // draft-ietf-oauth-status-list の 2 ビットの例
+        let statuses = [1, 2, 0, 3, 0, 1, 0, 1, 1, 2, 3, 3];
+        let list = list_with(2, &statuses);
+        assert_eq!(list.bytes, [0xc9, 0x44, 0xf9]);
+        assert_eq!(list.get(0), Some(Status::Invalid));
+        assert_eq!(list.get(1), Some(Status::Suspended));
+        assert_eq!(list.get(2), Some(Status::Valid));
+        assert_eq!(list.get(3), Some(Status::Other(3)));
+    }
+
+    #[test]
+    fn packs_four_and_eight_bit_statuses() {
+        let list = list_with(4, &[1, 2, 15, 0]);
+        assert_eq!(list.bytes, [0x21, 0x0f]);
+        assert_eq!(list.get(2), Some(Status::Other(15)));
+
+        let list = list_with(8, &[3, 0, 255]);
+        assert_eq!(list.bytes, [0x03, 0x00, 0xff]);
+        assert_eq!(list.get(2), Some(Status::Other(255)));
+    }
+
+    #[test]
+    fn rejects_out_of_range_indexes_and_values() {
+        assert!(StatusList::new(3, 8).is_err());
+        let mut list = StatusList::new(2, 10).unwrap();
+        // 10 個のステータスは 3 バイトに入り、残りのビットも使える
+        assert_eq!(list.len(), 12);
+        assert_eq!(list.get(11), Some(Status::Valid));
+        assert_eq!(list.get(12), None);
+        assert_eq!(list.get(usize::MAX), None);
+        assert!(list.set(12, Status::Invalid).is_err());
+        assert!(list.set(0, Status::Other(4)).is_err());
+
+        let mut list = StatusList::new(1, 8).unwrap();
+        assert!(list.set(0, Status::Suspended).is_err());
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let list = list_with(2, &[1, 2, 0, 3, 0, 1]);
+        let json = list.to_json().unwrap();
+        assert_eq!(json["bits"], 2);
+        assert_eq!(StatusList::from_json(&json).unwrap(), list);
+    }
+
+    #[test]
+    fn rejects_oversized_lists() {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+        encoder
+            .write_all(&vec![0; MAX_STATUS_LIST_BYTES as usize + 1])
+            .unwrap();
+        let lst = URL_SAFE_NO_PAD.encode(encoder.finish().unwrap());
+        let error = StatusList::from_json(&json!({ "bits": 1, "lst": lst })).unwrap_err();
+        assert!(error.to_string().contains("exceeds"));
+    }
+
+    #[test]
+    fn registries_sharing_a_file_do_not_lose_updates() {
+        let dir = std::env::temp_dir().join(format!("vc_vp_status_list_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("status_list.json");
+        let uri = "https://issuer.example.com/status/1";
+        let revoked = StatusListRegistry::open(&path, uri)
+            .unwrap()
+            .allocate("revoked")
+            .unwrap();
+
+        // 別のプロセスの発行サーバ・CLI と同じように、registry をそれぞれ開いて同時に更新する
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    let registry = StatusListRegistry::open(&path, uri).unwrap();
+                    if i == 0 {
+                        registry
+                            .set_subject_status("revoked", Status::Invalid)
+                            .unwrap();
+                    }
+                    (0..10)
+                        .map(|_| registry.allocate(&format!("user{i}")).unwrap().idx)
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        let mut indexes: Vec<usize> = handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect();
+        indexes.sort();
+        assert_eq!(indexes, (1..=80).collect::<Vec<_>>());
+
+        let registry = StatusListRegistry::open(&path, uri).unwrap();
+        assert_eq!(
+            registry.status_list().unwrap().get(revoked.idx),
+            Some(Status::Invalid)
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
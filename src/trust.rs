@@ -0,0 +1,316 @@
+//! Verifier が受け入れる発行者のトラストレジストリ
+//!
+//! 発行者の識別子 (`iss`) ごとに、署名を検証する鍵 (`kid`)・受け入れる `vct`・署名アルゴリズムを
+//! JSON ファイルで定義する。ファイルが更新されると次の検証で読み直す。
+//!
+//! ```json
+//! {
+//!   "issuers": [
+//!     {
+//!       "iss": "https://fujita-el-issuer.emotionlink.jp",
+//!       "keys": [{ "kid": "VCVk4e6-...", "pem_file": "el_issuer_public_key_ES256.pem" }],
+//!       "vct": ["https://credentials.emotionlink.jp/fujitaapp_credential"],
+//!       "algorithms": ["ES256"]
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! 鍵は `pem_file` (レジストリのファイルからの相対パス、PEM または JWK のファイル) か `jwk` で指定する。
+//! `vct` と `algorithms` を省略した場合は制限しない。
+//! `kid_fallback` を `true` にした発行者は、JWS ヘッダの `kid` に一致する鍵がない場合に `kid` を指定していない鍵で検証する。
+
+use crate::{alg::SigningAlgorithm, jwk, key::public_key_pem_to_jwk};
+use anyhow::{anyhow, bail, Result};
+use serde::Deserialize;
+use serde_json::Value;
+use std::{
+    path::{Path, PathBuf},
+    sync::RwLock,
+    time::SystemTime,
+};
+
+/// 信頼する発行者の鍵
+#[derive(Debug, Clone)]
+pub struct TrustedKey {
+    /// None の場合は JWS ヘッダの `kid` を問わない
+    pub kid: Option<String>,
+    /// 公開鍵 (JWK)
+    pub jwk: Value,
+}
+
+/// 信頼する発行者
+#[derive(Debug, Clone)]
+pub struct TrustedIssuer {
+    pub iss: String,
+    pub keys: Vec<TrustedKey>,
+    /// 受け入れる `vct` (None の場合は制限しない)
+    pub vct: Option<Vec<String>>,
+    /// 受け入れる署名アルゴリズム (None の場合は対応しているものすべて)
+    pub algorithms: Option<Vec<SigningAlgorithm>>,
+    /// JWS ヘッダの `kid` に一致する鍵がない場合に、`kid` を指定していない鍵を候補にするか
+    pub kid_fallback: bool,
+}
+
+impl TrustedIssuer {
+    /// JWS ヘッダの `kid` に対応する鍵
+    /// ヘッダに `kid` がない場合はすべての鍵を候補にする
+    /// `kid` が一致する鍵がなければ、`kid_fallback` の場合だけ `kid` を指定していない鍵を候補にする
+    /// `#key-1` のような相対の `kid` は `iss` (DID) からの DID URL として比べる
+    /// RFC 9278 の JWK Thumbprint URI の `kid` は鍵の JWK Thumbprint と比べる
+    pub fn keys_for(&self, kid: Option<&str>) -> Vec<&TrustedKey> {
+        let Some(kid) = kid else {
+            return self.keys.iter().collect();
+        };
+        let absolute = kid.starts_with('#').then(|| format!("{}{kid}", self.iss));
+        let matched: Vec<&TrustedKey> = self
+            .keys
+            .iter()
+            .filter(|key| {
+                key.kid.as_deref() == Some(kid)
+                    || (absolute.is_some() && key.kid == absolute)
+                    || jwk::matches_thumbprint_uri(&key.jwk, kid)
+            })
+            .collect();
+        if !matched.is_empty() || !self.kid_fallback {
+            return matched;
+        }
+        self.keys.iter().filter(|key| key.kid.is_none()).collect()
+    }
+
+    pub fn allows_algorithm(&self, alg: SigningAlgorithm) -> bool {
+        self.algorithms
+            .as_ref()
+            .is_none_or(|algorithms| algorithms.contains(&alg))
+    }
+
+    pub fn allows_vct(&self, vct: Option<&str>) -> bool {
+        match &self.vct {
+            None => true,
+            Some(allowed) => vct.is_some_and(|vct| allowed.iter().any(|v| v == vct)),
+        }
+    }
+}
+
+/// レジストリのファイルの形式
+#[derive(Debug, Deserialize)]
+struct RegistryFile {
+    issuers: Vec<IssuerEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct IssuerEntry {
+    iss: String,
+    keys: Vec<KeyEntry>,
+    vct: Option<Vec<String>>,
+    algorithms: Option<Vec<String>>,
+    #[serde(default)]
+    kid_fallback: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct KeyEntry {
+    kid: Option<String>,
+    pem_file: Option<String>,
+    jwk: Option<Value>,
+}
+
+/// 読み込んだレジストリ
+struct Loaded {
+    issuers: Vec<TrustedIssuer>,
+    /// 読み込んだときのファイルの更新時刻
+    modified: Option<SystemTime>,
+}
+
+/// 信頼する発行者のレジストリ
+pub struct TrustRegistry {
+    /// None の場合はファイルから読み込んでいない (読み直さない)
+    path: Option<PathBuf>,
+    loaded: RwLock<Loaded>,
+}
+
+impl TrustRegistry {
+    pub fn new(issuers: Vec<TrustedIssuer>) -> Self {
+        Self {
+            path: None,
+            loaded: RwLock::new(Loaded {
+                issuers,
+                modified: None,
+            }),
+        }
+    }
+
+    /// JSON ファイルから読み込む
+    pub fn from_file(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let (issuers, modified) = load_file(&path)?;
+        Ok(Self {
+            path: Some(path),
+            loaded: RwLock::new(Loaded { issuers, modified }),
+        })
+    }
+
+    /// ファイルを読み直す
+    /// 読み込みに失敗した場合はそれまでの内容を使い続ける
+    /// (検証のときの自動の読み直しに失敗した場合は `log` の warn を出力する)
+    pub fn reload(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let (issuers, modified) = load_file(path)?;
+        let mut loaded = self.loaded.write().unwrap_or_else(|e| e.into_inner());
+        *loaded = Loaded { issuers, modified };
+        Ok(())
+    }
+
+    /// `iss` の発行者 (ファイルが更新されていれば読み直してから探す)
+    pub fn issuer(&self, iss: &str) -> Option<TrustedIssuer> {
+        self.reload_if_modified();
+        let loaded = self.loaded.read().unwrap_or_else(|e| e.into_inner());
+        loaded
+            .issuers
+            .iter()
+            .find(|issuer| issuer.iss == iss)
+            .cloned()
+    }
+
+    fn reload_if_modified(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        let current = self
+            .loaded
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .modified;
+        if modified.is_some() && modified != current {
+            if let Err(e) = self.reload() {
+                log::warn!("failed to reload trust registry {}: {e}", path.display());
+            }
+        }
+    }
+}
+
+/// レジストリのファイルを読み込む
+fn load_file(path: &Path) -> Result<(Vec<TrustedIssuer>, Option<SystemTime>)> {
+    let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| anyhow!("failed to read trust registry {}: {e:?}", path.display()))?;
+    let file: RegistryFile = serde_json::from_str(&contents)
+        .map_err(|e| anyhow!("invalid trust registry {}: {e}", path.display()))?;
+    let base = path.parent().unwrap_or(Path::new("."));
+    let issuers = file
+        .issuers
+        .into_iter()
+        .map(|entry| trusted_issuer(entry, base))
+        .collect::<Result<Vec<_>>>()?;
+    Ok((issuers, modified))
+}
+
+fn trusted_issuer(entry: IssuerEntry, base: &Path) -> Result<TrustedIssuer> {
+    let keys = entry
+        .keys
+        .into_iter()
+        .map(|key| {
+            let (kid, jwk) = match (key.pem_file, key.jwk) {
+                (Some(pem_file), None) => {
+                    let path = base.join(&pem_file);
+                    let pem = std::fs::read(&path).map_err(|e| {
+                        anyhow!("failed to read public key file {}: {e:?}", path.display())
+                    })?;
+                    let jwk = if jwk::is_jwk(&pem) {
+                        jwk::public_jwk(&jwk::parse_jwk(&pem)?)?
+                    } else {
+                        public_key_pem_to_jwk(&pem)?
+                    };
+                    (key.kid, jwk)
+                }
+                // `kid` を省略した場合は JWK の `kid`
+                (None, Some(jwk)) => {
+                    let kid = key
+                        .kid
+                        .or_else(|| jwk.get("kid").and_then(Value::as_str).map(str::to_string));
+                    (kid, jwk)
+                }
+                _ => bail!("{}: key must have either pem_file or jwk", entry.iss),
+            };
+            // 形式が正しいか確認しておく
+            SigningAlgorithm::from_jwk(&jwk)?;
+            Ok(TrustedKey { kid, jwk })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if keys.is_empty() {
+        bail!("{}: no keys", entry.iss);
+    }
+    let algorithms = entry
+        .algorithms
+        .map(|algorithms| {
+            algorithms
+                .iter()
+                .map(|alg| alg.parse())
+                .collect::<Result<Vec<SigningAlgorithm>>>()
+        })
+        .transpose()?;
+    Ok(TrustedIssuer {
+        iss: entry.iss,
+        keys,
+        vct: entry.vct,
+        algorithms,
+        kid_fallback: entry.kid_fallback,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn issuer(kid_fallback: bool) -> TrustedIssuer {
+        let key = |kid: Option<&str>, x: &str| TrustedKey {
+            kid: kid.map(str::to_string),
+            jwk: json!({ "kty": "OKP", "crv": "Ed25519", "x": x }),
+        };
+        TrustedIssuer {
+            iss: "did:example:issuer".to_string(),
+            keys: vec![
+                key(Some("did:example:issuer#key-1"), "AAAA"),
+                key(None, "BBBB"),
+            ],
+            vct: None,
+            algorithms: None,
+            kid_fallback,
+        }
+    }
+
+    fn xs(keys: Vec<&TrustedKey>) -> Vec<&str> {
+        keys.iter()
+            .map(|key| key.jwk["x"].as_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn keys_for_matches_kid() {
+        let issuer = issuer(false);
+        assert_eq!(xs(issuer.keys_for(None)), ["AAAA", "BBBB"]);
+        assert_eq!(
+            xs(issuer.keys_for(Some("did:example:issuer#key-1"))),
+            ["AAAA"]
+        );
+        // 相対の DID URL
+        assert_eq!(xs(issuer.keys_for(Some("#key-1"))), ["AAAA"]);
+        let thumbprint = jwk::jwk_thumbprint_uri(&issuer.keys[1].jwk).unwrap();
+        assert_eq!(xs(issuer.keys_for(Some(&thumbprint))), ["BBBB"]);
+    }
+
+    #[test]
+    fn kid_fallback_is_opt_in() {
+        assert!(issuer(false).keys_for(Some("unknown")).is_empty());
+        assert_eq!(xs(issuer(true).keys_for(Some("unknown"))), ["BBBB"]);
+        // 一致する鍵がある場合は使わない
+        assert_eq!(
+            xs(issuer(true).keys_for(Some("did:example:issuer#key-1"))),
+            ["AAAA"]
+        );
+    }
+}
@@ -0,0 +1,469 @@
+//! SD-JWT VC の Type Metadata
+//!
+//! 発行者は VC の種類の定義から Type Metadata (表示名・クレームのメタデータ・JSON Schema) を作成して
+//! `vct` の URL で公開し、VC の `vct#integrity` にその文書のハッシュ (Subresource Integrity) を入れる。
+//! Verifier は `vct` の URL (またはローカルのファイル) から取得し、`vct#integrity` と
+//! 開示されたクレームがスキーマに合うかを検証する。`extends` の親の型も同じように検証する。
+//! ハッシュは取得した文書のバイト列そのもので確認し、コンパイルしたスキーマは `vct` ごとにキャッシュする。
+//! Holder が開示しなかったクレームは検証できないので、`claims` の `sd` が `always`・`allowed` の
+//! クレームがないことによる `required` のエラーは無視する。
+
+use crate::{error::VerificationError, http};
+use anyhow::{anyhow, bail, Result};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256, Sha384, Sha512};
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+/// VC の `vct` の Type Metadata のハッシュを入れるクレーム
+pub const VCT_INTEGRITY_CLAIM: &str = "vct#integrity";
+/// 取得した Type Metadata をキャッシュする時間の既定値
+pub const DEFAULT_TYPE_METADATA_MAX_AGE: Duration = Duration::from_secs(60 * 60);
+/// `extends` をたどる深さの上限
+const MAX_EXTENDS_DEPTH: usize = 5;
+
+/// Type Metadata の文書
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TypeMetadata {
+    pub vct: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// 親の型の `vct`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extends: Option<String>,
+    #[serde(
+        rename = "extends#integrity",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub extends_integrity: Option<String>,
+    /// 言語ごとの表示情報 (`lang`・`name`・`description`・`rendering` など)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub display: Vec<Value>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub claims: Vec<ClaimMetadata>,
+    /// 開示されたクレームを検証する JSON Schema
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema_uri: Option<String>,
+    #[serde(
+        rename = "schema_uri#integrity",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub schema_uri_integrity: Option<String>,
+}
+
+/// クレームのメタデータ
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ClaimMetadata {
+    /// クレームのパス (文字列はメンバー名、`null` は配列のすべての要素)
+    pub path: Vec<Value>,
+    /// 言語ごとのラベル (`lang`・`label`・`description`)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub display: Vec<Value>,
+    /// 選択的開示にするか (`always`・`allowed`・`never`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sd: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub svg_id: Option<String>,
+}
+
+impl TypeMetadata {
+    /// 公開する文書 (`vct#integrity` はこのバイト列のハッシュ)
+    pub fn to_document(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+/// JSON Pointer (`*` は配列のすべての要素) を Type Metadata のクレームのパスにする
+pub fn claim_path(pointer: &str) -> Vec<Value> {
+    pointer
+        .trim_start_matches('/')
+        .split('/')
+        .map(|token| match token {
+            "*" => Value::Null,
+            token => match token.parse::<u64>() {
+                Ok(i) => Value::from(i),
+                Err(_) => Value::from(token.replace("~1", "/").replace("~0", "~")),
+            },
+        })
+        .collect()
+}
+
+/// `sha256-<Base64>` の Subresource Integrity
+pub fn integrity(document: &[u8]) -> String {
+    format!("sha256-{}", STANDARD.encode(Sha256::digest(document)))
+}
+
+/// `document` が Subresource Integrity (空白区切りの複数の値はいずれか) に一致するか
+pub fn check_integrity(document: &[u8], integrity: &str) -> Result<()> {
+    let mut supported = false;
+    for value in integrity.split_whitespace() {
+        let (alg, expected) = value
+            .split_once('-')
+            .ok_or_else(|| anyhow!("invalid integrity: {value}"))?;
+        // `sha256-...?opt` のようなオプションは無視する
+        let expected = expected.split('?').next().unwrap_or_default();
+        let actual = match alg {
+            "sha256" => STANDARD.encode(Sha256::digest(document)),
+            "sha384" => STANDARD.encode(Sha384::digest(document)),
+            "sha512" => STANDARD.encode(Sha512::digest(document)),
+            _ => continue,
+        };
+        supported = true;
+        if actual == expected {
+            return Ok(());
+        }
+    }
+    if !supported {
+        bail!("unsupported integrity: {integrity}");
+    }
+    bail!("integrity mismatch")
+}
+
+/// キャッシュした Type Metadata の文書
+struct CachedDocument {
+    document: Vec<u8>,
+    fetched_at: SystemTime,
+}
+
+/// `vct` ごとにコンパイルしたスキーマ (スキーマが変わった場合はコンパイルし直す)
+struct CachedValidator {
+    schema: Value,
+    validator: Arc<jsonschema::Validator>,
+}
+
+/// `vct` の Type Metadata を解決し、開示されたクレームを検証する
+pub struct TypeMetadataResolver {
+    /// `vct` の URL から取得するか (false の場合はローカルのファイルだけを使う)
+    fetch: bool,
+    /// `vct` ごとのローカルのファイル (取得の代わりに使う)
+    files: HashMap<String, PathBuf>,
+    max_age: Duration,
+    cache: Mutex<HashMap<String, CachedDocument>>,
+    validators: Mutex<HashMap<String, CachedValidator>>,
+}
+
+impl Default for TypeMetadataResolver {
+    fn default() -> Self {
+        Self::new(DEFAULT_TYPE_METADATA_MAX_AGE)
+    }
+}
+
+impl TypeMetadataResolver {
+    pub fn new(max_age: Duration) -> Self {
+        Self {
+            fetch: true,
+            files: HashMap::new(),
+            max_age,
+            cache: Mutex::new(HashMap::new()),
+            validators: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// `vct` の URL から取得するかを指定する
+    pub fn with_fetch(mut self, fetch: bool) -> Self {
+        self.fetch = fetch;
+        self
+    }
+
+    /// `vct` の Type Metadata を取得せずにファイルから読み込む
+    pub fn with_file(mut self, vct: impl Into<String>, path: impl Into<PathBuf>) -> Self {
+        self.files.insert(vct.into(), path.into());
+        self
+    }
+
+    /// `vct` の Type Metadata (`integrity` を指定した場合は文書のハッシュも確認する)
+    pub fn resolve(
+        &self,
+        vct: &str,
+        integrity: Option<&str>,
+    ) -> Result<TypeMetadata, VerificationError> {
+        let invalid = |reason: String| VerificationError::InvalidTypeMetadata(reason);
+        let document = self.document(vct)?;
+        if let Some(integrity) = integrity {
+            check_integrity(&document, integrity).map_err(|e| invalid(format!("{vct}: {e}")))?;
+        }
+        let metadata: TypeMetadata =
+            serde_json::from_slice(&document).map_err(|e| invalid(format!("{vct}: {e}")))?;
+        if metadata.vct != vct {
+            return Err(invalid(format!("vct is not {vct}: {}", metadata.vct)));
+        }
+        Ok(metadata)
+    }
+
+    /// `vct` の型と `extends` の親の型のスキーマで `claims` を検証する
+    pub fn validate_claims(
+        &self,
+        vct: &str,
+        integrity: Option<&str>,
+        claims: &Map<String, Value>,
+    ) -> Result<(), VerificationError> {
+        let instance = Value::Object(claims.clone());
+        let mut next = Some((vct.to_string(), integrity.map(str::to_string)));
+        let mut depth = 0;
+        while let Some((vct, integrity)) = next.take() {
+            depth += 1;
+            if depth > MAX_EXTENDS_DEPTH {
+                return Err(VerificationError::InvalidTypeMetadata(format!(
+                    "too deep extends at {vct}"
+                )));
+            }
+            let metadata = self.resolve(&vct, integrity.as_deref())?;
+            if let Some(schema) = self.schema(&metadata)? {
+                let validator = self.validator(&vct, schema)?;
+                validate_schema(&validator, &instance, &vct, &metadata.claims)?;
+            }
+            next = metadata
+                .extends
+                .map(|parent| (parent, metadata.extends_integrity));
+        }
+        Ok(())
+    }
+
+    /// `vct` のスキーマをコンパイルしたもの (同じスキーマの場合はキャッシュを使う)
+    fn validator(
+        &self,
+        vct: &str,
+        schema: Value,
+    ) -> Result<Arc<jsonschema::Validator>, VerificationError> {
+        let mut validators = self.validators.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(cached) = validators.get(vct).filter(|cached| cached.schema == schema) {
+            return Ok(cached.validator.clone());
+        }
+        let validator = Arc::new(
+            jsonschema::validator_for(&schema)
+                .map_err(|e| VerificationError::InvalidTypeMetadata(format!("{vct}: {e}")))?,
+        );
+        validators.insert(
+            vct.to_string(),
+            CachedValidator {
+                schema,
+                validator: validator.clone(),
+            },
+        );
+        Ok(validator)
+    }
+
+    /// `schema` または `schema_uri` のスキーマ
+    fn schema(&self, metadata: &TypeMetadata) -> Result<Option<Value>, VerificationError> {
+        let invalid = |reason: String| VerificationError::InvalidTypeMetadata(reason);
+        match (&metadata.schema, &metadata.schema_uri) {
+            (Some(_), Some(_)) => Err(invalid(format!(
+                "{} has both schema and schema_uri",
+                metadata.vct
+            ))),
+            (Some(schema), None) => Ok(Some(schema.clone())),
+            (None, Some(uri)) => {
+                let document = self.document(uri)?;
+                if let Some(integrity) = &metadata.schema_uri_integrity {
+                    check_integrity(&document, integrity)
+                        .map_err(|e| invalid(format!("{uri}: {e}")))?;
+                }
+                serde_json::from_slice(&document)
+                    .map(Some)
+                    .map_err(|e| invalid(format!("{uri}: {e}")))
+            }
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// ローカルのファイル、キャッシュ、URL の順に文書 (取得したバイト列そのもの) を探す
+    fn document(&self, uri: &str) -> Result<Vec<u8>, VerificationError> {
+        let unavailable = |reason: String| VerificationError::TypeMetadataUnavailable(reason);
+        if let Some(path) = self.files.get(uri) {
+            return std::fs::read(path)
+                .map_err(|e| unavailable(format!("{}: {e}", path.display())));
+        }
+
+        let now = SystemTime::now();
+        {
+            let cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some(cached) = cache.get(uri) {
+                if now.duration_since(cached.fetched_at).unwrap_or_default() < self.max_age {
+                    return Ok(cached.document.clone());
+                }
+            }
+        }
+        if !self.fetch {
+            return Err(unavailable(format!("no local type metadata for {uri}")));
+        }
+        if !uri.starts_with("https://") && !uri.starts_with("http://") {
+            return Err(unavailable(format!("{uri} is not an http(s) url")));
+        }
+        let document = http::get_bytes(uri).map_err(|e| unavailable(e.to_string()))?;
+        let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+        cache.insert(
+            uri.to_string(),
+            CachedDocument {
+                document: document.clone(),
+                fetched_at: now,
+            },
+        );
+        Ok(document)
+    }
+}
+
+/// スキーマで `instance` を検証する
+/// 選択的開示のクレーム (`claims` の `sd` が `always`・`allowed`) がないことによる `required` のエラーは無視する
+fn validate_schema(
+    validator: &jsonschema::Validator,
+    instance: &Value,
+    vct: &str,
+    claims: &[ClaimMetadata],
+) -> Result<(), VerificationError> {
+    let errors: Vec<String> = validator
+        .iter_errors(instance)
+        .filter(|e| !is_undisclosed(e, claims))
+        .map(|e| match e.instance_path.to_string() {
+            path if path.is_empty() => e.to_string(),
+            path => format!("{path}: {e}"),
+        })
+        .collect();
+    if !errors.is_empty() {
+        return Err(VerificationError::SchemaMismatch(format!(
+            "{vct}: {}",
+            errors.join(", ")
+        )));
+    }
+    Ok(())
+}
+
+/// `required` のエラーが、選択的開示のクレームが開示されていないことによるものか
+fn is_undisclosed(error: &jsonschema::ValidationError, claims: &[ClaimMetadata]) -> bool {
+    let jsonschema::error::ValidationErrorKind::Required { property } = &error.kind else {
+        return false;
+    };
+    let mut path = match error.instance_path.to_string() {
+        pointer if pointer.is_empty() => Vec::new(),
+        pointer => claim_path(&pointer),
+    };
+    path.push(property.clone());
+    claims.iter().any(|claim| {
+        matches!(claim.sd.as_deref(), Some("always" | "allowed"))
+            && claim.path.len() == path.len()
+            && claim.path.iter().zip(&path).all(|(expected, actual)| {
+                expected == actual || (expected.is_null() && actual.is_u64())
+            })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const VCT: &str = "https://issuer.example.com/vc/patient-id";
+
+    /// テストの一時ファイル (drop で削除する)
+    struct TempFile(PathBuf);
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn resolver(metadata: &Value) -> (TypeMetadataResolver, TempFile) {
+        let file = TempFile(std::env::temp_dir().join(format!(
+            "type-metadata-{}-{}.json",
+            std::process::id(),
+            rand::random::<u64>()
+        )));
+        std::fs::write(&file.0, serde_json::to_vec(metadata).unwrap()).unwrap();
+        let resolver = TypeMetadataResolver::default()
+            .with_fetch(false)
+            .with_file(VCT, &file.0);
+        (resolver, file)
+    }
+
+    fn claims(value: Value) -> Map<String, Value> {
+        value.as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn undisclosed_claims_do_not_fail_required() {
+        let metadata = json!({
+            "vct": VCT,
+            "claims": [
+                { "path": ["patient_id"], "sd": "always" },
+                { "path": ["address", "street"], "sd": "allowed" },
+            ],
+            "schema": {
+                "type": "object",
+                "required": ["patient_id", "medical_institution_code", "address"],
+                "properties": {
+                    "address": { "type": "object", "required": ["street", "locality"] },
+                },
+                "additionalProperties": true,
+            },
+        });
+        let (resolver, _file) = resolver(&metadata);
+
+        let disclosed = claims(json!({
+            "iss": "https://issuer.example.com",
+            "medical_institution_code": "1234",
+            "address": { "locality": "Toyoake" },
+        }));
+        resolver.validate_claims(VCT, None, &disclosed).unwrap();
+
+        // 選択的開示ではないクレームの required はエラーにする
+        let missing = claims(json!({ "address": {} }));
+        let error = resolver.validate_claims(VCT, None, &missing).unwrap_err();
+        let VerificationError::SchemaMismatch(reason) = error else {
+            panic!("unexpected error {error:?}");
+        };
+        assert!(reason.contains("medical_institution_code"), "{reason}");
+        assert!(reason.contains("locality"), "{reason}");
+        assert!(!reason.contains("patient_id"), "{reason}");
+        assert!(!reason.contains("street"), "{reason}");
+    }
+
+    #[test]
+    fn integrity_is_checked_over_the_exact_bytes() {
+        let metadata = json!({ "vct": VCT, "name": "Patient ID" });
+        let (resolver, _file) = resolver(&metadata);
+        // 公開しているのは空白のない JSON なので、整形し直した文書のハッシュとは一致しない
+        let served = serde_json::to_vec(&metadata).unwrap();
+        let reserialized = serde_json::to_string_pretty(&metadata).unwrap();
+        assert!(resolver.resolve(VCT, Some(&integrity(&served))).is_ok());
+        assert!(matches!(
+            resolver.resolve(VCT, Some(&integrity(reserialized.as_bytes()))),
+            Err(VerificationError::InvalidTypeMetadata(_))
+        ));
+    }
+
+    #[test]
+    fn validator_is_cached_per_vct() {
+        let metadata = |required: &str| {
+            json!({
+                "vct": VCT,
+                "schema": { "type": "object", "required": [required] },
+            })
+        };
+        let (resolver, file) = resolver(&metadata("patient_id"));
+        let claims = claims(json!({ "patient_id": "1" }));
+        resolver.validate_claims(VCT, None, &claims).unwrap();
+        let first = resolver.validators.lock().unwrap()[VCT].validator.clone();
+        resolver.validate_claims(VCT, None, &claims).unwrap();
+        let second = resolver.validators.lock().unwrap()[VCT].validator.clone();
+        assert!(Arc::ptr_eq(&first, &second));
+
+        // スキーマが変わった場合はコンパイルし直す
+        std::fs::write(&file.0, serde_json::to_vec(&metadata("name")).unwrap()).unwrap();
+        assert!(resolver.validate_claims(VCT, None, &claims).is_err());
+        let third = resolver.validators.lock().unwrap()[VCT].validator.clone();
+        assert!(!Arc::ptr_eq(&first, &third));
+        assert_eq!(resolver.validators.lock().unwrap().len(), 1);
+    }
+}
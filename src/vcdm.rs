@@ -0,0 +1,370 @@
+//! W3C Verifiable Credentials Data Model 2.0 の VC-JWT (`vc+jwt`) と VP-JWT (`vp+jwt`)
+//!
+//! VC-JOSE-COSE に従い、JWT のペイロードを VC / VP そのものにする。
+//! VP-JWT の `verifiableCredential` には VC-JWT を `EnvelopedVerifiableCredential`
+//! (`data:application/vc+jwt,<JWT>`) として入れる。
+
+use anyhow::{anyhow, bail, Result};
+use serde_json::{json, Map, Value};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// VCDM 2.0 の `@context`
+pub const CREDENTIALS_V2_CONTEXT: &str = "https://www.w3.org/ns/credentials/v2";
+/// VC-JWT の JWS ヘッダの `typ`
+pub const VC_JWT_TYP: &str = "vc+jwt";
+/// VP-JWT の JWS ヘッダの `typ`
+pub const VP_JWT_TYP: &str = "vp+jwt";
+/// `EnvelopedVerifiableCredential` の `id` の接頭辞
+const ENVELOPED_VC_JWT_PREFIX: &str = "data:application/vc+jwt,";
+
+/// VC のペイロード (`issuer`・`validFrom`・`validUntil`・`credentialSubject`)
+/// `vct` がある場合は `type` に加える
+pub fn credential(
+    issuer: &str,
+    vct: Option<&str>,
+    subject: Map<String, Value>,
+    valid_from: SystemTime,
+    valid_until: SystemTime,
+) -> Map<String, Value> {
+    let mut types = vec![Value::from("VerifiableCredential")];
+    if let Some(vct) = vct {
+        types.push(Value::from(vct));
+    }
+    json!({
+        "@context": [CREDENTIALS_V2_CONTEXT],
+        "type": types,
+        "issuer": issuer,
+        "validFrom": format_datetime(valid_from),
+        "validUntil": format_datetime(valid_until),
+        "credentialSubject": subject,
+    })
+    .as_object()
+    .cloned()
+    .unwrap_or_default()
+}
+
+/// VC-JWT を入れた VP のペイロード
+pub fn presentation(vc_jwts: &[&str], holder: Option<&str>) -> Map<String, Value> {
+    let credentials: Vec<Value> = vc_jwts
+        .iter()
+        .map(|jwt| {
+            json!({
+                "@context": [CREDENTIALS_V2_CONTEXT],
+                "id": format!("{ENVELOPED_VC_JWT_PREFIX}{jwt}"),
+                "type": "EnvelopedVerifiableCredential",
+            })
+        })
+        .collect();
+    let mut presentation = Map::new();
+    presentation.insert("@context".to_string(), json!([CREDENTIALS_V2_CONTEXT]));
+    presentation.insert("type".to_string(), json!(["VerifiablePresentation"]));
+    if let Some(holder) = holder {
+        presentation.insert("holder".to_string(), Value::from(holder));
+    }
+    presentation.insert(
+        "verifiableCredential".to_string(),
+        Value::Array(credentials),
+    );
+    presentation
+}
+
+/// VP の `verifiableCredential` から VC-JWT を取り出す
+pub fn enveloped_credentials(presentation: &Map<String, Value>) -> Result<Vec<String>> {
+    let credentials = match presentation.get("verifiableCredential") {
+        Some(Value::Array(credentials)) => credentials.clone(),
+        Some(credential) => vec![credential.clone()],
+        None => bail!("presentation has no verifiableCredential"),
+    };
+    if credentials.is_empty() {
+        bail!("presentation has no verifiableCredential");
+    }
+    credentials
+        .iter()
+        .map(|credential| {
+            credential
+                .get("id")
+                .and_then(Value::as_str)
+                .and_then(|id| id.strip_prefix(ENVELOPED_VC_JWT_PREFIX))
+                .filter(|_| {
+                    credential.get("type").and_then(Value::as_str)
+                        == Some("EnvelopedVerifiableCredential")
+                })
+                .map(str::to_string)
+                .ok_or_else(|| anyhow!("not an enveloped vc+jwt credential: {credential}"))
+        })
+        .collect()
+}
+
+/// VC の `@context`・`type`・`issuer`・`credentialSubject` と有効期間をチェックする
+pub fn check_credential(credential: &Map<String, Value>, now: SystemTime) -> Result<()> {
+    let first_context = match credential.get("@context") {
+        Some(Value::Array(contexts)) => contexts.first().and_then(Value::as_str),
+        _ => None,
+    };
+    if first_context != Some(CREDENTIALS_V2_CONTEXT) {
+        bail!("@context must start with {CREDENTIALS_V2_CONTEXT}");
+    }
+    if !types(credential).contains(&"VerifiableCredential") {
+        bail!("type does not contain VerifiableCredential");
+    }
+    if issuer(credential).is_none() {
+        bail!("credential has no issuer");
+    }
+    if !matches!(
+        credential.get("credentialSubject"),
+        Some(Value::Object(_) | Value::Array(_))
+    ) {
+        bail!("credential has no credentialSubject");
+    }
+    if let Some(valid_from) = credential.get("validFrom") {
+        let valid_from = parse_datetime(valid_from.as_str().unwrap_or_default())?;
+        if valid_from > now {
+            bail!("credential is not yet valid");
+        }
+    }
+    Ok(())
+}
+
+/// `validUntil` を過ぎているか
+pub fn is_expired(credential: &Map<String, Value>, now: SystemTime) -> Result<bool> {
+    match credential.get("validUntil") {
+        Some(valid_until) => Ok(parse_datetime(valid_until.as_str().unwrap_or_default())? <= now),
+        None => Ok(false),
+    }
+}
+
+/// `type` の値 (文字列の場合も配列として扱う)
+pub fn types(credential: &Map<String, Value>) -> Vec<&str> {
+    match credential.get("type") {
+        Some(Value::String(t)) => vec![t.as_str()],
+        Some(Value::Array(types)) => types.iter().filter_map(Value::as_str).collect(),
+        _ => vec![],
+    }
+}
+
+/// `issuer` (文字列、または `id` を持つオブジェクト)
+pub fn issuer(credential: &Map<String, Value>) -> Option<&str> {
+    match credential.get("issuer")? {
+        Value::String(issuer) => Some(issuer),
+        issuer => issuer.get("id").and_then(Value::as_str),
+    }
+}
+
+/// RFC 3339 の UTC の日時 (`2026-01-02T03:04:05Z`)
+pub fn format_datetime(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (days, rem) = (secs / 86400, secs % 86400);
+    let (year, month, day) = civil_from_days(days as i64);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+/// RFC 3339 の日時 (`Z` または `+09:00` のようなオフセット、秒の小数部は切り捨て)
+/// 年は 0000 から 9999 まで (それ以外は RFC 3339 で表せない)
+pub fn parse_datetime(value: &str) -> Result<SystemTime> {
+    let invalid = || anyhow!("invalid date-time: {value}");
+    // 桁数の決まった数字 (年は 4 桁、それ以外は 2 桁)
+    let number = |s: &str, len: usize| -> Result<i64> {
+        if s.len() != len || !s.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(invalid());
+        }
+        s.parse().map_err(|_| invalid())
+    };
+    let (date, time) = value.split_once(['T', 't']).ok_or_else(invalid)?;
+    let date: Vec<&str> = date.split('-').collect();
+    let [year, month, day] = date[..] else {
+        return Err(invalid());
+    };
+    let (year, month, day) = (number(year, 4)?, number(month, 2)?, number(day, 2)?);
+
+    // タイムゾーン (`Z` または `±HH:MM`)
+    let (time, offset) = if let Some(time) = time.strip_suffix(['Z', 'z']) {
+        (time, 0)
+    } else {
+        let i = time.rfind(['+', '-']).ok_or_else(invalid)?;
+        let (time, offset) = time.split_at(i);
+        let (sign, offset) = offset.split_at(1);
+        let (hours, minutes) = offset.split_once(':').ok_or_else(invalid)?;
+        let (hours, minutes) = (number(hours, 2)?, number(minutes, 2)?);
+        if hours > 23 || minutes > 59 {
+            return Err(invalid());
+        }
+        let offset = hours * 3600 + minutes * 60;
+        (time, if sign == "+" { offset } else { -offset })
+    };
+    let time = match time.split_once('.') {
+        Some((time, fraction))
+            if !fraction.is_empty() && fraction.bytes().all(|b| b.is_ascii_digit()) =>
+        {
+            time
+        }
+        Some(_) => return Err(invalid()),
+        None => time,
+    };
+    let time: Vec<&str> = time.split(':').collect();
+    let [hour, minute, second] = time[..] else {
+        return Err(invalid());
+    };
+    let (hour, minute, second) = (number(hour, 2)?, number(minute, 2)?, number(second, 2)?);
+    if !(1..=12).contains(&month)
+        || !(1..=days_in_month(year, month)).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return Err(invalid());
+    }
+
+    let secs = days_from_civil(year, month, day)
+        .checked_mul(86400)
+        .and_then(|secs| secs.checked_add(hour * 3600 + minute * 60 + second - offset))
+        .ok_or_else(invalid)?;
+    let secs = u64::try_from(secs).map_err(|_| invalid())?;
+    UNIX_EPOCH
+        .checked_add(Duration::from_secs(secs))
+        .ok_or_else(invalid)
+}
+
+/// `year` 年 `month` 月の日数
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// 1970-01-01 からの日数を年月日にする
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// 年月日を 1970-01-01 からの日数にする
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(value: &str) -> u64 {
+        parse_datetime(value)
+            .unwrap()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    #[test]
+    fn parses_utc_and_offsets() {
+        assert_eq!(secs("1970-01-01T00:00:00Z"), 0);
+        assert_eq!(secs("2026-01-02T03:04:05Z"), 1_767_323_045);
+        assert_eq!(secs("2026-01-02t03:04:05z"), 1_767_323_045);
+        assert_eq!(secs("2026-01-02T12:04:05+09:00"), 1_767_323_045);
+        assert_eq!(secs("2026-01-01T21:34:05-05:30"), 1_767_323_045);
+        assert_eq!(secs("2026-01-02T03:04:05+00:00"), 1_767_323_045);
+    }
+
+    #[test]
+    fn truncates_fractional_seconds() {
+        assert_eq!(secs("2026-01-02T03:04:05.999Z"), 1_767_323_045);
+        assert_eq!(
+            secs("2026-01-02T03:04:05.1+09:00"),
+            1_767_323_045 - 9 * 3600
+        );
+        assert!(parse_datetime("2026-01-02T03:04:05.Z").is_err());
+        assert!(parse_datetime("2026-01-02T03:04:05.1x2Z").is_err());
+    }
+
+    #[test]
+    fn checks_leap_days() {
+        assert_eq!(secs("2024-02-29T00:00:00Z"), 1_709_164_800);
+        assert_eq!(secs("2000-02-29T00:00:00Z"), 951_782_400);
+        for value in [
+            "2023-02-29T00:00:00Z",
+            "1900-02-29T00:00:00Z",
+            "2026-04-31T00:00:00Z",
+        ] {
+            assert!(parse_datetime(value).is_err(), "{value}");
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_date_times() {
+        for value in [
+            "",
+            "2026-01-02",
+            "2026-01-02 03:04:05Z",
+            "2026-01-02T03:04:05",
+            "2026-1-02T03:04:05Z",
+            "26-01-02T03:04:05Z",
+            "+2026-01-02T03:04:05Z",
+            "2026-13-02T03:04:05Z",
+            "2026-01-00T03:04:05Z",
+            "2026-01-02T24:00:00Z",
+            "2026-01-02T03:60:00Z",
+            "2026-01-02T03:04Z",
+            "2026-01-02T03:04:05+0900",
+            "2026-01-02T03:04:05+24:00",
+            "2026-01-02T03:04:05+09:60",
+            "1969-12-31T23:59:59Z",
+        ] {
+            assert!(parse_datetime(value).is_err(), "{value}");
+        }
+    }
+
+    #[test]
+    fn rejects_years_outside_rfc_3339() {
+        assert_eq!(secs("9999-12-31T23:59:59Z"), 253_402_300_799);
+        for value in [
+            "10000-01-01T00:00:00Z",
+            "99999999999999999-01-01T00:00:00Z",
+            "9223372036854775807-01-01T00:00:00Z",
+        ] {
+            assert!(parse_datetime(value).is_err(), "{value}");
+        }
+    }
+
+    #[test]
+    fn format_datetime_round_trips() {
+        for secs in [
+            0,
+            951_782_400,
+            1_709_164_800,
+            1_767_323_045,
+            253_402_300_799,
+        ] {
+            let time = UNIX_EPOCH + Duration::from_secs(secs);
+            let formatted = format_datetime(time);
+            assert_eq!(parse_datetime(&formatted).unwrap(), time, "{formatted}");
+        }
+        assert_eq!(
+            format_datetime(UNIX_EPOCH + Duration::from_secs(1_767_323_045)),
+            "2026-01-02T03:04:05Z"
+        );
+    }
+}
@@ -0,0 +1,900 @@
+//! VP (SD-JWT + KB-JWT) の検証
+
+use crate::{
+    alg::SigningAlgorithm,
+    did::{DidResolver, Relationship},
+    error::{JwtKind, VerificationError},
+    issuer_metadata::IssuerKeyResolver,
+    jose::{check_iat, check_key_algorithm, check_typ, decode_jwt, jwt_algorithm},
+    mdoc::MdocDocument,
+    nonce::NonceStore,
+    status_list::{Status, StatusList, StatusListCache, StatusReference, VerifiedStatusList},
+    trust::{TrustRegistry, TrustedIssuer},
+    type_metadata::{TypeMetadataResolver, VCT_INTEGRITY_CLAIM},
+    vcdm,
+};
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use josekit::{
+    jws::JwsHeader,
+    jwt::{self, JwtPayload},
+};
+use sd_jwt_payload::SdObjectDecoder;
+use serde_json::{Map, Value};
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+/// KB-JWT の `iat` と現在時刻のずれの許容範囲の既定値
+pub const DEFAULT_IAT_SKEW: Duration = Duration::from_secs(5 * 60);
+
+/// VC の署名を検証する発行者の鍵
+enum IssuerKeys {
+    /// PEM形式の発行者の公開鍵 (`iss` は問わない)
+    Pem(Vec<u8>),
+    /// `iss` ごとに鍵・`vct`・署名アルゴリズムを定めたトラストレジストリ
+    Registry(Arc<TrustRegistry>),
+    /// `iss` の発行者メタデータ (`/.well-known/jwt-vc-issuer`) の JWKS
+    Metadata(Arc<IssuerKeyResolver>),
+}
+
+/// VP を検証する Verifier
+pub struct Verifier {
+    issuer_keys: IssuerKeys,
+    /// VC の `aud` として期待する値
+    vc_audience: String,
+    /// KB-JWT の `aud` として期待する値
+    kb_audience: String,
+    /// KB-JWT の `iat` と現在時刻のずれの許容範囲
+    iat_skew: Duration,
+    /// 発行した nonce のストア (None の場合は使用済みチェックをしない)
+    nonce_store: Option<Arc<dyn NonceStore>>,
+    /// ステータスリストのキャッシュ (None の場合は `status` をチェックしない)
+    status_lists: Option<Arc<StatusListCache>>,
+    /// `cnf.kid` (DID URL) を解決する DID resolver (None の場合は `cnf.jwk` だけを受け付ける)
+    did_resolver: Option<DidResolver>,
+    /// `vct` の Type Metadata (None の場合は開示されたクレームをスキーマで検証しない)
+    type_metadata: Option<Arc<TypeMetadataResolver>>,
+}
+
+/// VP の検証結果
+#[derive(Debug, Clone)]
+pub struct VerificationResult {
+    /// 開示されたクレームを復元したオブジェクト
+    pub claims: Map<String, Value>,
+    /// VC の `iss`
+    pub issuer: Option<String>,
+    /// VC の `vct`
+    pub vct: Option<String>,
+    /// Holder の公開鍵 (VC の `cnf.jwk`、または `cnf.kid` の DID URL から解決した鍵)
+    pub holder_jwk: Value,
+    /// 提示された disclosures
+    pub disclosures: Vec<String>,
+}
+
+impl Verifier {
+    pub fn new(
+        issuer_public_key: Vec<u8>,
+        vc_audience: impl Into<String>,
+        kb_audience: impl Into<String>,
+    ) -> Self {
+        Self::with_issuer_keys(IssuerKeys::Pem(issuer_public_key), vc_audience, kb_audience)
+    }
+
+    /// トラストレジストリに登録された発行者の VC だけを受け入れる Verifier を作成
+    pub fn from_trust_registry(
+        registry: Arc<TrustRegistry>,
+        vc_audience: impl Into<String>,
+        kb_audience: impl Into<String>,
+    ) -> Self {
+        Self::with_issuer_keys(IssuerKeys::Registry(registry), vc_audience, kb_audience)
+    }
+
+    /// VC の `iss` の発行者メタデータから、JWS ヘッダの `kid` の鍵を取得して検証する Verifier を作成
+    pub fn from_issuer_metadata(
+        resolver: Arc<IssuerKeyResolver>,
+        vc_audience: impl Into<String>,
+        kb_audience: impl Into<String>,
+    ) -> Self {
+        Self::with_issuer_keys(IssuerKeys::Metadata(resolver), vc_audience, kb_audience)
+    }
+
+    fn with_issuer_keys(
+        issuer_keys: IssuerKeys,
+        vc_audience: impl Into<String>,
+        kb_audience: impl Into<String>,
+    ) -> Self {
+        Self {
+            issuer_keys,
+            vc_audience: vc_audience.into(),
+            kb_audience: kb_audience.into(),
+            iat_skew: DEFAULT_IAT_SKEW,
+            nonce_store: None,
+            status_lists: None,
+            did_resolver: None,
+            type_metadata: None,
+        }
+    }
+
+    /// KB-JWT の `iat` と現在時刻のずれの許容範囲を指定する
+    pub fn with_iat_skew(mut self, iat_skew: Duration) -> Self {
+        self.iat_skew = iat_skew;
+        self
+    }
+
+    /// nonce のストアを指定する
+    /// 検証に成功した VP の nonce は使用済みになり、再提示 (リプレイ) を拒否する
+    pub fn with_nonce_store(mut self, nonce_store: Arc<dyn NonceStore>) -> Self {
+        self.nonce_store = Some(nonce_store);
+        self
+    }
+
+    /// Type Metadata の resolver を指定する
+    /// VC の `vct` の Type Metadata を解決し、`vct#integrity` と開示されたクレームのスキーマを検証する
+    pub fn with_type_metadata(mut self, type_metadata: Arc<TypeMetadataResolver>) -> Self {
+        self.type_metadata = Some(type_metadata);
+        self
+    }
+
+    /// ステータスリストのキャッシュを指定する
+    /// VC に `status` がある場合、失効・一時停止されていれば拒否する
+    pub fn with_status_lists(mut self, status_lists: Arc<StatusListCache>) -> Self {
+        self.status_lists = Some(status_lists);
+        self
+    }
+
+    /// DID resolver を指定する
+    /// VC の `cnf` が `kid` (DID URL) の場合、DID ドキュメントの `authentication` の鍵で KB-JWT を検証する
+    pub fn with_did_resolver(mut self, did_resolver: DidResolver) -> Self {
+        self.did_resolver = Some(did_resolver);
+        self
+    }
+
+    /// PEMファイルから発行者の公開鍵を読み込んで Verifier を作成
+    /// JWK のファイル (秘密鍵の JWK の場合は公開鍵部分) も PEM にして読み込む
+    pub fn from_pem_file(
+        file_path: &str,
+        vc_audience: impl Into<String>,
+        kb_audience: impl Into<String>,
+    ) -> Result<Self> {
+        let issuer_public_key = std::fs::read(file_path)
+            .map_err(|e| anyhow!("failed to read public key file {file_path}: {e:?}"))?;
+        let issuer_public_key = if crate::jwk::is_jwk(&issuer_public_key) {
+            crate::jwk::public_jwk_to_pem(&crate::jwk::parse_jwk(&issuer_public_key)?)?
+        } else {
+            issuer_public_key
+        };
+        Ok(Self::new(issuer_public_key, vc_audience, kb_audience))
+    }
+
+    /// 発行者の署名付き JWT (SD-JWT の JWT 部分) を検証
+    pub fn verify_credential(
+        &self,
+        jwt: &str,
+    ) -> Result<(JwsHeader, Map<String, Value>), VerificationError> {
+        self.verify_issued_credential(jwt, "vc+sd-jwt")
+    }
+
+    /// W3C VCDM 2.0 の VC-JWT (`vc+jwt`) を検証
+    /// `issuer` は `iss` と一致し、現在時刻が `validFrom` から `validUntil` の間でなければならない
+    pub fn verify_vc_jwt(
+        &self,
+        jwt: &str,
+    ) -> Result<(JwsHeader, Map<String, Value>), VerificationError> {
+        let (header, claims) = self.verify_issued_credential(jwt, vcdm::VC_JWT_TYP)?;
+        let now = SystemTime::now();
+        vcdm::check_credential(&claims, now)
+            .map_err(|e| VerificationError::InvalidCredential(e.to_string()))?;
+        if vcdm::issuer(&claims) != claims.get("iss").and_then(Value::as_str) {
+            return Err(VerificationError::InvalidCredential(
+                "issuer does not match iss".to_string(),
+            ));
+        }
+        match vcdm::is_expired(&claims, now) {
+            Ok(false) => Ok((header, claims)),
+            Ok(true) => Err(VerificationError::Expired(JwtKind::Credential)),
+            Err(e) => Err(VerificationError::InvalidCredential(e.to_string())),
+        }
+    }
+
+    /// 発行者が署名した VC の JWT を検証する (ヘッダの `typ` は `typ`)
+    fn verify_issued_credential(
+        &self,
+        jwt: &str,
+        typ: &'static str,
+    ) -> Result<(JwsHeader, Map<String, Value>), VerificationError> {
+        let token = JwtKind::Credential;
+        // トラストレジストリ・発行者メタデータを使う場合は、署名を検証する前に `iss` から発行者を決める
+        let trusted = match &self.issuer_keys {
+            IssuerKeys::Pem(_) => None,
+            IssuerKeys::Registry(registry) => {
+                let iss = unverified_issuer(jwt, token)?;
+                let trusted = registry
+                    .issuer(&iss)
+                    .ok_or(VerificationError::UntrustedIssuer(iss))?;
+                Some(trusted)
+            }
+            IssuerKeys::Metadata(resolver) => {
+                let iss = unverified_issuer(jwt, token)?;
+                Some(resolver.trusted_issuer(&iss, jwt_kid(jwt).as_deref())?)
+            }
+        };
+        let (payload, header) =
+            self.decode_issuer_jwt(jwt, token, Some(&self.vc_audience), trusted.as_ref())?;
+
+        // VC の header の typ が vc+sd-jwt (VC-JWT の場合は vc+jwt) であるかチェック
+        check_typ(&header, token, typ)?;
+
+        let claims = payload.claims_set().clone();
+        if let Some(trusted) = &trusted {
+            let vct = credential_type(&claims);
+            if !trusted.allows_vct(vct) {
+                return Err(VerificationError::VctNotAllowed {
+                    iss: trusted.iss.clone(),
+                    vct: vct.map(str::to_string),
+                });
+            }
+        }
+        self.check_status(&claims, trusted.as_ref())?;
+        Ok((header, claims))
+    }
+
+    /// 発行者が署名した JWT (VC・ステータスリスト) を検証する
+    /// `trusted` がある場合は、その発行者に許可されたアルゴリズムと `kid` の鍵で検証する
+    fn decode_issuer_jwt(
+        &self,
+        jwt: &str,
+        token: JwtKind,
+        audience: Option<&str>,
+        trusted: Option<&TrustedIssuer>,
+    ) -> Result<(JwtPayload, JwsHeader), VerificationError> {
+        let trusted = match (&self.issuer_keys, trusted) {
+            (IssuerKeys::Pem(issuer_public_key), _) => {
+                return decode_jwt(jwt, token, audience, |alg| {
+                    let key_alg = SigningAlgorithm::from_pem(issuer_public_key)
+                        .map_err(|e| VerificationError::InvalidIssuerKey(e.to_string()))?;
+                    check_key_algorithm(token, alg, key_alg)?;
+                    alg.verifier_from_pem(issuer_public_key)
+                        .map_err(|e| VerificationError::InvalidIssuerKey(e.to_string()))
+                })
+            }
+            (_, Some(trusted)) => trusted,
+            (_, None) => {
+                return Err(VerificationError::InvalidIssuerKey(
+                    "issuer is not resolved".to_string(),
+                ))
+            }
+        };
+
+        let alg = jwt_algorithm(jwt, token)?;
+        if !trusted.allows_algorithm(alg) {
+            return Err(VerificationError::AlgorithmNotAllowed {
+                iss: trusted.iss.clone(),
+                alg,
+            });
+        }
+        let kid = jwt_kid(jwt);
+        let candidates: Vec<_> = trusted
+            .keys_for(kid.as_deref())
+            .into_iter()
+            .filter(|key| {
+                SigningAlgorithm::from_jwk(&key.jwk)
+                    .is_ok_and(|key_alg| alg.is_compatible_with(key_alg))
+            })
+            .collect();
+        // `kid` のない鍵が複数ある場合は、署名を検証できる鍵を使う
+        let key = candidates
+            .iter()
+            .find(|key| {
+                alg.verifier_from_jwk(&key.jwk)
+                    .is_ok_and(|verifier| jwt::decode_with_verifier(jwt, verifier.as_ref()).is_ok())
+            })
+            .or(candidates.first())
+            .ok_or_else(|| VerificationError::UnknownIssuerKey {
+                iss: trusted.iss.clone(),
+                kid: kid.clone(),
+            })?;
+        decode_jwt(jwt, token, audience, |alg| {
+            alg.verifier_from_jwk(&key.jwk)
+                .map_err(|e| VerificationError::InvalidIssuerKey(e.to_string()))
+        })
+    }
+
+    /// VC の `status` が指すステータスリストで、失効・一時停止されていないかチェックする
+    fn check_status(
+        &self,
+        claims: &Map<String, Value>,
+        trusted: Option<&TrustedIssuer>,
+    ) -> Result<(), VerificationError> {
+        let Some(status_lists) = &self.status_lists else {
+            return Ok(());
+        };
+        let Some(reference) = StatusReference::from_claims(claims) else {
+            return Ok(());
+        };
+        let reference =
+            reference.map_err(|e| VerificationError::InvalidStatusList(e.to_string()))?;
+        let status = status_lists.status(&reference, |jwt| {
+            self.verify_status_list(jwt, &reference.uri, trusted)
+        })?;
+        match status {
+            Status::Valid => Ok(()),
+            Status::Invalid => Err(VerificationError::Revoked),
+            Status::Suspended => Err(VerificationError::Suspended),
+            Status::Other(v) => Err(VerificationError::UnknownStatus(v)),
+        }
+    }
+
+    /// ステータスリストの JWT を VC の発行者の公開鍵で検証する
+    /// `sub` は VC の `status_list.uri` と一致しなければならない
+    fn verify_status_list(
+        &self,
+        jwt: &str,
+        uri: &str,
+        trusted: Option<&TrustedIssuer>,
+    ) -> Result<VerifiedStatusList, VerificationError> {
+        let token = JwtKind::StatusList;
+        let (payload, header) = self.decode_issuer_jwt(jwt, token, None, trusted)?;
+        check_typ(&header, token, crate::status_list::STATUS_LIST_TYP)?;
+
+        if let (Some(trusted), Some(iss)) = (trusted, payload.issuer()) {
+            if iss != trusted.iss {
+                return Err(VerificationError::InvalidStatusList(format!(
+                    "iss is not {}",
+                    trusted.iss
+                )));
+            }
+        }
+
+        if payload.subject() != Some(uri) {
+            return Err(VerificationError::InvalidStatusList(format!(
+                "sub is not {uri}"
+            )));
+        }
+        let list = payload
+            .claim("status_list")
+            .ok_or_else(|| VerificationError::InvalidStatusList("no status_list".to_string()))
+            .and_then(|v| {
+                StatusList::from_json(v)
+                    .map_err(|e| VerificationError::InvalidStatusList(e.to_string()))
+            })?;
+        Ok(VerifiedStatusList {
+            list,
+            expires_at: payload.expires_at(),
+            ttl: payload
+                .claim("ttl")
+                .and_then(Value::as_u64)
+                .map(Duration::from_secs),
+        })
+    }
+
+    /// VP を検証し、開示されたクレームを復元した結果を返す
+    /// KB-JWT の `nonce` は `nonce` と一致し、`iat` は許容範囲内でなければならない
+    pub fn verify_presentation(
+        &self,
+        vp: &str,
+        nonce: &str,
+    ) -> Result<VerificationResult, VerificationError> {
+        let sd_jwt = crate::sd_jwt::parse(vp)
+            .map_err(|e| VerificationError::MalformedSdJwt(e.to_string()))?;
+        let (_, vc_claims) = self.verify_credential(&sd_jwt.jwt)?;
+
+        // Holder の公開鍵を SD-JWT の cnf から取り出す
+        let holder_jwk = &self.holder_key(&vc_claims)?;
+
+        let kb_jwt = sd_jwt
+            .key_binding_jwt
+            .as_ref()
+            .ok_or(VerificationError::KbJwtMissing)?;
+        let token = JwtKind::KeyBinding;
+        let (kb_payload, kb_header) = decode_jwt(kb_jwt, token, Some(&self.kb_audience), |alg| {
+            let key_alg = SigningAlgorithm::from_jwk(holder_jwk)
+                .map_err(|e| VerificationError::InvalidHolderKey(e.to_string()))?;
+            check_key_algorithm(token, alg, key_alg)?;
+            alg.verifier_from_jwk(holder_jwk)
+                .map_err(|e| VerificationError::InvalidHolderKey(e.to_string()))
+        })?;
+
+        // KB-JWT の header の typ が kb+jwt であるかチェック
+        check_typ(&kb_header, token, "kb+jwt")?;
+
+        // kb-jwt の sd_hash の値が一致するかチェック
+        let expected = crate::sd_jwt::sd_hash(&sd_jwt.jwt, &sd_jwt.disclosures);
+        match kb_payload.claim("sd_hash") {
+            Some(Value::String(sd_hash)) if *sd_hash == expected => {}
+            _ => return Err(VerificationError::SdHashMismatch),
+        }
+
+        match kb_payload.claim("nonce") {
+            Some(Value::String(v)) if v == nonce => {}
+            _ => return Err(VerificationError::NonceMismatch),
+        }
+
+        check_iat(&kb_payload, token, self.iat_skew)?;
+
+        // Decode the payload by providing the disclosures that were parsed from the SD-JWT.
+        let decoder = SdObjectDecoder::new_with_sha256();
+        let claims = decoder
+            .decode(&vc_claims, &sd_jwt.disclosures)
+            .map_err(|e| VerificationError::InvalidDisclosure(e.to_string()))?;
+
+        if let Some(type_metadata) = &self.type_metadata {
+            let vct = vc_claims
+                .get("vct")
+                .and_then(Value::as_str)
+                .ok_or_else(|| {
+                    VerificationError::InvalidTypeMetadata("vc has no vct".to_string())
+                })?;
+            let integrity = vc_claims.get(VCT_INTEGRITY_CLAIM).and_then(Value::as_str);
+            type_metadata.validate_claims(vct, integrity, &claims)?;
+        }
+
+        // すべての検証に成功してから nonce を使用済みにする
+        if let Some(nonce_store) = &self.nonce_store {
+            if !nonce_store.consume(nonce) {
+                return Err(VerificationError::NonceReplayed);
+            }
+        }
+
+        let string_claim = |name: &str| {
+            vc_claims
+                .get(name)
+                .and_then(|v| v.as_str())
+                .map(str::to_string)
+        };
+        Ok(VerificationResult {
+            issuer: string_claim("iss"),
+            vct: string_claim("vct"),
+            holder_jwk: holder_jwk.clone(),
+            disclosures: sd_jwt.disclosures,
+            claims,
+        })
+    }
+
+    /// VC-JWT を入れた VP-JWT (`vp+jwt`) を検証し、VC ごとの検証結果を返す
+    /// VP-JWT は各 VC の `cnf` の Holder の鍵で署名され、`nonce`・`aud`・`iat` が正しくなければならない
+    pub fn verify_vp_jwt(
+        &self,
+        vp: &str,
+        nonce: &str,
+    ) -> Result<Vec<VerificationResult>, VerificationError> {
+        let token = JwtKind::Presentation;
+        let presentation = unverified_payload(vp, token)?;
+        let vc_jwts = vcdm::enveloped_credentials(&presentation)
+            .map_err(|e| VerificationError::InvalidCredential(e.to_string()))?;
+
+        let mut results = Vec::new();
+        let mut verified = None;
+        for vc_jwt in &vc_jwts {
+            let (_, claims) = self.verify_vc_jwt(vc_jwt)?;
+            let holder_jwk = self.holder_key(&claims)?;
+            let (payload, header) = decode_jwt(vp, token, Some(&self.kb_audience), |alg| {
+                let key_alg = SigningAlgorithm::from_jwk(&holder_jwk)
+                    .map_err(|e| VerificationError::InvalidHolderKey(e.to_string()))?;
+                check_key_algorithm(token, alg, key_alg)?;
+                alg.verifier_from_jwk(&holder_jwk)
+                    .map_err(|e| VerificationError::InvalidHolderKey(e.to_string()))
+            })?;
+            check_typ(&header, token, vcdm::VP_JWT_TYP)?;
+
+            // VP の holder は VC の credentialSubject の id と一致しなければならない
+            let subject_id = claims
+                .get("credentialSubject")
+                .and_then(|subject| subject.get("id"))
+                .and_then(Value::as_str);
+            if let (Some(holder), Some(subject_id)) = (payload.claim("holder"), subject_id) {
+                if holder.as_str() != Some(subject_id) {
+                    return Err(VerificationError::InvalidCredential(format!(
+                        "holder is not {subject_id}"
+                    )));
+                }
+            }
+            verified = Some(payload);
+
+            results.push(VerificationResult {
+                issuer: vcdm::issuer(&claims).map(str::to_string),
+                vct: credential_type(&claims).map(str::to_string),
+                holder_jwk,
+                disclosures: vec![],
+                claims,
+            });
+        }
+        let Some(payload) = verified else {
+            return Err(VerificationError::InvalidCredential(
+                "presentation has no verifiableCredential".to_string(),
+            ));
+        };
+
+        match payload.claim("nonce") {
+            Some(Value::String(v)) if v == nonce => {}
+            _ => return Err(VerificationError::NonceMismatch),
+        }
+        check_iat(&payload, token, self.iat_skew)?;
+
+        // すべての検証に成功してから nonce を使用済みにする
+        if let Some(nonce_store) = &self.nonce_store {
+            if !nonce_store.consume(nonce) {
+                return Err(VerificationError::NonceReplayed);
+            }
+        }
+        Ok(results)
+    }
+
+    /// mdoc の DeviceResponse (CBOR) を検証し、ドキュメントごとの開示された要素を返す
+    /// deviceSignature は `nonce`・リクエストの `client_id`・`response_uri` の SessionTranscript で検証する
+    /// (redirect_uri スキームでは `client_id` と `response_uri` は同じ値)
+    /// `issuerAuth` は発行者の公開鍵 (PEM) で検証する (トラストレジストリ・発行者メタデータは `iss` がないため使えない)
+    pub fn verify_device_response(
+        &self,
+        response: &[u8],
+        nonce: &str,
+        client_id: &str,
+        response_uri: &str,
+    ) -> Result<Vec<MdocDocument>, VerificationError> {
+        let IssuerKeys::Pem(issuer_public_key) = &self.issuer_keys else {
+            return Err(VerificationError::InvalidIssuerKey(
+                "mdoc requires an issuer public key".to_string(),
+            ));
+        };
+        let session_transcript = crate::mdoc::session_transcript(client_id, nonce, response_uri)
+            .map_err(|e| VerificationError::InvalidMdoc(e.to_string()))?;
+        let documents =
+            crate::mdoc::verify_device_response(response, &session_transcript, |alg, _kid| {
+                let key_alg = SigningAlgorithm::from_pem(issuer_public_key)
+                    .map_err(|e| VerificationError::InvalidIssuerKey(e.to_string()))?;
+                check_key_algorithm(JwtKind::Credential, alg, key_alg)?;
+                alg.verifier_from_pem(issuer_public_key)
+                    .map_err(|e| VerificationError::InvalidIssuerKey(e.to_string()))
+            })?;
+
+        // すべての検証に成功してから nonce を使用済みにする
+        if let Some(nonce_store) = &self.nonce_store {
+            if !nonce_store.consume(nonce) {
+                return Err(VerificationError::NonceReplayed);
+            }
+        }
+        Ok(documents)
+    }
+
+    /// VC の `cnf` の Holder の公開鍵 (`jwk`、または DID resolver で解決した `kid`)
+    fn holder_key(&self, vc_claims: &Map<String, Value>) -> Result<Value, VerificationError> {
+        let cnf = vc_claims.get("cnf").ok_or(VerificationError::MissingCnf)?;
+        if let Some(jwk) = cnf.get("jwk") {
+            return Ok(jwk.clone());
+        }
+        let (Some(kid), Some(did_resolver)) =
+            (cnf.get("kid").and_then(Value::as_str), &self.did_resolver)
+        else {
+            return Err(VerificationError::MissingCnf);
+        };
+        did_resolver
+            .resolve_verification_method(kid, Relationship::Authentication)
+            .and_then(|method| method.jwk())
+            .map_err(|e| VerificationError::InvalidHolderKey(e.to_string()))
+    }
+}
+
+/// 署名を検証する前の JWT のペイロードの `iss` (鍵を選ぶためだけに使う)
+fn unverified_issuer(jwt: &str, token: JwtKind) -> Result<String, VerificationError> {
+    unverified_payload(jwt, token)?
+        .get("iss")
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .ok_or_else(|| VerificationError::MalformedJwt {
+            token,
+            reason: "jwt has no iss".to_string(),
+        })
+}
+
+/// 署名を検証する前の JWT のペイロード
+fn unverified_payload(jwt: &str, token: JwtKind) -> Result<Map<String, Value>, VerificationError> {
+    let malformed = |reason: &str| VerificationError::MalformedJwt {
+        token,
+        reason: reason.to_string(),
+    };
+    let payload = jwt
+        .split('.')
+        .nth(1)
+        .ok_or_else(|| malformed("jwt has no payload"))?;
+    let payload = URL_SAFE_NO_PAD
+        .decode(payload)
+        .map_err(|e| malformed(&e.to_string()))?;
+    serde_json::from_slice(&payload).map_err(|e| malformed(&e.to_string()))
+}
+
+/// VC の種類 (`vct`、VC-JWT の場合は `VerifiableCredential` 以外の `type`)
+fn credential_type(claims: &Map<String, Value>) -> Option<&str> {
+    claims.get("vct").and_then(Value::as_str).or_else(|| {
+        vcdm::types(claims)
+            .into_iter()
+            .find(|t| *t != "VerifiableCredential")
+    })
+}
+
+/// JWT のヘッダの `kid`
+fn jwt_kid(jwt: &str) -> Option<String> {
+    jwt::decode_header(jwt).ok().and_then(|header| {
+        header
+            .claim("kid")
+            .and_then(Value::as_str)
+            .map(str::to_string)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        issuer::{GenerateVCParams, Issuer},
+        key::{generate_key_pair, public_key_pem_to_jwk},
+        Holder,
+    };
+    use serde_json::json;
+
+    const VC_AUDIENCE: &str = "https://verifier.example.com";
+    const KB_AUDIENCE: &str = "https://verifier.example.com/response";
+    const NONCE: &str = "n-0S6_WzA2Mj";
+
+    struct Keys {
+        issuer: Issuer,
+        issuer_public: Vec<u8>,
+        holder_private: Vec<u8>,
+        holder: Holder,
+        holder_jwk: Value,
+    }
+
+    fn keys() -> Keys {
+        let (issuer_private, issuer_public) = generate_key_pair(SigningAlgorithm::ES256).unwrap();
+        let (holder_private, holder_public) = generate_key_pair(SigningAlgorithm::ES256).unwrap();
+        Keys {
+            issuer: Issuer::new("https://issuer.example.com", issuer_private, "key-1").unwrap(),
+            issuer_public,
+            holder: Holder::new(holder_private.clone()).unwrap(),
+            holder_private,
+            holder_jwk: public_key_pem_to_jwk(&holder_public).unwrap(),
+        }
+    }
+
+    fn issue(keys: &Keys, holder_jwk: &Value, vc_expires_in: u64) -> String {
+        let claims = json!({ "account_name": "user01", "group_name": "staff" });
+        keys.issuer
+            .generate_sd_jwt_vc(GenerateVCParams {
+                vct: Some("https://credentials.example.com/test".to_string()),
+                vct_integrity: None,
+                holder_jwk: holder_jwk.clone(),
+                holder_kid: None,
+                claims: claims.as_object().unwrap().clone(),
+                disclosable: vec!["/account_name".to_string(), "/group_name".to_string()],
+                decoys: 0,
+                audience: VC_AUDIENCE.to_string(),
+                vc_expires_in,
+                status: None,
+            })
+            .unwrap()
+    }
+
+    fn verifier(keys: &Keys) -> Verifier {
+        Verifier::new(keys.issuer_public.clone(), VC_AUDIENCE, KB_AUDIENCE)
+    }
+
+    #[test]
+    fn presentation_is_verified() {
+        let keys = keys();
+        let vc = issue(&keys, &keys.holder_jwk, 3600);
+        let vp = keys
+            .holder
+            .present(&vc, &["account_name"], NONCE, KB_AUDIENCE)
+            .unwrap();
+        let result = verifier(&keys).verify_presentation(&vp, NONCE).unwrap();
+        assert_eq!(result.claims["account_name"], "user01");
+        assert!(!result.claims.contains_key("group_name"));
+        assert_eq!(result.holder_jwk, keys.holder_jwk);
+    }
+
+    #[test]
+    fn other_issuer_key_is_bad_issuer_signature() {
+        let keys = keys();
+        let vc = issue(&keys, &keys.holder_jwk, 3600);
+        let vp = keys.holder.present(&vc, &[], NONCE, KB_AUDIENCE).unwrap();
+        let (_, other_public) = generate_key_pair(SigningAlgorithm::ES256).unwrap();
+        let verifier = Verifier::new(other_public, VC_AUDIENCE, KB_AUDIENCE);
+        assert!(matches!(
+            verifier.verify_presentation(&vp, NONCE),
+            Err(VerificationError::BadIssuerSignature)
+        ));
+    }
+
+    #[test]
+    fn wrong_audience_is_rejected() {
+        let keys = keys();
+        let vc = issue(&keys, &keys.holder_jwk, 3600);
+        let vp = keys
+            .holder
+            .present(&vc, &[], NONCE, "https://other.example.com")
+            .unwrap();
+        assert!(matches!(
+            verifier(&keys).verify_presentation(&vp, NONCE),
+            Err(VerificationError::AudienceMismatch {
+                token: JwtKind::KeyBinding,
+                ..
+            })
+        ));
+
+        let verifier = Verifier::new(
+            keys.issuer_public.clone(),
+            "https://other.example.com",
+            KB_AUDIENCE,
+        );
+        let vp = keys.holder.present(&vc, &[], NONCE, KB_AUDIENCE).unwrap();
+        assert!(matches!(
+            verifier.verify_presentation(&vp, NONCE),
+            Err(VerificationError::AudienceMismatch {
+                token: JwtKind::Credential,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn kb_jwt_signed_by_another_key_is_rejected() {
+        let keys = keys();
+        // VC の cnf は別の鍵
+        let (_, other_public) = generate_key_pair(SigningAlgorithm::ES256).unwrap();
+        let vc = issue(&keys, &public_key_pem_to_jwk(&other_public).unwrap(), 3600);
+        let vp = keys.holder.present(&vc, &[], NONCE, KB_AUDIENCE).unwrap();
+        assert!(matches!(
+            verifier(&keys).verify_presentation(&vp, NONCE),
+            Err(VerificationError::BadKbJwtSignature)
+        ));
+    }
+
+    #[test]
+    fn dropped_disclosure_is_sd_hash_mismatch() {
+        let keys = keys();
+        let vc = issue(&keys, &keys.holder_jwk, 3600);
+        let vp = keys
+            .holder
+            .present(&vc, &["account_name", "group_name"], NONCE, KB_AUDIENCE)
+            .unwrap();
+        let sd_jwt = crate::sd_jwt::parse(&vp).unwrap();
+        let tampered = crate::sd_jwt::presentation(
+            &sd_jwt.jwt,
+            &sd_jwt.disclosures[1..],
+            sd_jwt.key_binding_jwt.as_deref(),
+        );
+        assert!(matches!(
+            verifier(&keys).verify_presentation(&tampered, NONCE),
+            Err(VerificationError::SdHashMismatch)
+        ));
+    }
+
+    #[test]
+    fn expired_credential_is_rejected() {
+        let keys = keys();
+        let vc = issue(&keys, &keys.holder_jwk, 0);
+        let vp = keys.holder.present(&vc, &[], NONCE, KB_AUDIENCE).unwrap();
+        assert!(matches!(
+            verifier(&keys).verify_presentation(&vp, NONCE),
+            Err(VerificationError::Expired(JwtKind::Credential))
+        ));
+    }
+
+    #[test]
+    fn other_nonce_is_nonce_mismatch() {
+        let keys = keys();
+        let vc = issue(&keys, &keys.holder_jwk, 3600);
+        let vp = keys.holder.present(&vc, &[], "other", KB_AUDIENCE).unwrap();
+        assert!(matches!(
+            verifier(&keys).verify_presentation(&vp, NONCE),
+            Err(VerificationError::NonceMismatch)
+        ));
+    }
+
+    #[test]
+    fn stale_kb_jwt_iat_is_rejected() {
+        let keys = keys();
+        let vc = issue(&keys, &keys.holder_jwk, 3600);
+        let sd_jwt = crate::sd_jwt::parse(&vc).unwrap();
+
+        // 10 分前に作成した KB-JWT
+        let iat = SystemTime::now() - Duration::from_secs(600);
+        let mut header = JwsHeader::new();
+        header.set_token_type("kb+jwt");
+        header.set_algorithm("ES256");
+        let mut payload = JwtPayload::new();
+        payload.set_claim("nonce", Some(json!(NONCE))).unwrap();
+        let sd_hash = crate::sd_jwt::sd_hash(&sd_jwt.jwt, &[]);
+        payload.set_claim("sd_hash", Some(json!(sd_hash))).unwrap();
+        payload.set_audience(vec![KB_AUDIENCE]);
+        payload.set_issued_at(&iat);
+        let signer = SigningAlgorithm::ES256
+            .signer_from_pem(&keys.holder_private)
+            .unwrap();
+        let kb_jwt = jwt::encode_with_signer(&payload, &header, signer.as_ref()).unwrap();
+        let vp = crate::sd_jwt::presentation(&sd_jwt.jwt, &[], Some(&kb_jwt));
+
+        assert!(matches!(
+            verifier(&keys).verify_presentation(&vp, NONCE),
+            Err(VerificationError::IatOutOfWindow(JwtKind::KeyBinding))
+        ));
+        // 許容範囲を広げれば受け付ける
+        let verifier = verifier(&keys).with_iat_skew(Duration::from_secs(900));
+        assert!(verifier.verify_presentation(&vp, NONCE).is_ok());
+    }
+
+    #[test]
+    fn nonce_is_accepted_only_once() {
+        let keys = keys();
+        let nonces = Arc::new(crate::nonce::InMemoryNonceStore::new());
+        let verifier = verifier(&keys).with_nonce_store(nonces.clone());
+        let nonce = nonces.issue(Duration::from_secs(60));
+
+        let vc = issue(&keys, &keys.holder_jwk, 3600);
+        let vp = keys.holder.present(&vc, &[], &nonce, KB_AUDIENCE).unwrap();
+        assert!(verifier.verify_presentation(&vp, &nonce).is_ok());
+        assert!(matches!(
+            verifier.verify_presentation(&vp, &nonce),
+            Err(VerificationError::NonceReplayed)
+        ));
+
+        // 発行していない nonce も受け付けない
+        let vp = keys.holder.present(&vc, &[], NONCE, KB_AUDIENCE).unwrap();
+        assert!(matches!(
+            verifier.verify_presentation(&vp, NONCE),
+            Err(VerificationError::NonceReplayed)
+        ));
+    }
+
+    #[test]
+    fn device_response_binds_client_id_and_response_uri() {
+        use crate::mdoc::{MdocParams, MDL_DOCTYPE, MDL_NAMESPACE};
+
+        // x509_san_dns スキームのように client_id と response_uri が異なる場合
+        const CLIENT_ID: &str = "x509_san_dns:verifier.example.com";
+        let keys = keys();
+        let elements = json!({ "given_name": "Taro", "age_over_18": true });
+        let mdoc = keys
+            .issuer
+            .generate_mdoc(MdocParams {
+                doc_type: MDL_DOCTYPE.to_string(),
+                namespaces: [(
+                    MDL_NAMESPACE.to_string(),
+                    elements.as_object().unwrap().clone(),
+                )]
+                .into(),
+                holder_jwk: keys.holder_jwk.clone(),
+                valid_for: std::time::Duration::from_secs(3600),
+            })
+            .unwrap();
+        let response = keys
+            .holder
+            .present_mdoc(&mdoc, &["given_name"], NONCE, CLIENT_ID, KB_AUDIENCE)
+            .unwrap();
+
+        let documents = verifier(&keys)
+            .verify_device_response(&response, NONCE, CLIENT_ID, KB_AUDIENCE)
+            .unwrap();
+        assert_eq!(documents[0].claims[MDL_NAMESPACE]["given_name"], "Taro");
+
+        // client_id と response_uri を入れ替えた・同じ値にした SessionTranscript では検証できない
+        for (client_id, response_uri) in [
+            (KB_AUDIENCE, CLIENT_ID),
+            (KB_AUDIENCE, KB_AUDIENCE),
+            (CLIENT_ID, CLIENT_ID),
+        ] {
+            assert!(
+                matches!(
+                    verifier(&keys).verify_device_response(
+                        &response,
+                        NONCE,
+                        client_id,
+                        response_uri
+                    ),
+                    Err(VerificationError::BadDeviceSignature)
+                ),
+                "{client_id} {response_uri}"
+            );
+        }
+    }
+}
@@ -0,0 +1,458 @@
+//! OID4VP の Authorization Request に応答する Wallet
+//!
+//! Authorization Request (値渡し・`request`・`request_uri`) を解釈し、保存しているクレデンシャルから
+//! 要求に合うものを選んで、要求されたクレームの disclosure だけを含む VP を `response_uri` に送る。
+//! KB-JWT の `nonce` と `aud` は Authorization Request の `nonce` と `client_id` にする。
+//!
+//! 応答する前に `client_id` を Client Identifier Scheme に従って確認する。
+//!
+//! - `redirect_uri` (既定): `client_id` が `response_uri` と一致しなければならない。署名のない要求はこれだけ
+//! - `did` (`decentralized_identifier:` の接頭辞): Request Object の署名を、`client_id` の DID ドキュメントの
+//!   `kid` の鍵で検証する
+//!
+//! それ以外のスキームには対応していないので応答しない。
+
+use crate::{
+    credential_store::{CredentialFilter, CredentialStore},
+    did::{did_of, DidResolver, Relationship},
+    holder::Holder,
+    http,
+    nonce::generate_nonce,
+    oid4vp::{
+        AuthorizationRequest, CredentialQuery, CLIENT_ID_SCHEME_REDIRECT_URI, REQUEST_OBJECT_TYP,
+    },
+    SigningAlgorithm,
+};
+use anyhow::{anyhow, bail, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use josekit::jwt;
+use sd_jwt_payload::SdObjectDecoder;
+use serde_json::{json, Map, Value};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// `response_uri` に送る Authorization Response
+#[derive(Debug, Clone)]
+pub struct AuthorizationResponse {
+    pub response_uri: String,
+    /// Presentation Definition の場合は VP、DCQL の場合は `{ "<ID>": ["<VP>"] }`
+    pub vp_token: String,
+    /// Presentation Definition の場合のみ
+    pub presentation_submission: Option<Value>,
+    pub state: String,
+}
+
+impl AuthorizationResponse {
+    /// `direct_post` で送るフォームのパラメータ
+    pub fn form(&self) -> Vec<(&'static str, String)> {
+        let mut form = vec![
+            ("vp_token", self.vp_token.clone()),
+            ("state", self.state.clone()),
+        ];
+        if let Some(submission) = &self.presentation_submission {
+            form.push(("presentation_submission", submission.to_string()));
+        }
+        form
+    }
+}
+
+/// クレデンシャルを保持し、OID4VP の要求に応答する Wallet
+pub struct Wallet {
+    holder: Holder,
+    /// 保存している SD-JWT VC
+    credentials: Vec<String>,
+}
+
+impl Wallet {
+    pub fn new(holder: Holder, credentials: Vec<String>) -> Self {
+        Self {
+            holder,
+            credentials,
+        }
+    }
+
+    /// ファイルに保存された SD-JWT VC を読み込んで Wallet を作成
+    pub fn from_files(holder: Holder, files: &[String]) -> Result<Self> {
+        let credentials = files
+            .iter()
+            .map(|file| {
+                std::fs::read_to_string(file)
+                    .map(|vc| vc.trim().to_string())
+                    .map_err(|e| anyhow!("failed to read credential file {file}: {e:?}"))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self::new(holder, credentials))
+    }
+
+    /// ストアに保存している期限切れでない SD-JWT VC で Wallet を作成
+    pub fn from_store(holder: Holder, store: &CredentialStore) -> Result<Self> {
+        let credentials = store
+            .list(&CredentialFilter::default())?
+            .into_iter()
+            .filter(|credential| credential.format.ends_with("+sd-jwt"))
+            .map(|credential| credential.credential)
+            .collect();
+        Ok(Self::new(holder, credentials))
+    }
+
+    /// 要求に合うクレデンシャルを探す
+    /// `vct` が要求されたものの 1 つで、要求されたクレームをすべて含み、期限切れでないもの
+    pub fn find_credential(&self, query: &CredentialQuery) -> Option<&str> {
+        self.credentials
+            .iter()
+            .find(|vc| {
+                let Some(claims) = credential_claims(vc) else {
+                    return false;
+                };
+                let vct = claims.get("vct").and_then(Value::as_str);
+                let vct_matches = query.vct_values.is_empty()
+                    || vct.is_some_and(|vct| query.vct_values.iter().any(|v| v == vct));
+                vct_matches
+                    && query.claims.iter().all(|claim| claims.contains_key(claim))
+                    && !is_expired(&claims)
+            })
+            .map(String::as_str)
+    }
+
+    /// Authorization Request に対する Authorization Response を作成する
+    /// `request` は `resolve_request` で `client_id` を確認したものでなければならない
+    pub fn respond(&self, request: &AuthorizationRequest) -> Result<AuthorizationResponse> {
+        if !request.client_id_verified {
+            bail!("client_id of the request is not verified (use wallet::resolve_request)");
+        }
+        let query = request.credential_query()?;
+        let vc = self
+            .find_credential(&query)
+            .ok_or_else(|| anyhow!("no credential matches the request"))?;
+
+        let claim_names: Vec<&str> = query.claims.iter().map(String::as_str).collect();
+        let vp = self
+            .holder
+            .present(vc, &claim_names, &request.nonce, &request.client_id)?;
+
+        let (vp_token, presentation_submission) = match &request.presentation_definition {
+            Some(definition) => {
+                let submission = json!({
+                    "id": generate_nonce(),
+                    "definition_id": definition.get("id"),
+                    "descriptor_map": [{ "id": query.id, "format": "vc+sd-jwt", "path": "$" }],
+                });
+                (vp, Some(submission))
+            }
+            None => (json!({ query.id: [vp] }).to_string(), None),
+        };
+        Ok(AuthorizationResponse {
+            response_uri: request.response_uri.clone(),
+            vp_token,
+            presentation_submission,
+            state: request.state.clone(),
+        })
+    }
+
+    /// Authorization Response を作成して `response_uri` に POST し、Verifier の応答を返す
+    pub fn submit(&self, request: &AuthorizationRequest) -> Result<Value> {
+        let response = self.respond(request)?;
+        let form = response.form();
+        let params: Vec<(&str, &str)> = form.iter().map(|(k, v)| (*k, v.as_str())).collect();
+        http::post_form(&response.response_uri, &params)
+    }
+}
+
+/// Authorization Request を解釈し、`client_id` を確認する
+///
+/// - `openid4vp://?client_id=...&request_uri=...` などの URL (`request_uri`、`request`、値渡しのパラメータ)
+/// - `http(s)://...` の `request_uri`
+/// - Request Object の JWT、または Authorization Request の JSON
+///
+/// `did` スキームの DID は `DidResolver::new()` で解決する
+pub fn resolve_request(input: &str) -> Result<AuthorizationRequest> {
+    resolve_request_with(input, &DidResolver::new())
+}
+
+/// `resolve_request` と同じ。`did` スキームの DID は `did_resolver` で解決する
+pub fn resolve_request_with(
+    input: &str,
+    did_resolver: &DidResolver,
+) -> Result<AuthorizationRequest> {
+    let input = input.trim();
+    let decode = |request_object: &str, client_id: Option<&str>| {
+        decode_request_object(request_object, client_id, did_resolver)
+    };
+    let (mut request, signed) = if input.starts_with('{') {
+        let request = serde_json::from_str(input)
+            .map_err(|e| anyhow!("invalid authorization request: {e}"))?;
+        (request, false)
+    } else if input.starts_with("https://") || input.starts_with("http://") {
+        decode(http::get(input)?.trim(), None)?
+    } else if let Some((_, query)) = input.split_once('?') {
+        let params: Map<String, Value> = form_urlencoded::parse(query.as_bytes())
+            .into_owned()
+            .map(|(name, value)| {
+                // 値渡しの場合、presentation_definition と dcql_query は JSON の文字列
+                let value = match name.as_str() {
+                    "presentation_definition" | "dcql_query" => {
+                        serde_json::from_str(&value).unwrap_or(Value::String(value))
+                    }
+                    _ => Value::String(value),
+                };
+                (name, value)
+            })
+            .collect();
+        let client_id = params.get("client_id").and_then(Value::as_str);
+        if let Some(request_uri) = params.get("request_uri").and_then(Value::as_str) {
+            decode(http::get(request_uri)?.trim(), client_id)?
+        } else if let Some(request) = params.get("request").and_then(Value::as_str) {
+            decode(request, client_id)?
+        } else {
+            let request = serde_json::from_value(Value::Object(params))
+                .map_err(|e| anyhow!("invalid authorization request: {e}"))?;
+            (request, false)
+        }
+    } else {
+        decode(input, None)?
+    };
+    check_request(&request)?;
+    check_client_id(&request, signed)?;
+    request.client_id_verified = true;
+    Ok(request)
+}
+
+/// Client Identifier Scheme (`client_id_scheme`、なければ `client_id` の接頭辞。どちらもなければ `redirect_uri`)
+fn client_id_scheme(request: &AuthorizationRequest) -> &str {
+    if let Some(scheme) = &request.client_id_scheme {
+        return scheme;
+    }
+    match request.client_id.split_once(':') {
+        Some(("redirect_uri", _)) => CLIENT_ID_SCHEME_REDIRECT_URI,
+        Some(("decentralized_identifier", _)) => "did",
+        _ => CLIENT_ID_SCHEME_REDIRECT_URI,
+    }
+}
+
+/// `client_id` から Client Identifier Scheme の接頭辞を除いた識別子
+fn client_identifier(request: &AuthorizationRequest) -> &str {
+    let client_id = request.client_id.as_str();
+    client_id
+        .strip_prefix("redirect_uri:")
+        .or_else(|| client_id.strip_prefix("decentralized_identifier:"))
+        .unwrap_or(client_id)
+}
+
+/// `client_id` が Client Identifier Scheme に従っているかチェックする
+/// `redirect_uri` 以外のスキームは、署名を検証した Request Object でなければならない
+fn check_client_id(request: &AuthorizationRequest, signed: bool) -> Result<()> {
+    match client_id_scheme(request) {
+        CLIENT_ID_SCHEME_REDIRECT_URI => {
+            if client_identifier(request) != request.response_uri {
+                bail!(
+                    "client_id {} does not match response_uri {}",
+                    request.client_id,
+                    request.response_uri
+                );
+            }
+        }
+        "did" if signed => {}
+        "did" => bail!("client_id_scheme did requires a signed request object"),
+        scheme => bail!("unsupported client_id_scheme {scheme}"),
+    }
+    Ok(())
+}
+
+/// Request Object (JWT) を Authorization Request にし、署名を検証したかどうかを返す
+/// 外側のパラメータの `client_id` がある場合は一致しなければならない
+///
+/// 署名付きの Request Object は `did` スキームのみ対応し、`kid` (`client_id` の DID の DID URL) の
+/// verification method の鍵で検証する
+fn decode_request_object(
+    request_object: &str,
+    client_id: Option<&str>,
+    did_resolver: &DidResolver,
+) -> Result<(AuthorizationRequest, bool)> {
+    let header =
+        jwt::decode_header(request_object).map_err(|e| anyhow!("invalid request object: {e:?}"))?;
+    let signed = header.claim("alg").and_then(Value::as_str) != Some("none");
+    let (payload, header) = if signed {
+        let alg = SigningAlgorithm::from_jwt(request_object)?;
+        let kid = header
+            .claim("kid")
+            .and_then(Value::as_str)
+            .ok_or_else(|| anyhow!("signed request object has no kid"))?
+            .to_string();
+        // 署名を検証する前に、payload の client_id が kid の DID か確認する
+        let unverified: Map<String, Value> = request_object
+            .split('.')
+            .nth(1)
+            .and_then(|payload| URL_SAFE_NO_PAD.decode(payload).ok())
+            .and_then(|payload| serde_json::from_slice(&payload).ok())
+            .ok_or_else(|| anyhow!("invalid request object payload"))?;
+        let request_client_id = unverified
+            .get("client_id")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        let did = request_client_id
+            .strip_prefix("decentralized_identifier:")
+            .unwrap_or(request_client_id);
+        if !did.starts_with("did:") || did_of(&kid) != did {
+            bail!("kid {kid} of the request object is not a key of client_id {request_client_id}");
+        }
+        let jwk = did_resolver
+            .resolve_verification_method(&kid, Relationship::Authentication)?
+            .jwk()?;
+        let verifier = alg.verifier_from_jwk(&jwk)?;
+        jwt::decode_with_verifier(request_object, verifier.as_ref())
+            .map_err(|e| anyhow!("invalid request object signature: {e:?}"))?
+    } else {
+        jwt::decode_unsecured(request_object)
+            .map_err(|e| anyhow!("invalid request object: {e:?}"))?
+    };
+    if let Some(typ) = header.token_type() {
+        if typ != REQUEST_OBJECT_TYP {
+            bail!("invalid request object typ {typ}");
+        }
+    }
+    let request: AuthorizationRequest = serde_json::from_value(Value::Object(payload.into()))
+        .map_err(|e| anyhow!("invalid authorization request: {e}"))?;
+    if let Some(client_id) = client_id {
+        if request.client_id != client_id {
+            bail!("client_id of the request object does not match");
+        }
+    }
+    if signed && client_id_scheme(&request) == CLIENT_ID_SCHEME_REDIRECT_URI {
+        bail!("request objects with the redirect_uri client_id scheme must not be signed");
+    }
+    Ok((request, signed))
+}
+
+/// 対応している Authorization Request かチェックする
+fn check_request(request: &AuthorizationRequest) -> Result<()> {
+    if request.response_type != "vp_token" {
+        bail!("unsupported response_type {}", request.response_type);
+    }
+    if request.response_mode != "direct_post" {
+        bail!("unsupported response_mode {}", request.response_mode);
+    }
+    if request.nonce.is_empty() {
+        bail!("request has no nonce");
+    }
+    Ok(())
+}
+
+/// SD-JWT VC のすべての disclosure を復元したクレーム (Holder 自身の確認用で、署名は検証しない)
+fn credential_claims(vc: &str) -> Option<Map<String, Value>> {
+    let sd_jwt = crate::sd_jwt::parse(vc).ok()?;
+    let payload = sd_jwt.jwt.split('.').nth(1)?;
+    let payload = URL_SAFE_NO_PAD.decode(payload).ok()?;
+    let claims: Map<String, Value> = serde_json::from_slice(&payload).ok()?;
+    SdObjectDecoder::new_with_sha256()
+        .decode(&claims, &sd_jwt.disclosures)
+        .ok()
+}
+
+/// `exp` が過ぎているか
+fn is_expired(claims: &Map<String, Value>) -> bool {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    claims
+        .get("exp")
+        .and_then(Value::as_u64)
+        .is_some_and(|exp| exp <= now)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        did::did_jwk,
+        key::{generate_key_pair, public_key_pem_to_jwk},
+    };
+    use josekit::{jws::JwsHeader, jwt::JwtPayload};
+
+    const RESPONSE_URI: &str = "https://verifier.example/response";
+
+    fn request(client_id: &str, client_id_scheme: Option<&str>) -> Value {
+        json!({
+            "client_id": client_id,
+            "client_id_scheme": client_id_scheme,
+            "response_type": "vp_token",
+            "response_mode": "direct_post",
+            "response_uri": RESPONSE_URI,
+            "nonce": "n-0S6_WzA2Mj",
+            "state": "state",
+            "dcql_query": { "credentials": [{ "id": "credential", "format": "vc+sd-jwt" }] },
+        })
+    }
+
+    fn request_object(claims: &Value, private_key: Option<(&[u8], &str)>) -> String {
+        let payload = JwtPayload::from_map(claims.as_object().unwrap().clone()).unwrap();
+        let mut header = JwsHeader::new();
+        header.set_token_type(REQUEST_OBJECT_TYP);
+        match private_key {
+            Some((private_key, kid)) => {
+                header.set_key_id(kid);
+                let signer = SigningAlgorithm::ES256
+                    .signer_from_pem(private_key)
+                    .unwrap();
+                jwt::encode_with_signer(&payload, &header, signer.as_ref()).unwrap()
+            }
+            None => jwt::encode_unsecured(&payload, &header).unwrap(),
+        }
+    }
+
+    #[test]
+    fn redirect_uri_client_id_must_be_response_uri() {
+        let resolved = resolve_request(&request(RESPONSE_URI, None).to_string()).unwrap();
+        assert!(resolved.client_id_verified);
+        let object = request_object(&request(RESPONSE_URI, Some("redirect_uri")), None);
+        resolve_request(&object).unwrap();
+        let prefixed = format!("redirect_uri:{RESPONSE_URI}");
+        resolve_request(&request(&prefixed, None).to_string()).unwrap();
+
+        // 別の Verifier の client_id で、VP を自分の response_uri に送らせる
+        let relayed = request("https://real-verifier.example", None);
+        assert!(resolve_request(&relayed.to_string()).is_err());
+        assert!(resolve_request(&request_object(&relayed, None)).is_err());
+        let url = format!(
+            "openid4vp://?{}",
+            form_urlencoded::Serializer::new(String::new())
+                .append_pair("client_id", "https://real-verifier.example")
+                .append_pair("request", &request_object(&relayed, None))
+                .finish()
+        );
+        assert!(resolve_request(&url).is_err());
+    }
+
+    #[test]
+    fn other_schemes_require_a_signed_request_object() {
+        let (private_key, public_key) = generate_key_pair(SigningAlgorithm::ES256).unwrap();
+        let did = did_jwk(&public_key_pem_to_jwk(&public_key).unwrap()).unwrap();
+        let kid = format!("{did}#0");
+        let claims = request(&did, Some("did"));
+
+        assert!(resolve_request(&claims.to_string()).is_err());
+        assert!(resolve_request(&request_object(&claims, None)).is_err());
+        let signed = request_object(&claims, Some((&private_key, &kid)));
+        assert!(resolve_request(&signed).unwrap().client_id_verified);
+        let prefixed = request(&format!("decentralized_identifier:{did}"), None);
+        resolve_request(&request_object(&prefixed, Some((&private_key, &kid)))).unwrap();
+
+        // client_id の DID 以外の鍵で署名したもの
+        let (other_key, _) = generate_key_pair(SigningAlgorithm::ES256).unwrap();
+        assert!(resolve_request(&request_object(&claims, Some((&other_key, &kid)))).is_err());
+
+        let unsupported = request("verifier.example", Some("x509_san_dns"));
+        assert!(
+            resolve_request(&request_object(&unsupported, Some((&private_key, &kid)))).is_err()
+        );
+    }
+
+    #[test]
+    fn respond_requires_a_resolved_request() {
+        let (private_key, _) = generate_key_pair(SigningAlgorithm::ES256).unwrap();
+        let wallet = Wallet::new(Holder::new(private_key).unwrap(), Vec::new());
+        let request: AuthorizationRequest =
+            serde_json::from_value(request(RESPONSE_URI, None)).unwrap();
+        let error = wallet.respond(&request).unwrap_err();
+        assert!(error.to_string().contains("not verified"));
+    }
+}